@@ -3,7 +3,10 @@ use crate::emulator::clint::*;
 use crate::emulator::plic::*;
 use crate::emulator::uart::*;
 use crate::emulator::virtio::*;
+use crate::emulator::disk::Disk;
 use crate::emulator::interrupt::IrqNumber;
+use crate::emulator::mmu::ACCESS;
+use std::time::Duration;
 
 /*
  * Physical Address Layout
@@ -23,6 +26,21 @@ use crate::emulator::interrupt::IrqNumber;
  * | 0x8800_0000 | 0xFFFF_FFFF | Reserved      |
  * +-------------+-------------+---------------+
  * 
+ * Physical Address Layout of the MCU machine (flash and SRAM replace the boot ROM and DRAM)
+ *
+ * +-------------+-------------+---------------+
+ * |     Base    |     Top     | Description   |
+ * +-------------+-------------+---------------+
+ * | 0x0000_0000 | 0x0003_FFFF | Flash (256KiB)|
+ * | 0x0004_0000 | 0x01FF_FFFF | Reserved      |
+ * | 0x0200_0000 | 0x0200_FFFF | CLINT         |
+ * | 0x0201_0000 | 0x0FFF_FFFF | Reserved      |
+ * | 0x1000_0000 | 0x1000_00FF | UART0         |
+ * | 0x1000_0100 | 0x1FFF_FFFF | Reserved      |
+ * | 0x2000_0000 | 0x2000_FFFF | SRAM (64KiB)  |
+ * | 0x2001_0000 | 0xFFFF_FFFF | Reserved      |
+ * +-------------+-------------+---------------+
+ *
  */
 
 pub const BOOT_ROM_BASE:    usize = 0x000_1000;
@@ -43,10 +61,96 @@ pub const VIRTIO_TOP:     usize = 0x1000_1FFF;
 pub const DRAM_BASE:    usize = 0x8000_0000;
 pub const DRAM_TOP:     usize = 0x87FF_FFFF;
 
+pub const FLASH_BASE:    usize = 0x0000_0000;
+pub const FLASH_TOP:     usize = 0x0003_FFFF;
+
+pub const SRAM_BASE:    usize = 0x2000_0000;
+pub const SRAM_TOP:     usize = 0x2000_FFFF;
+
+// Board whose memory map the bus implements
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Machine {
+    Virt,   // qemu's virt board: boot ROM, PLIC, VIRTIO and 128MiB of DRAM
+    Mcu,    // Microcontroller: firmware in flash at address 0, SRAM and CLINT
+}
+
+impl std::str::FromStr for Machine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "virt"  => Ok(Machine::Virt),
+            "mcu"   => Ok(Machine::Mcu),
+            _       => Err(format!("unsupported machine: {}", s)),
+        }
+    }
+}
+
+impl Machine {
+    // Physical memory attributes of every region in the memory map
+    pub fn pma_table(self) -> &'static [Pma] {
+        match self {
+            Machine::Virt   => &PMA_TABLE,
+            Machine::Mcu    => &MCU_PMA_TABLE,
+        }
+    }
+}
+
+/*
+ * Physical Memory Attributes
+ * Reference:   RISC-V Privileged ISA Specification 3.6 Physical Memory Attributes
+ *
+ * +---------+-------+-------+------------+------+-------+---------------+
+ * | Region  | Write | Exec  | Idempotent | AMO  | LR/SC | Access widths |
+ * +---------+-------+-------+------------+------+-------+---------------+
+ * | CLINT   |   o   |   -   |     -      |  -   |   -   | 4, 8          |
+ * | PLIC    |   o   |   -   |     -      |  -   |   -   | 4             |
+ * | UART0   |   o   |   -   |     -      |  -   |   -   | 1             |
+ * | VIRTIO  |   o   |   -   |     -      |  -   |   -   | 1, 2, 4       |
+ * | DRAM    |   o   |   o   |     o      |  o   |   o   | 1, 2, 4, 8    |
+ * | Flash   |   -   |   o   |     o      |  -   |   -   | 1, 2, 4, 8    |
+ * | SRAM    |   o   |   o   |     o      |  o   |   o   | 1, 2, 4, 8    |
+ * +---------+-------+-------+------------+------+-------+---------------+
+ *
+ */
+
+pub struct Pma {
+    pub base:       usize,
+    pub top:        usize,
+    pub writable:   bool,       // Stores are permitted
+    pub executable: bool,       // Instruction fetches are permitted
+    pub idempotent: bool,       // Accesses have no side effects, so misaligned accesses may be split
+    pub amo:        bool,       // AMOs are supported
+    pub lrsc:       bool,       // LR/SC reservations are supported
+    pub widths:     usize,      // Bitmap of the legal access sizes in bytes
+}
+
+const IO_REGION: Pma = Pma { base: 0, top: 0, writable: true, executable: false, idempotent: false, amo: false, lrsc: false, widths: 0 };
+
+pub const PMA_TABLE: [Pma; 5] = [
+    Pma { base: CLINT_BASE,  top: CLINT_TOP,  widths: 4 | 8,         ..IO_REGION },
+    Pma { base: PLIC_BASE,   top: PLIC_TOP,   widths: 4,             ..IO_REGION },
+    Pma { base: UART0_BASE,  top: UART0_TOP,  widths: 1,             ..IO_REGION },
+    Pma { base: VIRTIO_BASE, top: VIRTIO_TOP, widths: 1 | 2 | 4,     ..IO_REGION },
+    Pma { base: DRAM_BASE,   top: DRAM_TOP,   widths: 1 | 2 | 4 | 8, executable: true, idempotent: true, amo: true, lrsc: true, writable: true },
+];
+
+pub const MCU_PMA_TABLE: [Pma; 4] = [
+    Pma { base: FLASH_BASE,  top: FLASH_TOP,  widths: 1 | 2 | 4 | 8, executable: true, idempotent: true, amo: false, lrsc: false, writable: false },
+    Pma { base: CLINT_BASE,  top: CLINT_TOP,  widths: 4 | 8,         ..IO_REGION },
+    Pma { base: UART0_BASE,  top: UART0_TOP,  widths: 1,             ..IO_REGION },
+    Pma { base: SRAM_BASE,   top: SRAM_TOP,   widths: 1 | 2 | 4 | 8, executable: true, idempotent: true, amo: true, lrsc: true, writable: true },
+];
+
+// Longest time the host sleeps in a row while the hart is waiting for an interrupt
+const IDLE_TIMEOUT: Duration = Duration::from_millis(10);
 
 pub struct Bus {
     clock:  u64,
+    machine: Machine,
     dram:   Dram,
+    flash:  Dram,
+    sram:   Dram,
     clint:  Clint,
     pub plic:   Plic,
     uart0:  Uart,
@@ -57,7 +161,10 @@ impl Bus {
     pub fn new() -> Self {
         Bus {
             clock:  0,
+            machine: Machine::Virt,
             dram:   Dram::new(),
+            flash:  Dram::with_size(0),
+            sram:   Dram::with_size(0),
             clint:  Clint::new(),
             plic:   Plic::new(),
             uart0:  Uart::new(),
@@ -65,23 +172,103 @@ impl Bus {
         }
     }
 
+    // Switch to the memory map of `machine`, allocating only the memories it has
+    pub fn set_machine(&mut self, machine: Machine) {
+        if self.machine == machine {
+            return;
+        }
+        self.machine = machine;
+        match machine {
+            Machine::Virt   => {
+                self.dram = Dram::new();
+                self.flash = Dram::with_size(0);
+                self.sram = Dram::with_size(0);
+            },
+            Machine::Mcu    => {
+                self.dram = Dram::with_size(0);
+                self.flash = Dram::with_size(FLASH_TOP - FLASH_BASE + 1);
+                self.sram = Dram::with_size(SRAM_TOP - SRAM_BASE + 1);
+            },
+        }
+    }
+
+    // Physical memory attributes of the region containing `paddr`
+    pub fn pma(&self, paddr: usize) -> Option<&'static Pma> {
+        self.machine.pma_table().iter().find(|pma| pma.base <= paddr && paddr <= pma.top)
+    }
+
+    // Check an access of `size` bytes at `paddr` against the physical memory attributes.
+    // Returns true if the access is permitted.
+    pub fn pma_check(&self, paddr: usize, size: usize, access: ACCESS) -> bool {
+        let pma = match self.pma(paddr) {
+            Some(pma)   => pma,
+            None        => return false,
+        };
+
+        // The access must neither leave the region nor use an unsupported width
+        if paddr + size - 1 > pma.top || (pma.widths & size) == 0 {
+            return false;
+        }
+
+        // Accesses to I/O regions can not be split, so they must be naturally aligned
+        if !pma.idempotent && !paddr.is_multiple_of(size) {
+            return false;
+        }
+
+        match access {
+            ACCESS::EXEC    => pma.executable,
+            ACCESS::STORE   => pma.writable,
+            _               => true,
+        }
+    }
+
+    // The kernel is loaded at the reset vector: into DRAM on virt, and into flash on the MCU
     pub fn load_dram(&mut self, binary: Vec<u8>) {
-        self.dram.load(binary);
+        match self.machine {
+            Machine::Virt   => self.dram.load(binary),
+            Machine::Mcu    => self.flash.load(binary),
+        }
     }
 
     pub fn load_disk(&mut self, binary: Vec<u8>) {
         self.virtio.load(binary);
     }
 
+    pub fn set_disk(&mut self, disk: Disk) {
+        self.virtio.set_disk(disk);
+    }
+
     pub fn tick(&mut self, mip: &mut u64) {
 
         self.clint.tick(mip);
         self.virtio.tick(&mut self.dram);
         self.uart0.tick();
-        self.plic.tick(false, self.uart0.is_interrupting(), mip);
+        self.plic.tick(self.virtio.is_interrupting(), self.uart0.is_interrupting(), mip);
         self.clock = self.clock.wrapping_add(1);
     }
 
+    // Called while the hart is stalled by WFI: advance mtime to the next timer deadline (mtimecmp
+    // or the hart's `deadline`) if there is one, otherwise block the host until UART input arrives
+    // or the idle timeout expires.
+    // Virtio requests are completed synchronously by `tick`, so they need no waiting here.
+    pub fn wait_for_event(&mut self, deadline: Option<u64>) {
+        if !self.clint.skip_to_deadline(deadline) {
+            self.uart0.wait_for_input(IDLE_TIMEOUT);
+        }
+    }
+
+    pub fn mtime(&mut self) -> u64 {
+        self.clint.mtime()
+    }
+
+    pub fn attach_stdin(&mut self) {
+        self.uart0.attach_stdin();
+    }
+
+    pub fn set_virtio_legacy(&mut self, legacy: bool) {
+        self.virtio.set_legacy(legacy);
+    }
+
     pub fn get_irqno(&self) -> Option<IrqNumber> {
         self.plic.get_irqno()
     }
@@ -98,6 +285,8 @@ impl Bus {
             UART0_BASE ..= UART0_TOP        => self.uart0.write8(paddr - UART0_BASE, data),
             // VIRTIO
             VIRTIO_BASE ..= VIRTIO_TOP      => self.virtio.write8(paddr - VIRTIO_BASE, data),
+            // SRAM (MCU)
+            SRAM_BASE ..= SRAM_TOP if self.machine == Machine::Mcu      => self.sram.write8(paddr - SRAM_BASE, data),
             // DRAM
             DRAM_BASE ..= DRAM_TOP          => self.dram.write8(paddr - DRAM_BASE, data),
             _                               => unimplemented!(),
@@ -116,6 +305,8 @@ impl Bus {
             UART0_BASE ..= UART0_TOP        => self.uart0.write16(paddr - UART0_BASE, data),
             // VIRTIO
             VIRTIO_BASE ..= VIRTIO_TOP      => self.virtio.write16(paddr - VIRTIO_BASE, data),
+            // SRAM (MCU)
+            SRAM_BASE ..= SRAM_TOP if self.machine == Machine::Mcu      => self.sram.write16(paddr - SRAM_BASE, data),
             // DRAM
             DRAM_BASE ..= DRAM_TOP          => self.dram.write16(paddr - DRAM_BASE, data),
             _                               => unimplemented!(),
@@ -134,6 +325,8 @@ impl Bus {
             UART0_BASE ..= UART0_TOP        => self.uart0.write32(paddr - UART0_BASE, data),
             // VIRTIO
             VIRTIO_BASE ..= VIRTIO_TOP      => self.virtio.write32(paddr - VIRTIO_BASE, data),
+            // SRAM (MCU)
+            SRAM_BASE ..= SRAM_TOP if self.machine == Machine::Mcu      => self.sram.write32(paddr - SRAM_BASE, data),
             // DRAM
             DRAM_BASE ..= DRAM_TOP          => self.dram.write32(paddr - DRAM_BASE, data),
             _                               => unimplemented!(),
@@ -152,6 +345,8 @@ impl Bus {
             UART0_BASE ..= UART0_TOP        => self.uart0.write64(paddr - UART0_BASE, data),
             // VIRTIO
             VIRTIO_BASE ..= VIRTIO_TOP      => self.virtio.write64(paddr - VIRTIO_BASE, data),
+            // SRAM (MCU)
+            SRAM_BASE ..= SRAM_TOP if self.machine == Machine::Mcu      => self.sram.write64(paddr - SRAM_BASE, data),
             // DRAM
             DRAM_BASE ..= DRAM_TOP          => self.dram.write64(paddr - DRAM_BASE, data),
             _                               => unimplemented!(),
@@ -160,6 +355,8 @@ impl Bus {
 
     pub fn read8(&mut self, paddr: usize) -> u8 {
         match paddr {
+            // Flash (MCU)
+            FLASH_BASE ..= FLASH_TOP if self.machine == Machine::Mcu    => self.flash.read8(paddr - FLASH_BASE),
             // boot ROM
             BOOT_ROM_BASE ..= BOOT_ROM_TOP  => unimplemented!(),
             // CLINT
@@ -170,6 +367,8 @@ impl Bus {
             UART0_BASE ..= UART0_TOP        => self.uart0.read8(paddr - UART0_BASE),
             // VIRTIO
             VIRTIO_BASE ..= VIRTIO_TOP      => self.virtio.read8(paddr - VIRTIO_BASE),
+            // SRAM (MCU)
+            SRAM_BASE ..= SRAM_TOP if self.machine == Machine::Mcu      => self.sram.read8(paddr - SRAM_BASE),
             // DRAM
             DRAM_BASE ..= DRAM_TOP          => self.dram.read8(paddr - DRAM_BASE),
             _                               => panic!("invalid paddr: 0x{:016x}", paddr),
@@ -178,6 +377,8 @@ impl Bus {
     
     pub fn read16(&mut self, paddr: usize) -> u16 {
         match paddr {
+            // Flash (MCU)
+            FLASH_BASE ..= FLASH_TOP if self.machine == Machine::Mcu    => self.flash.read16(paddr - FLASH_BASE),
             // boot ROM
             BOOT_ROM_BASE ..= BOOT_ROM_TOP  => unimplemented!(),
             // CLINT
@@ -188,6 +389,8 @@ impl Bus {
             UART0_BASE ..= UART0_TOP        => self.uart0.read16(paddr - UART0_BASE),
             // VIRTIO
             VIRTIO_BASE ..= VIRTIO_TOP      => self.virtio.read16(paddr - VIRTIO_BASE),
+            // SRAM (MCU)
+            SRAM_BASE ..= SRAM_TOP if self.machine == Machine::Mcu      => self.sram.read16(paddr - SRAM_BASE),
             // DRAM
             DRAM_BASE ..= DRAM_TOP          => self.dram.read16(paddr - DRAM_BASE),
             _                               => panic!("invalid paddr: 0x{:016x}", paddr),
@@ -196,6 +399,8 @@ impl Bus {
 
     pub fn read32(&mut self, paddr: usize) -> u32 {
         match paddr {
+            // Flash (MCU)
+            FLASH_BASE ..= FLASH_TOP if self.machine == Machine::Mcu    => self.flash.read32(paddr - FLASH_BASE),
             // boot ROM
             BOOT_ROM_BASE ..= BOOT_ROM_TOP  => unimplemented!(),
             // CLINT
@@ -206,6 +411,8 @@ impl Bus {
             UART0_BASE ..= UART0_TOP        => self.uart0.read32(paddr - UART0_BASE),
             // VIRTIO
             VIRTIO_BASE ..= VIRTIO_TOP      => self.virtio.read32(paddr - VIRTIO_BASE),
+            // SRAM (MCU)
+            SRAM_BASE ..= SRAM_TOP if self.machine == Machine::Mcu      => self.sram.read32(paddr - SRAM_BASE),
             // DRAM
             DRAM_BASE ..= DRAM_TOP          => self.dram.read32(paddr - DRAM_BASE),
             _                               => panic!("invalid paddr: 0x{:016x}", paddr),
@@ -214,6 +421,8 @@ impl Bus {
     
     pub fn read64(&mut self, paddr: usize) -> u64 {
         match paddr {
+            // Flash (MCU)
+            FLASH_BASE ..= FLASH_TOP if self.machine == Machine::Mcu    => self.flash.read64(paddr - FLASH_BASE),
             // boot ROM
             BOOT_ROM_BASE ..= BOOT_ROM_TOP  => unimplemented!(),
             // CLINT
@@ -224,10 +433,32 @@ impl Bus {
             UART0_BASE ..= UART0_TOP        => self.uart0.read64(paddr - UART0_BASE),
             // VIRTIO
             VIRTIO_BASE ..= VIRTIO_TOP      => self.virtio.read64(paddr - VIRTIO_BASE),
+            // SRAM (MCU)
+            SRAM_BASE ..= SRAM_TOP if self.machine == Machine::Mcu      => self.sram.read64(paddr - SRAM_BASE),
             // DRAM
             DRAM_BASE ..= DRAM_TOP          => self.dram.read64(paddr - DRAM_BASE),
             _                               => panic!("invalid paddr: 0x{:016x}", paddr),
         }
     }
 
+    // Store `new` only if the word still holds `current`; returns whether the store happened
+    pub fn compare_exchange32(&mut self, paddr: usize, current: u32, new: u32) -> bool {
+        if self.read32(paddr) != current {
+            return false;
+        }
+        self.write32(paddr, new);
+        true
+    }
+
+    // Store `new` only if the doubleword still holds `current`; returns whether the store happened.
+    // This is not an atomic operation: it is only correct because there is a single hart and
+    // every access is serialized through `&mut Bus`.
+    pub fn compare_exchange64(&mut self, paddr: usize, current: u64, new: u64) -> bool {
+        if self.read64(paddr) != current {
+            return false;
+        }
+        self.write64(paddr, new);
+        true
+    }
+
 }
\ No newline at end of file
@@ -107,6 +107,33 @@ impl Clint {
         }
     }
 
+    pub fn mtime(&mut self) -> u64 {
+        self.read64(MTIME_BASE)
+    }
+
+    // Advance mtime to the nearest of mtimecmp (if the timer is armed) and `deadline`
+    // (e.g. stimecmp) that has not been reached yet. Returns whether time was skipped.
+    pub fn skip_to_deadline(&mut self, deadline: Option<u64>) -> bool {
+        let mtime = self.read64(MTIME_BASE);
+        let mtimecmp = match self.read64(MTIMECMP_BASE) {
+            0           => None,
+            mtimecmp    => Some(mtimecmp),
+        };
+
+        let next = mtimecmp.into_iter()
+            .chain(deadline)
+            .filter(|&deadline| deadline > mtime)
+            .min();
+
+        match next {
+            Some(next)  => {
+                self.write64(MTIME_BASE, next);
+                true
+            },
+            None        => false,
+        }
+    }
+
     pub fn tick(&mut self, mip: &mut u64) {
         self.clock = self.clock.wrapping_add(1);
 
@@ -0,0 +1,218 @@
+// Expansion of the standard compressed instructions (C extension) into the 32-bit instructions they stand for.
+// Reference:   The RISC-V Instruction Set Manual Volume I, "C" Standard Extension for Compressed Instructions
+
+const OP_LOAD: u32      = 0b000_0011;
+const OP_STORE: u32     = 0b010_0011;
+const OP_IMM: u32       = 0b001_0011;
+const OP_IMM_32: u32    = 0b001_1011;
+const OP: u32           = 0b011_0011;
+const OP_32: u32        = 0b011_1011;
+const OP_LUI: u32       = 0b011_0111;
+const OP_BRANCH: u32    = 0b110_0011;
+const OP_JAL: u32       = 0b110_1111;
+const OP_JALR: u32      = 0b110_0111;
+
+const EBREAK: u32       = 0x0010_0073;     // C.EBREAK expands to the only SYSTEM instruction with a compressed form
+
+/*
+ *  Compressed instruction formats used below (rd', rs1' and rs2' are 3-bit fields naming x8-x15)
+ *
+ *  15   13 12 11    7 6     2 1  0
+ *  +------+--+-------+-------+----+
+ *  |funct3|  | rd/rs1|  rs2  | op |   CR, CI, CSS: the immediate is scattered over bits 12:2
+ *  +------+--+-------+-------+----+
+ *  |funct3|  |  |rs1'|   |rd'| op |   CIW, CL, CS, CA, CB: rs1' in bits 9:7, rd'/rs2' in bits 4:2
+ *  +------+--+-------+-------+----+
+ */
+
+// The 32-bit instruction `inst` expands to with XLEN `xlen`, or None if it is reserved
+// or needs an extension that is not implemented (the floating-point loads and stores)
+pub fn expand(inst: u16, xlen: usize) -> Option<u32> {
+    let inst = inst as u32;
+    let funct3 = inst >> 13;
+    let rd = (inst >> 7) & 0x1F;
+    let rs2 = (inst >> 2) & 0x1F;
+    let rd_ = ((inst >> 2) & 0b111) + 8;
+    let rs1_ = ((inst >> 7) & 0b111) + 8;
+    let imm6 = sext(((inst >> 7) & 0x20) | ((inst >> 2) & 0x1F), 6);
+    let shamt = ((inst >> 7) & 0x20) | ((inst >> 2) & 0x1F);
+    let rv64 = xlen == 64;
+
+    let expanded = match (inst & 0b11, funct3) {
+        // C.ADDI4SPN: the all-zero instruction is illegal, and a zero immediate is reserved
+        (0b00, 0b000)   => {
+            let imm = ((inst >> 7) & 0x30) | ((inst >> 1) & 0x3C0) | ((inst >> 4) & 0x4) | ((inst >> 2) & 0x8);
+            if imm == 0 {
+                return None;
+            }
+            itype(imm as i32, 2, 0b000, rd_, OP_IMM)
+        },
+        // C.LW
+        (0b00, 0b010)   => itype(clw_offset(inst) as i32, rs1_, 0b010, rd_, OP_LOAD),
+        // C.LD
+        (0b00, 0b011) if rv64 => itype(cld_offset(inst) as i32, rs1_, 0b011, rd_, OP_LOAD),
+        // C.SW
+        (0b00, 0b110)   => stype(clw_offset(inst) as i32, rd_, rs1_, 0b010),
+        // C.SD
+        (0b00, 0b111) if rv64 => stype(cld_offset(inst) as i32, rd_, rs1_, 0b011),
+        // C.ADDI (C.NOP with rd=x0)
+        (0b01, 0b000)   => itype(imm6, rd, 0b000, rd, OP_IMM),
+        // C.JAL
+        (0b01, 0b001) if !rv64 => jtype(cj_offset(inst), 1),
+        // C.ADDIW
+        (0b01, 0b001)   => {
+            if rd == 0 {
+                return None;
+            }
+            itype(imm6, rd, 0b000, rd, OP_IMM_32)
+        },
+        // C.LI
+        (0b01, 0b010)   => itype(imm6, 0, 0b000, rd, OP_IMM),
+        // C.ADDI16SP
+        (0b01, 0b011) if rd == 2 => {
+            let imm = ((inst >> 3) & 0x200) | ((inst >> 2) & 0x10) | ((inst << 1) & 0x40) | ((inst << 4) & 0x180) | ((inst << 3) & 0x20);
+            if imm == 0 {
+                return None;
+            }
+            itype(sext(imm, 10), 2, 0b000, 2, OP_IMM)
+        },
+        // C.LUI
+        (0b01, 0b011)   => {
+            if imm6 == 0 {
+                return None;
+            }
+            ((imm6 as u32 & 0xF_FFFF) << 12) | (rd << 7) | OP_LUI
+        },
+        (0b01, 0b100)   => match (inst >> 10) & 0b11 {
+            // C.SRLI and C.SRAI: RV32 has no shift amounts above 31
+            0b00 | 0b01 => {
+                if !rv64 && shamt >= 32 {
+                    return None;
+                }
+                let funct6 = if (inst >> 10) & 0b11 == 0b01 { 0b01_0000 } else { 0 };
+                itype(((funct6 << 6) | shamt) as i32, rs1_, 0b101, rs1_, OP_IMM)
+            },
+            // C.ANDI
+            0b10        => itype(imm6, rs1_, 0b111, rs1_, OP_IMM),
+            _           => match ((inst >> 12) & 1, (inst >> 5) & 0b11) {
+                // C.SUB
+                (0, 0b00)   => rtype(0b010_0000, rd_, rs1_, 0b000, rs1_, OP),
+                // C.XOR
+                (0, 0b01)   => rtype(0, rd_, rs1_, 0b100, rs1_, OP),
+                // C.OR
+                (0, 0b10)   => rtype(0, rd_, rs1_, 0b110, rs1_, OP),
+                // C.AND
+                (0, 0b11)   => rtype(0, rd_, rs1_, 0b111, rs1_, OP),
+                // C.SUBW
+                (1, 0b00) if rv64 => rtype(0b010_0000, rd_, rs1_, 0b000, rs1_, OP_32),
+                // C.ADDW
+                (1, 0b01) if rv64 => rtype(0, rd_, rs1_, 0b000, rs1_, OP_32),
+                _           => return None,
+            },
+        },
+        // C.J
+        (0b01, 0b101)   => jtype(cj_offset(inst), 0),
+        // C.BEQZ and C.BNEZ
+        (0b01, 0b110)   |
+        (0b01, 0b111)   => {
+            let offset = ((inst >> 4) & 0x100) | ((inst >> 7) & 0x18) | ((inst << 1) & 0xC0) | ((inst >> 2) & 0x6) | ((inst << 3) & 0x20);
+            btype(sext(offset, 9), 0, rs1_, funct3 & 1)
+        },
+        // C.SLLI
+        (0b10, 0b000)   => {
+            if !rv64 && shamt >= 32 {
+                return None;
+            }
+            itype(shamt as i32, rd, 0b001, rd, OP_IMM)
+        },
+        // C.LWSP
+        (0b10, 0b010)   => {
+            if rd == 0 {
+                return None;
+            }
+            let offset = ((inst >> 7) & 0x20) | ((inst >> 2) & 0x1C) | ((inst << 4) & 0xC0);
+            itype(offset as i32, 2, 0b010, rd, OP_LOAD)
+        },
+        // C.LDSP
+        (0b10, 0b011) if rv64 => {
+            if rd == 0 {
+                return None;
+            }
+            let offset = ((inst >> 7) & 0x20) | ((inst >> 2) & 0x18) | ((inst << 4) & 0x1C0);
+            itype(offset as i32, 2, 0b011, rd, OP_LOAD)
+        },
+        (0b10, 0b100)   => match ((inst >> 12) & 1, rd, rs2) {
+            // C.JR: rs1=x0 is reserved
+            (0, 0, 0)   => return None,
+            (0, _, 0)   => itype(0, rd, 0b000, 0, OP_JALR),
+            // C.MV
+            (0, _, _)   => rtype(0, rs2, 0, 0b000, rd, OP),
+            // C.EBREAK
+            (1, 0, 0)   => EBREAK,
+            // C.JALR
+            (1, _, 0)   => itype(0, rd, 0b000, 1, OP_JALR),
+            // C.ADD
+            _           => rtype(0, rs2, rd, 0b000, rd, OP),
+        },
+        // C.SWSP
+        (0b10, 0b110)   => {
+            let offset = ((inst >> 7) & 0x3C) | ((inst >> 1) & 0xC0);
+            stype(offset as i32, rs2, 2, 0b010)
+        },
+        // C.SDSP
+        (0b10, 0b111) if rv64 => {
+            let offset = ((inst >> 7) & 0x38) | ((inst >> 1) & 0x1C0);
+            stype(offset as i32, rs2, 2, 0b011)
+        },
+        // C.FLD, C.FLW, C.FSD, C.FSW and their SP-relative forms need F or D, and 0b00/0b100 is reserved
+        _               => return None,
+    };
+
+    Some(expanded)
+}
+
+// Sign-extend the low `bits` bits of `value`
+fn sext(value: u32, bits: u32) -> i32 {
+    ((value << (32 - bits)) as i32) >> (32 - bits)
+}
+
+// Offset of C.LW and C.SW: uimm[5:3] in bits 12:10, uimm[2|6] in bits 6:5
+fn clw_offset(inst: u32) -> u32 {
+    ((inst >> 7) & 0x38) | ((inst << 1) & 0x40) | ((inst >> 4) & 0x4)
+}
+
+// Offset of C.LD and C.SD: uimm[5:3] in bits 12:10, uimm[7:6] in bits 6:5
+fn cld_offset(inst: u32) -> u32 {
+    ((inst >> 7) & 0x38) | ((inst << 1) & 0xC0)
+}
+
+// Offset of C.J and C.JAL: offset[11|4|9:8|10|6|7|3:1|5] in bits 12:2
+fn cj_offset(inst: u32) -> i32 {
+    let offset = ((inst >> 1) & 0x800) | ((inst >> 7) & 0x10) | ((inst >> 1) & 0x300) | ((inst << 2) & 0x400) |
+                 ((inst >> 1) & 0x40) | ((inst << 1) & 0x80) | ((inst >> 2) & 0xE) | ((inst << 3) & 0x20);
+    sext(offset, 12)
+}
+
+fn itype(imm: i32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    ((imm as u32 & 0xFFF) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+fn stype(imm: i32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    let imm = imm as u32;
+    (((imm >> 5) & 0x7F) << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | ((imm & 0x1F) << 7) | OP_STORE
+}
+
+fn rtype(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+fn btype(imm: i32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    let imm = imm as u32;
+    (((imm >> 12) & 1) << 31) | (((imm >> 5) & 0x3F) << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) |
+    (((imm >> 1) & 0xF) << 8) | (((imm >> 11) & 1) << 7) | OP_BRANCH
+}
+
+fn jtype(imm: i32, rd: u32) -> u32 {
+    let imm = imm as u32;
+    (((imm >> 20) & 1) << 31) | (((imm >> 1) & 0x3FF) << 21) | (((imm >> 11) & 1) << 20) | (((imm >> 12) & 0xFF) << 12) | (rd << 7) | OP_JAL
+}
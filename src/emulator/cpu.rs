@@ -4,6 +4,12 @@ use crate::emulator::csr::*;
 use crate::emulator::exception::Exception;
 use crate::emulator::bus::*;
 use crate::emulator::interrupt::{ Interrupt, IrqNumber };
+use crate::emulator::vector::{ self, VRegisters };
+use crate::emulator::float::FRegisters;
+use crate::emulator::crypto;
+use crate::emulator::compressed;
+use crate::emulator::fdt;
+use crate::emulator::disk::{ Disk, DiskMode };
 
 use std::fs::read;
 use std::fmt;
@@ -12,6 +18,7 @@ use std::io::stdin;
 type Instruction    = u32;
 
 const NREGISTERS:   usize = 32;
+const NREGISTERS_E: usize = 16;     // RV32E and RV64E only have x0-x15
 const INIT_PC:      usize = DRAM_BASE;
 
 // General Registers (standardized names as part of the RISC-V application binary interface (ABI))
@@ -93,11 +100,13 @@ pub fn reg_name(index: u8) -> String {
 #[derive(Debug)]
 pub struct XRegisters {
     register: [u64; NREGISTERS],        // General registers
+    pub xlen: usize,                    // Effective XLEN of the running instruction (32 or 64)
+    pub count: usize,                   // Number of implemented registers (16 with the E base ISA)
 }
 
 impl fmt::Display for XRegisters {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (i, reg) in self.register.iter().enumerate() {
+        for (i, reg) in self.register.iter().take(self.count).enumerate() {
             write!(f, "  x{:2}({})\t= 0x{:16x}\n", i, reg_name(i as u8), reg)?;
         }
         write!(f, "")
@@ -110,18 +119,40 @@ impl XRegisters {
         register[Registers::SP as usize] = DRAM_TOP as u64;
         XRegisters {
             register: register,
+            xlen: 64,
+            count: NREGISTERS,
         }
     }
 
+    // With XLEN=32 only the low 32 bits of a register are used, and they are kept sign-extended
     pub fn write(&mut self, index: usize, data: u64) {
         if index == 0 {
             return;
         }
-        self.register[index] = data;
+        self.register[index] = match self.xlen {
+            32  => data as i32 as i64 as u64,
+            _   => data,
+        };
     }
 
     pub fn read(&self, index: usize) -> u64 {
-        self.register[index]
+        match self.xlen {
+            32  => self.register[index] as i32 as i64 as u64,
+            _   => self.register[index],
+        }
+    }
+
+    // The register value zero-extended from XLEN, for unsigned operations that look at the upper bits
+    pub fn read_unsigned(&self, index: usize) -> u64 {
+        match self.xlen {
+            32  => self.register[index] as u32 as u64,
+            _   => self.register[index],
+        }
+    }
+
+    // Shift amount held in the low log2(XLEN) bits of a register
+    pub fn shamt(&self, index: usize) -> u32 {
+        (self.register[index] & (self.xlen as u64 - 1)) as u32
     }
 }
 
@@ -133,28 +164,63 @@ pub enum WatchExec {
 pub struct Cpu {
     pub register: XRegisters,       // General registers
     pub instruction: Instruction,   // Current instruction
+    pub inst_len: usize,            // Length of the current instruction in bytes (2 if compressed)
     pub pc: usize,                  // Program counter
     pub mmu: Mmu,                   // MMU (Memory Management Unit)
     pub csr: Csr,                   // CSRs (Control/Status Registers)
+    pub vreg: VRegisters,           // Vector registers
+    pub freg: FRegisters,           // Floating-point registers, used by the vector unit
     pub debug: bool,                // Debug flag
     pub step: bool,                 // Step execution mode flag
+    pub wfi: bool,                  // Stalled by WFI until an interrupt is pending
+    pub exit_at_zero: bool,         // Exit the emulator when a program jumps to address 0
+    minstret_written: bool,         // The current instruction wrote minstret, so it does not count itself
     watchpoint: (Registers, u64, WatchExec),
-    clock: u64,
 }
 
 impl Cpu {
     pub fn new() -> Self {
-        Cpu {
+        let mut cpu = Cpu {
             register:       XRegisters::new(),
             instruction:    0,
+            inst_len:       4,
             pc:             INIT_PC,
             mmu:            Mmu::new(),
-            csr:            Csr::new(),
+            csr:            Csr::new(0),
+            vreg:           VRegisters::new(vector::VLEN, vector::ELEN),
+            freg:           FRegisters::new(),
             debug:          false,
             step:           false,
+            wfi:            false,
+            exit_at_zero:   false,
+            minstret_written: false,
             watchpoint:     (Registers::ZERO, 1, WatchExec::EXIT),
-            clock:          0,
-        }
+        };
+        cpu.configure_vector(vector::VLEN, vector::ELEN);
+        cpu
+    }
+
+    // Run as an RV32 (RV32IMAC) or RV64 hart
+    pub fn configure_xlen(&mut self, xlen: usize) {
+        self.csr.set_mxlen(xlen);
+        self.register.xlen = xlen;
+    }
+
+    // Run with the RV32E or RV64E base ISA, where x16-x31 do not exist
+    pub fn configure_embedded(&mut self) {
+        self.csr.set_embedded();
+        self.register.count = NREGISTERS_E;
+    }
+
+    // Use the memory map of `machine`, starting at its reset vector with the stack at the top of RAM
+    pub fn configure_machine(&mut self, machine: Machine) {
+        self.mmu.set_machine(machine);
+        let (pc, sp) = match machine {
+            Machine::Virt   => (DRAM_BASE, DRAM_TOP),
+            Machine::Mcu    => (FLASH_BASE, SRAM_TOP),
+        };
+        self.pc = pc;
+        self.register.write(Registers::SP as usize, sp as u64);
     }
 
     pub fn load_dram(&mut self, filename: &String) -> usize {
@@ -166,11 +232,34 @@ impl Cpu {
         len
     }
 
-    pub fn load_disk(&mut self, filename: &String) -> usize {
-        let binary = read(filename).unwrap();
-        let len = binary.len();
+    // Place the device tree of the virt machine at the top of DRAM, below which the stack starts,
+    // and pass it to the kernel in a1 with the hart ID in a0
+    pub fn load_dtb(&mut self) -> usize {
+        let mmu_type = match self.csr.mxlen() {
+            32  => "riscv,sv32",
+            _   => "riscv,sv57",
+        };
+        let dtb = fdt::virt_device_tree(&self.csr.isa_string(), mmu_type);
+        let addr = (DRAM_TOP + 1 - dtb.len()) & !0x7;
+
+        for (i, byte) in dtb.iter().enumerate() {
+            self.mmu.write8(&self.csr, addr + i, *byte).unwrap();
+        }
+        self.register.write(Registers::SP as usize, addr as u64);
+        self.register.write(Registers::A0 as usize, 0);
+        self.register.write(Registers::A1 as usize, addr as u64);
+
+        addr
+    }
+
+    pub fn load_disk(&mut self, filename: &String, mode: DiskMode) -> usize {
+        let disk = match Disk::open(filename, mode) {
+            Ok(disk)    => disk,
+            Err(error)  => panic!("[ERROR] can not open the disk image {}: {}", filename, error),
+        };
+        let len = disk.size() as usize;
 
-        self.mmu.load_disk(binary);
+        self.mmu.set_disk(disk);
 
         len
     }
@@ -189,7 +278,7 @@ impl Cpu {
             }
 
             if self.debug { println!("[INFO] pc: 0x{:08x}(0x{:08x})", self.pc, self.mmu.translate_addr(&self.csr, self.pc).unwrap()); }
-            if self.debug { println!("{}", inspect_instruciton(self.instruction)); }
+            if self.debug { println!("{}", inspect_instruciton(self.instruction, self.csr.xlen())); }
             if self.debug { println!("mie: 0x{:x}", self.csr.read(MIE)); }
             if self.debug { println!("[INFO] ==Register==\n{}", self.register); }
             if self.step { stdin().read_line(&mut input).unwrap(); }
@@ -215,52 +304,106 @@ impl Cpu {
                 },
             }
 
+            // A trap already points pc at the handler, and the trapped instruction does not retire
             match self.execute() {
-                Ok(_)           => {},
+                Ok(_)           => {
+                    self.pc = self.pc.wrapping_add(self.inst_len);
+                    self.retire();
+                },
                 Err(exception)  => exception.take_trap(self),
             }
 
-            self.pc = self.pc.wrapping_add(4);
+            let page_walks = self.mmu.take_page_walks();
+            self.csr.count_event(HPM_EVENT_TLB_MISS, page_walks);
+
             self.tick();
+
+            if self.wfi {
+                self.wait_for_interrupt();
+            }
             
             match self.check_interrupt() {
                 Some(mut interrupt) => interrupt.take_trap(self),
                 None                => {},
             }
-            
-            self.clock = self.clock.wrapping_add(1);
-            self.csr.write(CYCLE, self.clock);
+
+            self.csr.increment_counter(MCYCLE);
+        }
+    }
+
+    // Count a retired instruction. A write to minstret takes precedence over the increment
+    // by the instruction that writes it.
+    pub fn retire(&mut self) {
+        match self.minstret_written {
+            true    => self.minstret_written = false,
+            false   => self.csr.increment_counter(MINSTRET),
         }
     }
 
     fn tick(&mut self) {
         let mut mip = self.csr.read(MIP);
         self.mmu.tick(&mut mip);
-        self.csr.write(MIP, mip);
+        self.csr.write_hw(MIP, mip | self.csr.software_seip());
+
+        let time = self.mmu.mtime();
+        self.csr.update_time(time);
+    }
+
+    // Idle until an enabled interrupt is pending, whether or not it can be taken.
+    // Instead of spinning the interpreter, the timer is fast-forwarded to its next
+    // deadline, or the host thread sleeps until a device has something to deliver.
+    pub fn wait_for_interrupt(&mut self) {
+        while (self.csr.read(MIP) & self.csr.read(MIE)) == 0 {
+            self.mmu.wait_for_event(self.csr.stimecmp_deadline());
+            self.tick();
+        }
+        self.wfi = false;
     }
 
     pub fn check_interrupt(&mut self) -> Option<Interrupt> {
-        match self.csr.priv_level {
-            PrivLevel::MACHINE      => {
-                if (self.csr.read(MSTATUS) & MSTATUS_MIE) == 0 {
-                    return None;
-                }
-            },
-            PrivLevel::SUPERVISOR   => {
-                if (self.csr.read(SSTATUS) & SSTATUS_SIE) == 0 {
-                    return None;
-                }
-            },
-            _   => return None,
+        let pending = self.csr.read(MIE) & self.csr.read(MIP);
+        if pending == 0 {
+            return None;
         }
 
+        let mideleg = self.csr.read(MIDELEG);
+        let hideleg = self.csr.read(HIDELEG);
+        let virt = self.csr.virt;
+
+        // Interrupts for a more privileged mode are always enabled, those for a less privileged mode never are,
+        // and those for the current mode follow its global enable bit
+        let m_enabled = match self.csr.priv_level {
+            PrivLevel::MACHINE      => (self.csr.read(MSTATUS) & MSTATUS_MIE) != 0,
+            _                       => true,
+        };
+        let hs_enabled = match self.csr.priv_level {
+            PrivLevel::MACHINE      => false,
+            PrivLevel::SUPERVISOR if !virt  => (self.csr.read(MSTATUS) & SSTATUS_SIE) != 0,
+            _                       => true,
+        };
+        let vs_enabled = match self.csr.priv_level {
+            PrivLevel::SUPERVISOR if virt   => (self.csr.read(VSSTATUS) & SSTATUS_SIE) != 0,
+            PrivLevel::USER if virt         => true,
+            _                       => false,
+        };
+
+        // Interrupts for M-mode are taken before those for HS-mode, which are taken before those for VS-mode
+        let levels = [
+            (pending & !mideleg, m_enabled),
+            (pending & mideleg & !hideleg, hs_enabled),
+            (pending & mideleg & hideleg, vs_enabled),
+        ];
+        let pending = match levels.iter().find(|&&(pending, enabled)| enabled && pending != 0) {
+            Some(&(pending, _)) => pending,
+            None                => return None,
+        };
+
         let irq = match self.mmu.get_irqno() {
             Some(irq)   => irq,
             None        => IrqNumber::NONE,
         };
 
-        let pending = self.csr.read(MIE) & self.csr.read(MIP);
-
+        // Priority among interrupts for the same mode
         if (pending & MIP_MEIP) != 0 {
             return Some(Interrupt::MachineExtIrq(irq as u64));
         }
@@ -279,20 +422,61 @@ impl Cpu {
         else if (pending & MIP_STIP) != 0 {
             return Some(Interrupt::SupervisorTimerIrq);
         }
-        
+        else if (pending & MIP_VSEIP) != 0 {
+            return Some(Interrupt::VirtualSupervisorExtIrq);
+        }
+        else if (pending & MIP_VSSIP) != 0 {
+            return Some(Interrupt::VirtualSupervisorSoftwareIrq);
+        }
+        else if (pending & MIP_VSTIP) != 0 {
+            return Some(Interrupt::VirtualSupervisorTimerIrq);
+        }
+        else if (pending & MIP_LCOFIP) != 0 {
+            return Some(Interrupt::CounterOverflowIrq);
+        }
+
         None
     }
 
     pub fn fetch(&mut self) -> Result<(), Exception> {
-        self.instruction = self.mmu.fetch32(&self.csr, self.pc)?;
+        self.instruction = self.mmu.fetch(&self.csr, self.pc)?;
         Ok(())
     }
 
     pub fn execute(&mut self) -> Result<(), Exception> {
+        self.register.xlen = self.csr.xlen();
+
+        if (self.instruction & 0b11) == 0b11 {
+            self.inst_len = 4;
+            return self.execute_expanded();
+        }
+
+        // A compressed instruction executes as the instruction it expands to, but traps with its own encoding
+        self.inst_len = 2;
+        let compressed = self.instruction;
+        let result = match compressed::expand(compressed as u16, self.register.xlen) {
+            Some(instruction) if self.csr.has_extension('C') => {
+                self.instruction = instruction;
+                self.execute_expanded()
+            },
+            _   => Err(Exception::IllegalInst),
+        };
+        self.instruction = compressed;
+        result
+    }
+
+    fn execute_expanded(&mut self) -> Result<(), Exception> {
         // Decode instruction
         let imm:    u32     = ((self.instruction >> 12) & 0xF_FFFF) as u32;
         let rd:     usize   = ((self.instruction >> 7) & 0x1F) as usize;
         let opcode: u8      = (self.instruction & 0x7F) as u8;
+
+        if self.register.xlen == 32 {
+            self.check_rv32_encoding()?;
+        }
+        if self.csr.has_extension('E') {
+            self.check_embedded_registers()?;
+        }
         
         match opcode {
             // LOAD
@@ -317,14 +501,9 @@ impl Cpu {
                                          ((self.instruction & 0x100000)    >>  9) |     // imm[11]
                                           (self.instruction  & 0xFF000)) as i32;        // imm[19:12]
                 offset = ((offset + (0b1000_0000_0000_0000)) & (0xFFFFF)) - 0b1000_0000_0000_0000;        // sign extension
-                if rd != 0 {
-                    self.register.write(rd, self.pc as u64 + 4);
-                };
-                self.pc = (self.pc as i64 + offset as i64) as usize;
-                if self.pc == 0 {
-                    std::process::exit(0);
-                }
-                self.pc -= 4;
+                let link = (self.pc + self.inst_len) as u64;
+                self.jump((self.pc as i64 + offset as i64) as usize)?;
+                self.register.write(rd, link);
             },
             // B-type
             0b110_0011  => self.decode_btype()?,
@@ -334,20 +513,15 @@ impl Cpu {
                 let mut imm:    i16 = ((self.instruction >> 20) & 0xFFF) as i16;
                 imm = ((imm + (0b1000_0000_0000)) & (0xFFF)) - 0b1000_0000_0000;     // sign extension
                 let rs1:    usize   = ((self.instruction >> 15) & 0x1F) as usize;
-                let rd:     usize   = ((self.instruction >> 7)  & 0xF) as usize;
+                let rd:     usize   = ((self.instruction >> 7)  & 0x1F) as usize;
 
                 let addr = self.register.read(rs1);
-                if rd != 0 {
-                    self.register.write(rd, ((self.pc as u64) + 4) & 0xFFFF_FFFF_FFFF_FFFE);
-                }
-                self.pc = (addr as i64  + imm as i64) as u64 as usize;
-                if self.pc == 0 {
-                    std::process::exit(0);
-                }
-                self.pc -= 4;
+                let link = (self.pc + self.inst_len) as u64;
+                self.jump(((addr as i64 + imm as i64) as u64 & !1) as usize)?;
+                self.register.write(rd, link);
             },
-            // FENCE
-            0b000_1111  => return Ok(()),      // treat as nop
+            // MISC-MEM
+            0b000_1111  => self.decode_misc_mem()?,
             // SYSTEM
             0b111_0011  => self.decode_system()?,
             // RV64I Integer Register-Immediate Instructions
@@ -356,12 +530,109 @@ impl Cpu {
             0b011_1011  => self.decode_rv64im_rtype()?,
             // RV64A
             0b010_1111  => self.decode_rv64a()?,
+            // Vector loads
+            0b000_0111  => self.decode_vector_memory(false)?,
+            // Vector stores
+            0b010_0111  => self.decode_vector_memory(true)?,
+            // OP-V
+            0b101_0111  => self.decode_opv()?,
             _           => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
         }
 
         Ok(())
     }
 
+    // Continue execution at `target`, which must be aligned to an instruction boundary:
+    // 16 bits with the C extension, 32 bits without it
+    fn jump(&mut self, target: usize) -> Result<(), Exception> {
+        let target = match self.register.xlen {
+            32  => target as u32 as usize,
+            _   => target,
+        };
+        let ialign = if self.csr.has_extension('C') { 2 } else { 4 };
+        if !target.is_multiple_of(ialign) {
+            return Err(Exception::InstAddrMisalign(target));
+        }
+        if target == 0 && self.exit_at_zero {
+            std::process::exit(0);
+        }
+        self.pc = target.wrapping_sub(self.inst_len);   // pc is incremented after execution
+        Ok(())
+    }
+
+    // With XLEN=32, the RV64-only instructions are illegal, and so are the bit-manipulation,
+    // scalar cryptography and vector instructions, which are only implemented for XLEN=64
+    fn check_rv32_encoding(&self) -> Result<(), Exception> {
+        let funct7: u8      = ((self.instruction >> 25) & 0x7F) as u8;
+        let funct3: u8      = ((self.instruction >> 12) & 0x7) as u8;
+        let opcode: u8      = (self.instruction & 0x7F) as u8;
+
+        let legal = match opcode {
+            // LD, LWU and SD
+            0b000_0011  => !matches!(funct3, 0b011 | 0b110),
+            0b010_0011  => funct3 != 0b011,
+            // ADD ... AND, SUB/SRA, RV32M and Zicond
+            0b011_0011  => matches!(funct7, 0b000_0000 | 0b010_0000 | 0b000_0001 | 0b000_0111),
+            // SLLI, SRLI and SRAI with shamt[5] clear
+            0b001_0011  => match funct3 {
+                0b001       => funct7 == 0b000_0000,
+                0b101       => matches!(funct7, 0b000_0000 | 0b010_0000),
+                _           => true,
+            },
+            // RV64A
+            0b010_1111  => funct3 != 0b011,
+            // OP-IMM-32, OP-32, vector loads and stores, OP-V
+            0b001_1011  |
+            0b011_1011  |
+            0b000_0111  |
+            0b010_0111  |
+            0b101_0111  => false,
+            _           => true,
+        };
+
+        match legal {
+            true    => Ok(()),
+            false   => Err(Exception::IllegalInst),
+        }
+    }
+
+    // With the E base ISA, an instruction that names one of x16-x31 in a register field is illegal
+    fn check_embedded_registers(&self) -> Result<(), Exception> {
+        let funct3: u8      = ((self.instruction >> 12) & 0x7) as u8;
+        let opcode: u8      = (self.instruction & 0x7F) as u8;
+
+        // Which of the rd, rs1 and rs2 fields name registers rather than holding immediates
+        let (rd, rs1, rs2) = match opcode {
+            // LUI, AUIPC and JAL
+            0b011_0111  |
+            0b001_0111  |
+            0b110_1111  => (true, false, false),
+            // LOAD, OP-IMM, OP-IMM-32, JALR and MISC-MEM
+            0b000_0011  |
+            0b001_0011  |
+            0b001_1011  |
+            0b110_0111  |
+            0b000_1111  => (true, true, false),
+            // STORE and BRANCH
+            0b010_0011  |
+            0b110_0011  => (false, true, true),
+            // CSRRW, CSRRS and CSRRC hold part of the CSR address in rs2
+            0b111_0011 if (0b001..=0b011).contains(&funct3) => (true, true, false),
+            // CSRRWI, CSRRSI and CSRRCI hold an immediate in rs1 as well
+            0b111_0011 if funct3 >= 0b101 => (true, false, false),
+            // The fences and HSV name rs2, the other privileged instructions and HLV hold a function code there
+            0b111_0011  => (true, true, (self.instruction >> 25) & 1 == 1),
+            // OP, OP-32 and AMO
+            _           => (true, true, true),
+        };
+
+        let upper = |shift: u32| ((self.instruction >> shift) & 0x1F) as usize >= NREGISTERS_E;
+        match (rd && upper(7)) || (rs1 && upper(15)) || (rs2 && upper(20)) {
+            true    => Err(Exception::IllegalInst),
+            false   => Ok(()),
+        }
+    }
+
     fn decode_rtype(&mut self) -> Result<(), Exception> {
         // Decode instruction
         let funct7: u8      = ((self.instruction >> 25) & 0x7F) as u8;
@@ -376,7 +647,7 @@ impl Cpu {
                     // ADD
                     0b000   => self.register.write(rd, self.register.read(rs1).wrapping_add(self.register.read(rs2))),
                     // SLL
-                    0b001   => self.register.write(rd, self.register.read(rs1).wrapping_shl(self.register.read(rs2) as u32)),
+                    0b001   => self.register.write(rd, self.register.read(rs1) << self.register.shamt(rs2)),
                     // SLT
                     0b010   => {
                         if (self.register.read(rs1)  as i64) < self.register.read(rs2) as i64 {
@@ -398,7 +669,7 @@ impl Cpu {
                     // XOR
                     0b100   => self.register.write(rd, self.register.read(rs1) ^ self.register.read(rs2)),
                     // SRL
-                    0b101   => self.register.write(rd, self.register.read(rs1).wrapping_shr(self.register.read(rs2) as u32)),
+                    0b101   => self.register.write(rd, self.register.read_unsigned(rs1) >> self.register.shamt(rs2)),
                     // OR
                     0b110   => self.register.write(rd, self.register.read(rs1) | self.register.read(rs2)),
                     // AND
@@ -411,11 +682,127 @@ impl Cpu {
                     // SUB
                     0b000   => self.register.write(rd, (self.register.read(rs1) as i64 - self.register.read(rs2) as i64) as u64),
                     // SRA
-                    0b101   => self.register.write(rd, ((self.register.read(rs1) as i64).wrapping_shr(self.register.read(rs2) as u32)) as u64),
+                    0b101   => self.register.write(rd, ((self.register.read(rs1) as i64) >> self.register.shamt(rs2)) as u64),
+                    _ if !self.csr.has_extension('B') => return Err(Exception::IllegalInst),
+                    // XNOR
+                    0b100   => self.register.write(rd, !(self.register.read(rs1) ^ self.register.read(rs2))),
+                    // ORN
+                    0b110   => self.register.write(rd, self.register.read(rs1) | !self.register.read(rs2)),
+                    // ANDN
+                    0b111   => self.register.write(rd, self.register.read(rs1) & !self.register.read(rs2)),
                     _       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
                 }
             },
+            0b000_0001 if !self.csr.has_extension('M') => return Err(Exception::IllegalInst),
             0b000_0001      => self.decode_rv32m()?,
+            0b000_0101      => {
+                match funct3 {
+                    // CLMUL
+                    0b001   => self.register.write(rd, clmul(self.register.read(rs1), self.register.read(rs2))),
+                    // CLMULR
+                    0b010   => self.register.write(rd, clmulr(self.register.read(rs1), self.register.read(rs2))),
+                    // CLMULH
+                    0b011   => self.register.write(rd, clmulh(self.register.read(rs1), self.register.read(rs2))),
+                    _ if !self.csr.has_extension('B') => return Err(Exception::IllegalInst),
+                    // MIN
+                    0b100   => self.register.write(rd, (self.register.read(rs1) as i64).min(self.register.read(rs2) as i64) as u64),
+                    // MINU
+                    0b101   => self.register.write(rd, self.register.read(rs1).min(self.register.read(rs2))),
+                    // MAX
+                    0b110   => self.register.write(rd, (self.register.read(rs1) as i64).max(self.register.read(rs2) as i64) as u64),
+                    // MAXU
+                    0b111   => self.register.write(rd, self.register.read(rs1).max(self.register.read(rs2))),
+                    _       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
+                }
+            },
+            0b000_0111      => {
+                match funct3 {
+                    // CZERO.EQZ
+                    0b101   => self.register.write(rd, if self.register.read(rs2) == 0 { 0 } else { self.register.read(rs1) }),
+                    // CZERO.NEZ
+                    0b111   => self.register.write(rd, if self.register.read(rs2) != 0 { 0 } else { self.register.read(rs1) }),
+                    // The other funct3 values are reserved
+                    _       => return Err(Exception::IllegalInst),
+                }
+            },
+            // Zbkx
+            0b001_0100 if funct3 == 0b010 => self.register.write(rd, crypto::xperm4(self.register.read(rs1), self.register.read(rs2))),
+            0b001_0100 if funct3 == 0b100 => self.register.write(rd, crypto::xperm8(self.register.read(rs1), self.register.read(rs2))),
+            // Zkne and Zknd
+            0b001_1001 | 0b001_1011 | 0b001_1101 | 0b001_1111 | 0b011_1111 if funct3 == 0b000 => {
+                let (data1, data2) = (self.register.read(rs1), self.register.read(rs2));
+                match funct7 {
+                    // AES64ES
+                    0b001_1001  => self.register.write(rd, crypto::aes64es(data1, data2, false)),
+                    // AES64ESM
+                    0b001_1011  => self.register.write(rd, crypto::aes64es(data1, data2, true)),
+                    // AES64DS
+                    0b001_1101  => self.register.write(rd, crypto::aes64ds(data1, data2, false)),
+                    // AES64DSM
+                    0b001_1111  => self.register.write(rd, crypto::aes64ds(data1, data2, true)),
+                    // AES64KS2
+                    _           => self.register.write(rd, crypto::aes64ks2(data1, data2)),
+                }
+            },
+            // Zksed: funct7[6:5] selects the byte of rs2
+            // SM4ED
+            _ if (funct7 & 0x1F) == 0b1_1000 && funct3 == 0b000 => self.register.write(rd, crypto::sm4(self.register.read(rs1), self.register.read(rs2), funct7 >> 5, false)),
+            // SM4KS
+            _ if (funct7 & 0x1F) == 0b1_1010 && funct3 == 0b000 => self.register.write(rd, crypto::sm4(self.register.read(rs1), self.register.read(rs2), funct7 >> 5, true)),
+            // Zba, Zbb, Zbs and Zbkb
+            0b000_0100 | 0b001_0000 | 0b011_0000 | 0b001_0100 | 0b010_0100 | 0b011_0100 if !self.csr.has_extension('B') => return Err(Exception::IllegalInst),
+            0b000_0100      => {
+                match funct3 {
+                    // PACK
+                    0b100   => self.register.write(rd, (self.register.read(rs2) << 32) | (self.register.read(rs1) as u32 as u64)),
+                    // PACKH
+                    0b111   => self.register.write(rd, ((self.register.read(rs2) & 0xFF) << 8) | (self.register.read(rs1) & 0xFF)),
+                    _       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
+                }
+            },
+            0b001_0000      => {
+                match funct3 {
+                    // SH1ADD
+                    0b010   => self.register.write(rd, (self.register.read(rs1) << 1).wrapping_add(self.register.read(rs2))),
+                    // SH2ADD
+                    0b100   => self.register.write(rd, (self.register.read(rs1) << 2).wrapping_add(self.register.read(rs2))),
+                    // SH3ADD
+                    0b110   => self.register.write(rd, (self.register.read(rs1) << 3).wrapping_add(self.register.read(rs2))),
+                    _       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
+                }
+            },
+            0b011_0000      => {
+                match funct3 {
+                    // ROL
+                    0b001   => self.register.write(rd, self.register.read(rs1).rotate_left((self.register.read(rs2) & 0x3F) as u32)),
+                    // ROR
+                    0b101   => self.register.write(rd, self.register.read(rs1).rotate_right((self.register.read(rs2) & 0x3F) as u32)),
+                    _       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
+                }
+            },
+            0b001_0100      => {
+                match funct3 {
+                    // BSET
+                    0b001   => self.register.write(rd, self.register.read(rs1) | (1 << (self.register.read(rs2) & 0x3F))),
+                    _       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
+                }
+            },
+            0b010_0100      => {
+                match funct3 {
+                    // BCLR
+                    0b001   => self.register.write(rd, self.register.read(rs1) & !(1 << (self.register.read(rs2) & 0x3F))),
+                    // BEXT
+                    0b101   => self.register.write(rd, (self.register.read(rs1) >> (self.register.read(rs2) & 0x3F)) & 1),
+                    _       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
+                }
+            },
+            0b011_0100      => {
+                match funct3 {
+                    // BINV
+                    0b001   => self.register.write(rd, self.register.read(rs1) ^ (1 << (self.register.read(rs2) & 0x3F))),
+                    _       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
+                }
+            },
             _               => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
         }
 
@@ -434,10 +821,79 @@ impl Cpu {
         match funct3 {
             // ADDI
             0b000   => self.register.write(rd, ((self.register.read(rs1) as i64).wrapping_add(imm as i64)) as u64),
-            // SLLI
             0b001   => {
-                let wdata = self.register.read(rs1);
-                self.register.write(rd, (wdata.wrapping_shl(shamt as u32)) as u64);
+                match (imm >> 6) & 0x3F {
+                    // SLLI
+                    0b00_0000   => {
+                        let wdata = self.register.read(rs1);
+                        self.register.write(rd, (wdata.wrapping_shl(shamt as u32)) as u64);
+                    },
+                    // Zknh and Zksh
+                    0b00_0100   => {
+                        let wdata = self.register.read(rs1);
+                        match shamt & 0x3F {
+                            // SHA256SUM0
+                            0b00_0000   => self.register.write(rd, crypto::sha256sum0(wdata)),
+                            // SHA256SUM1
+                            0b00_0001   => self.register.write(rd, crypto::sha256sum1(wdata)),
+                            // SHA256SIG0
+                            0b00_0010   => self.register.write(rd, crypto::sha256sig0(wdata)),
+                            // SHA256SIG1
+                            0b00_0011   => self.register.write(rd, crypto::sha256sig1(wdata)),
+                            // SHA512SUM0
+                            0b00_0100   => self.register.write(rd, crypto::sha512sum0(wdata)),
+                            // SHA512SUM1
+                            0b00_0101   => self.register.write(rd, crypto::sha512sum1(wdata)),
+                            // SHA512SIG0
+                            0b00_0110   => self.register.write(rd, crypto::sha512sig0(wdata)),
+                            // SHA512SIG1
+                            0b00_0111   => self.register.write(rd, crypto::sha512sig1(wdata)),
+                            // SM3P0
+                            0b00_1000   => self.register.write(rd, crypto::sm3p0(wdata)),
+                            // SM3P1
+                            0b00_1001   => self.register.write(rd, crypto::sm3p1(wdata)),
+                            _           => return Err(Exception::IllegalInst),
+                        }
+                    },
+                    // Zkne and Zknd
+                    0b00_1100   => {
+                        let wdata = self.register.read(rs1);
+                        match shamt & 0x3F {
+                            // AES64IM
+                            0b00_0000   => self.register.write(rd, crypto::aes64im(wdata)),
+                            // AES64KS1I: round numbers above 0xA are reserved
+                            0b01_0000 ..= 0b01_1111 => {
+                                let data = crypto::aes64ks1i(wdata, shamt & 0xF).ok_or(Exception::IllegalInst)?;
+                                self.register.write(rd, data);
+                            },
+                            _           => return Err(Exception::IllegalInst),
+                        }
+                    },
+                    _ if !self.csr.has_extension('B') => return Err(Exception::IllegalInst),
+                    0b01_1000   => {
+                        let wdata = self.register.read(rs1);
+                        match shamt & 0x3F {
+                            // CLZ
+                            0b00_0000   => self.register.write(rd, wdata.leading_zeros() as u64),
+                            // CTZ
+                            0b00_0001   => self.register.write(rd, wdata.trailing_zeros() as u64),
+                            // CPOP
+                            0b00_0010   => self.register.write(rd, wdata.count_ones() as u64),
+                            // SEXT.B
+                            0b00_0100   => self.register.write(rd, wdata as i8 as i64 as u64),
+                            // SEXT.H
+                            0b00_0101   => self.register.write(rd, wdata as i16 as i64 as u64),
+                            _           => return Err(Exception::IllegalInst),
+                        }
+                    },
+                    // BSETI
+                    0b00_1010   => self.register.write(rd, self.register.read(rs1) | (1 << (shamt & 0x3F))),
+                    // BCLRI
+                    0b01_0010   => self.register.write(rd, self.register.read(rs1) & !(1 << (shamt & 0x3F))),
+                    // BINVI
+                    0b01_1010   => self.register.write(rd, self.register.read(rs1) ^ (1 << (shamt & 0x3F))),
+                    _           => return Err(Exception::IllegalInst),
+                }
             },
             // SLTI
             0b010   => {
@@ -463,7 +919,7 @@ impl Cpu {
                 match (imm >> 6) & 0x3F {
                     // SRLI
                     0b00_0000   => {
-                        let wdata = self.register.read(rs1);
+                        let wdata = self.register.read_unsigned(rs1);
                         self.register.write(rd, (wdata.wrapping_shr(shamt as u32)) as u64);
                     },
                     // SRAI
@@ -471,7 +927,18 @@ impl Cpu {
                         let wdata = self.register.read(rs1) as i64;
                         self.register.write(rd, (wdata >> shamt) as u64);
                     },
-                    _           => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
+                    _ if !self.csr.has_extension('B') => return Err(Exception::IllegalInst),
+                    // RORI
+                    0b01_1000   => self.register.write(rd, self.register.read(rs1).rotate_right((shamt & 0x3F) as u32)),
+                    // BEXTI
+                    0b01_0010   => self.register.write(rd, (self.register.read(rs1) >> (shamt & 0x3F)) & 1),
+                    // ORC.B
+                    0b00_1010 if (shamt & 0x3F) == 0b00_0111 => self.register.write(rd, orc_b(self.register.read(rs1))),
+                    // REV8
+                    0b01_1010 if (shamt & 0x3F) == 0b11_1000 => self.register.write(rd, self.register.read(rs1).swap_bytes()),
+                    // BREV8
+                    0b01_1010 if (shamt & 0x3F) == 0b00_0111 => self.register.write(rd, crypto::brev8(self.register.read(rs1))),
+                    _           => return Err(Exception::IllegalInst),
                 }
             },
             // ORI
@@ -499,66 +966,44 @@ impl Cpu {
             // BEQ
             0b000   => {
                 if self.register.read(rs1) == self.register.read(rs2) {
-                    self.pc = (self.pc as i64 + imm as i64) as usize;
-                    if self.pc == 0 {
-                        std::process::exit(0);
-                    }
-                    self.pc -= 4;
+                    self.jump((self.pc as i64 + imm as i64) as usize)?;
                 }
             },
             // BNE
             0b001   => {
                 if self.register.read(rs1) != self.register.read(rs2) {
-                    self.pc = (self.pc as i64 + imm as i64) as usize;
-                    if self.pc == 0 {
-                        std::process::exit(0);
-                    }
-                    self.pc -= 4;
+                    self.jump((self.pc as i64 + imm as i64) as usize)?;
                 }
             },
             // BLT
             0b100   => {
                 if (self.register.read(rs1) as i64) < (self.register.read(rs2) as i64) {
-                    self.pc = (self.pc as i64 + imm as i64) as usize;
-                    if self.pc == 0 {
-                        std::process::exit(0);
-                    }
-                    self.pc -= 4;
+                    self.jump((self.pc as i64 + imm as i64) as usize)?;
                 }
             },
             // BGE
             0b101   => {
                 if (self.register.read(rs1) as i64) >= (self.register.read(rs2) as i64) {
-                    self.pc = (self.pc as i64 + imm as i64) as usize;
-                    if self.pc == 0 {
-                        std::process::exit(0);
-                    }
-                    self.pc -= 4;
+                    self.jump((self.pc as i64 + imm as i64) as usize)?;
                 }
             },
             // BLTU
             0b110   => {
                 if self.register.read(rs1) < self.register.read(rs2) {
-                    self.pc = (self.pc as i64 + imm as i64) as usize;
-                    if self.pc == 0 {
-                        std::process::exit(0);
-                    }
-                    self.pc -= 4;
+                    self.jump((self.pc as i64 + imm as i64) as usize)?;
                 }
             },
             // BGEU
             0b111   => {
                 if self.register.read(rs1) >= self.register.read(rs2) {
-                    self.pc = (self.pc as i64 + imm as i64) as usize;
-                    if self.pc == 0 {
-                        std::process::exit(0);
-                    }
-                    self.pc -= 4;
+                    self.jump((self.pc as i64 + imm as i64) as usize)?;
                 }
             },
             _       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
         }
 
+        self.csr.count_event(HPM_EVENT_BRANCH, 1);
+
         Ok(())
     }
 
@@ -619,6 +1064,8 @@ impl Cpu {
             _       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
         }
 
+        self.csr.count_event(HPM_EVENT_LOAD, 1);
+
         Ok(())
     }
 
@@ -659,6 +1106,69 @@ impl Cpu {
             _       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
         }
 
+        self.csr.count_event(HPM_EVENT_STORE, 1);
+
+        Ok(())
+    }
+
+    fn decode_misc_mem(&mut self) -> Result<(), Exception> {
+        // Decode instruction
+        let funct12:    u16     = ((self.instruction >> 20) & 0xFFF) as u16;
+        let rs1:        usize   = ((self.instruction >> 15) & 0x1F) as usize;
+        let funct3:     u8      = ((self.instruction >> 12) & 0x7) as u8;
+        let rd:         usize   = ((self.instruction >> 7)  & 0x1F) as usize;
+
+        match funct3 {
+            // FENCE (PAUSE is a FENCE with pred=W and succ=0): the hart performs memory accesses in order
+            0b000   => (),
+            // FENCE.I: there is no instruction cache, so fetches already see every store
+            0b001   => (),
+            0b010 if rd == 0 => {
+                let addr = self.register.read(rs1) as usize;
+                match funct12 {
+                    // CBO.INVAL: without caches, invalidating a block is the same as flushing it
+                    0b0000  => {
+                        self.check_cbo_access(ENVCFG_CBIE)?;
+                        self.mmu.cbo_manage(&self.csr, addr)?;
+                    },
+                    // CBO.CLEAN and CBO.FLUSH
+                    0b0001  |
+                    0b0010  => {
+                        self.check_cbo_access(ENVCFG_CBCFE)?;
+                        self.mmu.cbo_manage(&self.csr, addr)?;
+                    },
+                    // CBO.ZERO
+                    0b0100  => {
+                        self.check_cbo_access(ENVCFG_CBZE)?;
+                        self.mmu.cbo_zero(&self.csr, addr)?;
+                        self.csr.count_event(HPM_EVENT_STORE, 1);
+                    },
+                    // The other CBO encodings are reserved
+                    _       => return Err(Exception::IllegalInst),
+                }
+            },
+            // CBO with rd != 0, and funct3 011-111, are reserved
+            _       => return Err(Exception::IllegalInst),
+        }
+
+        Ok(())
+    }
+
+    // Below M-mode, a CBO instruction needs its `enable` field set in menvcfg, then in henvcfg
+    // with V=1, then in senvcfg in U-mode (or VU-mode)
+    fn check_cbo_access(&self, enable: u64) -> Result<(), Exception> {
+        if self.csr.priv_level == PrivLevel::MACHINE {
+            return Ok(());
+        }
+        if (self.csr.read(MENVCFG) & enable) == 0 {
+            return Err(Exception::IllegalInst);
+        }
+        if self.csr.virt && (self.csr.read(HENVCFG) & enable) == 0 {
+            return Err(Exception::VirtualInst);
+        }
+        if self.csr.priv_level == PrivLevel::USER && (self.csr.read(SENVCFG) & enable) == 0 {
+            return Err(if self.csr.virt { Exception::VirtualInst } else { Exception::IllegalInst });
+        }
         Ok(())
     }
 
@@ -676,6 +1186,7 @@ impl Cpu {
                     0b0000_0000_0000    => {
                         match self.csr.priv_level {
                             PrivLevel::USER         => return Err(Exception::EnvCallUmode),
+                            PrivLevel::SUPERVISOR if self.csr.virt  => return Err(Exception::EnvCallVSmode),
                             PrivLevel::SUPERVISOR   => return Err(Exception::EnvCallSmode),
                             PrivLevel::MACHINE      => return Err(Exception::EnvCallMmode),
                             _                       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
@@ -683,40 +1194,101 @@ impl Cpu {
                     },
                     // EBREAK
                     0b0000_0000_0001    => return Err(Exception::Breakpoint),
-                    // URET
-                    0b0000_0000_0010    => unimplemented!(),
+                    // URET belongs to the N extension, which is not implemented
+                    0b0000_0000_0010    => return Err(Exception::IllegalInst),
                     // SRET
                     0b0001_0000_0010    => {
+                        // VU-mode, and VS-mode while hstatus.VTSR is set, leave SRET to the hypervisor
+                        match self.csr.priv_level {
+                            PrivLevel::USER if self.csr.virt    => return Err(Exception::VirtualInst),
+                            PrivLevel::USER                     => return Err(Exception::IllegalInst),
+                            PrivLevel::SUPERVISOR if self.csr.virt && (self.csr.read(HSTATUS) & HSTATUS_VTSR) != 0 => return Err(Exception::VirtualInst),
+                            _                                   => (),
+                        }
+
+                        // Outside a virtual machine, SRET enters it if hstatus.SPV is set
+                        let hstatus = self.csr.read(HSTATUS);
+                        let spv = !self.csr.virt && (hstatus & HSTATUS_SPV) != 0;
+                        if !self.csr.virt {
+                            self.csr.write(HSTATUS, hstatus & !HSTATUS_SPV);
+                        }
+
                         let spp = self.csr.read_bit(SSTATUS, 8);    // Get SPP bits
                         let spie = self.csr.read_bit(SSTATUS, 5);   // Get SPIE bit
                         self.csr.write_bit(SSTATUS, 1, spie);       // Set SIE bit
                         self.csr.set_priv_level(spp as u8);
                         self.csr.write_bit(SSTATUS, 5, true);
                         self.csr.write_bit(SSTATUS, 8, false);
+                        self.csr.write_bit(MSTATUS, MSTATUS_MPRV_BIT, false);   // Returning to S/U-mode clears MPRV
 
-                        self.pc = self.csr.read(SEPC) as usize;
-                        self.pc -= 4;
+                        self.pc = (self.csr.read(SEPC) as usize).wrapping_sub(self.inst_len);
+                        self.csr.virt |= spv;
 
                     },
                     // MRET
                     0b0011_0000_0010    => {
                         let mpp = self.csr.read_bits(MSTATUS, 11..12+1);    // Get MPP bits
                         let mpie = self.csr.read_bit(MSTATUS, 7);           // Get MPIE bit
+                        let mpv = (self.csr.read(MSTATUS) & MSTATUS_MPV) != 0;  // Get MPV bit
                         self.csr.write_bit(MSTATUS, 3, mpie);               // Set MIE bit
                         self.csr.set_priv_level(mpp as u8);
                         self.csr.write_bit(MSTATUS, 7, true);
                         self.csr.write_bits(MSTATUS, 11..12+1, PrivLevel::USER as u64);
+                        self.csr.write(MSTATUS, self.csr.read(MSTATUS) & !MSTATUS_MPV);
+                        if self.csr.priv_level != PrivLevel::MACHINE {
+                            self.csr.write_bit(MSTATUS, MSTATUS_MPRV_BIT, false);
+                        }
 
-                        self.pc = self.csr.read(MEPC) as usize;
-                        self.pc -= 4;
+                        self.pc = (self.csr.read(MEPC) as usize).wrapping_sub(self.inst_len);
+                        self.csr.virt = mpv && self.csr.priv_level != PrivLevel::MACHINE;
+                    },
+                    // WFI
+                    0b0001_0000_0101    => {
+                        // S-mode may only wait while TW is clear, U-mode never. With TW clear,
+                        // VU-mode, and VS-mode while hstatus.VTW is set, leave WFI to the hypervisor
+                        let tw = self.csr.read_bit(MSTATUS, MSTATUS_TW_BIT);
+                        let vtw = (self.csr.read(HSTATUS) & HSTATUS_VTW) != 0;
+                        match self.csr.priv_level {
+                            PrivLevel::MACHINE                  => (),
+                            PrivLevel::SUPERVISOR if !tw && !(self.csr.virt && vtw) => (),
+                            _ if !tw && self.csr.virt           => return Err(Exception::VirtualInst),
+                            _                                   => return Err(Exception::IllegalInst),
+                        }
+                        self.wfi = true;
                     },
                     _   => match funct7 {
                             // SFENCE.VMA
-                            0b000_1001  =>  (),     // treat as nop
+                            0b000_1001  =>  {
+                                // Only M-mode, or S-mode while mstatus.TVM (hstatus.VTVM in VS-mode) is clear, may flush the TLB
+                                let tvm = match self.csr.virt {
+                                    true    => (self.csr.read(HSTATUS) & HSTATUS_VTVM) != 0,
+                                    false   => self.csr.read_bit(MSTATUS, MSTATUS_TVM_BIT),
+                                };
+                                match self.csr.priv_level {
+                                    PrivLevel::MACHINE              => (),
+                                    PrivLevel::SUPERVISOR if !tvm   => (),
+                                    _ if self.csr.virt              => return Err(Exception::VirtualInst),
+                                    _                               => return Err(Exception::IllegalInst),
+                                }
+                            },
+                            // HFENCE.VVMA and HFENCE.GVMA
+                            0b001_0001  |
+                            0b011_0001  =>  {
+                                // Only M-mode and HS-mode may flush the guest TLB, HFENCE.GVMA in HS-mode only while mstatus.TVM is clear
+                                let tvm = self.csr.read_bit(MSTATUS, MSTATUS_TVM_BIT);
+                                match self.csr.priv_level {
+                                    _ if self.csr.virt              => return Err(Exception::VirtualInst),
+                                    PrivLevel::MACHINE              => (),
+                                    PrivLevel::SUPERVISOR if funct7 == 0b001_0001 || !tvm   => (),
+                                    _                               => return Err(Exception::IllegalInst),
+                                }
+                            },
                             _           =>  panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
                     }
                 }
             },
+            // HLV, HLVX and HSV
+            0b100   => self.decode_hypervisor_memory()?,
             // Zicsr
             _       => self.decode_zicsr()?,
         }
@@ -724,6 +1296,90 @@ impl Cpu {
         Ok(())
     }
 
+    fn decode_hypervisor_memory(&mut self) -> Result<(), Exception> {
+        // Decode instruction
+        let funct7: u8      = ((self.instruction >> 25) & 0x7F) as u8;
+        let rs2:    usize   = ((self.instruction >> 20) & 0x1F) as usize;
+        let rs1:    usize   = ((self.instruction >> 15) & 0x1F) as usize;
+        let rd:     usize   = ((self.instruction >> 7)  & 0x1F) as usize;
+
+        // M-mode and HS-mode may access the virtual machine's memory, U-mode only while hstatus.HU is set
+        let hstatus = self.csr.read(HSTATUS);
+        match self.csr.priv_level {
+            _ if self.csr.virt                                  => return Err(Exception::VirtualInst),
+            PrivLevel::USER if (hstatus & HSTATUS_HU) == 0      => return Err(Exception::IllegalInst),
+            _                                                   => (),
+        }
+
+        // The access is translated and checked as if made from the privilege in hstatus.SPVP with V=1
+        let mut csr = self.csr;
+        csr.priv_level = match (hstatus & HSTATUS_SPVP) != 0 {
+            true    => PrivLevel::SUPERVISOR,
+            false   => PrivLevel::USER,
+        };
+        csr.virt = true;
+
+        let addr = self.register.read(rs1) as usize;
+        match (funct7, rs2) {
+            // HLV.B
+            (0b011_0000, 0b0_0000)  => {
+                let data = self.mmu.read8(&csr, addr)? as i8 as i64;
+                self.register.write(rd, data as u64);
+            },
+            // HLV.BU
+            (0b011_0000, 0b0_0001)  => {
+                let data = self.mmu.read8(&csr, addr)?;
+                self.register.write(rd, data as u64);
+            },
+            // HLV.H
+            (0b011_0010, 0b0_0000)  => {
+                let data = self.mmu.read16(&csr, addr)? as i16 as i64;
+                self.register.write(rd, data as u64);
+            },
+            // HLV.HU
+            (0b011_0010, 0b0_0001)  => {
+                let data = self.mmu.read16(&csr, addr)?;
+                self.register.write(rd, data as u64);
+            },
+            // HLVX.HU
+            (0b011_0010, 0b0_0011)  => {
+                let data = self.mmu.read_executable(&csr, addr, 2)?;
+                self.register.write(rd, data);
+            },
+            // HLV.W
+            (0b011_0100, 0b0_0000)  => {
+                let data = self.mmu.read32(&csr, addr)? as i32 as i64;
+                self.register.write(rd, data as u64);
+            },
+            // HLV.WU
+            (0b011_0100, 0b0_0001)  => {
+                let data = self.mmu.read32(&csr, addr)?;
+                self.register.write(rd, data as u64);
+            },
+            // HLVX.WU
+            (0b011_0100, 0b0_0011)  => {
+                let data = self.mmu.read_executable(&csr, addr, 4)?;
+                self.register.write(rd, data);
+            },
+            // HLV.D
+            (0b011_0110, 0b0_0000)  => {
+                let data = self.mmu.read64(&csr, addr)?;
+                self.register.write(rd, data);
+            },
+            // HSV.B
+            (0b011_0001, _)         => self.mmu.write8(&csr, addr, self.register.read(rs2) as u8)?,
+            // HSV.H
+            (0b011_0011, _)         => self.mmu.write16(&csr, addr, self.register.read(rs2) as u16)?,
+            // HSV.W
+            (0b011_0101, _)         => self.mmu.write32(&csr, addr, self.register.read(rs2) as u32)?,
+            // HSV.D
+            (0b011_0111, _)         => self.mmu.write64(&csr, addr, self.register.read(rs2))?,
+            _                       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
+        }
+
+        Ok(())
+    }
+
     fn decode_zicsr(&mut self) -> Result<(), Exception> {
         // Decode instruction
         let csr:    u16     = ((self.instruction >> 20) & 0xFFF) as u16;
@@ -732,51 +1388,70 @@ impl Cpu {
         let funct3: u8      = ((self.instruction >> 12) & 0x7) as u8;
         let rd:     usize   = ((self.instruction >> 7)  & 0x1F) as usize;
 
+        // CSRRW(I) always write, CSRRS(I)/CSRRC(I) only when rs1 (or uimm) is not zero
+        let writes = match funct3 {
+            0b001 | 0b101   => true,
+            _               => rs1 != 0,
+        };
+        self.check_csr_access(csr, writes)?;
+
         match funct3 {
             // CSRRW
             0b001   => {
                 let wdata = self.register.read(rs1);
                 if rd != 0 {
-                    let data: u64 = self.csr.read(csr) as u64;
+                    let data: u64 = self.read_csr(csr) as u64;
                     self.register.write(rd, data);
                 }
-                self.csr.write(csr, wdata);
+                self.write_csr(csr, wdata);
             },
             // CSRRS
             0b010   => {
-                let data: u64 = self.csr.read(csr) as u64;
+                let data: u64 = self.read_csr(csr) as u64;
                 let wdata:u64 = self.register.read(rs1);
                 self.register.write(rd, data);
-                self.csr.write(csr, data | wdata);
+                if writes {
+                    let data = self.csr.read_modify_write_base(csr, data);
+                    self.write_csr(csr, data | wdata);
+                }
             },
             // CSRRC
             0b011   => {
-                let mut data: u64 = self.csr.read(csr);
+                let mut data: u64 = self.read_csr(csr);
                 let wdata: u64 = self.register.read(rs1);
                 self.register.write(rd, data);
-                data &= !(wdata);
-                self.csr.write(csr, data);
+                if writes {
+                    data = self.csr.read_modify_write_base(csr, data);
+                    data &= !(wdata);
+                    self.write_csr(csr, data);
+                }
             },
             // CSRRWI
             0b101   => {
                 if rd != 0 {
-                    let data: u64 = self.csr.read(csr) as u64;
+                    let data: u64 = self.read_csr(csr) as u64;
                     self.register.write(rd, data);
                 }
-                self.csr.write(csr, uimm as u64);
+                self.write_csr(csr, uimm as u64);
             },
             // CSRRSI
             0b110   => {
-                let data: u64 = self.csr.read(csr) as u64;
+                let data: u64 = self.read_csr(csr) as u64;
                 self.register.write(rd, data);
-                self.csr.write(csr, data | (uimm as u64));
+                if writes {
+                    let data = self.csr.read_modify_write_base(csr, data);
+                    self.write_csr(csr, data | (uimm as u64));
+                }
             },
             // CSRRCI
             0b111   => {
-                let mut data: u64 = self.csr.read(csr);
+                let mut data: u64 = self.read_csr(csr);
                 self.register.write(rd, data);
-                data &= !(uimm) as u64;
-                self.csr.write(csr, data);
+                if writes {
+                    data = self.csr.read_modify_write_base(csr, data);
+                    data &= !(uimm) as u64;
+                    self.write_csr(csr, data);
+                }
             },
             _       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
         }
@@ -784,6 +1459,106 @@ impl Cpu {
         Ok(())
     }
 
+    // CSR instructions access the 32-bit view of the CSRs on an RV32 hart
+    fn read_csr(&self, csr: u16) -> u64 {
+        match self.csr.mxlen() {
+            32  => self.csr.read_rv32(csr),
+            _   => self.csr.read(csr),
+        }
+    }
+
+    fn write_csr(&mut self, csr: u16, data: u64) {
+        self.minstret_written |= matches!(csr, MINSTRET | MINSTRETH);
+        match self.csr.mxlen() {
+            32  => self.csr.write_rv32(csr, data),
+            _   => self.csr.write(csr, data),
+        }
+    }
+
+    /*
+     *  CSR address
+     *
+     *  11  10 9   8 7                 0
+     *  +-----+-----+-------------------+
+     *  | R/W | Priv|      Number       |
+     *  +-----+-----+-------------------+
+     *
+     *  R/W = 0b11: read-only, Priv: lowest privilege level that can access the CSR
+     */
+    fn check_csr_access(&self, csr: u16, writes: bool) -> Result<(), Exception> {
+        if !self.csr.is_implemented(csr) {
+            return Err(Exception::IllegalInst);
+        }
+
+        // Hypervisor and VS CSRs (privilege field 0b10) belong to HS-mode. In a virtual machine, M-level CSRs
+        // are illegal, and the others trap to the hypervisor unless VS-mode (or VU-mode) may access them
+        let level = (csr >> 8) & 0b11;
+        match self.csr.virt {
+            true if level == 0b11   => return Err(Exception::IllegalInst),
+            true if level == 0b10 || level > self.csr.priv_level as u16 => return Err(Exception::VirtualInst),
+            false if level == 0b10 && self.csr.priv_level < PrivLevel::SUPERVISOR   => return Err(Exception::IllegalInst),
+            false if level != 0b10 && level > self.csr.priv_level as u16            => return Err(Exception::IllegalInst),
+            _                       => (),
+        }
+
+        if writes && (csr >> 10) == 0b11 {
+            return Err(Exception::IllegalInst);
+        }
+
+        // Counters are visible below M-mode only as mcounteren (hcounteren with V=1, and scounteren for U-mode) allow
+        if let CYCLE ..= HPMCOUNTER31 | CYCLEH ..= HPMCOUNTER31H = csr {
+            let bit = 1 << (csr & 0x1F);
+            if self.csr.priv_level != PrivLevel::MACHINE && (self.csr.read(MCOUNTEREN) & bit) == 0 {
+                return Err(Exception::IllegalInst);
+            }
+            if self.csr.virt && (self.csr.read(HCOUNTEREN) & bit) == 0 {
+                return Err(Exception::VirtualInst);
+            }
+            if self.csr.priv_level == PrivLevel::USER && (self.csr.read(SCOUNTEREN) & bit) == 0 {
+                return Err(if self.csr.virt { Exception::VirtualInst } else { Exception::IllegalInst });
+            }
+        }
+
+        // Sstc: S-mode may use stimecmp only if menvcfg.STCE and mcounteren.TM are set
+        if (csr == STIMECMP || csr == STIMECMPH) && self.csr.priv_level != PrivLevel::MACHINE {
+            let stce = (self.csr.read(MENVCFG) & MENVCFG_STCE) != 0;
+            let tm = (self.csr.read(MCOUNTEREN) >> 1) & 1 != 0;
+            if !stce || !tm {
+                return Err(Exception::IllegalInst);
+            }
+            // There is no vstimecmp: henvcfg.STCE is read-only zero
+            if self.csr.virt {
+                return Err(Exception::VirtualInst);
+            }
+        }
+
+        // Vector CSRs are accessible only while the vector unit is enabled
+        if let VSTART | VXSAT | VXRM | VCSR | VL | VTYPE | VLENB = csr {
+            if !self.csr.vector_enabled() {
+                return Err(Exception::IllegalInst);
+            }
+        }
+
+        // Likewise the floating-point CSRs while the floating-point state is Off
+        if let FFLAGS | FRM | FCSR = csr {
+            if !self.csr.float_enabled() {
+                return Err(Exception::IllegalInst);
+            }
+        }
+
+        // mstatus.TVM traps HS-mode accesses to satp and hgatp, hstatus.VTVM traps VS-mode accesses to vsatp
+        if self.csr.priv_level == PrivLevel::SUPERVISOR {
+            if !self.csr.virt && (csr == SATP || csr == HGATP) && self.csr.read_bit(MSTATUS, MSTATUS_TVM_BIT) {
+                return Err(Exception::IllegalInst);
+            }
+            if self.csr.virt && csr == SATP && (self.csr.read(HSTATUS) & HSTATUS_VTVM) != 0 {
+                return Err(Exception::VirtualInst);
+            }
+        }
+
+        Ok(())
+    }
+
     fn decode_rv64i_itype(&mut self) -> Result<(), Exception> {
         // Decode instruction
         let mut imm:    i16     = ((self.instruction >> 20) & 0xFFF) as i16;
@@ -796,14 +1571,38 @@ impl Cpu {
         match funct3 {
             // ADDIW
             0b000   => self.register.write(rd, ((self.register.read(rs1) as i32).wrapping_add(imm as i32)) as u64),
-            // SLLIW
-            0b001   => self.register.write(rd, ((self.register.read(rs1) as i32).wrapping_shl(imm as u32)) as u64),
+            0b001   => {
+                match funct7 {
+                    // SLLIW
+                    0b000_0000  => self.register.write(rd, ((self.register.read(rs1) as i32).wrapping_shl(imm as u32)) as u64),
+                    _ if !self.csr.has_extension('B') => return Err(Exception::IllegalInst),
+                    // SLLI.UW
+                    0b000_0100  |
+                    0b000_0101  => self.register.write(rd, (self.register.read(rs1) as u32 as u64) << (imm & 0x3F)),
+                    0b011_0000  => {
+                        let wdata = self.register.read(rs1) as u32;
+                        match imm & 0x1F {
+                            // CLZW
+                            0b0_0000    => self.register.write(rd, wdata.leading_zeros() as u64),
+                            // CTZW
+                            0b0_0001    => self.register.write(rd, wdata.trailing_zeros() as u64),
+                            // CPOPW
+                            0b0_0010    => self.register.write(rd, wdata.count_ones() as u64),
+                            _           => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
+                        }
+                    },
+                    _           => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
+                }
+            },
             0b101   => {
                 match funct7 {
                     // SRLIW
                     0b000_0000  => self.register.write(rd, ((self.register.read(rs1) as u32).wrapping_shr(imm as u32))  as i32 as u64),
                     // SRAIW
                     0b010_0000  => self.register.write(rd, ((self.register.read(rs1) as i32).wrapping_shr(imm as u32)) as u64),
+                    // RORIW
+                    0b011_0000 if !self.csr.has_extension('B') => return Err(Exception::IllegalInst),
+                    0b011_0000  => self.register.write(rd, (self.register.read(rs1) as u32).rotate_right((imm & 0x1F) as u32) as i32 as u64),
                     _           => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
                 }
             },
@@ -837,7 +1636,33 @@ impl Cpu {
                 0b101       => self.register.write(rd, ((self.register.read(rs1) as i32).wrapping_shr((self.register.read(rs2) & 0x1F) as u32)) as u64),
                 _           => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
             },
+            // Zba, Zbb and Zbkb
+            0b000_0100 | 0b001_0000 | 0b011_0000 if !self.csr.has_extension('B') => return Err(Exception::IllegalInst),
+            0b000_0100  => match funct3 {
+                // ADD.UW
+                0b000       => self.register.write(rd, (self.register.read(rs1) as u32 as u64).wrapping_add(self.register.read(rs2))),
+                // PACKW (ZEXT.H when rs2 is x0)
+                0b100       => self.register.write(rd, (((self.register.read(rs2) & 0xFFFF) << 16) | (self.register.read(rs1) & 0xFFFF)) as u32 as i32 as u64),
+                _           => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
+            },
+            0b001_0000  => match funct3 {
+                // SH1ADD.UW
+                0b010       => self.register.write(rd, ((self.register.read(rs1) as u32 as u64) << 1).wrapping_add(self.register.read(rs2))),
+                // SH2ADD.UW
+                0b100       => self.register.write(rd, ((self.register.read(rs1) as u32 as u64) << 2).wrapping_add(self.register.read(rs2))),
+                // SH3ADD.UW
+                0b110       => self.register.write(rd, ((self.register.read(rs1) as u32 as u64) << 3).wrapping_add(self.register.read(rs2))),
+                _           => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
+            },
+            0b011_0000  => match funct3 {
+                // ROLW
+                0b001       => self.register.write(rd, (self.register.read(rs1) as u32).rotate_left((self.register.read(rs2) & 0x1F) as u32) as i32 as u64),
+                // RORW
+                0b101       => self.register.write(rd, (self.register.read(rs1) as u32).rotate_right((self.register.read(rs2) & 0x1F) as u32) as i32 as u64),
+                _           => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
+            },
             // RV64M
+            0b000_0001 if !self.csr.has_extension('M') => return Err(Exception::IllegalInst),
             0b000_0001  => match funct3 {
                 // MULW
                 0b000       => {
@@ -904,6 +1729,7 @@ impl Cpu {
         let rs1:    usize   = ((self.instruction >> 15) & 0x1F) as usize;
         let funct3: u8      = ((self.instruction >> 12) & 0x7) as u8;
         let rd:     usize   = ((self.instruction >> 7)  & 0x1F) as usize;
+        let xlen:   usize   = self.register.xlen;
 
         match funct3 {
             // MUL
@@ -911,20 +1737,20 @@ impl Cpu {
                 let result: u128 = (self.register.read(rs1) as i64 as i128).wrapping_mul(self.register.read(rs2) as i64 as i128) as u128;
                 self.register.write(rd, (result & 0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF) as u64);
             },
-            // MULH
+            // MULH: the upper XLEN bits of the 2*XLEN-bit product
             0b001   => {
                 let result: u128 = (self.register.read(rs1) as i64 as i128).wrapping_mul(self.register.read(rs2) as i64 as i128) as u128;
-                self.register.write(rd, ((result >> 64) & 0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF) as u64);
+                self.register.write(rd, ((result >> xlen) & 0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF) as u64);
             },
             // MULHSU
             0b010   => {
-                let result: u128 = (self.register.read(rs1) as i64 as i128).wrapping_mul(self.register.read(rs2) as u64 as i128) as u128;
-                self.register.write(rd, ((result >> 64) & 0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF) as u64);
+                let result: u128 = (self.register.read(rs1) as i64 as i128).wrapping_mul(self.register.read_unsigned(rs2) as i128) as u128;
+                self.register.write(rd, ((result >> xlen) & 0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF) as u64);
             },
             // MULHU
             0b011   => {
-                let result: u128 = (self.register.read(rs1) as u128).wrapping_mul(self.register.read(rs2) as u128) as u128;
-                self.register.write(rd, ((result >> 64) & 0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF) as u64);
+                let result: u128 = (self.register.read_unsigned(rs1) as u128).wrapping_mul(self.register.read_unsigned(rs2) as u128) as u128;
+                self.register.write(rd, ((result >> xlen) & 0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF) as u64);
             },
             // DIV
             0b100   => {
@@ -944,7 +1770,7 @@ impl Cpu {
                     self.register.write(rd, -1 as i64 as u64);
                 }
                 else {
-                    let result: u64 = (self.register.read(rs1)as u64).wrapping_div(divisor);
+                    let result: u64 = self.register.read_unsigned(rs1).wrapping_div(self.register.read_unsigned(rs2));
                     self.register.write(rd, result);
                 }
             },
@@ -962,8 +1788,8 @@ impl Cpu {
             },
             // REMU
             0b111   => {
-                let dividend = self.register.read(rs1);
-                let divisor = self.register.read(rs2);
+                let dividend = self.register.read_unsigned(rs1);
+                let divisor = self.register.read_unsigned(rs2);
                 if divisor == 0 {
                     self.register.write(rd, dividend as u64);
                 }
@@ -985,165 +1811,168 @@ impl Cpu {
         let funct3: u8      = ((self.instruction >> 12) & 0x7) as u8;
         let rd:     usize   = ((self.instruction >> 7)  & 0x1F) as usize;
 
+        if !self.csr.has_extension('A') {
+            return Err(Exception::IllegalInst);
+        }
+
         match funct3 {
             // RV32A
             0b010   => match funct7 & 0x7C {
                 // LR.W
-                0b000_1000 => unimplemented!(),
+                0b000_1000 => {
+                    let addr = self.register.read(rs1) as usize;
+                    let data = self.mmu.load_reserved32(&self.csr, addr)?;
+                    self.register.write(rd, data as i32 as i64 as u64);
+                },
                 // SC.W
-                0b000_1100 => unimplemented!(),
+                0b000_1100 => {
+                    let addr = self.register.read(rs1) as usize;
+                    let src = self.register.read(rs2) as u32;
+                    let stored = self.mmu.store_conditional32(&self.csr, addr, src)?;
+                    self.register.write(rd, !stored as u64);
+                },
                 // AMOSWAP.W
                 0b000_0100 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)? as i32 as i64 as u64;
-                    let wdata = self.register.read(rs2);
-                    self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, wdata)?;
-                    self.register.write(rs2, data);
+                    let src = self.register.read(rs2) as u32;
+                    let data = self.mmu.amo32(&self.csr, addr, |_| src)?;
+                    self.register.write(rd, data as i32 as i64 as u64);
                 },
                 // AMOADD.W
                 0b000_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)? as i32 as i64 as u64;
-                    let wdata = self.register.read(rs2);
-                    self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, data.wrapping_add(wdata) as i32 as i64 as u64)?;
+                    let src = self.register.read(rs2) as u32;
+                    let data = self.mmu.amo32(&self.csr, addr, |data| data.wrapping_add(src))?;
+                    self.register.write(rd, data as i32 as i64 as u64);
                 },
                 // AMOXOR.W
                 0b001_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)? as i32 as i64 as u64;
-                    let wdata = self.register.read(rs2);
-                    self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, data ^ (wdata as i32 as i64 as u64))?;
+                    let src = self.register.read(rs2) as u32;
+                    let data = self.mmu.amo32(&self.csr, addr, |data| data ^ src)?;
+                    self.register.write(rd, data as i32 as i64 as u64);
                 },
                 // AMOAND.W
                 0b011_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)? as i32 as i64 as u64;
-                    let wdata = self.register.read(rs2);
-                    self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, data & (wdata as i32 as i64 as u64))?;
+                    let src = self.register.read(rs2) as u32;
+                    let data = self.mmu.amo32(&self.csr, addr, |data| data & src)?;
+                    self.register.write(rd, data as i32 as i64 as u64);
                 },
                 // AMOOR.W
                 0b010_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)? as i32 as i64 as u64;
-                    let wdata = self.register.read(rs2);
-                    self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, data | (wdata as i32 as i64 as u64))?;
+                    let src = self.register.read(rs2) as u32;
+                    let data = self.mmu.amo32(&self.csr, addr, |data| data | src)?;
+                    self.register.write(rd, data as i32 as i64 as u64);
                 },
                 // AMOMIN.W
                 0b100_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)? as i32 as i64 as u64;
-                    self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, std::cmp::min(data as i64, self.register.read(rs2) as i32 as i64) as u64)?;
+                    let src = self.register.read(rs2) as u32;
+                    let data = self.mmu.amo32(&self.csr, addr, |data| std::cmp::min(data as i32, src as i32) as u32)?;
+                    self.register.write(rd, data as i32 as i64 as u64);
                 },
                 // AMOMAX.W
                 0b101_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)? as i32 as i64 as u64;
-                    let wdata = self.register.read(rs2);
-                    self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, std::cmp::max(data as i64, wdata as i32 as i64) as u64)?;
+                    let src = self.register.read(rs2) as u32;
+                    let data = self.mmu.amo32(&self.csr, addr, |data| std::cmp::max(data as i32, src as i32) as u32)?;
+                    self.register.write(rd, data as i32 as i64 as u64);
                 },
                 // AMOMINU.W
                 0b110_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)? as i32 as i64 as u64;
-                    let wdata = self.register.read(rs2);
-                    self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, std::cmp::min(data, wdata))?;
+                    let src = self.register.read(rs2) as u32;
+                    let data = self.mmu.amo32(&self.csr, addr, |data| std::cmp::min(data, src))?;
+                    self.register.write(rd, data as i32 as i64 as u64);
                 },
                 // AMOMAXU.W
                 0b111_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)? as i32 as i64 as u64;
-                    let wdata = self.register.read(rs2);
-                    self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, std::cmp::max(data, wdata))?;
+                    let src = self.register.read(rs2) as u32;
+                    let data = self.mmu.amo32(&self.csr, addr, |data| std::cmp::max(data, src))?;
+                    self.register.write(rd, data as i32 as i64 as u64);
                 },
                 _       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
             },
             // RV64A
             0b011   => match funct7 & 0x7C {
                 // LR.D
-                0b000_1000 => unimplemented!(),
+                0b000_1000 => {
+                    let addr = self.register.read(rs1) as usize;
+                    let data = self.mmu.load_reserved64(&self.csr, addr)?;
+                    self.register.write(rd, data);
+                },
                 // SC.D
-                0b000_1100 => unimplemented!(),
+                0b000_1100 => {
+                    let addr = self.register.read(rs1) as usize;
+                    let src = self.register.read(rs2);
+                    let stored = self.mmu.store_conditional64(&self.csr, addr, src)?;
+                    self.register.write(rd, !stored as u64);
+                },
                 // AMOSWAP.D
                 0b000_0100 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)? as i32 as i64 as u64;
-                    let wdata = self.register.read(rs2);
+                    let src = self.register.read(rs2);
+                    let data = self.mmu.amo64(&self.csr, addr, |_| src)?;
                     self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, wdata)?;
-                    self.register.write(rs2, data);
                 },
                 // AMOADD.D
                 0b000_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)?;
-                    let wdata = self.register.read(rs2);
+                    let src = self.register.read(rs2);
+                    let data = self.mmu.amo64(&self.csr, addr, |data| data.wrapping_add(src))?;
                     self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, data.wrapping_add(wdata))?;
                 },
                 // AMOXOR.D
                 0b001_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)?;
-                    let wdata = self.register.read(rs2);
+                    let src = self.register.read(rs2);
+                    let data = self.mmu.amo64(&self.csr, addr, |data| data ^ src)?;
                     self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, data ^ (wdata))?;
                 },
                 // AMOAND.D
                 0b011_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)?;
-                    let wdata = self.register.read(rs2);
+                    let src = self.register.read(rs2);
+                    let data = self.mmu.amo64(&self.csr, addr, |data| data & src)?;
                     self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, data & (wdata))?;
                 },
                 // AMOOR.D
                 0b010_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)?;
-                    let wdata = self.register.read(rs2);
+                    let src = self.register.read(rs2);
+                    let data = self.mmu.amo64(&self.csr, addr, |data| data | src)?;
                     self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, data | (wdata))?;
                 },
                 // AMOMIN.D
                 0b100_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)?;
-                    let wdata = self.register.read(rs2);
+                    let src = self.register.read(rs2);
+                    let data = self.mmu.amo64(&self.csr, addr, |data| std::cmp::min(data as i64, src as i64) as u64)?;
                     self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, std::cmp::min(data as i64, wdata as i64) as u64)?;
                 },
                 // AMOMAX.D
                 0b101_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)?;
-                    let wdata = self.register.read(rs2);
+                    let src = self.register.read(rs2);
+                    let data = self.mmu.amo64(&self.csr, addr, |data| std::cmp::max(data as i64, src as i64) as u64)?;
                     self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, std::cmp::max(data as i64, wdata as i64) as u64)?;
                 },
                 // AMOMINU.D
                 0b110_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)?;
-                    let wdata = self.register.read(rs2);
+                    let src = self.register.read(rs2);
+                    let data = self.mmu.amo64(&self.csr, addr, |data| std::cmp::min(data, src))?;
                     self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, std::cmp::min(data, wdata))?;
                 },
                 // AMOMAXU.D
                 0b111_0000 => {
                     let addr = self.register.read(rs1) as usize;
-                    let data = self.mmu.read64(&self.csr, addr)?;
-                    let wdata = self.register.read(rs2);
+                    let src = self.register.read(rs2);
+                    let data = self.mmu.amo64(&self.csr, addr, |data| std::cmp::max(data, src))?;
                     self.register.write(rd, data);
-                    self.mmu.write64(&self.csr, addr, std::cmp::max(data, wdata))?;
                 },
                 _       => panic!("[ERROR] unknown instruciton: 0x{:08x} (pc: 0x{:08x})", self.instruction, self.pc),
             },
@@ -1162,13 +1991,39 @@ impl Cpu {
 
 }
 
-fn inspect_instruciton(instruction: Instruction) -> String {
+// Carry-less multiplication (Zbc): the low, high and reversed halves of the 128-bit product
+fn clmul(a: u64, b: u64) -> u64 {
+    (0..64).filter(|i| (b >> i) & 1 != 0).fold(0, |acc, i| acc ^ (a << i))
+}
+
+fn clmulh(a: u64, b: u64) -> u64 {
+    (1..64).filter(|i| (b >> i) & 1 != 0).fold(0, |acc, i| acc ^ (a >> (64 - i)))
+}
+
+fn clmulr(a: u64, b: u64) -> u64 {
+    (0..64).filter(|i| (b >> i) & 1 != 0).fold(0, |acc, i| acc ^ (a >> (63 - i)))
+}
+
+// OR-combine within each byte: every non-zero byte becomes 0xFF
+fn orc_b(data: u64) -> u64 {
+    (0..8).filter(|i| (data >> (i * 8)) & 0xFF != 0).fold(0, |acc, i| acc | (0xFF << (i * 8)))
+}
+
+fn inspect_instruciton(instruction: Instruction, xlen: usize) -> String {
+    // A compressed instruction is shown with the instruction it expands to
+    if (instruction & 0b11) != 0b11 {
+        return match compressed::expand(instruction as u16, xlen) {
+            Some(expanded)  => format!("[INFO] compressed instruction(0x{:04x}) => {}", instruction, &inspect_instruciton(expanded, xlen)[7..]),
+            None            => format!("[INFO] compressed instruction(0x{:04x}): unknown", instruction),
+        };
+    }
+
     let funct12:    u16     = ((instruction >> 20) & 0xFFF) as u16;
     let funct7:     u8      = ((instruction >> 25) & 0x7F) as u8;
     let _rs2:       usize   = ((instruction >> 20) & 0x1F) as usize;
     let _rs1:       usize   = ((instruction >> 15) & 0x1F) as usize;
     let funct3:     u8      = ((instruction >> 12) & 0x7) as u8;
-    let _rd:        usize   = ((instruction >> 7) & 0x1F) as usize;
+    let rd:         usize   = ((instruction >> 7) & 0x1F) as usize;
     let opcode:     u8      = (instruction & 0x7F) as u8;
 
     let mut output = format!("[INFO] instruction(0x{:08x})", instruction);
@@ -1209,7 +2064,13 @@ fn inspect_instruciton(instruction: Instruction) -> String {
             0b010       => output = format!("{}: SLTI", output),
             0b011       => output = format!("{}: SLTIU", output),
             0b100       => output = format!("{}: XORI", output),
-            0b110       => output = format!("{}: ORI", output),
+            // Zicbop prefetches are ORIs to x0
+            0b110       => match (rd, funct12 & 0x1F) {
+                (0, 0b00000)    => output = format!("{}: PREFETCH.I", output),
+                (0, 0b00001)    => output = format!("{}: PREFETCH.R", output),
+                (0, 0b00011)    => output = format!("{}: PREFETCH.W", output),
+                _               => output = format!("{}: ORI", output),
+            },
             0b111       => output = format!("{}: ANDI", output),
             0b001       => output = format!("{}: SLLI", output),
             0b101       =>  match funct7 >> 1 {
@@ -1249,6 +2110,7 @@ fn inspect_instruciton(instruction: Instruction) -> String {
             0b101       =>  match funct7 {
                 0b000_0000  => output = format!("{}: SRL", output),
                 0b000_0001  => output = format!("{}: DIVU", output),
+                0b000_0111  => output = format!("{}: CZERO.EQZ", output),
                 0b010_0000  => output = format!("{}: SRA", output),
                 _           => return format!("{}: unknown", output),
             },
@@ -1260,13 +2122,24 @@ fn inspect_instruciton(instruction: Instruction) -> String {
             0b111       => match funct7 {
                 0b000_0000  => output = format!("{}: AND", output),
                 0b000_0001  => output = format!("{}: REMU", output),
+                0b000_0111  => output = format!("{}: CZERO.NEZ", output),
                 _           => return format!("{}: unknown", output),
             },
             _           => return format!("{}: unknown", output),
         },
         0b000_1111  => match funct3 {
-            0b000       => output = format!("{}: FENCE", output),
+            0b000       => match instruction {
+                0x0100_000F => output = format!("{}: PAUSE", output),
+                _           => output = format!("{}: FENCE", output),
+            },
             0b001       => output = format!("{}: FENCE.I", output),
+            0b010       => match funct12 {
+                0b0000      => output = format!("{}: CBO.INVAL", output),
+                0b0001      => output = format!("{}: CBO.CLEAN", output),
+                0b0010      => output = format!("{}: CBO.FLUSH", output),
+                0b0100      => output = format!("{}: CBO.ZERO", output),
+                _           => return format!("{}: unknown", output),
+            },
             _           => return format!("{}: unknown", output),
         },
         0b111_0011  => match funct3 {
@@ -1278,9 +2151,15 @@ fn inspect_instruciton(instruction: Instruction) -> String {
                     0b000_1000          => output = format!("{}: SRET", output),
                     0b001_1000          => output = format!("{}: MRET", output),
                     0b000_1001          => output = format!("{}: SFENCE.VMA", output),
+                    0b001_0001          => output = format!("{}: HFENCE.VVMA", output),
+                    0b011_0001          => output = format!("{}: HFENCE.GVMA", output),
                     _                   => return format!("{}: unknown", output),
                 },
             },
+            0b100       => match funct7 & 1 {
+                0           => output = format!("{}: HLV", output),
+                _           => output = format!("{}: HSV", output),
+            },
             0b001       => output = format!("{}: CSRRW", output),
             0b010       => output = format!("{}: CSRRS", output),
             0b011       => output = format!("{}: CSRRC", output),
@@ -1355,6 +2234,12 @@ fn inspect_instruciton(instruction: Instruction) -> String {
                 
             _           => return format!("{}: unknown", output),
         }
+        0b000_0111  => output = format!("{}: VL*", output),
+        0b010_0111  => output = format!("{}: VS*", output),
+        0b101_0111  => match funct3 {
+            0b111       => output = format!("{}: VSETVL*", output),
+            _           => output = format!("{}: OP-V", output),
+        },
         _           => return format!("{}: unknown", output),
     }
 
@@ -0,0 +1,246 @@
+/*
+ * Scalar Cryptography Extensions (Zkn and Zks, RV64)
+ *
+ * Zkne/Zknd: AES encryption and decryption rounds on half of the 128-bit state, which is
+ *            held in two registers (rs1: columns 0 and 1, rs2: columns 2 and 3).
+ * Zknh:      SHA-256 and SHA-512 sigma and sum functions.
+ * Zksed:     SM4 round and key-schedule functions on one byte of rs2 selected by bs.
+ * Zksh:      SM3 permutation functions.
+ * Zbkx:      crossbar permutations, used for constant-time table lookups.
+ *
+ * Zbkb and Zbkc are otherwise covered by the Zbb and Zbc instructions in cpu.rs.
+ */
+
+// AES S-box (FIPS 197)
+const AES_SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+// AES inverse S-box
+const AES_INV_SBOX: [u8; 256] = [
+    0x52, 0x09, 0x6a, 0xd5, 0x30, 0x36, 0xa5, 0x38, 0xbf, 0x40, 0xa3, 0x9e, 0x81, 0xf3, 0xd7, 0xfb,
+    0x7c, 0xe3, 0x39, 0x82, 0x9b, 0x2f, 0xff, 0x87, 0x34, 0x8e, 0x43, 0x44, 0xc4, 0xde, 0xe9, 0xcb,
+    0x54, 0x7b, 0x94, 0x32, 0xa6, 0xc2, 0x23, 0x3d, 0xee, 0x4c, 0x95, 0x0b, 0x42, 0xfa, 0xc3, 0x4e,
+    0x08, 0x2e, 0xa1, 0x66, 0x28, 0xd9, 0x24, 0xb2, 0x76, 0x5b, 0xa2, 0x49, 0x6d, 0x8b, 0xd1, 0x25,
+    0x72, 0xf8, 0xf6, 0x64, 0x86, 0x68, 0x98, 0x16, 0xd4, 0xa4, 0x5c, 0xcc, 0x5d, 0x65, 0xb6, 0x92,
+    0x6c, 0x70, 0x48, 0x50, 0xfd, 0xed, 0xb9, 0xda, 0x5e, 0x15, 0x46, 0x57, 0xa7, 0x8d, 0x9d, 0x84,
+    0x90, 0xd8, 0xab, 0x00, 0x8c, 0xbc, 0xd3, 0x0a, 0xf7, 0xe4, 0x58, 0x05, 0xb8, 0xb3, 0x45, 0x06,
+    0xd0, 0x2c, 0x1e, 0x8f, 0xca, 0x3f, 0x0f, 0x02, 0xc1, 0xaf, 0xbd, 0x03, 0x01, 0x13, 0x8a, 0x6b,
+    0x3a, 0x91, 0x11, 0x41, 0x4f, 0x67, 0xdc, 0xea, 0x97, 0xf2, 0xcf, 0xce, 0xf0, 0xb4, 0xe6, 0x73,
+    0x96, 0xac, 0x74, 0x22, 0xe7, 0xad, 0x35, 0x85, 0xe2, 0xf9, 0x37, 0xe8, 0x1c, 0x75, 0xdf, 0x6e,
+    0x47, 0xf1, 0x1a, 0x71, 0x1d, 0x29, 0xc5, 0x89, 0x6f, 0xb7, 0x62, 0x0e, 0xaa, 0x18, 0xbe, 0x1b,
+    0xfc, 0x56, 0x3e, 0x4b, 0xc6, 0xd2, 0x79, 0x20, 0x9a, 0xdb, 0xc0, 0xfe, 0x78, 0xcd, 0x5a, 0xf4,
+    0x1f, 0xdd, 0xa8, 0x33, 0x88, 0x07, 0xc7, 0x31, 0xb1, 0x12, 0x10, 0x59, 0x27, 0x80, 0xec, 0x5f,
+    0x60, 0x51, 0x7f, 0xa9, 0x19, 0xb5, 0x4a, 0x0d, 0x2d, 0xe5, 0x7a, 0x9f, 0x93, 0xc9, 0x9c, 0xef,
+    0xa0, 0xe0, 0x3b, 0x4d, 0xae, 0x2a, 0xf5, 0xb0, 0xc8, 0xeb, 0xbb, 0x3c, 0x83, 0x53, 0x99, 0x61,
+    0x17, 0x2b, 0x04, 0x7e, 0xba, 0x77, 0xd6, 0x26, 0xe1, 0x69, 0x14, 0x63, 0x55, 0x21, 0x0c, 0x7d,
+];
+
+// SM4 S-box (GB/T 32907-2016)
+const SM4_SBOX: [u8; 256] = [
+    0xd6, 0x90, 0xe9, 0xfe, 0xcc, 0xe1, 0x3d, 0xb7, 0x16, 0xb6, 0x14, 0xc2, 0x28, 0xfb, 0x2c, 0x05,
+    0x2b, 0x67, 0x9a, 0x76, 0x2a, 0xbe, 0x04, 0xc3, 0xaa, 0x44, 0x13, 0x26, 0x49, 0x86, 0x06, 0x99,
+    0x9c, 0x42, 0x50, 0xf4, 0x91, 0xef, 0x98, 0x7a, 0x33, 0x54, 0x0b, 0x43, 0xed, 0xcf, 0xac, 0x62,
+    0xe4, 0xb3, 0x1c, 0xa9, 0xc9, 0x08, 0xe8, 0x95, 0x80, 0xdf, 0x94, 0xfa, 0x75, 0x8f, 0x3f, 0xa6,
+    0x47, 0x07, 0xa7, 0xfc, 0xf3, 0x73, 0x17, 0xba, 0x83, 0x59, 0x3c, 0x19, 0xe6, 0x85, 0x4f, 0xa8,
+    0x68, 0x6b, 0x81, 0xb2, 0x71, 0x64, 0xda, 0x8b, 0xf8, 0xeb, 0x0f, 0x4b, 0x70, 0x56, 0x9d, 0x35,
+    0x1e, 0x24, 0x0e, 0x5e, 0x63, 0x58, 0xd1, 0xa2, 0x25, 0x22, 0x7c, 0x3b, 0x01, 0x21, 0x78, 0x87,
+    0xd4, 0x00, 0x46, 0x57, 0x9f, 0xd3, 0x27, 0x52, 0x4c, 0x36, 0x02, 0xe7, 0xa0, 0xc4, 0xc8, 0x9e,
+    0xea, 0xbf, 0x8a, 0xd2, 0x40, 0xc7, 0x38, 0xb5, 0xa3, 0xf7, 0xf2, 0xce, 0xf9, 0x61, 0x15, 0xa1,
+    0xe0, 0xae, 0x5d, 0xa4, 0x9b, 0x34, 0x1a, 0x55, 0xad, 0x93, 0x32, 0x30, 0xf5, 0x8c, 0xb1, 0xe3,
+    0x1d, 0xf6, 0xe2, 0x2e, 0x82, 0x66, 0xca, 0x60, 0xc0, 0x29, 0x23, 0xab, 0x0d, 0x53, 0x4e, 0x6f,
+    0xd5, 0xdb, 0x37, 0x45, 0xde, 0xfd, 0x8e, 0x2f, 0x03, 0xff, 0x6a, 0x72, 0x6d, 0x6c, 0x5b, 0x51,
+    0x8d, 0x1b, 0xaf, 0x92, 0xbb, 0xdd, 0xbc, 0x7f, 0x11, 0xd9, 0x5c, 0x41, 0x1f, 0x10, 0x5a, 0xd8,
+    0x0a, 0xc1, 0x31, 0x88, 0xa5, 0xcd, 0x7b, 0xbd, 0x2d, 0x74, 0xd0, 0x12, 0xb8, 0xe5, 0xb4, 0xb0,
+    0x89, 0x69, 0x97, 0x4a, 0x0c, 0x96, 0x77, 0x7e, 0x65, 0xb9, 0xf1, 0x09, 0xc5, 0x6e, 0xc6, 0x84,
+    0x18, 0xf0, 0x7d, 0xec, 0x3a, 0xdc, 0x4d, 0x20, 0x79, 0xee, 0x5f, 0x3e, 0xd7, 0xcb, 0x39, 0x48,
+];
+
+// AES key-schedule round constants, indexed by rnum (0xA: no constant, for AES-256)
+const AES_RCON: [u8; 11] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36, 0x00];
+
+// Multiply by x in GF(2^8) modulo the AES polynomial x^8 + x^4 + x^3 + x + 1
+fn xtime(b: u8) -> u8 {
+    (b << 1) ^ if (b & 0x80) != 0 { 0x1b } else { 0 }
+}
+
+fn gf_mul(mut a: u8, b: u8) -> u8 {
+    let mut product = 0;
+    for i in 0..8 {
+        if (b >> i) & 1 != 0 {
+            product ^= a;
+        }
+        a = xtime(a);
+    }
+    product
+}
+
+fn bytes_of(x: u64) -> [u8; 8] {
+    x.to_le_bytes()
+}
+
+// Apply `sbox` to every byte of a word
+fn sub_bytes(x: u64, sbox: &[u8; 256]) -> u64 {
+    u64::from_le_bytes(bytes_of(x).map(|b| sbox[b as usize]))
+}
+
+// Columns 0 and 1 of ShiftRows (or InvShiftRows) applied to the state {rs2, rs1}
+fn shift_rows(rs1: u64, rs2: u64, inverse: bool) -> u64 {
+    let state = [bytes_of(rs1), bytes_of(rs2)].concat();
+    let mut out = [0u8; 8];
+    for (i, out) in out.iter_mut().enumerate() {
+        let (col, row) = (i / 4, i % 4);
+        let from = match inverse {
+            false   => (col + row) % 4,
+            true    => (col + 4 - row) % 4,
+        };
+        *out = state[from * 4 + row];
+    }
+    u64::from_le_bytes(out)
+}
+
+// MixColumns (or InvMixColumns) on the column held in the low 32 bits of `col`
+fn mix_column(col: u32, inverse: bool) -> u32 {
+    let s = col.to_le_bytes();
+    let m: [u8; 4] = match inverse {
+        false   => [2, 3, 1, 1],
+        true    => [14, 11, 13, 9],
+    };
+    let mut out = [0u8; 4];
+    for (row, out) in out.iter_mut().enumerate() {
+        *out = (0..4).fold(0, |acc, i| acc ^ gf_mul(s[i], m[(4 + i - row) % 4]));
+    }
+    u32::from_le_bytes(out)
+}
+
+fn mix_columns(x: u64, inverse: bool) -> u64 {
+    (mix_column((x >> 32) as u32, inverse) as u64) << 32 | mix_column(x as u32, inverse) as u64
+}
+
+// AES64ES and AES64ESM: final and middle encryption rounds
+pub fn aes64es(rs1: u64, rs2: u64, mix: bool) -> u64 {
+    let x = sub_bytes(shift_rows(rs1, rs2, false), &AES_SBOX);
+    if mix { mix_columns(x, false) } else { x }
+}
+
+// AES64DS and AES64DSM: final and middle decryption rounds
+pub fn aes64ds(rs1: u64, rs2: u64, mix: bool) -> u64 {
+    let x = sub_bytes(shift_rows(rs1, rs2, true), &AES_INV_SBOX);
+    if mix { mix_columns(x, true) } else { x }
+}
+
+// AES64IM: InvMixColumns, to turn encryption round keys into decryption round keys
+pub fn aes64im(rs1: u64) -> u64 {
+    mix_columns(rs1, true)
+}
+
+// AES64KS1I: SubWord(RotWord(w3)) ^ rcon in both halves; None for a reserved rnum
+pub fn aes64ks1i(rs1: u64, rnum: u8) -> Option<u64> {
+    let rcon = *AES_RCON.get(rnum as usize)? as u32;
+    let word = (rs1 >> 32) as u32;
+    let word = if rnum == 0xA { word } else { word.rotate_right(8) };
+    let word = sub_bytes(word as u64, &AES_SBOX) as u32 ^ rcon;
+    Some((word as u64) << 32 | word as u64)
+}
+
+// AES64KS2: the other two words of the next round key
+pub fn aes64ks2(rs1: u64, rs2: u64) -> u64 {
+    let w0 = (rs1 >> 32) as u32 ^ rs2 as u32;
+    let w1 = w0 ^ (rs2 >> 32) as u32;
+    (w1 as u64) << 32 | w0 as u64
+}
+
+// SHA-256 functions: 32-bit results, sign-extended
+pub fn sha256sig0(x: u64) -> u64 {
+    let x = x as u32;
+    (x.rotate_right(7) ^ x.rotate_right(18) ^ (x >> 3)) as i32 as u64
+}
+
+pub fn sha256sig1(x: u64) -> u64 {
+    let x = x as u32;
+    (x.rotate_right(17) ^ x.rotate_right(19) ^ (x >> 10)) as i32 as u64
+}
+
+pub fn sha256sum0(x: u64) -> u64 {
+    let x = x as u32;
+    (x.rotate_right(2) ^ x.rotate_right(13) ^ x.rotate_right(22)) as i32 as u64
+}
+
+pub fn sha256sum1(x: u64) -> u64 {
+    let x = x as u32;
+    (x.rotate_right(6) ^ x.rotate_right(11) ^ x.rotate_right(25)) as i32 as u64
+}
+
+// SHA-512 functions
+pub fn sha512sig0(x: u64) -> u64 {
+    x.rotate_right(1) ^ x.rotate_right(8) ^ (x >> 7)
+}
+
+pub fn sha512sig1(x: u64) -> u64 {
+    x.rotate_right(19) ^ x.rotate_right(61) ^ (x >> 6)
+}
+
+pub fn sha512sum0(x: u64) -> u64 {
+    x.rotate_right(28) ^ x.rotate_right(34) ^ x.rotate_right(39)
+}
+
+pub fn sha512sum1(x: u64) -> u64 {
+    x.rotate_right(14) ^ x.rotate_right(18) ^ x.rotate_right(41)
+}
+
+// SM3 permutations: 32-bit results, sign-extended
+pub fn sm3p0(x: u64) -> u64 {
+    let x = x as u32;
+    (x ^ x.rotate_left(9) ^ x.rotate_left(17)) as i32 as u64
+}
+
+pub fn sm3p1(x: u64) -> u64 {
+    let x = x as u32;
+    (x ^ x.rotate_left(15) ^ x.rotate_left(23)) as i32 as u64
+}
+
+// SM4ED and SM4KS: S-box on byte `bs` of rs2, the linear transform of the round (or key
+// schedule), rotated back into position and accumulated into rs1
+pub fn sm4(rs1: u64, rs2: u64, bs: u8, key_schedule: bool) -> u64 {
+    let x = SM4_SBOX[((rs2 >> (8 * bs)) & 0xFF) as usize] as u32;
+    let y = match key_schedule {
+        false   => x ^ (x << 2) ^ (x << 10) ^ (x << 18) ^ (x << 24),
+        true    => x ^ (x << 13) ^ (x << 23),
+    };
+    (y.rotate_left(8 * bs as u32) ^ rs1 as u32) as i32 as u64
+}
+
+// BREV8: reverse the bits of every byte
+pub fn brev8(x: u64) -> u64 {
+    u64::from_le_bytes(bytes_of(x).map(|b| b.reverse_bits()))
+}
+
+// XPERM8: look up every byte of rs2 in the byte table rs1 (0 when out of range)
+pub fn xperm8(rs1: u64, rs2: u64) -> u64 {
+    (0..8).map(|i| (rs2 >> (8 * i)) & 0xFF)
+        .enumerate()
+        .filter(|&(_, index)| index < 8)
+        .fold(0, |acc, (i, index)| acc | ((rs1 >> (8 * index)) & 0xFF) << (8 * i))
+}
+
+// XPERM4: look up every nibble of rs2 in the nibble table rs1
+pub fn xperm4(rs1: u64, rs2: u64) -> u64 {
+    (0..16).map(|i| (rs2 >> (4 * i)) & 0xF)
+        .enumerate()
+        .fold(0, |acc, (i, index)| acc | ((rs1 >> (4 * index)) & 0xF) << (4 * i))
+}
@@ -1,15 +1,17 @@
+use crate::emulator::pmp::*;
+
 // Maximum number of Control and Status registers
 pub const CSR_SIZE: usize       = 4096;
 
 /* 
- * User Trap Setup
+ * User Trap Setup (N extension, which is not implemented: accesses raise IllegalInst)
  */
 pub const USTATUS: u16          = 0x000;    // User status register.
 pub const UIE: u16              = 0x004;    // User interrupt-enable register.
 pub const UTVEC: u16            = 0x005;    // User trap handler base address.
 
 /* 
- * User Trap Handling
+ * User Trap Handling (N extension, not implemented)
  */
 pub const USCRATCH: u16         = 0x040;    // Scratch register for user trap handlers.
 pub const UEPC: u16             = 0x041;    // User exception program counter.
@@ -24,6 +26,17 @@ pub const FFLAGS: u16           = 0x001;    // Floating-Point Accrued Exceptions
 pub const FRM: u16              = 0x002;    // Floating-Point Dynamic Rounding Mode.
 pub const FCSR: u16             = 0x003;    // Floating-Point Control and Status Register (frm + fflags).
 
+/*
+ * User Vector CSRs
+ */
+pub const VSTART: u16           = 0x008;    // Vector start position.
+pub const VXSAT: u16            = 0x009;    // Fixed-point accrued saturation flag.
+pub const VXRM: u16             = 0x00A;    // Fixed-point rounding mode.
+pub const VCSR: u16             = 0x00F;    // Vector control and status register (vxrm + vxsat).
+pub const VL: u16               = 0xC20;    // Vector length.
+pub const VTYPE: u16            = 0xC21;    // Vector data type register.
+pub const VLENB: u16            = 0xC22;    // VLEN/8 (vector register length in bytes).
+
 /*
  * User Counter/Timers
  */
@@ -100,12 +113,17 @@ pub const HPMCOUNTER31H: u16    = 0xC9F;
  * Supervisor Trap Setup
  */
 pub const SSTATUS: u16          = 0x100;    // Supervisor status register.
-pub const SEDELEG: u16          = 0x102;    // Supervisor exception delegation register.
-pub const SIDELEG: u16          = 0x103;    // Supervisor interrupt delegation register.
+pub const SEDELEG: u16          = 0x102;    // Supervisor exception delegation register (N extension, not implemented).
+pub const SIDELEG: u16          = 0x103;    // Supervisor interrupt delegation register (N extension, not implemented).
 pub const SIE: u16              = 0x104;    // Supervisor interrupt-enable register.
 pub const STVEC: u16            = 0x105;    // Supervisor trap handler base address.
 pub const SCOUNTEREN: u16       = 0x106;    // Supervisor counter enable.
 
+/*
+ * Supervisor Configuration
+ */
+pub const SENVCFG: u16          = 0x10A;    // Supervisor environment configuration register.
+
 /*
  * Supervisor Trap Handling
  */
@@ -115,11 +133,69 @@ pub const SCAUSE: u16           = 0x142;    // Supervisor trap cause.
 pub const STVAL: u16            = 0x143;    // Supervisor bad address or instruction.
 pub const SIP: u16              = 0x144;    // Supervisor interrupt pending.
 
+/*
+ * Supervisor Timer Compare (Sstc)
+ */
+pub const STIMECMP: u16         = 0x14D;    // Supervisor timer compare.
+pub const STIMECMPH: u16        = 0x15D;    // Upper 32 bits of stimecmp, RV32 only.
+
+/*
+ * Supervisor Count Overflow
+ */
+pub const SCOUNTOVF: u16        = 0xDA0;    // Supervisor count overflow.
+
 /*
  * Supervisor Protection and Translation
  */
 pub const SATP: u16             = 0x180;    // Supervisor address translation and protection.
 
+/*
+ * Hypervisor Trap Setup
+ */
+pub const HSTATUS: u16          = 0x600;    // Hypervisor status register.
+pub const HEDELEG: u16          = 0x602;    // Hypervisor exception delegation register.
+pub const HIDELEG: u16          = 0x603;    // Hypervisor interrupt delegation register.
+pub const HIE: u16              = 0x604;    // Hypervisor interrupt-enable register.
+pub const HCOUNTEREN: u16       = 0x606;    // Hypervisor counter enable.
+pub const HGEIE: u16            = 0x607;    // Hypervisor guest external interrupt-enable register.
+
+/*
+ * Hypervisor Trap Handling
+ */
+pub const HTVAL: u16            = 0x643;    // Hypervisor bad guest physical address.
+pub const HIP: u16              = 0x644;    // Hypervisor interrupt pending.
+pub const HVIP: u16             = 0x645;    // Hypervisor virtual interrupt pending.
+pub const HTINST: u16           = 0x64A;    // Hypervisor trap instruction (transformed).
+pub const HGEIP: u16            = 0xE12;    // Hypervisor guest external interrupt pending.
+
+/*
+ * Hypervisor Configuration
+ */
+pub const HENVCFG: u16          = 0x60A;    // Hypervisor environment configuration register.
+
+/*
+ * Hypervisor Protection and Translation
+ */
+pub const HGATP: u16            = 0x680;    // Hypervisor guest address translation and protection.
+
+/*
+ * Hypervisor Counter/Timer Virtualization Registers
+ */
+pub const HTIMEDELTA: u16       = 0x605;    // Delta for VS/VU-mode timer.
+
+/*
+ * Virtual Supervisor Registers
+ */
+pub const VSSTATUS: u16         = 0x200;    // Virtual supervisor status register.
+pub const VSIE: u16             = 0x204;    // Virtual supervisor interrupt-enable register.
+pub const VSTVEC: u16           = 0x205;    // Virtual supervisor trap handler base address.
+pub const VSSCRATCH: u16        = 0x240;    // Virtual supervisor scratch register.
+pub const VSEPC: u16            = 0x241;    // Virtual supervisor exception program counter.
+pub const VSCAUSE: u16          = 0x242;    // Virtual supervisor trap cause.
+pub const VSTVAL: u16           = 0x243;    // Virtual supervisor bad address or instruction.
+pub const VSIP: u16             = 0x244;    // Virtual supervisor interrupt pending.
+pub const VSATP: u16            = 0x280;    // Virtual supervisor address translation and protection.
+
 /*
  * Machine Information Registers
  */
@@ -127,6 +203,7 @@ pub const MVENDORID: u16        = 0xF11;    // Vendor ID.
 pub const MARCHID: u16          = 0xF12;    // Architecture ID.
 pub const MIMPID: u16           = 0xF13;    // Implementation ID.
 pub const MHARTID: u16          = 0xF14;    // Hardware thread ID.
+pub const MCONFIGPTR: u16       = 0xF15;    // Pointer to configuration data structure.
 
 /*
  * Machine Trap Setup
@@ -138,6 +215,13 @@ pub const MIDELEG: u16          = 0x303;    // Machine interrupt delegation regi
 pub const MIE: u16              = 0x304;    // Machine interrupt-enable register.
 pub const MTVEC: u16            = 0x305;    // Machine trap-handler base address.
 pub const MCOUNTEREN: u16       = 0x306;    // Machine counter enable.
+pub const MSTATUSH: u16         = 0x310;    // Additional machine status register, RV32 only.
+
+/*
+ * Machine Configuration
+ */
+pub const MENVCFG: u16          = 0x30A;    // Machine environment configuration register.
+pub const MENVCFGH: u16         = 0x31A;    // Upper 32 bits of menvcfg, RV32 only.
 
 /*
  * Machine Trap Handling
@@ -147,6 +231,8 @@ pub const MEPC: u16             = 0x341;    // Machine exception program counter
 pub const MCAUSE: u16           = 0x342;    // Machine trap cause.
 pub const MTVAL: u16            = 0x343;    // Machine bad address or instruction.
 pub const MIP: u16              = 0x344;    // Machine interrupt pending.
+pub const MTINST: u16           = 0x34A;    // Machine trap instruction (transformed).
+pub const MTVAL2: u16           = 0x34B;    // Machine bad guest physical address.
 
 /*
  * Machine Memory Protection
@@ -155,6 +241,18 @@ pub const PMPCFG0: u16          = 0x3A0;    // Physical memory protection config
 pub const PMPCFG1: u16          = 0x3A1;    // Physical memory protection configuration, RV32 only.
 pub const PMPCFG2: u16          = 0x3A2;    // Physical memory protection configuration.
 pub const PMPCFG3: u16          = 0x3A3;    // Physical memory protection configuration, RV32 only.
+pub const PMPCFG4: u16          = 0x3A4;
+pub const PMPCFG5: u16          = 0x3A5;    // Physical memory protection configuration, RV32 only.
+pub const PMPCFG6: u16          = 0x3A6;
+pub const PMPCFG7: u16          = 0x3A7;    // Physical memory protection configuration, RV32 only.
+pub const PMPCFG8: u16          = 0x3A8;
+pub const PMPCFG9: u16          = 0x3A9;    // Physical memory protection configuration, RV32 only.
+pub const PMPCFG10: u16         = 0x3AA;
+pub const PMPCFG11: u16         = 0x3AB;    // Physical memory protection configuration, RV32 only.
+pub const PMPCFG12: u16         = 0x3AC;
+pub const PMPCFG13: u16         = 0x3AD;    // Physical memory protection configuration, RV32 only.
+pub const PMPCFG14: u16         = 0x3AE;
+pub const PMPCFG15: u16         = 0x3AF;    // Physical memory protection configuration, RV32 only.
 pub const PMPADDR0: u16         = 0x3B0;    // Physical memory protection address register.
 pub const PMPADDR1: u16         = 0x3B1;
 pub const PMPADDR2: u16         = 0x3B2;
@@ -171,6 +269,54 @@ pub const PMPADDR12: u16        = 0x3BC;
 pub const PMPADDR13: u16        = 0x3BD;
 pub const PMPADDR14: u16        = 0x3BE;
 pub const PMPADDR15: u16        = 0x3BF;
+pub const PMPADDR16: u16        = 0x3C0;
+pub const PMPADDR17: u16        = 0x3C1;
+pub const PMPADDR18: u16        = 0x3C2;
+pub const PMPADDR19: u16        = 0x3C3;
+pub const PMPADDR20: u16        = 0x3C4;
+pub const PMPADDR21: u16        = 0x3C5;
+pub const PMPADDR22: u16        = 0x3C6;
+pub const PMPADDR23: u16        = 0x3C7;
+pub const PMPADDR24: u16        = 0x3C8;
+pub const PMPADDR25: u16        = 0x3C9;
+pub const PMPADDR26: u16        = 0x3CA;
+pub const PMPADDR27: u16        = 0x3CB;
+pub const PMPADDR28: u16        = 0x3CC;
+pub const PMPADDR29: u16        = 0x3CD;
+pub const PMPADDR30: u16        = 0x3CE;
+pub const PMPADDR31: u16        = 0x3CF;
+pub const PMPADDR32: u16        = 0x3D0;
+pub const PMPADDR33: u16        = 0x3D1;
+pub const PMPADDR34: u16        = 0x3D2;
+pub const PMPADDR35: u16        = 0x3D3;
+pub const PMPADDR36: u16        = 0x3D4;
+pub const PMPADDR37: u16        = 0x3D5;
+pub const PMPADDR38: u16        = 0x3D6;
+pub const PMPADDR39: u16        = 0x3D7;
+pub const PMPADDR40: u16        = 0x3D8;
+pub const PMPADDR41: u16        = 0x3D9;
+pub const PMPADDR42: u16        = 0x3DA;
+pub const PMPADDR43: u16        = 0x3DB;
+pub const PMPADDR44: u16        = 0x3DC;
+pub const PMPADDR45: u16        = 0x3DD;
+pub const PMPADDR46: u16        = 0x3DE;
+pub const PMPADDR47: u16        = 0x3DF;
+pub const PMPADDR48: u16        = 0x3E0;
+pub const PMPADDR49: u16        = 0x3E1;
+pub const PMPADDR50: u16        = 0x3E2;
+pub const PMPADDR51: u16        = 0x3E3;
+pub const PMPADDR52: u16        = 0x3E4;
+pub const PMPADDR53: u16        = 0x3E5;
+pub const PMPADDR54: u16        = 0x3E6;
+pub const PMPADDR55: u16        = 0x3E7;
+pub const PMPADDR56: u16        = 0x3E8;
+pub const PMPADDR57: u16        = 0x3E9;
+pub const PMPADDR58: u16        = 0x3EA;
+pub const PMPADDR59: u16        = 0x3EB;
+pub const PMPADDR60: u16        = 0x3EC;
+pub const PMPADDR61: u16        = 0x3ED;
+pub const PMPADDR62: u16        = 0x3EE;
+pub const PMPADDR63: u16        = 0x3EF;
 
 /*
  * Machine Counter/Timers
@@ -273,6 +419,36 @@ pub const MHPMEVENT29: u16      = 0x33D;
 pub const MHPMEVENT30: u16      = 0x33E;
 pub const MHPMEVENT31: u16      = 0x33F;
 
+pub const MHPMEVENT3H: u16      = 0x723;    // Upper 32 bits of mhpmevent3, RV32 only.
+pub const MHPMEVENT4H: u16      = 0x724;
+pub const MHPMEVENT5H: u16      = 0x725;
+pub const MHPMEVENT6H: u16      = 0x726;
+pub const MHPMEVENT7H: u16      = 0x727;
+pub const MHPMEVENT8H: u16      = 0x728;
+pub const MHPMEVENT9H: u16      = 0x729;
+pub const MHPMEVENT10H: u16     = 0x72A;
+pub const MHPMEVENT11H: u16     = 0x72B;
+pub const MHPMEVENT12H: u16     = 0x72C;
+pub const MHPMEVENT13H: u16     = 0x72D;
+pub const MHPMEVENT14H: u16     = 0x72E;
+pub const MHPMEVENT15H: u16     = 0x72F;
+pub const MHPMEVENT16H: u16     = 0x730;
+pub const MHPMEVENT17H: u16     = 0x731;
+pub const MHPMEVENT18H: u16     = 0x732;
+pub const MHPMEVENT19H: u16     = 0x733;
+pub const MHPMEVENT20H: u16     = 0x734;
+pub const MHPMEVENT21H: u16     = 0x735;
+pub const MHPMEVENT22H: u16     = 0x736;
+pub const MHPMEVENT23H: u16     = 0x737;
+pub const MHPMEVENT24H: u16     = 0x738;
+pub const MHPMEVENT25H: u16     = 0x739;
+pub const MHPMEVENT26H: u16     = 0x73A;
+pub const MHPMEVENT27H: u16     = 0x73B;
+pub const MHPMEVENT28H: u16     = 0x73C;
+pub const MHPMEVENT29H: u16     = 0x73D;
+pub const MHPMEVENT30H: u16     = 0x73E;
+pub const MHPMEVENT31H: u16     = 0x73F;
+
 /*
  * Debug/Trace Registers (shared with Debug Mode)
  */
@@ -292,15 +468,164 @@ pub const DSCRATCH1: u16        = 0x7B3;    // Debug scratch register 1.
 // Flag bit
 pub const SSTATUS_SIE:  u64 = 1 << 1;
 pub const MSTATUS_MIE:  u64 = 1 << 3;
+pub const MSTATUS_MPRV_BIT: u8  = 17;       // Modify privilege
+pub const MSTATUS_SUM_BIT:  u8  = 18;       // Permit supervisor user memory access
+pub const MSTATUS_MXR_BIT:  u8  = 19;       // Make executable readable
+pub const MSTATUS_TVM_BIT:  u8  = 20;       // Trap virtual memory: satp and SFENCE.VMA trap in S-mode
+pub const MSTATUS_TW_BIT:   u8  = 21;       // Timeout wait: WFI traps outside of M-mode
+pub const MSTATUS_GVA:  u64 = 1 << 38;      // Guest virtual address: mtval holds a guest virtual address
+pub const MSTATUS_MPV:  u64 = 1 << 39;      // Machine previous virtualization mode
 pub const MIP_USIP:     u64 = 1 << 0;       // User software interrupt
 pub const MIP_SSIP:     u64 = 1 << 1;       // Supervisor software interrupt
+pub const MIP_VSSIP:    u64 = 1 << 2;       // Virtual supervisor software interrupt
 pub const MIP_MSIP:     u64 = 1 << 3;       // Machine software interrupt
 pub const MIP_UTIP:     u64 = 1 << 4;       // User timer interrupt
 pub const MIP_STIP:     u64 = 1 << 5;       // Sueprvisor timer interrupt
+pub const MIP_VSTIP:    u64 = 1 << 6;       // Virtual supervisor timer interrupt
 pub const MIP_MTIP:     u64 = 1 << 7;       // Machine timer interrupt
 pub const MIP_UEIP:     u64 = 1 << 8;       // User external interrupt
 pub const MIP_SEIP:     u64 = 1 << 9;       // Supervisor external interrupt
+pub const MIP_VSEIP:    u64 = 1 << 10;      // Virtual supervisor external interrupt
 pub const MIP_MEIP:     u64 = 1 << 11;      // Machine external interrupt
+pub const MIP_SGEIP:    u64 = 1 << 12;      // Supervisor guest external interrupt
+pub const MIP_LCOFIP:   u64 = 1 << 13;      // Local counter overflow interrupt (Sscofpmf)
+
+// Environment configuration fields shared by menvcfg, henvcfg and senvcfg (Zicbom/Zicboz)
+pub const ENVCFG_CBIE: u64  = 0b11 << 4;    // Enables CBO.INVAL (0b01: performed as a flush, 0b11: invalidate)
+pub const ENVCFG_CBCFE: u64 = 1 << 6;       // Enables CBO.CLEAN and CBO.FLUSH
+pub const ENVCFG_CBZE: u64  = 1 << 7;       // Enables CBO.ZERO
+const ENVCFG_CBIE_RESERVED: u64 = 0b10 << 4;
+const ENVCFG_WRITABLE: u64  = ENVCFG_CBIE | ENVCFG_CBCFE | ENVCFG_CBZE;
+
+// Machine environment configuration (menvcfg)
+pub const MENVCFG_STCE: u64 = 1 << 63;      // Enables stimecmp (Sstc)
+const MENVCFG_WRITABLE: u64 = MENVCFG_STCE | ENVCFG_WRITABLE;
+
+// Events counted by mhpmcounter3..31, selected by mhpmevent3..31
+pub const HPM_EVENT_NONE:       u64 = 0;
+pub const HPM_EVENT_LOAD:       u64 = 1;    // Loads retired
+pub const HPM_EVENT_STORE:      u64 = 2;    // Stores retired
+pub const HPM_EVENT_BRANCH:     u64 = 3;    // Conditional branches retired
+pub const HPM_EVENT_TLB_MISS:   u64 = 4;    // Page-table walks (there is no TLB, so every translation misses)
+pub const HPM_EVENT_TRAP:       u64 = 5;    // Exceptions and interrupts taken
+
+// mhpmevent fields (Sscofpmf)
+pub const MHPMEVENT_OF:     u64 = 1 << 63;  // Overflowed: set when the counter wraps, suppresses further interrupts
+pub const MHPMEVENT_MINH:   u64 = 1 << 62;  // Do not count in M-mode
+pub const MHPMEVENT_SINH:   u64 = 1 << 61;  // Do not count in S-mode
+pub const MHPMEVENT_UINH:   u64 = 1 << 60;  // Do not count in U-mode
+const MHPMEVENT_EVENT: u64      = (1 << 56) - 1;
+
+// Software-writable fields (mstatus: SIE/MIE/SPIE/MPIE/SPP/VS/MPP/FS/MPRV/SUM/MXR/TVM/TW/TSR/GVA/MPV).
+// Without the N extension there are no user-level interrupts, so UIE, UPIE and the U-level interrupt bits are zero
+const MSTATUS_WRITABLE: u64     = 0xC0_007E_7FAA;
+const SSTATUS_MASK: u64         = 0x8000_0003_000D_E762;
+const MSTATUS_MPP: u64          = 0b11 << 11;
+pub const MSTATUS_VS: u64       = 0b11 << 9;    // Vector state: Off, Initial, Clean or Dirty
+pub const MSTATUS_FS: u64       = 0b11 << 13;   // Floating-point state: Off, Initial, Clean or Dirty
+const MSTATUS_SD: u64           = 1 << 63;
+pub const MSTATUS_UXL: u64      = 0b11 << 32;   // U-mode XLEN (1: 32, 2: 64)
+pub const MSTATUS_SXL: u64      = 0b11 << 34;   // S-mode XLEN (1: 32, 2: 64)
+const MSTATUSH_MASK: u64        = MSTATUS_GVA | MSTATUS_MPV;    // Fields above bit 31 that RV32 shows in mstatush
+const SIE_MASK: u64             = 0x2222;   // S-mode software, timer, external and counter-overflow interrupts
+const MIE_WRITABLE: u64         = 0x3EEE;   // S/VS/M software, timer and external, guest external and counter-overflow interrupts
+const MIP_WRITABLE: u64         = 0x2226;   // M-mode pending bits are only set by the hardware
+const MEDELEG_WRITABLE: u64     = 0xF0_B7FF;    // Every exception but ECALL from M-mode
+const MIDELEG_WRITABLE: u64     = 0x2222;   // S-mode and counter-overflow interrupts
+const MIDELEG_HYPERVISOR: u64   = 0x1444;   // VS-level and guest external interrupts are always delegated to HS-mode
+const COUNTEREN_WRITABLE: u64   = 0xFFFF_FFFF;
+const MCOUNTINHIBIT_WRITABLE: u64   = 0xFFFF_FFFD;  // There is no TM bit: time can not be inhibited
+
+// Hypervisor status (hstatus)
+pub const HSTATUS_GVA:  u64 = 1 << 6;       // Guest virtual address: stval holds a guest virtual address
+pub const HSTATUS_SPV:  u64 = 1 << 7;       // Supervisor previous virtualization mode
+pub const HSTATUS_SPVP: u64 = 1 << 8;       // Supervisor previous virtual privilege (HLV/HSV privilege)
+pub const HSTATUS_HU:   u64 = 1 << 9;       // Hypervisor in U-mode: HLV/HSV are allowed in U-mode
+pub const HSTATUS_VTVM: u64 = 1 << 20;      // Virtual TVM: vsatp and SFENCE.VMA trap in VS-mode
+pub const HSTATUS_VTW:  u64 = 1 << 21;      // Virtual TW: WFI traps in VS-mode
+pub const HSTATUS_VTSR: u64 = 1 << 22;      // Virtual TSR: SRET traps in VS-mode
+const HSTATUS_WRITABLE: u64     = HSTATUS_GVA | HSTATUS_SPV | HSTATUS_SPVP | HSTATUS_HU | HSTATUS_VTVM | HSTATUS_VTW | HSTATUS_VTSR;
+const HSTATUS_VSXL: u64         = 0b11 << 32;   // VS-mode XLEN (1: 32, 2: 64)
+const HSTATUS_VSXL_64: u64      = 2 << 32;  // VS-mode XLEN = 64
+const VSSTATUS_WRITABLE: u64    = SSTATUS_MASK & MSTATUS_WRITABLE;
+const VS_INTERRUPTS: u64        = MIP_VSSIP | MIP_VSTIP | MIP_VSEIP;
+const HEDELEG_WRITABLE: u64     = 0xB1FF;   // Exceptions raised below HS-mode that VS-mode can handle
+const HGATP_PPN: u64            = 0xFFF_FFFF_FFFC;  // The G-stage root page table is 16KiB aligned
+
+// Machine ISA (misa)
+pub const MISA_MXL_32: u64      = 1 << 62;  // XLEN = 32
+pub const MISA_MXL_64: u64      = 2 << 62;  // XLEN = 64
+const MISA_MXL: u64             = 3 << 62;
+pub const MISA_EXTENSIONS: &str = "IMABCHSUV";  // Extensions supported by the hart (B: Zba, Zbb and Zbs)
+pub const MISA_EXTENSIONS_RV32: &str = "IMACSU";    // Extensions supported by an RV32 hart
+pub const MISA_EXTENSIONS_RV32E: &str = "EMACSU";   // RV32E: the H and V extensions need the I base
+pub const MISA_EXTENSIONS_RV64E: &str = "EMABCSU";
+const MISA_WRITABLE: &str       = "MAB";    // Extensions that can be disabled at runtime
+
+// Multi-letter extensions in canonical order, with the misa bit they depend on
+// (Zbkb shares its rotates, logic-with-negate and rev8 with Zbb, so it follows misa.B too)
+const ISA_EXTENSIONS: [(&str, Option<char>); 23] = [
+    ("zicbom",      None),
+    ("zicbop",      None),
+    ("zicboz",      None),
+    ("zicntr",      None),
+    ("zicond",      None),
+    ("zicsr",       None),
+    ("zifencei",    None),
+    ("zihintpause", None),
+    ("zihpm",       None),
+    ("zba",         Some('B')),
+    ("zbb",         Some('B')),
+    ("zbc",         None),
+    ("zbkb",        Some('B')),
+    ("zbkc",        None),
+    ("zbkx",        None),
+    ("zbs",         Some('B')),
+    ("zknd",        None),
+    ("zkne",        None),
+    ("zknh",        None),
+    ("zksed",       None),
+    ("zksh",        None),
+    ("sscofpmf",    None),
+    ("sstc",        None),
+];
+
+// Multi-letter extensions that are only implemented for XLEN=64, and so not advertised by an RV32 hart
+const RV64_ONLY_EXTENSIONS: [&str; 9] = ["zbc", "zbkb", "zbkc", "zbkx", "zknd", "zkne", "zknh", "zksed", "zksh"];
+
+// misa bit of the extension `ext`
+pub fn misa_bit(ext: char) -> u64 {
+    1 << (ext as u8 - b'A')
+}
+
+fn misa_bits(extensions: &str) -> u64 {
+    extensions.chars().fold(0, |bits, ext| bits | misa_bit(ext))
+}
+
+// The 64-bit CSR whose upper 32 bits the RV32-only CSR `csr` holds
+fn high_half_of(csr: u16) -> Option<u16> {
+    match csr {
+        CYCLEH ..= HPMCOUNTER31H        |
+        MCYCLEH ..= MHPMCOUNTER31H      => Some(csr - 0x80),
+        MHPMEVENT3H ..= MHPMEVENT31H    => Some(csr - MHPMEVENT3H + MHPMEVENT3),
+        STIMECMPH                       => Some(STIMECMP),
+        MENVCFGH                        => Some(MENVCFG),
+        _                               => None,
+    }
+}
+
+// Address-translation modes (satp.MODE)
+pub const SATP_MODE_BARE:   u64 = 0;        // No translation or protection
+pub const SATP_MODE_SV32:   u64 = 1;        // Page-based 32-bit virtual addressing (RV32, satp.MODE is bit 31)
+pub const SATP_MODE_SV39:   u64 = 8;        // Page-based 39-bit virtual addressing
+pub const SATP_MODE_SV48:   u64 = 9;        // Page-based 48-bit virtual addressing
+pub const SATP_MODE_SV57:   u64 = 10;       // Page-based 57-bit virtual addressing
+
+// G-stage translation modes (hgatp.MODE): satp modes with two extra bits of guest physical address
+pub const HGATP_MODE_BARE:      u64 = 0;    // No G-stage translation or protection
+pub const HGATP_MODE_SV39X4:    u64 = 8;    // Page-based 41-bit guest physical addressing
+pub const HGATP_MODE_SV48X4:    u64 = 9;    // Page-based 50-bit guest physical addressing
+pub const HGATP_MODE_SV57X4:    u64 = 10;   // Page-based 59-bit guest physical addressing
 
 // Privilege levels
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
@@ -315,20 +640,144 @@ pub enum PrivLevel {
 pub struct Csr {
     csr: [u64; CSR_SIZE],
     pub priv_level: PrivLevel,
+    pub virt: bool,                 // Virtualization mode (V): S and U are VS-mode and VU-mode
+    pub pmp_entries: usize,         // Number of implemented PMP entries (0, 16 or 64)
+    seip: bool,                     // mip.SEIP as written by software, which the PLIC output is OR'd into
 }
 
 impl Csr {
-    pub fn new() -> Self {
+    pub fn new(hartid: u64) -> Self {
         let mut csr = [0; CSR_SIZE];
 
-        csr[SSTATUS as usize] |= (0x2 << 34) | (0x2 << 32);     // SXL and UXL is 0x2 (XLEN = 64bit)
+        csr[MSTATUS as usize] |= (0x2 << 34) | (0x2 << 32);     // SXL and UXL is 0x2 (XLEN = 64bit)
+        csr[MISA as usize] = MISA_MXL_64 | misa_bits(MISA_EXTENSIONS);
+        csr[MHARTID as usize] = hartid;
+        csr[HSTATUS as usize] = HSTATUS_VSXL_64;
+        csr[VSSTATUS as usize] = 0x2 << 32;     // UXL is 0x2 (XLEN = 64bit)
 
         Csr {
             csr: csr,   
             priv_level: PrivLevel::MACHINE,
+            virt: false,
+            pmp_entries: 16,
+            seip: false,
         }
     }
     
+    // Make this an RV32 hart (MXL=1), or an RV64 hart again, with the extensions each supports.
+    // SXL and UXL follow MXL: only an RV64 hart can run S-mode or U-mode with another XLEN
+    pub fn set_mxlen(&mut self, mxlen: usize) {
+        let mxl = match mxlen {
+            32  => MISA_MXL_32,
+            _   => MISA_MXL_64,
+        };
+        let xl = mxl >> 62;
+        self.csr[MISA as usize] = mxl | (self.csr[MISA as usize] & misa_bit('E'));
+        self.csr[MISA as usize] |= misa_bits(self.misa_extensions());
+        self.csr[MSTATUS as usize] = (self.csr[MSTATUS as usize] & !(MSTATUS_SXL | MSTATUS_UXL)) | (xl << 34) | (xl << 32);
+        self.csr[HSTATUS as usize] = (self.csr[HSTATUS as usize] & !HSTATUS_VSXL) | (xl << 32);
+        self.csr[VSSTATUS as usize] = (self.csr[VSSTATUS as usize] & !MSTATUS_UXL) | (xl << 32);
+    }
+
+    // Reduce the base ISA to RV32E or RV64E, which only has the registers x0-x15
+    pub fn set_embedded(&mut self) {
+        self.csr[MISA as usize] = (self.csr[MISA as usize] & MISA_MXL) | misa_bit('E');
+        self.csr[MISA as usize] |= misa_bits(self.misa_extensions());
+    }
+
+    // Native XLEN of the hart (misa.MXL), which is also the width of every CSR
+    pub fn mxlen(&self) -> usize {
+        16 << (self.csr[MISA as usize] >> 62)
+    }
+
+    // Effective XLEN of the current privilege mode: MXL in M-mode, SXL in S-mode and UXL in U-mode,
+    // taken from hstatus.VSXL and vsstatus.UXL in VS-mode and VU-mode
+    pub fn xlen(&self) -> usize {
+        let xl = match (self.priv_level, self.virt) {
+            (PrivLevel::SUPERVISOR, false)  => self.csr[MSTATUS as usize] >> 34,
+            (PrivLevel::USER, false)        => self.csr[MSTATUS as usize] >> 32,
+            (PrivLevel::SUPERVISOR, true)   => self.csr[HSTATUS as usize] >> 32,
+            (PrivLevel::USER, true)         => self.csr[VSSTATUS as usize] >> 32,
+            _                               => self.csr[MISA as usize] >> 62,
+        };
+        16 << (xl & 0b11)
+    }
+
+    // Whether the extension `ext` is currently enabled in misa
+    pub fn has_extension(&self, ext: char) -> bool {
+        (self.csr[MISA as usize] & misa_bit(ext)) != 0
+    }
+
+    // Extensions of misa that this hart implements
+    fn misa_extensions(&self) -> &'static str {
+        match (self.mxlen(), self.has_extension('E')) {
+            (32, false) => MISA_EXTENSIONS_RV32,
+            (32, true)  => MISA_EXTENSIONS_RV32E,
+            (_, false)  => MISA_EXTENSIONS,
+            (_, true)   => MISA_EXTENSIONS_RV64E,
+        }
+    }
+
+    // ISA string of the enabled extensions (e.g. "rv64imab_zicntr_..."), as advertised in a device tree
+    pub fn isa_string(&self) -> String {
+        let mut isa = format!("rv{}", self.mxlen());
+
+        // Single-letter extensions in canonical order; S and U are privilege modes, not ISA extensions
+        for ext in "IEMAFDQCBVH".chars().filter(|&ext| self.has_extension(ext)) {
+            isa.push(ext.to_ascii_lowercase());
+        }
+
+        let rv32 = self.mxlen() == 32;
+        for (name, _) in ISA_EXTENSIONS.iter()
+            .filter(|(_, ext)| ext.is_none_or(|ext| self.has_extension(ext)))
+            .filter(|(name, _)| !(rv32 && RV64_ONLY_EXTENSIONS.contains(name))) {
+            isa.push('_');
+            isa.push_str(name);
+        }
+
+        isa
+    }
+
+    // Whether `csr` exists on this hart; accessing any other CSR raises an illegal instruction exception
+    pub fn is_implemented(&self, csr: u16) -> bool {
+        let rv32 = self.mxlen() == 32;
+
+        // The hypervisor and vector CSRs exist only with their extension
+        if !self.has_extension('H') && matches!(csr,
+            HSTATUS | HEDELEG | HIDELEG | HIE | HCOUNTEREN | HGEIE | HTIMEDELTA | HENVCFG    |
+            HTVAL | HIP | HVIP | HTINST | HGEIP | HGATP                                     |
+            VSSTATUS | VSIE | VSTVEC | VSSCRATCH | VSEPC | VSCAUSE | VSTVAL | VSIP | VSATP  |
+            MTINST | MTVAL2
+        ) {
+            return false;
+        }
+        // There is no scalar floating-point unit: the floating-point CSRs serve the vector unit
+        if !self.has_extension('V') && matches!(csr, VSTART | VXSAT | VXRM | VCSR | VL | VTYPE | VLENB | FFLAGS | FRM | FCSR) {
+            return false;
+        }
+
+        matches!(csr,
+            FFLAGS | FRM | FCSR                                                             |
+            VSTART | VXSAT | VXRM | VCSR | VL | VTYPE | VLENB                               |
+            CYCLE ..= HPMCOUNTER31                                                          |
+            SSTATUS | SIE | STVEC | SCOUNTEREN | SENVCFG                |
+            SSCRATCH | SEPC | SCAUSE | STVAL | SIP | SATP | STIMECMP                        |
+            HSTATUS | HEDELEG | HIDELEG | HIE | HCOUNTEREN | HGEIE | HTIMEDELTA | HENVCFG    |
+            HTVAL | HIP | HVIP | HTINST | HGEIP | HGATP                                     |
+            VSSTATUS | VSIE | VSTVEC | VSSCRATCH | VSEPC | VSCAUSE | VSTVAL | VSIP | VSATP  |
+            MVENDORID | MARCHID | MIMPID | MHARTID | MCONFIGPTR                             |
+            MSTATUS | MISA | MEDELEG | MIDELEG | MIE | MTVEC | MCOUNTEREN                   |
+            MSCRATCH | MEPC | MCAUSE | MTVAL | MIP | MTINST | MTVAL2 | MENVCFG              |
+            PMPADDR0 ..= PMPADDR63                                                          |
+            MCYCLE | MINSTRET | MHPMCOUNTER3 ..= MHPMCOUNTER31 | SCOUNTOVF                  |
+            MCOUNTINHIBIT | MHPMEVENT3 ..= MHPMEVENT31
+        ) || ((PMPCFG0 ..= PMPCFG15).contains(&csr) && (rv32 || csr.is_multiple_of(2)))    // pmpcfg1, 3, ... are RV32 only
+          || (rv32 && matches!(csr,
+            CYCLEH ..= HPMCOUNTER31H | STIMECMPH | MSTATUSH | MENVCFGH                      |
+            MCYCLEH | MINSTRETH | MHPMCOUNTER3H ..= MHPMCOUNTER31H | MHPMEVENT3H ..= MHPMEVENT31H
+        ))
+    }
+
     pub fn set_priv_level(&mut self, priv_level: u8) {
         match priv_level {
             0b00    =>  self.priv_level = PrivLevel::USER,
@@ -338,15 +787,61 @@ impl Csr {
         }
     }
 
-    pub fn write_bit(&mut self, csr: u16, digit: u8, bit: bool) {
-        let addr = match csr {
+    // Privilege level used for loads and stores (mstatus.MPRV substitutes mstatus.MPP in M-mode)
+    pub fn effective_priv_level(&self) -> PrivLevel {
+        if self.priv_level == PrivLevel::MACHINE && self.read_bit(MSTATUS, MSTATUS_MPRV_BIT) {
+            match self.read_bits(MSTATUS, 11..12+1) {
+                0b00    => PrivLevel::USER,
+                0b01    => PrivLevel::SUPERVISOR,
+                _       => PrivLevel::MACHINE,
+            }
+        }
+        else {
+            self.priv_level
+        }
+    }
+
+    // Virtualization mode used for loads and stores (mstatus.MPRV substitutes mstatus.MPV in M-mode)
+    pub fn effective_virt(&self) -> bool {
+        match self.priv_level == PrivLevel::MACHINE && self.read_bit(MSTATUS, MSTATUS_MPRV_BIT) {
+            true    => self.effective_priv_level() != PrivLevel::MACHINE && (self.csr[MSTATUS as usize] & MSTATUS_MPV) != 0,
+            false   => self.virt,
+        }
+    }
+
+    // With V=1, the supervisor CSRs are substituted by their virtual supervisor counterparts
+    fn virtual_alias(&self, csr: u16) -> u16 {
+        if !self.virt {
+            return csr;
+        }
+        match csr {
+            SSTATUS     => VSSTATUS,
+            SIE         => VSIE,
+            STVEC       => VSTVEC,
+            SSCRATCH    => VSSCRATCH,
+            SEPC        => VSEPC,
+            SCAUSE      => VSCAUSE,
+            STVAL       => VSTVAL,
+            SIP         => VSIP,
+            SATP        => VSATP,
+            _           => csr,
+        }
+    }
+
+    // Register that holds the bits of `csr` for the bit accessors
+    fn bit_field_addr(&self, csr: u16) -> u16 {
+        match self.virtual_alias(csr) {
             FFLAGS  |
             FRM     => FCSR,
             SSTATUS => MSTATUS,
             SIE     => MIE,
             SIP     => MIP,
-            _       => csr,
-        };
+            csr     => csr,
+        }
+    }
+
+    pub fn write_bit(&mut self, csr: u16, digit: u8, bit: bool) {
+        let addr = self.bit_field_addr(csr);
         
         if bit {
             self.csr[addr as usize] |= 1 << digit;
@@ -357,26 +852,12 @@ impl Csr {
     }
 
     pub fn read_bit(&self, csr: u16, digit: u8) -> bool {
-        let addr = match csr {
-            FFLAGS  |
-            FRM     => FCSR,
-            SSTATUS => MSTATUS,
-            SIE     => MIE,
-            SIP     => MIP,
-            _       => csr,
-        };
+        let addr = self.bit_field_addr(csr);
         ((self.read(addr) >> digit) & 0b1) == 1
     }
 
     pub fn write_bits(&mut self, csr: u16, digits: std::ops::Range<u8>, bits: u64) {
-        let addr = match csr {
-            FFLAGS  |
-            FRM     => FCSR,
-            SSTATUS => MSTATUS,
-            SIE     => MIE,
-            SIP     => MIP,
-            _       => csr,
-        };
+        let addr = self.bit_field_addr(csr);
 
         for (i, digit) in digits.enumerate() {
             self.write_bit(addr, digit as u8, ((bits >> i) & 0b1) == 1);
@@ -384,14 +865,7 @@ impl Csr {
     }
 
     pub fn read_bits(&self, csr: u16, digits: std::ops::Range<u8>) -> u64 {
-        let addr = match csr {
-            FFLAGS  |
-            FRM     => FCSR,
-            SSTATUS => MSTATUS,
-            SIE     => MIE,
-            SIP     => MIP,
-            _       => csr,
-        };
+        let addr = self.bit_field_addr(csr);
 
         let mut bits = 0;
 
@@ -404,40 +878,452 @@ impl Csr {
     }
 
     pub fn write(&mut self, csr: u16, data: u64) {
+        let csr = self.virtual_alias(csr);
         match csr {
             FFLAGS  => {
 				self.csr[FCSR as usize] &= !0x1f;
 				self.csr[FCSR as usize] |= data & 0x1f;
+				self.set_fs_dirty();
             },
             FRM     =>{
-				self.csr[FRM as usize] &= !0xe0;
-				self.csr[FRM as usize] |= (data << 5) & 0xe0;
-			},
-            SSTATUS =>{
-				self.csr[MSTATUS as usize] &= !0x80000003000de162;
-				self.csr[MSTATUS as usize] |= data & 0x80000003000de162;
+				self.csr[FCSR as usize] &= !0xe0;
+				self.csr[FCSR as usize] |= (data << 5) & 0xe0;
+				self.set_fs_dirty();
 			},
+            FCSR    => {
+                self.csr[csr as usize] = data & 0xff;
+                self.set_fs_dirty();
+            },
+            SSTATUS => {
+                let mstatus = (self.csr[MSTATUS as usize] & !SSTATUS_MASK) | (data & SSTATUS_MASK);
+                self.write(MSTATUS, mstatus);
+            },
 			SIE     => {
-				self.csr[MIE as usize] &= !0x222;
-				self.csr[MIE as usize] |= data & 0x222;
+				self.csr[MIE as usize] &= !SIE_MASK;
+				self.csr[MIE as usize] |= data & SIE_MASK;
 			},
-			SIP     => {
-				self.csr[MIP as usize] &= !0x222;
-				self.csr[MIP as usize] |= data & 0x222;
+			SIP     => self.write_masked(MIP, data, SIE_MASK & !MIP_SEIP & self.stip_writable()),
+            HSTATUS => self.write_masked(csr, data, HSTATUS_WRITABLE),
+            HEDELEG => self.write_masked(csr, data, HEDELEG_WRITABLE),
+            HIDELEG => self.write_masked(csr, data, VS_INTERRUPTS),
+            HIE     => self.write_masked(MIE, data, VS_INTERRUPTS | MIP_SGEIP),
+            HIP     => self.write_masked(MIP, data, MIP_VSSIP),
+            HVIP    => self.write_masked(MIP, data, VS_INTERRUPTS),
+            HCOUNTEREN  => self.write_masked(csr, data, COUNTEREN_WRITABLE),
+            HGATP   => {
+                // A write with an unsupported MODE has no effect at all, and there are no VMID bits
+                match data >> 60 {
+                    HGATP_MODE_BARE     |
+                    HGATP_MODE_SV39X4   |
+                    HGATP_MODE_SV48X4   |
+                    HGATP_MODE_SV57X4   => self.csr[csr as usize] = data & ((0xF << 60) | HGATP_PPN),
+                    _                   => (),
+                }
+            },
+            HGEIE   |
+            HGEIP   => (),      // no guest external interrupts
+            VSSTATUS    => {
+                let mut value = (self.csr[csr as usize] & !VSSTATUS_WRITABLE) | (data & VSSTATUS_WRITABLE);
+                value = self.legalize_uxl(value, data);
+                match (value & MSTATUS_FS) == MSTATUS_FS || (value & MSTATUS_VS) == MSTATUS_VS {
+                    true    => value |= MSTATUS_SD,
+                    false   => value &= !MSTATUS_SD,
+                }
+                self.csr[csr as usize] = value;
+            },
+            // vsie and vsip show the VS-level interrupts delegated by hideleg at the S-level bit positions
+            VSIE    => {
+                let mask = self.csr[HIDELEG as usize] & VS_INTERRUPTS;
+                self.write_masked(MIE, data << 1, mask);
+            },
+            VSIP    => {
+                let mask = self.csr[HIDELEG as usize] & MIP_VSSIP;
+                self.write_masked(MIP, data << 1, mask);
+            },
+            VSTART  => {
+                // vstart only holds element indices below VLEN
+                let vlen = self.csr[VLENB as usize] * 8;
+                self.csr[csr as usize] = data & (vlen - 1);
+                self.set_vs_dirty();
+            },
+            VXSAT   => {
+                self.csr[csr as usize] = data & 0b1;
+                self.set_vs_dirty();
+            },
+            VXRM    => {
+                self.csr[csr as usize] = data & 0b11;
+                self.set_vs_dirty();
+            },
+            VCSR    => {
+                self.csr[VXSAT as usize] = data & 0b1;
+                self.csr[VXRM as usize] = (data >> 1) & 0b11;
+                self.set_vs_dirty();
+            },
+            MSTATUS => self.write_mstatus(data),
+            MISA    => {
+                let writable = misa_bits(MISA_WRITABLE) & misa_bits(self.misa_extensions());
+                self.csr[csr as usize] = (self.csr[csr as usize] & !writable) | (data & writable);
+            },
+            MVENDORID   |
+            MARCHID     |
+            MIMPID      |
+            MHARTID     |
+            MCONFIGPTR  => (),      // read-only
+            MIE     => self.write_masked(csr, data, MIE_WRITABLE),
+            MIP     => {
+                self.write_masked(csr, data, MIP_WRITABLE & self.stip_writable());
+                self.seip = (data & MIP_SEIP) != 0;
+            },
+            MENVCFG |
+            HENVCFG |
+            SENVCFG => {
+                let writable = if csr == MENVCFG { MENVCFG_WRITABLE } else { ENVCFG_WRITABLE };
+                // CBIE is WARL: the reserved encoding leaves the field unchanged
+                let data = match data & ENVCFG_CBIE {
+                    ENVCFG_CBIE_RESERVED    => (data & !ENVCFG_CBIE) | (self.csr[csr as usize] & ENVCFG_CBIE),
+                    _                       => data,
+                };
+                self.write_masked(csr, data, writable);
+            },
+            MEDELEG => self.write_masked(csr, data, MEDELEG_WRITABLE),
+            MIDELEG => self.write_masked(csr, data, MIDELEG_WRITABLE),
+            MCOUNTEREN  |
+            SCOUNTEREN  => self.write_masked(csr, data, COUNTEREN_WRITABLE),
+            MCOUNTINHIBIT               => self.write_masked(csr, data, MCOUNTINHIBIT_WRITABLE),
+            MHPMEVENT3 ..= MHPMEVENT31  => {
+                // Unknown events are WARL and count nothing
+                let event = match data & MHPMEVENT_EVENT {
+                    event @ HPM_EVENT_NONE ..= HPM_EVENT_TRAP   => event,
+                    _                                           => HPM_EVENT_NONE,
+                };
+                let flags = MHPMEVENT_OF | MHPMEVENT_MINH | MHPMEVENT_SINH | MHPMEVENT_UINH;
+                self.csr[csr as usize] = (data & flags) | event;
+            },
+            MTVEC   |
+            STVEC   |
+            VSTVEC  => {
+                // MODE is WARL: only direct (0) and vectored (1) are supported
+                let mode = match data & 0b11 {
+                    0b00 | 0b01 => data & 0b11,
+                    _           => self.csr[csr as usize] & 0b11,
+                };
+                self.csr[csr as usize] = (data & !0b11) | mode;
+            },
+            MEPC    |
+            SEPC    |
+            VSEPC   => {
+                // xepc holds instruction addresses, so the bits below IALIGN are zero
+                let mask = if self.has_extension('C') { !0b1 } else { !0b11 };
+                self.csr[csr as usize] = data & mask;
+            },
+            PMPCFG0 ..= PMPCFG15    => self.write_pmpcfg(csr, data),
+            PMPADDR0 ..= PMPADDR63  => self.write_pmpaddr(csr, data),
+            SATP    |
+            VSATP   => {
+                // A write with an unsupported MODE has no effect at all
+                match data >> 60 {
+                    SATP_MODE_BARE  |
+                    SATP_MODE_SV39  |
+                    SATP_MODE_SV48  |
+                    SATP_MODE_SV57  => self.csr[csr as usize] = data,
+                    _               => (),
+                }
             },
-            MIDELEG => self.csr[csr as usize] = data & 0x666,
             _       => self.csr[csr as usize] = data,
         }
     }
 
     pub fn read(&self, csr: u16) -> u64 {
+        let csr = self.virtual_alias(csr);
         match csr {
             FFLAGS  =>  self.csr[FCSR as usize] & 0x1F,
-            FRM     => (self.csr[FRM as usize] >> 5) & 0x7,
-            SSTATUS =>  self.csr[MSTATUS as usize] & 0x80000003000DE162,
-			SIE     =>  self.csr[MIE as usize] & 0x222,
-			SIP     =>  self.csr[MIP as usize] & 0x222,
+            FRM     => (self.csr[FCSR as usize] >> 5) & 0x7,
+            SSTATUS =>  self.csr[MSTATUS as usize] & SSTATUS_MASK,
+            VCSR    => (self.csr[VXRM as usize] << 1) | self.csr[VXSAT as usize],
+			SIE     =>  self.csr[MIE as usize] & SIE_MASK,
+			SIP     =>  self.csr[MIP as usize] & SIE_MASK,
+            // The user-level counters are read-only shadows of the machine counters
+            CYCLE       |
+            INSTRET     |
+            HPMCOUNTER3 ..= HPMCOUNTER31    => self.csr[(csr - CYCLE + MCYCLE) as usize],
+            // VS-mode and VU-mode see the guest's time, offset by htimedelta
+            TIME if self.virt   => self.csr[TIME as usize].wrapping_add(self.csr[HTIMEDELTA as usize]),
+            MIDELEG =>  self.csr[MIDELEG as usize] | MIDELEG_HYPERVISOR,
+            HIE     =>  self.csr[MIE as usize] & (VS_INTERRUPTS | MIP_SGEIP),
+            HIP     =>  self.csr[MIP as usize] & (VS_INTERRUPTS | MIP_SGEIP),
+            HVIP    =>  self.csr[MIP as usize] & VS_INTERRUPTS,
+            VSIE    => (self.csr[MIE as usize] & self.csr[HIDELEG as usize] & VS_INTERRUPTS) >> 1,
+            VSIP    => (self.csr[MIP as usize] & self.csr[HIDELEG as usize] & VS_INTERRUPTS) >> 1,
+            SCOUNTOVF   => {
+                let mut ovf = 0;
+                for i in 3..32 {
+                    ovf |= (self.csr[(MHPMEVENT3 + i - 3) as usize] >> 63) << i;
+                }
+                match self.priv_level {
+                    PrivLevel::MACHINE  => ovf,
+                    _                   => ovf & self.csr[MCOUNTEREN as usize],
+                }
+            },
             _       =>  self.csr[csr as usize],
         }
     }
+
+    // CSR instructions on an RV32 hart see 32-bit CSRs: the low half of each register, with the
+    // high half of the 64-bit ones in their ...H counterparts. SD and the interrupt bit of xcause
+    // are the most significant bit, so they move to bit 31
+    pub fn read_rv32(&self, csr: u16) -> u64 {
+        let msb_to_bit31 = |data: u64| (data & 0x7FFF_FFFF) | ((data >> 63) << 31);
+        match csr {
+            MSTATUS     |
+            SSTATUS     |
+            MCAUSE      |
+            SCAUSE      => msb_to_bit31(self.read(csr)),
+            MSTATUSH    => (self.read(MSTATUS) & MSTATUSH_MASK) >> 32,
+            MISA        => (self.read(MISA) & 0x3FF_FFFF) | ((self.read(MISA) >> 62) << 30),
+            SATP        => {
+                let satp = self.read(SATP);
+                (((satp >> 60) & SATP_MODE_SV32) << 31) | (satp & 0x3F_FFFF)
+            },
+            PMPCFG0 ..= PMPCFG15    => {
+                let data = self.read(csr & !1);
+                if csr & 1 == 0 { data & 0xFFFF_FFFF } else { data >> 32 }
+            },
+            _           => match high_half_of(csr) {
+                Some(csr)   => self.read(csr) >> 32,
+                None        => self.read(csr) & 0xFFFF_FFFF,
+            },
+        }
+    }
+
+    pub fn write_rv32(&mut self, csr: u16, data: u64) {
+        let data = data & 0xFFFF_FFFF;
+        let low = |old: u64| (old & !0xFFFF_FFFF) | data;
+        let high = |old: u64| (old & 0xFFFF_FFFF) | (data << 32);
+        match csr {
+            MCAUSE      |
+            SCAUSE      => self.write(csr, (data & 0x7FFF_FFFF) | ((data >> 31) << 63)),
+            MSTATUSH    => self.write(MSTATUS, (self.read(MSTATUS) & !MSTATUSH_MASK) | ((data << 32) & MSTATUSH_MASK)),
+            // Only the supported modes (Bare and Sv32) are kept, and there are no ASID bits
+            SATP        => {
+                let mode = (data >> 31) & SATP_MODE_SV32;
+                self.csr[self.virtual_alias(SATP) as usize] = (mode << 60) | (data & 0x3F_FFFF);
+            },
+            PMPCFG0 ..= PMPCFG15    => {
+                let reg = csr & !1;
+                let old = self.read(reg);
+                self.write(reg, if csr & 1 == 0 { low(old) } else { high(old) });
+            },
+            _           => match high_half_of(csr) {
+                Some(csr)   => self.write(csr, high(self.read(csr))),
+                None        => self.write(csr, low(self.read(csr))),
+            },
+        }
+    }
+
+    // Advance mcycle or minstret by one unless it is inhibited by mcountinhibit
+    pub fn increment_counter(&mut self, counter: u16) {
+        if (self.csr[MCOUNTINHIBIT as usize] >> (counter - MCYCLE)) & 1 == 0 {
+            self.csr[counter as usize] = self.csr[counter as usize].wrapping_add(1);
+        }
+    }
+
+    // Count `count` occurrences of `event` in every hpmcounter that selects it
+    pub fn count_event(&mut self, event: u64, count: u64) {
+        let inhibit = match self.priv_level {
+            PrivLevel::MACHINE      => MHPMEVENT_MINH,
+            PrivLevel::SUPERVISOR   => MHPMEVENT_SINH,
+            _                       => MHPMEVENT_UINH,
+        };
+
+        for i in 3..32 {
+            let selector = self.csr[(MHPMEVENT3 + i - 3) as usize];
+
+            if (selector & MHPMEVENT_EVENT) != event || (selector & inhibit) != 0 {
+                continue;
+            }
+            if (self.csr[MCOUNTINHIBIT as usize] >> i) & 1 != 0 {
+                continue;
+            }
+
+            let counter = (MHPMCOUNTER3 + i - 3) as usize;
+            let (value, overflow) = self.csr[counter].overflowing_add(count);
+            self.csr[counter] = value;
+
+            // Sscofpmf: the first overflow sets OF and raises a local counter-overflow interrupt
+            if overflow && (selector & MHPMEVENT_OF) == 0 {
+                self.csr[(MHPMEVENT3 + i - 3) as usize] |= MHPMEVENT_OF;
+                self.csr[MIP as usize] |= MIP_LCOFIP;
+            }
+        }
+    }
+
+    // Latch the current mtime into time and, with Sstc enabled, drive STIP from stimecmp
+    pub fn update_time(&mut self, time: u64) {
+        self.csr[TIME as usize] = time;
+
+        if (self.csr[MENVCFG as usize] & MENVCFG_STCE) != 0 {
+            match time >= self.csr[STIMECMP as usize] {
+                true    => self.csr[MIP as usize] |= MIP_STIP,
+                false   => self.csr[MIP as usize] &= !MIP_STIP,
+            }
+        }
+    }
+
+    // The time at which stimecmp raises STIP, if Sstc is enabled
+    pub fn stimecmp_deadline(&self) -> Option<u64> {
+        match (self.csr[MENVCFG as usize] & MENVCFG_STCE) != 0 {
+            true    => Some(self.csr[STIMECMP as usize]),
+            false   => None,
+        }
+    }
+
+    // With Sstc enabled STIP reflects stimecmp and can not be written by software
+    fn stip_writable(&self) -> u64 {
+        match (self.csr[MENVCFG as usize] & MENVCFG_STCE) != 0 {
+            true    => !MIP_STIP,
+            false   => !0,
+        }
+    }
+
+    // Whether the `field` state (mstatus.FS or VS) is not Off, and with V=1 not Off in vsstatus either
+    fn state_enabled(&self, field: u64) -> bool {
+        (self.csr[MSTATUS as usize] & field) != 0 && (!self.virt || (self.csr[VSSTATUS as usize] & field) != 0)
+    }
+
+    // Mark the `field` state (mstatus.FS or VS) as modified, in vsstatus as well with V=1
+    fn set_state_dirty(&mut self, field: u64) {
+        self.csr[MSTATUS as usize] |= field | MSTATUS_SD;
+        if self.virt {
+            self.csr[VSSTATUS as usize] |= field | MSTATUS_SD;
+        }
+    }
+
+    // Whether vector instructions and CSRs may be used: misa.V is set and the vector state is not Off
+    pub fn vector_enabled(&self) -> bool {
+        self.has_extension('V') && self.state_enabled(MSTATUS_VS)
+    }
+
+    // Whether floating-point instructions and CSRs may be used: the floating-point state is not Off
+    pub fn float_enabled(&self) -> bool {
+        self.state_enabled(MSTATUS_FS)
+    }
+
+    // Mark the vector state as modified
+    pub fn set_vs_dirty(&mut self) {
+        self.set_state_dirty(MSTATUS_VS);
+    }
+
+    // Mark the floating-point state (f registers and fcsr) as modified
+    pub fn set_fs_dirty(&mut self) {
+        self.set_state_dirty(MSTATUS_FS);
+    }
+
+    // Update a CSR as the hardware does, bypassing the rules for software writes
+    // (e.g. mip bits that only devices may set or clear)
+    pub fn write_hw(&mut self, csr: u16, data: u64) {
+        self.csr[csr as usize] = data;
+    }
+
+    // mip.SEIP as written by software. The hart ORs the PLIC's S-context output into it.
+    pub fn software_seip(&self) -> u64 {
+        match self.seip {
+            true    => MIP_SEIP,
+            false   => 0,
+        }
+    }
+
+    // CSRRS and CSRRC modify the SEIP written by software, not the one that reads return
+    pub fn read_modify_write_base(&self, csr: u16, data: u64) -> u64 {
+        match csr {
+            MIP => (data & !MIP_SEIP) | self.software_seip(),
+            _   => data,
+        }
+    }
+
+    fn write_masked(&mut self, csr: u16, data: u64, mask: u64) {
+        self.csr[csr as usize] = (self.csr[csr as usize] & !mask) | (data & mask);
+    }
+
+    fn write_mstatus(&mut self, data: u64) {
+        let old = self.csr[MSTATUS as usize];
+        let mut value = (old & !MSTATUS_WRITABLE) | (data & MSTATUS_WRITABLE);
+
+        // MPP is WARL: the reserved privilege level 2 leaves it unchanged
+        if (value & MSTATUS_MPP) == (PrivLevel::RESERVED as u64) << 11 {
+            value = (value & !MSTATUS_MPP) | (old & MSTATUS_MPP);
+        }
+        value = self.legalize_uxl(value, data);
+
+        // SD summarizes whether FS or VS is dirty
+        if (value & MSTATUS_FS) == MSTATUS_FS || (value & MSTATUS_VS) == MSTATUS_VS {
+            value |= MSTATUS_SD;
+        }
+        else {
+            value &= !MSTATUS_SD;
+        }
+
+        self.csr[MSTATUS as usize] = value;
+    }
+
+    // UXL is WARL: an RV64 hart runs U-mode with XLEN 32 or 64, and other values leave it unchanged
+    fn legalize_uxl(&self, value: u64, data: u64) -> u64 {
+        match (self.mxlen(), (data & MSTATUS_UXL) >> 32) {
+            (64, 1) |
+            (64, 2) => (value & !MSTATUS_UXL) | (data & MSTATUS_UXL),
+            _       => value,
+        }
+    }
+
+    // Locked entries keep their value, W without R is reserved, and unimplemented entries are zero
+    fn write_pmpcfg(&mut self, csr: u16, data: u64) {
+        let reg = (csr - PMPCFG0) as usize;
+
+        // Only the even-numbered pmpcfg registers exist on RV64
+        if !reg.is_multiple_of(2) {
+            return;
+        }
+
+        let mut value = 0;
+        for i in 0..8 {
+            let index = reg / 2 * 8 + i;
+            let old = (self.csr[csr as usize] >> (i * 8)) as u8;
+            let mut cfg = (data >> (i * 8)) as u8 & !0x60;
+
+            if index >= self.pmp_entries {
+                cfg = 0;
+            }
+            else if (old & PMP_L) != 0 {
+                cfg = old;
+            }
+            else if (cfg & PMP_R) == 0 && (cfg & PMP_W) != 0 {
+                cfg &= !(PMP_R | PMP_W);
+            }
+
+            value |= (cfg as u64) << (i * 8);
+        }
+
+        self.csr[csr as usize] = value;
+    }
+
+    // pmpaddr is read-only while its entry is locked, or while the next entry is a locked TOR region
+    fn write_pmpaddr(&mut self, csr: u16, data: u64) {
+        let index = (csr - PMPADDR0) as usize;
+
+        if index >= self.pmp_entries {
+            return;
+        }
+
+        if (pmpcfg(self, index) & PMP_L) != 0 {
+            return;
+        }
+
+        if index + 1 < self.pmp_entries {
+            let next = pmpcfg(self, index + 1);
+            if (next & PMP_L) != 0 && (next & PMP_A) == PMP_A_TOR {
+                return;
+            }
+        }
+
+        self.csr[csr as usize] = data & PMPADDR_MASK;
+    }
 }
\ No newline at end of file
@@ -0,0 +1,274 @@
+/*
+ * Disk images backing the virtio block device
+ *
+ * Images are accessed with positional I/O, so they are never held in memory and
+ * may be of any size. By default writes only live in memory and are lost when the
+ * emulator exits. An overlay keeps every write in a separate file, so that one image
+ * can be shared by any number of emulators.
+ */
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::{ File, OpenOptions };
+use std::io::{ Error, ErrorKind, Result };
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(windows)]
+use std::os::windows::fs::FileExt;
+
+pub const SECTOR_SIZE: usize = 512;
+
+/*
+ * Overlay file layout
+ *
+ * +----------------+-----------------------------------------------------------+
+ * |     Offset     | Description                                               |
+ * +----------------+-----------------------------------------------------------+
+ * | 0x000          | OVERLAY_MAGIC                                             |
+ * | 0x008          | Size of the base image in bytes (little endian)           |
+ * | 0x200          | One bit per sector, set once the sector has been written  |
+ * | data_offset    | Written sectors, at their offset in the image             |
+ * +----------------+-----------------------------------------------------------+
+ *
+ * The bitmap is padded to a whole sector, and the data is sparse.
+ */
+const OVERLAY_MAGIC:        &[u8; 8]    = b"RVCOWOVL";
+const OVERLAY_HEADER_SIZE:  u64         = SECTOR_SIZE as u64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiskMode {
+    Volatile,           // Writes are kept in memory, and the image is never modified
+    WriteBack,          // Writes go to the image file
+    ReadOnly,           // The guest can not write, and sees VIRTIO_BLK_F_RO
+    Overlay(String),    // Writes go to the overlay file at the path, and the image is never modified
+}
+
+#[derive(Debug)]
+pub enum Disk {
+    Memory(Vec<u8>),
+    File {
+        file:       File,
+        size:       u64,
+        readonly:   bool,
+    },
+    Overlay {
+        base:       File,
+        overlay:    File,
+        size:       u64,
+        bitmap:     Vec<u8>,
+    },
+    Volatile {
+        base:       File,
+        size:       u64,
+        sectors:    HashMap<u64, Vec<u8>>,  // Written sectors by number
+    },
+}
+
+// Positional I/O. On Windows, seek_read and seek_write may be short and also move the
+// file cursor, which nothing here relies on.
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> Result<()> {
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, data: &[u8], offset: u64) -> Result<()> {
+    file.write_all_at(data, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> Result<()> {
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0)   => return Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+            Ok(len) => {
+                buf = &mut buf[len..];
+                offset += len as u64;
+            },
+            Err(error) if error.kind() == ErrorKind::Interrupted => (),
+            Err(error)  => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut data: &[u8], mut offset: u64) -> Result<()> {
+    while !data.is_empty() {
+        match file.seek_write(data, offset) {
+            Ok(0)   => return Err(Error::new(ErrorKind::WriteZero, "failed to write whole buffer")),
+            Ok(len) => {
+                data = &data[len..];
+                offset += len as u64;
+            },
+            Err(error) if error.kind() == ErrorKind::Interrupted => (),
+            Err(error)  => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+// Split the range of `len` bytes at `offset` at sector boundaries, into (position, start in the range, length)
+fn sector_chunks(offset: u64, len: usize) -> impl Iterator<Item = (u64, usize, usize)> {
+    let mut done = 0;
+    std::iter::from_fn(move || {
+        if done == len {
+            return None;
+        }
+        let pos = offset + done as u64;
+        let count = (SECTOR_SIZE - (pos as usize % SECTOR_SIZE)).min(len - done);
+        let chunk = (pos, done, count);
+        done += count;
+        Some(chunk)
+    })
+}
+
+impl Disk {
+    pub fn open(path: &str, mode: DiskMode) -> Result<Disk> {
+        let readonly = mode != DiskMode::WriteBack;
+        let file = OpenOptions::new().read(true).write(!readonly).open(path)?;
+        let size = file.metadata()?.len();
+
+        match mode {
+            DiskMode::Volatile          => Ok(Disk::Volatile { base: file, size, sectors: HashMap::new() }),
+            DiskMode::WriteBack |
+            DiskMode::ReadOnly          => Ok(Disk::File { file, size, readonly }),
+            DiskMode::Overlay(overlay)  => Disk::open_overlay(file, size, &overlay),
+        }
+    }
+
+    // Create the overlay if it is empty or does not exist, otherwise check that it belongs to the image
+    fn open_overlay(base: File, size: u64, path: &str) -> Result<Disk> {
+        let overlay = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let sectors = size.div_ceil(SECTOR_SIZE as u64) as usize;
+        let mut bitmap = vec![0; sectors.div_ceil(8)];
+
+        if overlay.metadata()?.len() == 0 {
+            let mut header = OVERLAY_MAGIC.to_vec();
+            header.extend_from_slice(&size.to_le_bytes());
+            write_all_at(&overlay, &header, 0)?;
+        }
+        else {
+            let mut header = [0; 16];
+            read_exact_at(&overlay, &mut header, 0)?;
+            if &header[0..8] != OVERLAY_MAGIC || u64::from_le_bytes(header[8..16].try_into().unwrap()) != size {
+                return Err(Error::new(ErrorKind::InvalidData, format!("{} is not an overlay of this image", path)));
+            }
+            read_exact_at(&overlay, &mut bitmap, OVERLAY_HEADER_SIZE)?;
+        }
+
+        Ok(Disk::Overlay { base, overlay, size, bitmap })
+    }
+
+    pub fn size(&self) -> u64 {
+        match self {
+            Disk::Memory(data)          => data.len() as u64,
+            Disk::File { size, .. }     |
+            Disk::Overlay { size, .. }  |
+            Disk::Volatile { size, .. } => *size,
+        }
+    }
+
+    pub fn is_readonly(&self) -> bool {
+        match self {
+            Disk::File { readonly, .. } => *readonly,
+            _                           => false,
+        }
+    }
+
+    // Offset in the overlay of the data of the image at `offset`
+    fn overlay_data_offset(bitmap: &[u8], offset: u64) -> u64 {
+        let bitmap_size = (bitmap.len() as u64).next_multiple_of(SECTOR_SIZE as u64);
+        OVERLAY_HEADER_SIZE + bitmap_size + offset
+    }
+
+    // `buf` must not extend past the end of the image
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        match self {
+            Disk::Memory(data)  => {
+                let offset = offset as usize;
+                buf.copy_from_slice(&data[offset..offset + buf.len()]);
+                Ok(())
+            },
+            Disk::File { file, .. } => read_exact_at(file, buf, offset),
+            // Sector by sector, from the overlay if it has been written there
+            Disk::Overlay { base, overlay, bitmap, .. } => {
+                for (pos, start, count) in sector_chunks(offset, buf.len()) {
+                    let sector = (pos / SECTOR_SIZE as u64) as usize;
+                    let chunk = &mut buf[start..start + count];
+                    match (bitmap[sector / 8] >> (sector % 8)) & 1 {
+                        1   => read_exact_at(overlay, chunk, Disk::overlay_data_offset(bitmap, pos))?,
+                        _   => read_exact_at(base, chunk, pos)?,
+                    }
+                }
+                Ok(())
+            },
+            Disk::Volatile { base, sectors, .. } => {
+                for (pos, start, count) in sector_chunks(offset, buf.len()) {
+                    let chunk = &mut buf[start..start + count];
+                    match sectors.get(&(pos / SECTOR_SIZE as u64)) {
+                        Some(sector)    => {
+                            let first = pos as usize % SECTOR_SIZE;
+                            chunk.copy_from_slice(&sector[first..first + count]);
+                        },
+                        None            => read_exact_at(base, chunk, pos)?,
+                    }
+                }
+                Ok(())
+            },
+        }
+    }
+
+    // `data` must not extend past the end of the image. Writes to an overlay or a volatile disk must be whole sectors.
+    pub fn write_at(&mut self, data: &[u8], offset: u64) -> Result<()> {
+        match self {
+            Disk::Memory(disk)  => {
+                let offset = offset as usize;
+                disk[offset..offset + data.len()].copy_from_slice(data);
+                Ok(())
+            },
+            Disk::File { readonly: true, .. }   => Err(Error::new(ErrorKind::PermissionDenied, "read-only disk")),
+            Disk::File { file, .. }             => write_all_at(file, data, offset),
+            // The data is written before the bitmap marks it valid
+            Disk::Overlay { overlay, bitmap, .. } => {
+                Disk::check_aligned(data, offset)?;
+                write_all_at(overlay, data, Disk::overlay_data_offset(bitmap, offset))?;
+
+                let first = offset as usize / SECTOR_SIZE;
+                let last = first + data.len() / SECTOR_SIZE;
+                for sector in first .. last {
+                    bitmap[sector / 8] |= 1 << (sector % 8);
+                }
+                if first < last {
+                    let bytes = first / 8 .. (last - 1) / 8 + 1;
+                    write_all_at(overlay, &bitmap[bytes.clone()], OVERLAY_HEADER_SIZE + bytes.start as u64)?;
+                }
+                Ok(())
+            },
+            Disk::Volatile { sectors, .. } => {
+                Disk::check_aligned(data, offset)?;
+                for (i, sector) in data.chunks(SECTOR_SIZE).enumerate() {
+                    sectors.insert(offset / SECTOR_SIZE as u64 + i as u64, sector.to_vec());
+                }
+                Ok(())
+            },
+        }
+    }
+
+    fn check_aligned(data: &[u8], offset: u64) -> Result<()> {
+        match offset.is_multiple_of(SECTOR_SIZE as u64) && data.len().is_multiple_of(SECTOR_SIZE) {
+            true    => Ok(()),
+            false   => Err(Error::new(ErrorKind::InvalidInput, "unaligned write to a sector-granular disk")),
+        }
+    }
+
+    // Make completed writes durable
+    pub fn flush(&mut self) -> Result<()> {
+        match self {
+            Disk::Memory(_)                 |
+            Disk::Volatile { .. }           => Ok(()),
+            Disk::File { file, .. }         => file.sync_data(),
+            Disk::Overlay { overlay, .. }   => overlay.sync_data(),
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::convert::TryInto;
+
 pub const DRAM_SIZE: usize = 1024 * 1024 * 128;     // 128MiB
 
 pub struct Dram {
@@ -6,14 +8,19 @@ pub struct Dram {
 
 impl Dram {
     pub fn new() -> Self {
+        Dram::with_size(DRAM_SIZE)
+    }
+
+    // RAM or ROM of `size` bytes, such as the flash and SRAM of a microcontroller
+    pub fn with_size(size: usize) -> Self {
         Dram {
-            dram: vec![0; DRAM_SIZE],
+            dram: vec![0; size],
         }
     }
 
     pub fn load(&mut self, binary: Vec<u8>) {
-        if binary.len() > DRAM_SIZE {
-            panic!("[ERROR] too large binary: {}[Byte] (limit: {}[Byte])", binary.len(), DRAM_SIZE);
+        if binary.len() > self.dram.len() {
+            panic!("[ERROR] too large binary: {}[Byte] (limit: {}[Byte])", binary.len(), self.dram.len());
         }
 
         for (i, byte) in binary.iter().enumerate() {
@@ -21,20 +28,24 @@ impl Dram {
         }
     }
 
+    pub fn size(&self) -> usize {
+        self.dram.len()
+    }
+
     pub fn read8(&self, paddr: usize) -> u8 {
         self.dram[paddr]
     }
     
     pub fn read16(&self, paddr: usize) -> u16 {
-        self.read8(paddr) as u16 | (self.read8(paddr + 1)  as u16) << 8
+        u16::from_le_bytes(self.dram[paddr..paddr + 2].try_into().unwrap())
     }
 
     pub fn read32(&self, paddr: usize) -> u32 {
-        self.read16(paddr) as u32 | (self.read16(paddr + 2)  as u32) << 16
+        u32::from_le_bytes(self.dram[paddr..paddr + 4].try_into().unwrap())
     }
     
     pub fn read64(&self, paddr: usize) -> u64 {
-        self.read32(paddr) as u64 | (self.read32(paddr + 4) as u64) << 32
+        u64::from_le_bytes(self.dram[paddr..paddr + 8].try_into().unwrap())
     }
 
     pub fn write8(&mut self, paddr: usize, data: u8) {
@@ -42,17 +53,14 @@ impl Dram {
     }
 
     pub fn write16(&mut self, paddr: usize, data: u16) {
-        self.write8(paddr, (data & 0xFF) as u8);
-        self.write8(paddr + 1, ((data >> 8) & 0xFF) as u8);
+        self.dram[paddr..paddr + 2].copy_from_slice(&data.to_le_bytes());
     }
 
     pub fn write32(&mut self, paddr: usize, data: u32) {
-        self.write16(paddr, (data & 0xFFFF) as u16);
-        self.write16(paddr + 2, ((data >> 16) & 0xFFFF) as u16);
+        self.dram[paddr..paddr + 4].copy_from_slice(&data.to_le_bytes());
     }
 
     pub fn write64(&mut self, paddr: usize, data: u64) {
-        self.write32(paddr, (data & 0xFFFF_FFFF) as u32);
-        self.write32(paddr + 4, ((data >> 32) & 0xFFFF_FFFF) as u32);
+        self.dram[paddr..paddr + 8].copy_from_slice(&data.to_le_bytes());
     }
 }
\ No newline at end of file
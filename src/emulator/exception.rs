@@ -1,85 +1,176 @@
 use crate::emulator::cpu::Cpu;
 use crate::emulator::csr::*;
 
+// Address exceptions carry the faulting virtual address, which is reported in xtval.
+// Guest-page faults also carry the faulting guest physical address, which is reported in htval or mtval2
 #[derive(Debug)]
 pub enum Exception {
-    InstAddrMisalign,
-    InstAccessFault,
+    InstAddrMisalign(usize),
+    InstAccessFault(usize),
     IllegalInst,
     Breakpoint,
-    LoadAddrMislign,
-    LoadAccessFault,
-    StoreAddrMisalign,
-    StoreAccessFault,
+    LoadAddrMislign(usize),
+    LoadAccessFault(usize),
+    StoreAddrMisalign(usize),
+    StoreAccessFault(usize),
     EnvCallUmode,
     EnvCallSmode,
-    // 10: Reserved
+    EnvCallVSmode,
     EnvCallMmode,
-    InstPageFault,
-    LoadPageFault,
+    InstPageFault(usize),
+    LoadPageFault(usize),
     // 14: Reserved for future standard use
-    StorePageFault,
-    // 16~: Reserved
+    StorePageFault(usize),
+    // 16~19: Reserved
+    InstGuestPageFault(usize, usize),
+    LoadGuestPageFault(usize, usize),
+    VirtualInst,
+    StoreGuestPageFault(usize, usize),
+    // 24~: Reserved
+}
+
+// HLV, HLVX and HSV: loads and stores made as VS-mode or VU-mode
+fn is_hypervisor_memory(inst: u32) -> bool {
+    (inst & 0x7F) == 0b111_0011 && ((inst >> 12) & 0x7) == 0b100
 }
 
 impl Exception {
     fn exc_code(&self) -> u8 {
         match self {
-            Exception::InstAddrMisalign     =>  0,
-            Exception::InstAccessFault      =>  1,
+            Exception::InstAddrMisalign(_)  =>  0,
+            Exception::InstAccessFault(_)   =>  1,
             Exception::IllegalInst          =>  2,
             Exception::Breakpoint           =>  3,
-            Exception::LoadAddrMislign      =>  4,
-            Exception::LoadAccessFault      =>  5,
-            Exception::StoreAddrMisalign    =>  6,
-            Exception::StoreAccessFault     =>  7,
+            Exception::LoadAddrMislign(_)   =>  4,
+            Exception::LoadAccessFault(_)   =>  5,
+            Exception::StoreAddrMisalign(_) =>  6,
+            Exception::StoreAccessFault(_)  =>  7,
             Exception::EnvCallUmode         =>  8,
             Exception::EnvCallSmode         =>  9,
+            Exception::EnvCallVSmode        =>  10,
             Exception::EnvCallMmode         =>  11,
-            Exception::InstPageFault        =>  12,
-            Exception::LoadPageFault        =>  13,
-            Exception::StorePageFault       =>  15,
+            Exception::InstPageFault(_)     =>  12,
+            Exception::LoadPageFault(_)     =>  13,
+            Exception::StorePageFault(_)    =>  15,
+            Exception::InstGuestPageFault(..)   =>  20,
+            Exception::LoadGuestPageFault(..)   =>  21,
+            Exception::VirtualInst              =>  22,
+            Exception::StoreGuestPageFault(..)  =>  23,
+        }
+    }
+
+    // Value written to htval or mtval2: the faulting guest physical address shifted right by 2
+    fn guest_physical_addr(&self) -> u64 {
+        match self {
+            Exception::InstGuestPageFault(_, gpa)   |
+            Exception::LoadGuestPageFault(_, gpa)   |
+            Exception::StoreGuestPageFault(_, gpa)  => (*gpa as u64) >> 2,
+            _                                       => 0,
+        }
+    }
+
+    // Whether xtval holds a guest virtual address (hstatus.GVA and mstatus.GVA)
+    fn is_guest_virtual(&self, cpu: &Cpu) -> bool {
+        match self {
+            Exception::InstAddrMisalign(_)      |
+            Exception::InstAccessFault(_)       |
+            Exception::InstPageFault(_)         => cpu.csr.virt,
+            Exception::LoadAddrMislign(_)       |
+            Exception::LoadAccessFault(_)       |
+            Exception::StoreAddrMisalign(_)     |
+            Exception::StoreAccessFault(_)      |
+            Exception::LoadPageFault(_)         |
+            Exception::StorePageFault(_)        => cpu.csr.effective_virt() || is_hypervisor_memory(cpu.instruction),
+            Exception::InstGuestPageFault(..)   |
+            Exception::LoadGuestPageFault(..)   |
+            Exception::StoreGuestPageFault(..)  => true,
+            _                                   => false,
+        }
+    }
+
+    // Value written to xtval when the trap is taken
+    fn tval(&self, cpu: &Cpu) -> u64 {
+        match self {
+            Exception::InstAddrMisalign(addr)   |
+            Exception::InstAccessFault(addr)    |
+            Exception::LoadAddrMislign(addr)    |
+            Exception::LoadAccessFault(addr)    |
+            Exception::StoreAddrMisalign(addr)  |
+            Exception::StoreAccessFault(addr)   |
+            Exception::InstPageFault(addr)      |
+            Exception::LoadPageFault(addr)      |
+            Exception::StorePageFault(addr)     |
+            Exception::InstGuestPageFault(addr, _)  |
+            Exception::LoadGuestPageFault(addr, _)  |
+            Exception::StoreGuestPageFault(addr, _) => *addr as u64,
+            Exception::IllegalInst              |
+            Exception::VirtualInst              => cpu.instruction as u64,
+            Exception::Breakpoint               => cpu.pc as u64,
+            _                                   => 0,
         }
     }
 
     pub fn take_trap(&self, cpu: &mut Cpu) {
         if cpu.debug { eprintln!("[DEBUG] {:?}-{:?} take trap : {:?} {:?}", file!(), line!(), self, cpu.csr.priv_level);}
         let cur_priv_level = cpu.csr.priv_level;
+        let cur_virt = cpu.csr.virt;
         let cur_pc = cpu.pc; 
         let cause = self.exc_code()  as u64;
+        let tval = self.tval(cpu);
+        let gva = self.is_guest_virtual(cpu);
+
+        cpu.csr.count_event(HPM_EVENT_TRAP, 1);
 
         let mdeleg = cpu.csr.read(MEDELEG);
-        let sdeleg = cpu.csr.read(SEDELEG);
+        let hdeleg = cpu.csr.read(HEDELEG);
 
         let pos = cause & 0xFFFF;
 
-        let new_priv_level = match ((mdeleg >> pos) & 1) == 0 {
+        // Without the N extension there is no sedeleg, so traps are never taken in U-mode.
+        // Traps never go to a less privileged mode, so those from M-mode stay there.
+        let new_priv_level = match cur_priv_level == PrivLevel::MACHINE || ((mdeleg >> pos) & 1) == 0 {
             true    => PrivLevel::MACHINE,
-            false   => match ((sdeleg >> pos) & 1) == 0 {
-                true    => PrivLevel::SUPERVISOR,
-                false   => PrivLevel::USER,
-            }
+            false   => PrivLevel::SUPERVISOR,
         };
 
+        // Exceptions from VS-mode and VU-mode delegated by hedeleg are taken in VS-mode, the others leave the
+        // guest. hedeleg does not apply outside a guest.
+        let new_virt = cur_virt && new_priv_level == PrivLevel::SUPERVISOR && ((hdeleg >> pos) & 1) == 1;
+
         cpu.csr.priv_level = new_priv_level;
+        cpu.csr.virt = new_virt;
 
          match cpu.csr.priv_level {
             PrivLevel::MACHINE      => {
                 cpu.csr.write(MEPC, cur_pc as u64);
                 cpu.csr.write(MCAUSE, cause as u64);
-                cpu.csr.write(MTVAL, cur_pc as u64);    // ToDo: branch based on exception code
+                cpu.csr.write(MTVAL, tval);
+                cpu.csr.write(MTVAL2, self.guest_physical_addr());
+                cpu.csr.write(MTINST, 0);
                 cpu.pc = cpu.csr.read(MTVEC) as usize;
 
                 let status = cpu.csr.read(MSTATUS);
 				let mie = (status >> 3) & 1;
-                let new_status = (status & !0x1888) | (mie << 7) | ((cur_priv_level as u64) << 11) as u64;
+                let mpv = if cur_virt { MSTATUS_MPV } else { 0 };
+                let gva = if gva { MSTATUS_GVA } else { 0 };
+                let new_status = (status & !(0x1888 | MSTATUS_MPV | MSTATUS_GVA)) | (mie << 7) | ((cur_priv_level as u64) << 11) | mpv | gva;
                 cpu.csr.write(MSTATUS, new_status);
                 
             },
             PrivLevel::SUPERVISOR   => {
+                if !new_virt {
+                    let status = cpu.csr.read(HSTATUS) & !(HSTATUS_SPV | HSTATUS_GVA);
+                    let status = match cur_virt {
+                        true    => (status & !HSTATUS_SPVP) | HSTATUS_SPV | (((cur_priv_level as u64) & 1) << 8),
+                        false   => status,
+                    };
+                    cpu.csr.write(HSTATUS, if gva { status | HSTATUS_GVA } else { status });
+                    cpu.csr.write(HTVAL, self.guest_physical_addr());
+                    cpu.csr.write(HTINST, 0);
+                }
                 cpu.csr.write(SEPC, cur_pc as u64);
                 cpu.csr.write(SCAUSE, cause as u64);
-                cpu.csr.write(STVAL, cur_pc as u64);    // ToDo: branch based on exception code
+                cpu.csr.write(STVAL, tval);
                 cpu.pc = cpu.csr.read(STVEC) as usize;
                 
                 let status = cpu.csr.read(SSTATUS);
@@ -87,14 +178,7 @@ impl Exception {
                 let new_status = (status & !0x122) | (sie << 5) | (((cur_priv_level as u64) & 1) << 8) as u64;
                 cpu.csr.write(SSTATUS, new_status);
             },
-            PrivLevel::USER         => {
-                cpu.csr.write(UEPC, cur_pc as u64);
-                cpu.csr.write(UCAUSE, cause as u64);
-                cpu.csr.write(UTVAL, cur_pc as u64);    // ToDo: branch based on exception code
-                cpu.pc = cpu.csr.read(UTVEC) as usize;
-
-                unimplemented!();
-            },
+            PrivLevel::USER         |
             PrivLevel::RESERVED     => panic!(),
         }
     }
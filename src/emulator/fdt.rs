@@ -0,0 +1,237 @@
+/*
+ * Flattened device tree (devicetree specification v0.4, 5 Flattened Devicetree (DTB) Format)
+ *
+ * The emulator describes the virt machine to the kernel with a device tree, placed at the top
+ * of DRAM and passed in a1 as on QEMU. It advertises the memory, the devices and the ISA
+ * string of the hart, so that a kernel does not have to probe for extensions.
+ *
+ * +----------------------+
+ * | Header (40 bytes)    |
+ * | Memory reservations  |   empty: a single terminating entry
+ * | Structure block      |   FDT_BEGIN_NODE, FDT_PROP and FDT_END_NODE tokens, ended by FDT_END
+ * | Strings block        |   property names, NUL-terminated
+ * +----------------------+
+ *
+ * All values are big endian.
+ */
+
+use crate::emulator::bus::*;
+use crate::emulator::dram::DRAM_SIZE;
+use crate::emulator::interrupt::IrqNumber;
+
+const FDT_MAGIC:            u32 = 0xD00D_FEED;
+const FDT_VERSION:          u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const FDT_HEADER_SIZE:      usize = 40;
+const FDT_RSVMAP_SIZE:      usize = 16;
+
+const FDT_BEGIN_NODE:   u32 = 0x1;
+const FDT_END_NODE:     u32 = 0x2;
+const FDT_PROP:         u32 = 0x3;
+const FDT_END:          u32 = 0x9;
+
+// mtime is not tied to the host clock, so this is the nominal rate of QEMU's virt machine
+const TIMEBASE_FREQUENCY: u32 = 10_000_000;
+
+// phandles of the interrupt controllers
+const PHANDLE_CPU_INTC: u32 = 1;
+const PHANDLE_PLIC:     u32 = 2;
+
+struct Fdt {
+    structure:  Vec<u8>,
+    strings:    Vec<u8>,
+}
+
+impl Fdt {
+    fn new() -> Self {
+        Fdt {
+            structure:  Vec::new(),
+            strings:    Vec::new(),
+        }
+    }
+
+    fn push_u32(&mut self, data: u32) {
+        self.structure.extend_from_slice(&data.to_be_bytes());
+    }
+
+    // Tokens are aligned to four bytes
+    fn align(&mut self) {
+        let len = self.structure.len().next_multiple_of(4);
+        self.structure.resize(len, 0);
+    }
+
+    // Offset of `name` in the strings block, which each property name is added to only once
+    fn string_offset(&mut self, name: &str) -> u32 {
+        let mut offset = 0;
+        for string in self.strings.split(|&byte| byte == 0) {
+            if string == name.as_bytes() && offset < self.strings.len() {
+                return offset as u32;
+            }
+            offset += string.len() + 1;
+        }
+
+        let offset = self.strings.len();
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        offset as u32
+    }
+
+    fn begin_node(&mut self, name: &str) {
+        self.push_u32(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.align();
+    }
+
+    fn end_node(&mut self) {
+        self.push_u32(FDT_END_NODE);
+    }
+
+    fn property(&mut self, name: &str, value: &[u8]) {
+        let nameoff = self.string_offset(name);
+        self.push_u32(FDT_PROP);
+        self.push_u32(value.len() as u32);
+        self.push_u32(nameoff);
+        self.structure.extend_from_slice(value);
+        self.align();
+    }
+
+    // A property without a value, such as "interrupt-controller"
+    fn property_empty(&mut self, name: &str) {
+        self.property(name, &[]);
+    }
+
+    fn property_string(&mut self, name: &str, value: &str) {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        self.property(name, &bytes);
+    }
+
+    fn property_u32(&mut self, name: &str, value: u32) {
+        self.property_cells(name, &[value]);
+    }
+
+    fn property_cells(&mut self, name: &str, cells: &[u32]) {
+        let bytes: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes()).collect();
+        self.property(name, &bytes);
+    }
+
+    // A "reg" property of one region, with two address cells and two size cells
+    fn property_reg(&mut self, base: usize, size: usize) {
+        let (base, size) = (base as u64, size as u64);
+        self.property_cells("reg", &[(base >> 32) as u32, base as u32, (size >> 32) as u32, size as u32]);
+    }
+
+    // The device tree blob
+    fn finish(mut self) -> Vec<u8> {
+        self.push_u32(FDT_END);
+
+        let off_dt_struct = FDT_HEADER_SIZE + FDT_RSVMAP_SIZE;
+        let off_dt_strings = off_dt_struct + self.structure.len();
+        let totalsize = off_dt_strings + self.strings.len();
+
+        let header = [
+            FDT_MAGIC,
+            totalsize as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            FDT_HEADER_SIZE as u32,         // off_mem_rsvmap
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            0,                              // boot_cpuid_phys
+            self.strings.len() as u32,      // size_dt_strings
+            self.structure.len() as u32,    // size_dt_struct
+        ];
+
+        let mut blob: Vec<u8> = header.iter().flat_map(|field| field.to_be_bytes()).collect();
+        blob.resize(off_dt_struct, 0);
+        blob.extend_from_slice(&self.structure);
+        blob.extend_from_slice(&self.strings);
+        blob
+    }
+}
+
+// Device tree of the virt machine with a single hart implementing `isa`
+pub fn virt_device_tree(isa: &str, mmu_type: &str) -> Vec<u8> {
+    let mut fdt = Fdt::new();
+
+    fdt.begin_node("");
+    fdt.property_u32("#address-cells", 2);
+    fdt.property_u32("#size-cells", 2);
+    fdt.property_string("compatible", "riscv-virtio");
+    fdt.property_string("model", "riscv-virtio");
+
+    fdt.begin_node("chosen");
+    fdt.property_string("stdout-path", &format!("/soc/serial@{:x}", UART0_BASE));
+    fdt.end_node();
+
+    fdt.begin_node(&format!("memory@{:x}", DRAM_BASE));
+    fdt.property_string("device_type", "memory");
+    fdt.property_reg(DRAM_BASE, DRAM_SIZE);
+    fdt.end_node();
+
+    fdt.begin_node("cpus");
+    fdt.property_u32("#address-cells", 1);
+    fdt.property_u32("#size-cells", 0);
+    fdt.property_u32("timebase-frequency", TIMEBASE_FREQUENCY);
+    fdt.begin_node("cpu@0");
+    fdt.property_string("device_type", "cpu");
+    fdt.property_u32("reg", 0);
+    fdt.property_string("status", "okay");
+    fdt.property_string("compatible", "riscv");
+    fdt.property_string("riscv,isa", isa);
+    fdt.property_string("mmu-type", mmu_type);
+    fdt.begin_node("interrupt-controller");
+    fdt.property_u32("#interrupt-cells", 1);
+    fdt.property_empty("interrupt-controller");
+    fdt.property_string("compatible", "riscv,cpu-intc");
+    fdt.property_u32("phandle", PHANDLE_CPU_INTC);
+    fdt.end_node();
+    fdt.end_node();
+    fdt.end_node();
+
+    fdt.begin_node("soc");
+    fdt.property_u32("#address-cells", 2);
+    fdt.property_u32("#size-cells", 2);
+    fdt.property_string("compatible", "simple-bus");
+    fdt.property_empty("ranges");
+
+    // Machine software and timer interrupts
+    fdt.begin_node(&format!("clint@{:x}", CLINT_BASE));
+    fdt.property_string("compatible", "sifive,clint0");
+    fdt.property_reg(CLINT_BASE, CLINT_TOP - CLINT_BASE + 1);
+    fdt.property_cells("interrupts-extended", &[PHANDLE_CPU_INTC, 3, PHANDLE_CPU_INTC, 7]);
+    fdt.end_node();
+
+    // Machine and supervisor external interrupts
+    fdt.begin_node(&format!("plic@{:x}", PLIC_BASE));
+    fdt.property_string("compatible", "sifive,plic-1.0.0");
+    fdt.property_reg(PLIC_BASE, PLIC_TOP - PLIC_BASE + 1);
+    fdt.property_u32("#interrupt-cells", 1);
+    fdt.property_u32("#address-cells", 0);
+    fdt.property_empty("interrupt-controller");
+    fdt.property_cells("interrupts-extended", &[PHANDLE_CPU_INTC, 11, PHANDLE_CPU_INTC, 9]);
+    fdt.property_u32("riscv,ndev", IrqNumber::UART as u32);    // The highest source number
+    fdt.property_u32("phandle", PHANDLE_PLIC);
+    fdt.end_node();
+
+    fdt.begin_node(&format!("serial@{:x}", UART0_BASE));
+    fdt.property_string("compatible", "ns16550a");
+    fdt.property_reg(UART0_BASE, UART0_TOP - UART0_BASE + 1);
+    fdt.property_u32("clock-frequency", 0x38_4000);
+    fdt.property_u32("interrupt-parent", PHANDLE_PLIC);
+    fdt.property_u32("interrupts", IrqNumber::UART as u32);
+    fdt.end_node();
+
+    fdt.begin_node(&format!("virtio_mmio@{:x}", VIRTIO_BASE));
+    fdt.property_string("compatible", "virtio,mmio");
+    fdt.property_reg(VIRTIO_BASE, VIRTIO_TOP - VIRTIO_BASE + 1);
+    fdt.property_u32("interrupt-parent", PHANDLE_PLIC);
+    fdt.property_u32("interrupts", IrqNumber::VIRTIO as u32);
+    fdt.end_node();
+
+    fdt.end_node();
+
+    fdt.end_node();
+    fdt.finish()
+}
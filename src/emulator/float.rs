@@ -0,0 +1,562 @@
+/*
+ * Floating-Point Arithmetic (IEEE 754 binary32 and binary64)
+ *
+ * The host only rounds to nearest, ties to even, and does not report exceptions, so the
+ * arithmetic is done on integers: the operands are unpacked, the exact result is computed (or
+ * enough of it, with the rest folded into a sticky bit), and it is rounded once to the
+ * destination format under the RISC-V rounding mode, accruing the exception flags.
+ *
+ * As RISC-V requires, NaN results are the canonical NaN and tininess is detected after rounding.
+ * There are no scalar floating-point instructions: the f registers are used by the vector unit.
+ */
+
+// Floating-point exception flags (fflags)
+pub const FFLAGS_NV: u64 = 1 << 4;  // Invalid operation
+pub const FFLAGS_DZ: u64 = 1 << 3;  // Divide by zero
+pub const FFLAGS_OF: u64 = 1 << 2;  // Overflow
+pub const FFLAGS_UF: u64 = 1 << 1;  // Underflow
+pub const FFLAGS_NX: u64 = 1 << 0;  // Inexact
+
+// Rounding modes (frm)
+pub const RM_RNE: u64 = 0b000;      // Round to nearest, ties to even
+pub const RM_RTZ: u64 = 0b001;      // Round towards zero
+pub const RM_RDN: u64 = 0b010;      // Round down (towards -infinity)
+pub const RM_RUP: u64 = 0b011;      // Round up (towards +infinity)
+pub const RM_RMM: u64 = 0b100;      // Round to nearest, ties to max magnitude
+pub const RM_ROD: u64 = 0b101;      // Round to odd: reserved in frm, only used by vfncvt.rod.f.f.w
+
+const NREGISTERS: usize = 32;
+
+#[derive(Default)]
+pub struct FRegisters {
+    freg: [u64; NREGISTERS],    // Floating-point registers f0..f31 (FLEN = 64)
+}
+
+impl FRegisters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Values narrower than FLEN are NaN-boxed: reading one from a register whose upper bits are
+    // not all ones gives the canonical NaN
+    pub fn read(&self, index: usize, format: Format) -> u64 {
+        let data = self.freg[index];
+        match (data | format.mask()) == u64::MAX {
+            true    => data & format.mask(),
+            false   => format.canonical_nan(),
+        }
+    }
+
+    pub fn write(&mut self, index: usize, format: Format, data: u64) {
+        self.freg[index] = data | !format.mask();
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Format {
+    exp_bits: u32,      // Width of the biased exponent
+    frac_bits: u32,     // Width of the fraction (the significand without its implicit bit)
+}
+
+pub const F32: Format = Format { exp_bits: 8, frac_bits: 23 };
+pub const F64: Format = Format { exp_bits: 11, frac_bits: 52 };
+
+impl Format {
+    // binary32 or binary64 for 32- or 64-bit values
+    pub fn of_width(width: usize) -> Option<Format> {
+        match width {
+            32  => Some(F32),
+            64  => Some(F64),
+            _   => None,
+        }
+    }
+
+    pub fn width(self) -> usize {
+        (1 + self.exp_bits + self.frac_bits) as usize
+    }
+
+    fn mask(self) -> u64 {
+        u64::MAX >> (64 - self.width())
+    }
+
+    pub fn sign_bit(self) -> u64 {
+        1 << (self.width() - 1)
+    }
+
+    fn frac_mask(self) -> u64 {
+        (1 << self.frac_bits) - 1
+    }
+
+    // Biased exponent of infinities and NaNs
+    fn exp_max(self) -> u64 {
+        (1 << self.exp_bits) - 1
+    }
+
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits - 1)) - 1
+    }
+
+    // Exponent of the smallest normal value
+    fn emin(self) -> i32 {
+        1 - self.bias()
+    }
+
+    // Significand bits, including the implicit one
+    fn precision(self) -> i32 {
+        self.frac_bits as i32 + 1
+    }
+
+    pub fn canonical_nan(self) -> u64 {
+        (self.exp_max() << self.frac_bits) | (1 << (self.frac_bits - 1))
+    }
+
+    fn zero(self, sign: bool) -> u64 {
+        if sign { self.sign_bit() } else { 0 }
+    }
+
+    fn infinity(self, sign: bool) -> u64 {
+        self.zero(sign) | (self.exp_max() << self.frac_bits)
+    }
+
+    fn max_finite(self, sign: bool) -> u64 {
+        self.zero(sign) | ((self.exp_max() - 1) << self.frac_bits) | self.frac_mask()
+    }
+
+    fn biased_exp(self, x: u64) -> u64 {
+        (x >> self.frac_bits) & self.exp_max()
+    }
+
+    pub fn is_negative(self, x: u64) -> bool {
+        (x & self.sign_bit()) != 0
+    }
+
+    pub fn is_nan(self, x: u64) -> bool {
+        self.biased_exp(x) == self.exp_max() && (x & self.frac_mask()) != 0
+    }
+
+    // Signaling NaNs have the most significant fraction bit clear
+    pub fn is_snan(self, x: u64) -> bool {
+        self.is_nan(x) && (x >> (self.frac_bits - 1)) & 1 == 0
+    }
+
+    pub fn is_inf(self, x: u64) -> bool {
+        self.biased_exp(x) == self.exp_max() && (x & self.frac_mask()) == 0
+    }
+
+    pub fn is_zero(self, x: u64) -> bool {
+        (x & self.mask() & !self.sign_bit()) == 0
+    }
+
+    // A finite value as (sign, exponent, significand): x = (-1)^sign * significand * 2^exponent
+    fn unpack(self, x: u64) -> (bool, i32, u128) {
+        let fraction = (x & self.frac_mask()) as u128;
+        match self.biased_exp(x) {
+            0       => (self.is_negative(x), self.emin() - self.frac_bits as i32, fraction),
+            biased  => (self.is_negative(x), biased as i32 - self.bias() - self.frac_bits as i32, fraction | (1 << self.frac_bits)),
+        }
+    }
+
+    // Key that orders the values that are not NaNs, with -0 equal to +0
+    fn order(self, x: u64) -> i128 {
+        let magnitude = (x & self.mask() & !self.sign_bit()) as i128;
+        if self.is_negative(x) { -magnitude } else { magnitude }
+    }
+
+    // The class of `x` as a one-hot mask, as returned by vfclass
+    pub fn classify(self, x: u64) -> u64 {
+        let negative = self.is_negative(x);
+        let class = match () {
+            _ if self.is_snan(x)                => 8,
+            _ if self.is_nan(x)                 => 9,
+            _ if self.is_inf(x)                 => if negative { 0 } else { 7 },
+            _ if self.is_zero(x)                => if negative { 3 } else { 4 },
+            _ if self.biased_exp(x) == 0        => if negative { 2 } else { 5 },
+            _                                   => if negative { 1 } else { 6 },
+        };
+        1 << class
+    }
+}
+
+// Shift `sig` right by `shift` bits, ORing the bits shifted out into the lowest bit
+fn shift_right_jam(sig: u128, shift: u32) -> u128 {
+    match shift {
+        0           => sig,
+        1 ..= 127   => (sig >> shift) | ((sig & ((1 << shift) - 1)) != 0) as u128,
+        _           => (sig != 0) as u128,
+    }
+}
+
+// Shift the magnitude `sig` right by `shift` bits, rounding under `rm`.
+// Returns the rounded value and whether it is inexact.
+fn round_shift(rm: u64, sign: bool, sig: u128, shift: i32) -> (u128, bool) {
+    if shift <= 0 {
+        return (sig << -shift, false);
+    }
+
+    // The bits shifted out, and the value of half a unit in the last kept place
+    let (kept, rest, half) = match shift {
+        1 ..= 127   => (sig >> shift, sig & ((1 << shift) - 1), 1 << (shift - 1)),
+        128         => (0, sig, 1 << 127),
+        _           => (0, sig.min(1), 2),
+    };
+
+    let increment = match rm {
+        _ if rest == 0  => false,
+        RM_RNE          => rest > half || (rest == half && (kept & 1) != 0),
+        RM_RDN          => sign,
+        RM_RUP          => !sign,
+        RM_RMM          => rest >= half,
+        _               => false,
+    };
+
+    let mut kept = kept + increment as u128;
+    // Round to odd truncates and sets the lowest bit of an inexact result
+    if rm == RM_ROD && rest != 0 {
+        kept |= 1;
+    }
+
+    (kept, rest != 0)
+}
+
+// isqrt(n), with n < 2^128
+fn isqrt(n: u128) -> u128 {
+    let mut root = 0;
+    for bit in (0..64).rev() {
+        let candidate: u128 = root | (1 << bit);
+        if candidate * candidate <= n {
+            root = candidate;
+        }
+    }
+    root
+}
+
+/*
+ * The rounding mode of an instruction and the exception flags it raises
+ */
+pub struct FloatEnv {
+    pub rm: u64,
+    pub flags: u64,
+}
+
+impl FloatEnv {
+    pub fn new(rm: u64) -> Self {
+        FloatEnv {
+            rm,
+            flags: 0,
+        }
+    }
+
+    // Whether any operand is a NaN, in which case the result is the canonical NaN.
+    // Signaling NaNs raise the invalid operation flag.
+    fn any_nan(&mut self, format: Format, operands: &[u64]) -> bool {
+        if operands.iter().any(|&x| format.is_snan(x)) {
+            self.flags |= FFLAGS_NV;
+        }
+        operands.iter().any(|&x| format.is_nan(x))
+    }
+
+    fn invalid(&mut self, format: Format) -> u64 {
+        self.flags |= FFLAGS_NV;
+        format.canonical_nan()
+    }
+
+    // Round (-1)^sign * sig * 2^exp to `format`. Either `sig` is exact, or it has at least two bits
+    // more than the precision of `format` and the inexact rest of the result is ORed into its lowest bit.
+    fn round(&mut self, format: Format, sign: bool, exp: i32, sig: u128) -> u64 {
+        if sig == 0 {
+            return format.zero(sign);
+        }
+
+        let precision = format.precision();
+        let msb = exp + 127 - sig.leading_zeros() as i32;
+
+        // Exponent of the last kept bit, which is fixed for subnormal results
+        let unbounded_lsb = msb - (precision - 1);
+        let mut lsb = unbounded_lsb.max(format.emin() - (precision - 1));
+        let (mut kept, inexact) = round_shift(self.rm, sign, sig, lsb - exp);
+        if (kept >> precision) != 0 {
+            kept >>= 1;
+            lsb += 1;
+        }
+
+        if inexact {
+            self.flags |= FFLAGS_NX;
+
+            // Tiny: the result rounded with an unbounded exponent range is below 2^emin
+            let tiny = msb < format.emin() && {
+                let (unbounded, _) = round_shift(self.rm, sign, sig, unbounded_lsb - exp);
+                unbounded_lsb + 127 - (unbounded.leading_zeros() as i32) < format.emin()
+            };
+            if tiny {
+                self.flags |= FFLAGS_UF;
+            }
+        }
+
+        // A subnormal result has no implicit bit and a biased exponent of 0
+        let biased = match (kept >> (precision - 1)) != 0 {
+            true    => lsb + precision - 1 + format.bias(),
+            false   => 0,
+        };
+
+        if biased >= format.exp_max() as i32 {
+            self.flags |= FFLAGS_OF | FFLAGS_NX;
+            // Rounding towards zero, or away from an infinity, gives the largest finite value
+            let infinite = match self.rm {
+                RM_RTZ | RM_ROD => false,
+                RM_RDN          => sign,
+                RM_RUP          => !sign,
+                _               => true,
+            };
+            return match infinite {
+                true    => format.infinity(sign),
+                false   => format.max_finite(sign),
+            };
+        }
+
+        format.zero(sign) | ((biased as u64) << format.frac_bits) | (kept as u64 & format.frac_mask())
+    }
+
+    // Round x + y, where both are exact (sign, exponent, significand) values with significands below 2^120
+    fn round_sum(&mut self, format: Format, x: (bool, i32, u128), y: (bool, i32, u128)) -> u64 {
+        match (x.2 == 0, y.2 == 0) {
+            // An exact zero sum is negative only if both zeros are, or when rounding down
+            (true, true)    => return format.zero(if x.0 == y.0 { x.0 } else { self.rm == RM_RDN }),
+            (true, false)   => return self.round(format, y.0, y.1, y.2),
+            (false, true)   => return self.round(format, x.0, x.1, x.2),
+            _               => (),
+        }
+
+        // With both leading bits at bit 124, aligning the smaller operand only loses bits far below
+        // the result, unless the operands are close and cancel, in which case no bits are lost
+        let normalize = |(sign, exp, sig): (bool, i32, u128)| {
+            let shift = sig.leading_zeros() as i32 - 3;
+            (sign, exp - shift, sig << shift)
+        };
+        let (x, y) = (normalize(x), normalize(y));
+        let (x, y) = if x.1 >= y.1 { (x, y) } else { (y, x) };
+        let y_sig = shift_right_jam(y.2, (x.1 - y.1) as u32);
+
+        let (sign, sig) = match () {
+            _ if x.0 == y.0     => (x.0, x.2 + y_sig),
+            _ if x.2 >= y_sig   => (x.0, x.2 - y_sig),
+            _                   => (y.0, y_sig - x.2),
+        };
+        if sig == 0 {
+            return format.zero(self.rm == RM_RDN);
+        }
+
+        self.round(format, sign, x.1, sig)
+    }
+
+    pub fn add(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        if self.any_nan(format, &[a, b]) {
+            return format.canonical_nan();
+        }
+
+        match (format.is_inf(a), format.is_inf(b)) {
+            (true, true) if format.is_negative(a) != format.is_negative(b) => self.invalid(format),
+            (true, _)   => a,
+            (_, true)   => b,
+            _           => self.round_sum(format, format.unpack(a), format.unpack(b)),
+        }
+    }
+
+    pub fn sub(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        self.add(format, a, b ^ format.sign_bit())
+    }
+
+    pub fn mul(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        if self.any_nan(format, &[a, b]) {
+            return format.canonical_nan();
+        }
+
+        let sign = format.is_negative(a) != format.is_negative(b);
+        let infinite = format.is_inf(a) || format.is_inf(b);
+        match (infinite, format.is_zero(a) || format.is_zero(b)) {
+            (true, true)    => self.invalid(format),
+            (true, false)   => format.infinity(sign),
+            _               => {
+                let (_, exp_a, sig_a) = format.unpack(a);
+                let (_, exp_b, sig_b) = format.unpack(b);
+                self.round(format, sign, exp_a + exp_b, sig_a * sig_b)
+            },
+        }
+    }
+
+    // a * b + c with a single rounding
+    pub fn fma(&mut self, format: Format, a: u64, b: u64, c: u64) -> u64 {
+        // Infinity times zero is invalid even if c is a quiet NaN
+        let infinite = format.is_inf(a) || format.is_inf(b);
+        if infinite && (format.is_zero(a) || format.is_zero(b)) {
+            return self.invalid(format);
+        }
+        if self.any_nan(format, &[a, b, c]) {
+            return format.canonical_nan();
+        }
+
+        let sign = format.is_negative(a) != format.is_negative(b);
+        match (infinite, format.is_inf(c)) {
+            (true, true) if sign != format.is_negative(c) => self.invalid(format),
+            (true, _)   => format.infinity(sign),
+            (_, true)   => c,
+            _           => {
+                let (_, exp_a, sig_a) = format.unpack(a);
+                let (_, exp_b, sig_b) = format.unpack(b);
+                self.round_sum(format, (sign, exp_a + exp_b, sig_a * sig_b), format.unpack(c))
+            },
+        }
+    }
+
+    pub fn div(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        if self.any_nan(format, &[a, b]) {
+            return format.canonical_nan();
+        }
+
+        let sign = format.is_negative(a) != format.is_negative(b);
+        match (format.is_inf(a), format.is_inf(b), format.is_zero(a), format.is_zero(b)) {
+            (true, true, _, _) | (_, _, true, true) => self.invalid(format),
+            (true, _, _, _) => format.infinity(sign),
+            (_, true, _, _) | (_, _, true, _)   => format.zero(sign),
+            (_, _, _, true) => {
+                self.flags |= FFLAGS_DZ;
+                format.infinity(sign)
+            },
+            _   => {
+                // With the dividend's leading bit at bit 125, the quotient has more than 70 bits
+                let (_, exp_a, sig_a) = format.unpack(a);
+                let (_, exp_b, sig_b) = format.unpack(b);
+                let shift = sig_a.leading_zeros() as i32 - 2;
+                let dividend = sig_a << shift;
+                let quotient = (dividend / sig_b) | (!dividend.is_multiple_of(sig_b)) as u128;
+                self.round(format, sign, exp_a - shift - exp_b, quotient)
+            },
+        }
+    }
+
+    pub fn sqrt(&mut self, format: Format, a: u64) -> u64 {
+        if self.any_nan(format, &[a]) {
+            return format.canonical_nan();
+        }
+
+        match () {
+            // sqrt(-0) is -0
+            _ if format.is_zero(a)      => a,
+            _ if format.is_negative(a)  => self.invalid(format),
+            _ if format.is_inf(a)       => a,
+            _                           => {
+                // Make the exponent even, with the leading bit at bit 124 or 125 for a root of 63 bits
+                let (_, exp, sig) = format.unpack(a);
+                let mut shift = sig.leading_zeros() as i32 - 3;
+                if (exp - shift) & 1 != 0 {
+                    shift += 1;
+                }
+                let (exp, sig) = (exp - shift, sig << shift);
+                let root = isqrt(sig);
+                self.round(format, false, exp / 2, root | (root * root != sig) as u128)
+            },
+        }
+    }
+
+    // IEEE 754-2019 minimumNumber: a NaN operand is ignored, and -0 is below +0
+    pub fn min(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        self.min_max(format, a, b, true)
+    }
+
+    // IEEE 754-2019 maximumNumber
+    pub fn max(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        self.min_max(format, a, b, false)
+    }
+
+    fn min_max(&mut self, format: Format, a: u64, b: u64, min: bool) -> u64 {
+        self.any_nan(format, &[a, b]);
+        match (format.is_nan(a), format.is_nan(b)) {
+            (true, true)    => format.canonical_nan(),
+            (true, false)   => b,
+            (false, true)   => a,
+            _ if format.order(a) == format.order(b) => if min { a | b } else { a & b },
+            _ if (format.order(a) < format.order(b)) == min => a,
+            _               => b,
+        }
+    }
+
+    // Quiet equality: only signaling NaNs are invalid
+    pub fn eq(&mut self, format: Format, a: u64, b: u64) -> bool {
+        !self.any_nan(format, &[a, b]) && format.order(a) == format.order(b)
+    }
+
+    // Signaling comparisons: any NaN is invalid
+    pub fn lt(&mut self, format: Format, a: u64, b: u64) -> bool {
+        !self.unordered(format, a, b) && format.order(a) < format.order(b)
+    }
+
+    pub fn le(&mut self, format: Format, a: u64, b: u64) -> bool {
+        !self.unordered(format, a, b) && format.order(a) <= format.order(b)
+    }
+
+    fn unordered(&mut self, format: Format, a: u64, b: u64) -> bool {
+        let unordered = format.is_nan(a) || format.is_nan(b);
+        if unordered {
+            self.flags |= FFLAGS_NV;
+        }
+        unordered
+    }
+
+    // Convert between floating-point formats
+    pub fn convert(&mut self, from: Format, to: Format, a: u64) -> u64 {
+        if self.any_nan(from, &[a]) {
+            return to.canonical_nan();
+        }
+        if from.is_inf(a) {
+            return to.infinity(from.is_negative(a));
+        }
+
+        let (sign, exp, sig) = from.unpack(a);
+        self.round(to, sign, exp, sig)
+    }
+
+    // Convert to a `width`-bit integer. NaNs and out of range values are invalid and saturate.
+    pub fn to_int(&mut self, format: Format, a: u64, width: usize, signed: bool) -> u64 {
+        let (min, max): (i128, i128) = match signed {
+            true    => (-(1 << (width - 1)), (1 << (width - 1)) - 1),
+            false   => (0, (1 << width) - 1),
+        };
+        let mask = u64::MAX >> (64 - width);
+
+        if format.is_nan(a) {
+            self.flags |= FFLAGS_NV;
+            return max as u64 & mask;
+        }
+
+        let sign = format.is_negative(a);
+        let rounded = match format.is_inf(a) {
+            true    => None,
+            false   => match format.unpack(a) {
+                // Too large for any integer
+                (_, exp, _) if exp > 64 => None,
+                (_, exp, sig)           => Some(round_shift(self.rm, sign, sig, -exp)),
+            },
+        };
+
+        match rounded.map(|(magnitude, inexact)| (if sign { -(magnitude as i128) } else { magnitude as i128 }, inexact)) {
+            Some((value, inexact)) if value >= min && value <= max => {
+                if inexact {
+                    self.flags |= FFLAGS_NX;
+                }
+                value as u64 & mask
+            },
+            _   => {
+                self.flags |= FFLAGS_NV;
+                (if sign { min } else { max }) as u64 & mask
+            },
+        }
+    }
+
+    // Convert a `width`-bit integer
+    pub fn from_int(&mut self, format: Format, a: u64, width: usize, signed: bool) -> u64 {
+        let value = match signed {
+            true    => (((a << (64 - width)) as i64) >> (64 - width)) as i128,
+            false   => (a & (u64::MAX >> (64 - width))) as i128,
+        };
+        self.round(format, value < 0, 0, value.unsigned_abs())
+    }
+}
@@ -10,36 +10,37 @@ pub enum IrqNumber {
 
 #[derive(Debug)]
 pub enum Interrupt {
-    UserSoftwareIrq,
     SupervisorSoftwareIrq,
+    VirtualSupervisorSoftwareIrq,
     MachineSoftwareIrq,
-    UserTimerIrq,
     SupervisorTimerIrq,
+    VirtualSupervisorTimerIrq,
     MachineTimerIrq,
-    UserExtIrq(u64),
     SupervisorExtIrq(u64),
+    VirtualSupervisorExtIrq,
     MachineExtIrq(u64),
+    CounterOverflowIrq,
 }
 
 impl Interrupt {
     fn exc_code(&self) -> u64 {
         let code: u64 = 1 << 63;
         match self {
-            Interrupt::UserSoftwareIrq          => code + 0,
             Interrupt::SupervisorSoftwareIrq    => code + 1,
+            Interrupt::VirtualSupervisorSoftwareIrq => code + 2,
             Interrupt::MachineSoftwareIrq       => code + 3,
-            Interrupt::UserTimerIrq             => code + 4,
             Interrupt::SupervisorTimerIrq       => code + 5,
+            Interrupt::VirtualSupervisorTimerIrq    => code + 6,
             Interrupt::MachineTimerIrq          => code + 7,
-            Interrupt::UserExtIrq(_)            => code + 8,
             Interrupt::SupervisorExtIrq(_)      => code + 9,
+            Interrupt::VirtualSupervisorExtIrq  => code + 10,
             Interrupt::MachineExtIrq(_)         => code + 11,
+            Interrupt::CounterOverflowIrq       => code + 13,
         }
     }
 
     fn irq(&self) -> u64 {
         match self {
-            Interrupt::UserExtIrq(irq)          |
             Interrupt::SupervisorExtIrq(irq)    |
             Interrupt::MachineExtIrq(irq)       => *irq,
             _                                   => 0,
@@ -55,64 +56,66 @@ impl Interrupt {
         */
         let cur_pc = cpu.pc;
         let cur_priv_level = cpu.csr.priv_level;
+        let cur_virt = cpu.csr.virt;
 
         let cause = self.exc_code();
         let pos = cause & 0xFF;
 
         let mideleg = cpu.csr.read(MIDELEG);
-        let sideleg = cpu.csr.read(SIDELEG);
+        let hideleg = cpu.csr.read(HIDELEG);
 
+        // Without the N extension there is no sideleg, so interrupts are never taken in U-mode
         let new_priv_level = match ((mideleg >> pos) & 1) == 0 {
             true    => PrivLevel::MACHINE,
-            false   => match ((sideleg >> pos) & 1) == 0 {
-                true    => PrivLevel::SUPERVISOR,
-                false   => PrivLevel::USER,
-            },
+            false   => PrivLevel::SUPERVISOR,
         };
 
+        // VS-level interrupts delegated by hideleg are taken in VS-mode, and only while V=1
+        let new_virt = new_priv_level == PrivLevel::SUPERVISOR && ((hideleg >> pos) & 1) == 1;
+        if new_virt && !cur_virt {
+            return;
+        }
+
+        // U-mode has no interrupt enable of its own, as it never takes interrupts
         let cur_status = match cpu.csr.priv_level {
             PrivLevel::MACHINE      => cpu.csr.read(MSTATUS),
             PrivLevel::SUPERVISOR   => cpu.csr.read(SSTATUS),
-            PrivLevel::USER         => cpu.csr.read(USTATUS),
+            PrivLevel::USER         => 0,
             PrivLevel::RESERVED     => panic!(),
         };
 
-        let ie = match new_priv_level {
-            PrivLevel::MACHINE      => cpu.csr.read(MIE),
-            PrivLevel::SUPERVISOR   => cpu.csr.read(SIE),
-            PrivLevel::USER         => cpu.csr.read(UIE),
-            PrivLevel::RESERVED     => panic!(),
-        };
+        // sie is vsie while V=1, so S-level enables are read from mie
+        let ie = cpu.csr.read(MIE);
 
         let cur_mie = (cur_status >> 3) & 1;
         let cur_sie = (cur_status >> 1) & 1;
-        let cur_uie =  cur_status & 1;
 
         // Software interrupt enable
         let msie = (ie >> 3) & 1;
+        let vssie = (ie >> 2) & 1;
         let ssie = (ie >> 1) & 1;
-        let usie =  ie & 1;
 
         // Timer interrupt enable
         let mtie = (ie >> 7) & 1;
+        let vstie = (ie >> 6) & 1;
         let stie = (ie >> 5) & 1;
-        let utie = (ie >> 4) & 1;
 
         // External interrupt enable
         let meie = (ie >> 11) & 1;
+        let vseie = (ie >> 10) & 1;
         let seie = (ie >> 9) & 1;
-        let ueie = (ie >> 8) & 1;
+
+        // Local counter overflow interrupt enable
+        let lcofie = (ie >> 13) & 1;
         
         /*
         println!("[DEBUG] {}-{}: priv_level: {:?})", file!(), line!(), cur_priv_level);
         println!("[DEBUG] {}-{}: new_priv_level: {:?})", file!(), line!(), new_priv_level);
         println!("[DEBUG] {}-{}: mideleg: 0b{:b})", file!(), line!(), mideleg);
-        println!("[DEBUG] {}-{}: sideleg: 0b{:b})", file!(), line!(), sideleg);
         println!("[DEBUG] {}-{}: mip: 0b{:b})", file!(), line!(), cpu.csr.read(MIP));
         println!("[DEBUG] {}-{}: ie: 0b{:b})", file!(), line!(), ie);
         println!("[DEBUG] {}-{}: msie: {}, mtie: {}, meie: {})", file!(), line!(), msie, mtie, meie);
         println!("[DEBUG] {}-{}: ssie: {}, stie: {}, seie: {})", file!(), line!(), ssie, stie, seie);
-        println!("[DEBUG] {}-{}: usie: {}, utie: {}, ueie: {})", file!(), line!(), usie, utie, ueie);
         */
 
         if new_priv_level < cur_priv_level {
@@ -125,30 +128,28 @@ impl Interrupt {
                         return;
                     }
                 },
+                // VS-mode is less privileged than HS-mode, so HS-level interrupts are always enabled there
                 PrivLevel::SUPERVISOR   => {
-                    if cur_sie == 0 {
-                        return;
-                    }
-                },
-                PrivLevel::USER => {
-                    if cur_uie == 0 {
+                    if cur_sie == 0 && new_virt == cur_virt {
                         return;
                     }
                 },
-                PrivLevel::RESERVED => unimplemented!(),            
+                // new_priv_level is MACHINE or SUPERVISOR, so cur_priv_level is one of them here
+                PrivLevel::USER     |
+                PrivLevel::RESERVED => unreachable!(),
             }
         }
         
         //println!("[DEBUG] {}-{}", file!(), line!());
 
         match self {
-            Interrupt::UserSoftwareIrq  => {
-                if usie == 0 {
+            Interrupt::SupervisorSoftwareIrq  => {
+                if ssie == 0 {
                     return;
                 }
             },
-            Interrupt::SupervisorSoftwareIrq  => {
-                if ssie == 0 {
+            Interrupt::VirtualSupervisorSoftwareIrq  => {
+                if vssie == 0 {
                     return;
                 }
             },
@@ -157,13 +158,13 @@ impl Interrupt {
                     return;
                 }
             },
-            Interrupt::UserTimerIrq  => {
-                if utie == 0 {
+            Interrupt::SupervisorTimerIrq  => {
+                if stie == 0 {
                     return;
                 }
             },
-            Interrupt::SupervisorTimerIrq  => {
-                if stie == 0 {
+            Interrupt::VirtualSupervisorTimerIrq  => {
+                if vstie == 0 {
                     return;
                 }
             },
@@ -172,13 +173,13 @@ impl Interrupt {
                     return;
                 }
             },
-            Interrupt::UserExtIrq(_)  => {
-                if ueie == 0 {
+            Interrupt::SupervisorExtIrq(_)  => {
+                if seie == 0 {
                     return;
                 }
             },
-            Interrupt::SupervisorExtIrq(_)  => {
-                if seie == 0 {
+            Interrupt::VirtualSupervisorExtIrq  => {
+                if vseie == 0 {
                     return;
                 }
             },
@@ -187,37 +188,27 @@ impl Interrupt {
                     return;
                 }
             },
+            Interrupt::CounterOverflowIrq  => {
+                if lcofie == 0 {
+                    return;
+                }
+            },
         }
 
         //println!("[DEBUG] {}-{}", file!(), line!());
 
+        cpu.csr.count_event(HPM_EVENT_TRAP, 1);
+
         cpu.csr.priv_level = new_priv_level;
+        cpu.csr.virt = new_virt;
+
+        // VS-level interrupts are reported to VS-mode with the cause of the matching S-level interrupt
+        let cause = if new_virt { cause - 1 } else { cause };
         
-        let epc_addr = match cpu.csr.priv_level {
-            PrivLevel::MACHINE      => MEPC,
-            PrivLevel::SUPERVISOR   => SEPC,
-            PrivLevel::USER         => UEPC,
-            PrivLevel::RESERVED     => panic!(),
-        };
-        
-        let cause_addr = match cpu.csr.priv_level {
-            PrivLevel::MACHINE      => MCAUSE,
-            PrivLevel::SUPERVISOR   => SCAUSE,
-            PrivLevel::USER         => UCAUSE,
-            PrivLevel::RESERVED     => panic!(),
-        };
-        
-        let tval_addr = match cpu.csr.priv_level {
-            PrivLevel::MACHINE      => MTVAL,
-            PrivLevel::SUPERVISOR   => STVAL,
-            PrivLevel::USER         => UTVAL,
-            PrivLevel::RESERVED     => panic!(),
-        };
-        
-        let tvec_addr = match cpu.csr.priv_level {
-            PrivLevel::MACHINE      => MTVEC,
-            PrivLevel::SUPERVISOR   => STVEC,
-            PrivLevel::USER         => UTVEC,
+        let (epc_addr, cause_addr, tval_addr, tvec_addr) = match cpu.csr.priv_level {
+            PrivLevel::MACHINE      => (MEPC, MCAUSE, MTVAL, MTVEC),
+            PrivLevel::SUPERVISOR   => (SEPC, SCAUSE, STVAL, STVEC),
+            PrivLevel::USER         |
             PrivLevel::RESERVED     => panic!(),
         };
 
@@ -236,45 +227,59 @@ impl Interrupt {
             PrivLevel::MACHINE  => {
                 let status  = cpu.csr.read(MSTATUS);
                 let mie     = (status >> 3) & 1;
-                let new_status = (status & !0x1888) | (mie << 7) | ((cur_priv_level as u64) << 11);
+                let mpv     = if cur_virt { MSTATUS_MPV } else { 0 };
+                let new_status = (status & !(0x1888 | MSTATUS_MPV | MSTATUS_GVA)) | (mie << 7) | ((cur_priv_level as u64) << 11) | mpv;
                 cpu.csr.write(MSTATUS, new_status);
             },
             PrivLevel::SUPERVISOR   => {
+                if !new_virt {
+                    let status = cpu.csr.read(HSTATUS) & !(HSTATUS_SPV | HSTATUS_GVA);
+                    let status = match cur_virt {
+                        true    => (status & !HSTATUS_SPVP) | HSTATUS_SPV | (((cur_priv_level as u64) & 1) << 8),
+                        false   => status,
+                    };
+                    cpu.csr.write(HSTATUS, status);
+                }
                 let status  = cpu.csr.read(SSTATUS);
                 let sie     = (status >> 1) & 1;
                 let new_status = (status & !0x122) | (sie << 5) | (((cur_priv_level as u64) & 1) << 8);
                 cpu.csr.write(SSTATUS, new_status);
             },
-            PrivLevel::USER     => unimplemented!(),
+            PrivLevel::USER     |
             PrivLevel::RESERVED => panic!(),
         }
 
         match self {
             Interrupt::MachineExtIrq(_) => {
                 let data = cpu.csr.read(MIP) & !MIP_MEIP;
-                cpu.csr.write(MIP, data);
+                cpu.csr.write_hw(MIP, data);
             },
             Interrupt::MachineSoftwareIrq   => {
                 let data = cpu.csr.read(MIP) & !MIP_MSIP;
-                cpu.csr.write(MIP, data);
+                cpu.csr.write_hw(MIP, data);
             },
             Interrupt::MachineTimerIrq  => {
                 let data = cpu.csr.read(MIP) & !MIP_MTIP;
-                cpu.csr.write(MIP, data);
+                cpu.csr.write_hw(MIP, data);
             },
             Interrupt::SupervisorExtIrq(_)  => {
                 let data = cpu.csr.read(MIP) & !MIP_SEIP;
-                cpu.csr.write(MIP, data);
+                cpu.csr.write_hw(MIP, data);
             },
             Interrupt::SupervisorSoftwareIrq    => {
                 let data = cpu.csr.read(MIP) & !MIP_SSIP;
-                cpu.csr.write(MIP, data);
+                cpu.csr.write_hw(MIP, data);
             },
             Interrupt::SupervisorTimerIrq   => {
                 let data = cpu.csr.read(MIP) & !MIP_STIP;
-                cpu.csr.write(MIP, data);
+                cpu.csr.write_hw(MIP, data);
             },
-            _   => unimplemented!(),
+            // Cleared by software, once it has handled the overflowed counters
+            Interrupt::CounterOverflowIrq   => {},
+            // Injected and cleared by the hypervisor through hvip
+            Interrupt::VirtualSupervisorSoftwareIrq |
+            Interrupt::VirtualSupervisorTimerIrq    |
+            Interrupt::VirtualSupervisorExtIrq      => {},
         }
     }
 }
\ No newline at end of file
@@ -1,24 +1,65 @@
-use crate::emulator::csr::{ Csr, SATP, PrivLevel };
+use crate::emulator::csr::*;
+use crate::emulator::pmp::pmp_check;
 use crate::emulator::exception::{ Exception };
-use crate::emulator::bus::Bus;
+use crate::emulator::bus::{ Bus, Machine, Pma };
 use crate::emulator::interrupt::IrqNumber;
+use crate::emulator::disk::Disk;
 
 pub const PAGE_SIZE: usize  = 1024 * 4;     // Page size: 4KiB (2**12)
-pub const LEVELS: i8        = 3;            // Paging levels (Sv39)
-pub const PTE_SIZE: usize   = 8;            // Page teble entry size (Sv39)
+pub const PAGE_SHIFT: usize = 12;           // Number of page offset bits
+pub const VPN_BITS: usize   = 9;            // Number of bits in each VPN field (Sv39/Sv48/Sv57)
+pub const MAX_LEVELS: usize = 5;            // Maximum paging levels (Sv57)
+pub const PTE_SIZE: usize   = 8;            // Page teble entry size (Sv39/Sv48/Sv57)
+pub const CACHE_BLOCK_SIZE: usize = 64;     // Default size of the blocks managed by the CBO instructions
 
-#[derive(PartialEq)]
-enum ACCESS {
+const PTE_A: u64            = 1 << 6;       // Accessed
+const PTE_D: u64            = 1 << 7;       // Dirty
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ACCESS {
     NONE,
     LOAD,
     STORE,
     EXEC,
 }
 
+// How the A and D bits of leaf PTEs are kept up to date
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AdUpdate {
+    Hardware,   // Svadu: the page walker sets A and D itself
+    Software,   // Svade: a page fault is raised and software sets A and D
+}
+
+// How loads and stores that are not naturally aligned are handled
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Misaligned {
+    Trap,       // Raise an address-misaligned exception so that firmware can emulate the access
+    Emulate,    // Perform the access byte by byte, translating every page it touches
+}
+
+// Whether `size` can be the cache-block size: a power of two from a doubleword up to a page
+pub fn is_valid_cache_block_size(size: usize) -> bool {
+    size.is_power_of_two() && (8..=PAGE_SIZE).contains(&size)
+}
+
+// Address translation stage
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Stage {
+    Single,     // satp: virtual to physical, outside a virtual machine
+    VS,         // vsatp: guest virtual to guest physical
+    G,          // hgatp: guest physical to supervisor physical
+}
+
 // Memory Management Unit
 pub struct Mmu {
     bus: Bus,
     access: ACCESS,
+    hlvx: bool,                     // The load is an HLVX, which needs execute rather than read permission
+    reservation: Option<usize>,     // Physical address reserved by the last LR
+    page_walks: u64,                // Page-table walks since the last take_page_walks
+    pub ad_update: AdUpdate,
+    pub misaligned: Misaligned,
+    pub cache_block_size: usize,    // Bytes operated on by CBO.ZERO, CBO.CLEAN, CBO.FLUSH and CBO.INVAL
 }
 
 impl Mmu {
@@ -26,6 +67,12 @@ impl Mmu {
         Mmu {
             bus: Bus::new(),
             access: ACCESS::NONE,
+            hlvx: false,
+            reservation: None,
+            page_walks: 0,
+            ad_update: AdUpdate::Hardware,
+            misaligned: Misaligned::Emulate,
+            cache_block_size: CACHE_BLOCK_SIZE,
         }
     }
 
@@ -33,81 +80,341 @@ impl Mmu {
         self.bus.load_dram(binary);
     }
 
+    pub fn set_machine(&mut self, machine: Machine) {
+        self.bus.set_machine(machine);
+    }
+
     pub fn load_disk(&mut self, binary: Vec<u8>) {
         self.bus.load_disk(binary);
     }
 
+    pub fn set_disk(&mut self, disk: Disk) {
+        self.bus.set_disk(disk);
+    }
+
     pub fn tick(&mut self, mip: &mut u64) {
         self.bus.tick(mip);
     }
 
+    pub fn attach_stdin(&mut self) {
+        self.bus.attach_stdin();
+    }
+
+    pub fn set_virtio_legacy(&mut self, legacy: bool) {
+        self.bus.set_virtio_legacy(legacy);
+    }
+
+    pub fn wait_for_event(&mut self, deadline: Option<u64>) {
+        self.bus.wait_for_event(deadline);
+    }
+
+    pub fn mtime(&mut self) -> u64 {
+        self.bus.mtime()
+    }
+
+    // Number of page-table walks since the last call, for the TLB-miss performance event
+    pub fn take_page_walks(&mut self) -> u64 {
+        std::mem::replace(&mut self.page_walks, 0)
+    }
+
     pub fn get_irqno(&self) -> Option<IrqNumber> {
         self.bus.get_irqno()
     }
     
     pub fn read8(&mut self, csr: &Csr, vaddr: usize) -> Result<u8, Exception> {
         self.access = ACCESS::LOAD;
-        let paddr = self.translate_addr(&csr, vaddr)?;
+        let paddr = self.translate(csr, vaddr, 1)?;
         Ok(self.bus.read8(paddr))
     }
     
     pub fn read16(&mut self, csr: &Csr, vaddr: usize) -> Result<u16, Exception> {
         self.access = ACCESS::LOAD;
-        let paddr = self.translate_addr(&csr, vaddr)?;
+        if !vaddr.is_multiple_of(2) {
+            return Ok(self.read_misaligned(csr, vaddr, 2)? as u16);
+        }
+        let paddr = self.translate(csr, vaddr, 2)?;
         Ok(self.bus.read16(paddr))
     }
 
     pub fn read32(&mut self, csr: &Csr, vaddr: usize) -> Result<u32, Exception> {
         self.access = ACCESS::LOAD;
-        let paddr = self.translate_addr(&csr, vaddr)?;
+        if !vaddr.is_multiple_of(4) {
+            return Ok(self.read_misaligned(csr, vaddr, 4)? as u32);
+        }
+        let paddr = self.translate(csr, vaddr, 4)?;
         Ok(self.bus.read32(paddr))
     }
     
     pub fn read64(&mut self, csr: &Csr, vaddr: usize) -> Result<u64, Exception> {
         self.access = ACCESS::LOAD;
-        let paddr = self.translate_addr(&csr, vaddr)?;
+        if !vaddr.is_multiple_of(8) {
+            return self.read_misaligned(csr, vaddr, 8);
+        }
+        let paddr = self.translate(csr, vaddr, 8)?;
         Ok(self.bus.read64(paddr))
     }
 
-    pub fn fetch32(&mut self, csr: &Csr, vaddr: usize) -> Result<u32, Exception> {
+    // HLVX.HU and HLVX.WU: read instruction memory of the virtual machine
+    pub fn read_executable(&mut self, csr: &Csr, vaddr: usize, size: usize) -> Result<u64, Exception> {
+        self.hlvx = true;
+        let data = match size {
+            2   => self.read16(csr, vaddr).map(|data| data as u64),
+            _   => self.read32(csr, vaddr).map(|data| data as u64),
+        };
+        self.hlvx = false;
+        data
+    }
+
+    // Fetch the instruction at `vaddr`: a compressed instruction is a single 16-bit parcel, any other
+    // takes two. Instructions are aligned to 16 bits with the C extension and to 32 bits without it
+    pub fn fetch(&mut self, csr: &Csr, vaddr: usize) -> Result<u32, Exception> {
         self.access = ACCESS::EXEC;
-        let paddr = self.translate_addr(&csr, vaddr)?;
-        Ok(self.bus.read32(paddr))
+        let ialign = if csr.has_extension('C') { 2 } else { 4 };
+        if !vaddr.is_multiple_of(ialign) {
+            return Err(Exception::InstAddrMisalign(vaddr));
+        }
+
+        let paddr = self.translate(csr, vaddr, 2)?;
+        let low = self.bus.read16(paddr);
+        if (low & 0b11) != 0b11 {
+            return Ok(low as u32);
+        }
+
+        // The upper parcel of an instruction that crosses a page is translated on its own
+        let vaddr = vaddr.wrapping_add(2);
+        let paddr = match vaddr % PAGE_SIZE {
+            0   => self.translate(csr, vaddr, 2)?,
+            _   => {
+                self.check_access(csr, vaddr, paddr + 2, 2)?;
+                paddr + 2
+            },
+        };
+        let high = self.bus.read16(paddr);
+
+        Ok(((high as u32) << 16) | low as u32)
     }
 
     pub fn write8(&mut self, csr: &Csr, vaddr: usize, data: u8) -> Result<(), Exception>  {
         self.access = ACCESS::STORE;
-        let paddr = self.translate_addr(&csr, vaddr)?;
+        let paddr = self.translate(csr, vaddr, 1)?;
         self.bus.write8(paddr, data);
         Ok(())
     }
 
     pub fn write16(&mut self, csr: &Csr, vaddr: usize, data: u16) -> Result<(), Exception> {
         self.access = ACCESS::STORE;
-        let paddr = self.translate_addr(&csr, vaddr)?;
+        if !vaddr.is_multiple_of(2) {
+            return self.write_misaligned(csr, vaddr, 2, data as u64);
+        }
+        let paddr = self.translate(csr, vaddr, 2)?;
         self.bus.write16(paddr, data);
         Ok(())
     }
 
     pub fn write32(&mut self, csr: &Csr, vaddr: usize, data: u32) -> Result<(), Exception> {
         self.access = ACCESS::STORE;
-        let paddr = self.translate_addr(&csr, vaddr)?;
+        if !vaddr.is_multiple_of(4) {
+            return self.write_misaligned(csr, vaddr, 4, data as u64);
+        }
+        let paddr = self.translate(csr, vaddr, 4)?;
         self.bus.write32(paddr, data);
         Ok(())
     }
 
     pub fn write64(&mut self, csr: &Csr, vaddr: usize, data: u64) -> Result<(), Exception> { 
         self.access = ACCESS::STORE;
-        let paddr = self.translate_addr(&csr, vaddr)?;
+        if !vaddr.is_multiple_of(8) {
+            return self.write_misaligned(csr, vaddr, 8, data);
+        }
+        let paddr = self.translate(csr, vaddr, 8)?;
         self.bus.write64(paddr, data);
         Ok(())
     }
 
-    // Translate virtual address to physical address (Sv39)
+    fn read_misaligned(&mut self, csr: &Csr, vaddr: usize, size: usize) -> Result<u64, Exception> {
+        let paddrs = self.translate_misaligned(csr, vaddr, size)?;
+        let mut data = 0;
+        for (i, paddr) in paddrs.into_iter().enumerate() {
+            data |= (self.bus.read8(paddr) as u64) << (8 * i);
+        }
+        Ok(data)
+    }
+
+    fn write_misaligned(&mut self, csr: &Csr, vaddr: usize, size: usize, data: u64) -> Result<(), Exception> {
+        let paddrs = self.translate_misaligned(csr, vaddr, size)?;
+        for (i, paddr) in paddrs.into_iter().enumerate() {
+            self.bus.write8(paddr, (data >> (8 * i)) as u8);
+        }
+        Ok(())
+    }
+
+    // Translate every byte of a misaligned access up front, so that an access straddling
+    // two pages faults on the right page and never completes only partially
+    fn translate_misaligned(&mut self, csr: &Csr, vaddr: usize, size: usize) -> Result<Vec<usize>, Exception> {
+        if self.misaligned == Misaligned::Trap {
+            return match self.access {
+                ACCESS::LOAD    => Err(Exception::LoadAddrMislign(vaddr)),
+                _               => Err(Exception::StoreAddrMisalign(vaddr)),
+            };
+        }
+
+        let mut paddrs = Vec::with_capacity(size);
+        for i in 0..size {
+            let paddr = self.translate(csr, vaddr.wrapping_add(i), 1)?;
+
+            // Accesses to I/O regions can not be split
+            if !self.bus.pma(paddr).is_some_and(|pma| pma.idempotent) {
+                self.access_fault_exception(vaddr.wrapping_add(i))?;
+            }
+            paddrs.push(paddr);
+        }
+
+        Ok(paddrs)
+    }
+
+    // CBO.ZERO: store zeros to the whole cache block containing `vaddr`
+    pub fn cbo_zero(&mut self, csr: &Csr, vaddr: usize) -> Result<(), Exception> {
+        self.access = ACCESS::STORE;
+        let paddr = self.translate_block(csr, vaddr)?;
+        for offset in (0..self.cache_block_size).step_by(8) {
+            self.bus.write64(paddr + offset, 0);
+        }
+        Ok(())
+    }
+
+    // CBO.CLEAN, CBO.FLUSH and CBO.INVAL: memory is never cached, so only the permission check remains.
+    // The block must be accessible to a load or a store, and faults are reported as store faults
+    pub fn cbo_manage(&mut self, csr: &Csr, vaddr: usize) -> Result<(), Exception> {
+        self.access = ACCESS::LOAD;
+        if self.translate_block(csr, vaddr).is_ok() {
+            return Ok(());
+        }
+        self.access = ACCESS::STORE;
+        self.translate_block(csr, vaddr)?;
+        Ok(())
+    }
+
+    // Translate the cache block containing `vaddr` to the physical address of its first byte.
+    // Blocks never cross a page, and only cacheable (idempotent) memory is divided into blocks
+    fn translate_block(&mut self, csr: &Csr, vaddr: usize) -> Result<usize, Exception> {
+        let size = self.cache_block_size;
+        let paddr = self.translate_addr(csr, vaddr)? & !(size - 1);
+
+        let store = self.access == ACCESS::STORE;
+        let cacheable = self.bus.pma(paddr).is_some_and(|pma| pma.idempotent && paddr + size - 1 <= pma.top && (pma.writable || !store));
+        if !pmp_check(csr, paddr, size, self.access, csr.effective_priv_level()) || !cacheable {
+            self.access_fault_exception(vaddr)?;
+        }
+
+        Ok(paddr)
+    }
+
+    // Atomically replace the word at `vaddr` with `op(old)` and return the old value
+    pub fn amo32(&mut self, csr: &Csr, vaddr: usize, op: impl FnOnce(u32) -> u32) -> Result<u32, Exception> {
+        self.access = ACCESS::STORE;
+        let paddr = self.translate_atomic(csr, vaddr, 4, |pma| pma.amo)?;
+        let data = self.bus.read32(paddr);
+        self.bus.write32(paddr, op(data));
+        Ok(data)
+    }
+
+    // Atomically replace the doubleword at `vaddr` with `op(old)` and return the old value
+    pub fn amo64(&mut self, csr: &Csr, vaddr: usize, op: impl FnOnce(u64) -> u64) -> Result<u64, Exception> {
+        self.access = ACCESS::STORE;
+        let paddr = self.translate_atomic(csr, vaddr, 8, |pma| pma.amo)?;
+        let data = self.bus.read64(paddr);
+        self.bus.write64(paddr, op(data));
+        Ok(data)
+    }
+
+    pub fn load_reserved32(&mut self, csr: &Csr, vaddr: usize) -> Result<u32, Exception> {
+        self.access = ACCESS::LOAD;
+        let paddr = self.translate_atomic(csr, vaddr, 4, |pma| pma.lrsc)?;
+        self.reservation = Some(paddr);
+        Ok(self.bus.read32(paddr))
+    }
+
+    pub fn load_reserved64(&mut self, csr: &Csr, vaddr: usize) -> Result<u64, Exception> {
+        self.access = ACCESS::LOAD;
+        let paddr = self.translate_atomic(csr, vaddr, 8, |pma| pma.lrsc)?;
+        self.reservation = Some(paddr);
+        Ok(self.bus.read64(paddr))
+    }
+
+    // Store `data` only if the reservation set by LR still covers `vaddr`; returns whether the store happened
+    pub fn store_conditional32(&mut self, csr: &Csr, vaddr: usize, data: u32) -> Result<bool, Exception> {
+        self.access = ACCESS::STORE;
+        let paddr = self.translate_atomic(csr, vaddr, 4, |pma| pma.lrsc)?;
+        if self.reservation.take() != Some(paddr) {
+            return Ok(false);
+        }
+        self.bus.write32(paddr, data);
+        Ok(true)
+    }
+
+    pub fn store_conditional64(&mut self, csr: &Csr, vaddr: usize, data: u64) -> Result<bool, Exception> {
+        self.access = ACCESS::STORE;
+        let paddr = self.translate_atomic(csr, vaddr, 8, |pma| pma.lrsc)?;
+        if self.reservation.take() != Some(paddr) {
+            return Ok(false);
+        }
+        self.bus.write64(paddr, data);
+        Ok(true)
+    }
+
+    // Translate the address of an AMO or LR/SC, which must be naturally aligned and
+    // target a region whose attributes support the operation
+    fn translate_atomic(&mut self, csr: &Csr, vaddr: usize, size: usize, supported: impl FnOnce(&Pma) -> bool) -> Result<usize, Exception> {
+        if !vaddr.is_multiple_of(size) {
+            return match self.access {
+                ACCESS::LOAD    => Err(Exception::LoadAddrMislign(vaddr)),
+                _               => Err(Exception::StoreAddrMisalign(vaddr)),
+            };
+        }
+
+        let paddr = self.translate(csr, vaddr, size)?;
+
+        if !self.bus.pma(paddr).is_some_and(supported) {
+            self.access_fault_exception(vaddr)?;
+        }
+
+        Ok(paddr)
+    }
+
+    // Translate virtual address and check the resulting physical access against PMP and PMA
+    fn translate(&mut self, csr: &Csr, vaddr: usize, size: usize) -> Result<usize, Exception> {
+        let paddr = self.translate_addr(csr, vaddr)?;
+        self.check_access(csr, vaddr, paddr, size)?;
+        Ok(paddr)
+    }
+
+    // Check a physical access of `size` bytes at `paddr`, translated from `vaddr`, against PMP and PMA
+    fn check_access(&self, csr: &Csr, vaddr: usize, paddr: usize, size: usize) -> Result<(), Exception> {
+        let priv_level = match self.access {
+            ACCESS::EXEC    => csr.priv_level,
+            _               => csr.effective_priv_level(),
+        };
+
+        if !pmp_check(csr, paddr, size, self.access, priv_level) || !self.bus.pma_check(paddr, size, self.access) {
+            self.access_fault_exception(vaddr)?;
+        }
+
+        Ok(())
+    }
+
+    // Translate virtual address to physical address (Sv32/Sv39/Sv48/Sv57)
     // Reference:   RISC-V Privileged ISA Specification p.71~
     //              https://riscv.org/specifications/privileged-isa/
     pub fn translate_addr(&mut self, csr: &Csr, vaddr: usize) -> Result<usize, Exception> {
         /*
+         *  Sv32 virtual address
+         * 
+         *  31     22 21     12 11           0
+         *  +--------+--------+-------------+
+         *  | VPN[1] | VPN[0] | page offset |
+         *  +--------+--------+-------------+
+         * 
+         * 
          *  Sv39 virtual address
          * 
          *  38     30 29    21 20    12 11           0
@@ -116,7 +423,23 @@ impl Mmu {
          *  +--------+--------+--------+-------------+
          * 
          * 
-         *  Sv39 physical address
+         *  Sv48 virtual address
+         * 
+         *  47     39 38     30 29    21 20    12 11           0
+         *  +--------+--------+--------+--------+-------------+
+         *  | VPN[3] | VPN[2] | VPN[1] | VPN[0] | page offset |
+         *  +--------+--------+--------+--------+-------------+
+         * 
+         * 
+         *  Sv57 virtual address
+         * 
+         *  56     48 47     39 38     30 29    21 20    12 11           0
+         *  +--------+--------+--------+--------+--------+-------------+
+         *  | VPN[4] | VPN[3] | VPN[2] | VPN[1] | VPN[0] | page offset |
+         *  +--------+--------+--------+--------+--------+-------------+
+         * 
+         * 
+         *  Sv39 physical address (Sv48 and Sv57 split the same 44-bit PPN into more fields)
          * 
          *  55         30 29        21 20        12 11           0
          *  +------------+------------+------------+-------------+
@@ -133,18 +456,95 @@ impl Mmu {
          * 
          */
 
-        if csr.priv_level == PrivLevel::MACHINE {
+        // Addresses computed with XLEN=32 have only 32 bits
+        let vaddr = match csr.xlen() {
+            32  => vaddr & 0xFFFF_FFFF,
+            _   => vaddr,
+        };
+
+        // Loads and stores in M-mode are translated with the privilege (and V) in MPP (and MPV) while MPRV is set
+        let (priv_level, virt) = match self.access {
+            ACCESS::EXEC    => (csr.priv_level, csr.virt),
+            _               => (csr.effective_priv_level(), csr.effective_virt()),
+        };
+
+        if priv_level == PrivLevel::MACHINE {
             return Ok(vaddr);
         }
 
-        if csr.read_bits(SATP, 60..64) == 0 {
-            return Ok(vaddr);
+        // HLVX reads memory that only has to be executable
+        let access = match self.hlvx {
+            true    => ACCESS::EXEC,
+            false   => self.access,
+        };
+
+        if !virt {
+            return self.walk(csr, Stage::Single, vaddr, vaddr, priv_level, access);
         }
 
-        let vpn = [ (vaddr >> 12) & 0x1FF,
-                    (vaddr >> 21) & 0x1FF,
-                    (vaddr >> 30) & 0x1FF
-                ];
+        // Two-stage translation: the VS-stage yields a guest physical address that the G-stage translates
+        let gpa = self.walk(csr, Stage::VS, vaddr, vaddr, priv_level, access)?;
+        self.walk(csr, Stage::G, gpa, vaddr, PrivLevel::USER, access)
+    }
+
+    // Walk the page table of `stage` to translate `addr`, checking the leaf PTE for `access` at `priv_level`.
+    // Faults report `vaddr`, the address of the original access, and G-stage accesses are all U-mode accesses
+    fn walk(&mut self, csr: &Csr, stage: Stage, addr: usize, vaddr: usize, priv_level: PrivLevel, access: ACCESS) -> Result<usize, Exception> {
+        let atp = match stage {
+            Stage::Single   => csr.read(SATP),
+            Stage::VS       => csr.read(VSATP),
+            Stage::G        => csr.read(HGATP),
+        };
+
+        // hgatp modes are numbered like the satp modes they widen. Sv32 has 10-bit VPN fields and 4-byte PTEs
+        let (levels, vpn_bits, pte_size): (i8, usize, usize) = match atp >> 60 {
+            SATP_MODE_BARE  => return Ok(addr),
+            SATP_MODE_SV32  => (2, 10, 4),
+            SATP_MODE_SV39  => (3, VPN_BITS, PTE_SIZE),
+            SATP_MODE_SV48  => (4, VPN_BITS, PTE_SIZE),
+            SATP_MODE_SV57  => (5, VPN_BITS, PTE_SIZE),
+            // satp ignores writes of unsupported modes, but fail safely should one ever be set
+            _               => {
+                match stage {
+                    Stage::G    => self.guest_page_fault_exception(vaddr, addr)?,
+                    _           => self.page_fault_exception(vaddr)?,
+                }
+                return Ok(addr);
+            },
+        };
+
+        // The G-stage root page table is four times larger: its VPN field has two extra bits
+        let root_bits = match stage {
+            Stage::G    => vpn_bits + 2,
+            _           => vpn_bits,
+        };
+
+        let va_bits = PAGE_SHIFT + vpn_bits * (levels as usize - 1) + root_bits;
+        match stage {
+            // Sv32 translates every bit of a 32-bit address
+            _ if pte_size == 4  => (),
+            // Guest physical addresses are zero-extended
+            Stage::G    => {
+                if (addr >> va_bits) != 0 {
+                    self.guest_page_fault_exception(vaddr, addr)?;
+                }
+            },
+            // Bits above the virtual address width must all equal the most significant VA bit
+            _           => {
+                let upper = (addr as i64) >> (va_bits - 1);
+                if upper != 0 && upper != -1 {
+                    self.page_fault_exception(vaddr)?;
+                }
+            },
+        }
+
+        self.page_walks += 1;
+
+        let mut vpn = [0; MAX_LEVELS];
+        for (i, vpn) in vpn.iter_mut().enumerate().take(levels as usize) {
+            let bits = if i == levels as usize - 1 { root_bits } else { vpn_bits };
+            *vpn = (addr >> (PAGE_SHIFT + vpn_bits * i)) & ((1 << bits) - 1);
+        }
         let pte_v       = |pte: u64| (pte & 1u64);
         let pte_r       = |pte: u64| ((pte >> 1) & 1u64);
         let pte_w       = |pte: u64| ((pte >> 2) & 1u64);
@@ -153,35 +553,56 @@ impl Mmu {
         let _pte_g      = |pte: u64| ((pte >> 5) & 1u64);
         let pte_a       = |pte: u64| ((pte >> 6) & 1u64);
         let pte_d       = |pte: u64| ((pte >> 7) & 1u64);
+        let pte_rsvd    = |pte: u64| pte >> 54;      // No Svnapot/Svpbmt: bits 63:54 are reserved (Sv32 PTEs have none)
+
+        // The VS-stage uses the guest's vsstatus, although mstatus.MXR still makes executable pages readable
+        let (mxr, sum) = match stage {
+            Stage::VS   => (
+                csr.read_bit(MSTATUS, MSTATUS_MXR_BIT) || (csr.read(VSSTATUS) & (1 << MSTATUS_MXR_BIT)) != 0,
+                (csr.read(VSSTATUS) & (1 << MSTATUS_SUM_BIT)) != 0,
+            ),
+            _           => (csr.read_bit(MSTATUS, MSTATUS_MXR_BIT), csr.read_bit(MSTATUS, MSTATUS_SUM_BIT)),
+        };
+
+        // A stage fault is a page fault, or a guest-page fault for the G-stage
+        let fault = |mmu: &Self| match stage {
+            Stage::G    => mmu.guest_page_fault_exception(vaddr, addr),
+            _           => mmu.page_fault_exception(vaddr),
+        };
 
         // Step 1
-        let satp_ppn = csr.read(SATP) & 0x3F_FFFF;
+        let atp_ppn = atp & 0xFFF_FFFF_FFFF;
         
-        let mut a = satp_ppn as usize * PAGE_SIZE;
-        let mut i: i8 = LEVELS - 1;
-        let mut pte: u64 = 0;
-        let mut ppn: usize = 0;
-        let mut vpte = [0; LEVELS as usize];
-        let mut addr = 0;
+        let mut a = atp_ppn as usize * PAGE_SIZE;
+        let mut i: i8 = levels - 1;
+        let mut pte: u64;
+        let mut ppn: usize;
+        let mut pte_addr;
         
         // Step 2
-        while i >=0 {
-            addr = a + vpn[i as usize] * PTE_SIZE;
+        loop {
+            pte_addr = a + vpn[i as usize] * pte_size;
 
-            /* ToDo: implement PMA
-            if violate_pma(addr) || violate_pmp(addr) {
-                page_fault_exception();
+            // VS-stage page tables live in guest physical memory, and reading them is a G-stage load
+            if stage == Stage::VS {
+                pte_addr = self.walk(csr, Stage::G, pte_addr, vaddr, PrivLevel::USER, ACCESS::LOAD)?;
             }
-            */
 
-            pte = self.bus.read64(addr);
-            vpte[i as usize] = pte;
-            //eprintln!("[DEBUG] vaddr: 0x{:x}, level: {}, pte addr: 0x{:x}, pte: 0x{:x}", vaddr, i, addr, pte);
+            // The page-table walk itself is an S-mode read that PMP and PMA must permit
+            if !pmp_check(csr, pte_addr, pte_size, ACCESS::LOAD, PrivLevel::SUPERVISOR) || !self.bus.pma_check(pte_addr, pte_size, ACCESS::LOAD) {
+                self.access_fault_exception(vaddr)?;
+            }
+
+            pte = match pte_size {
+                4   => self.bus.read32(pte_addr) as u64,
+                _   => self.bus.read64(pte_addr),
+            };
+            //eprintln!("[DEBUG] vaddr: 0x{:x}, level: {}, pte addr: 0x{:x}, pte: 0x{:x}", vaddr, i, pte_addr, pte);
 
             // Step 3
-            if pte_v(pte) == 0u64 || (pte_r(pte) == 0u64 && pte_w(pte) == 1u64) {
+            if pte_v(pte) == 0u64 || (pte_r(pte) == 0u64 && pte_w(pte) == 1u64) || pte_rsvd(pte) != 0u64 {
                 eprintln!("[DEBUG] {}-{}: page fault exception", file!(), line!());
-                self.page_fault_exception()?;
+                fault(self)?;
             }
 
             ppn = ((pte >> 10) & 0xFFF_FFFF_FFFF) as usize;
@@ -192,82 +613,114 @@ impl Mmu {
                 break;
             }
 
+            // D, A and U are reserved in non-leaf PTEs
+            if pte_d(pte) == 1u64 || pte_a(pte) == 1u64 || pte_u(pte) == 1u64 {
+                fault(self)?;
+            }
+
             i -= 1;
 
             if i < 0 {
                 eprintln!("[DEBUG] {}-{}: page fault exception", file!(), line!());
-                self.page_fault_exception()?;
+                fault(self)?;
             }
 
         }
 
         // Step 5
-        if csr.priv_level == PrivLevel::USER && pte_u(pte) == 0u64 {
+        if priv_level == PrivLevel::USER && pte_u(pte) == 0u64 {
             eprintln!("[DEBUG] {}-{}: page fault exception", file!(), line!());
-            self.page_fault_exception()?;
+            fault(self)?;
         }
 
-        if self.access == ACCESS::LOAD && pte_r(pte) == 0u64 {
+        // S-mode never executes from U pages, and touches their data only while SUM is set
+        if priv_level == PrivLevel::SUPERVISOR && pte_u(pte) == 1u64 && (access == ACCESS::EXEC || !sum) {
             eprintln!("[DEBUG] {}-{}: page fault exception", file!(), line!());
-            self.page_fault_exception()?;
+            fault(self)?;
+        }
+
+        // MXR makes executable pages readable as well
+        if access == ACCESS::LOAD && pte_r(pte) == 0u64 && !(mxr && pte_x(pte) == 1u64) {
+            fault(self)?;
         }
 
-        if self.access == ACCESS::STORE && pte_w(pte) == 0u64 {
+        if access == ACCESS::STORE && pte_w(pte) == 0u64 {
             eprintln!("[DEBUG] {}-{}: page fault exception", file!(), line!());
-            self.page_fault_exception()?;
+            fault(self)?;
         }
 
-        if self.access == ACCESS::EXEC && pte_x(pte) == 0u64 {
+        if access == ACCESS::EXEC && pte_x(pte) == 0u64 {
             eprintln!("[DEBUG] {}-{}: page fault exception", file!(), line!());
-            self.page_fault_exception()?;
+            fault(self)?;
         }
 
         // Step 6
-        /* ToDo
-        if i > 0 && pte.ppn[i-1:0] != 0 {
-            page_fault_exception();
+        // A superpage must be aligned to its own size, i.e. pte.ppn[i-1:0] must be zero
+        if i > 0 && (ppn & ((1 << (vpn_bits * i as usize)) - 1)) != 0 {
+            fault(self)?;
         }
-        */
 
         // Step 7
-        if pte_a(pte) == 0u64 || (self.access == ACCESS::STORE && pte_d(pte) == 0u64) {
-            self.bus.write64(addr, pte | (1 << 6));     // set pte.a to 1
-            if self.access == ACCESS::STORE {
-                self.bus.write64(addr, pte | (1 << 7));     // also set pte.d to 1 
+        if pte_a(pte) == 0u64 || (access == ACCESS::STORE && pte_d(pte) == 0u64) {
+            match self.ad_update {
+                AdUpdate::Software  => {
+                    fault(self)?;
+                },
+                AdUpdate::Hardware  => {
+                    let mut new_pte = pte | PTE_A;
+                    if access == ACCESS::STORE {
+                        new_pte |= PTE_D;
+                    }
+                    // The A/D update is an atomic read-modify-write of the PTE
+                    if !pmp_check(csr, pte_addr, pte_size, ACCESS::STORE, PrivLevel::SUPERVISOR) || !self.bus.pma(pte_addr).is_some_and(|pma| pma.amo) {
+                        self.access_fault_exception(vaddr)?;
+                    }
+                    // The update must not clobber a PTE modified since it was read, so restart the walk instead
+                    let exchanged = match pte_size {
+                        4   => self.bus.compare_exchange32(pte_addr, pte as u32, new_pte as u32),
+                        _   => self.bus.compare_exchange64(pte_addr, pte, new_pte),
+                    };
+                    if !exchanged {
+                        return self.walk(csr, stage, addr, vaddr, priv_level, access);
+                    }
+                },
             }
         }
 
         // Step 8
-
-        let pa_pgoff = vaddr & 0xFFF;
-        let ppns = [ ((vpte[0] >> 10) & 0x1FF) as usize,
-                     ((vpte[1] >> 19) & 0x1FF) as usize,
-                     ((vpte[2] >> 28) & 0x3FF_FFFF) as usize
-                ];
-        
-        //eprintln!("[DEBUG] ppn2: 0x{:x}, ppn1: 0x{:x}, ppn0: 0x{:x}", ppns[2], ppns[1], ppns[0]);
-        //eprintln!("[DEBUG] vpn2: 0x{:x}, vpn1: 0x{:x}, vpn0: 0x{:x}", vpn[2], vpn[1], vpn[0]);
-        //eprintln!("[DEBUG] pa_pgoff: 0x{:x}", pa_pgoff);
-
-        //eprintln!("[DEBUG] level: {}", i);
-
-        let paddr = match i {
-            2   => (ppns[2] << 30) | (vpn[1]  << 21) | (vpn[0] << 12) + pa_pgoff,
-            1   => (ppns[2] << 30) | (ppns[1] << 21) | (vpn[0] << 12) + pa_pgoff,
-            0   => (ppn << 12) + pa_pgoff,
-            _   => panic!(),
-        };
+        // A superpage at level i takes its low PPN fields from the VPN fields of the virtual address
+        let offset_bits = PAGE_SHIFT + vpn_bits * i as usize;
+        let offset_mask = (1 << offset_bits) - 1;
+        let paddr = ((ppn << PAGE_SHIFT) & !offset_mask) | (addr & offset_mask);
 
         //eprintln!("[DEBUG] paddr: 0x{:16x}", paddr);
 
         Ok(paddr)
     }
 
-    fn page_fault_exception(&self) -> Result<(), Exception> {
+    fn page_fault_exception(&self, vaddr: usize) -> Result<(), Exception> {
+        match self.access {
+            ACCESS::LOAD    => Err(Exception::LoadPageFault(vaddr)),
+            ACCESS::STORE   => Err(Exception::StorePageFault(vaddr)),
+            ACCESS::EXEC    => Err(Exception::InstPageFault(vaddr)),
+            _               => unimplemented!(),
+        }
+    }
+
+    fn guest_page_fault_exception(&self, vaddr: usize, gpa: usize) -> Result<(), Exception> {
+        match self.access {
+            ACCESS::LOAD    => Err(Exception::LoadGuestPageFault(vaddr, gpa)),
+            ACCESS::STORE   => Err(Exception::StoreGuestPageFault(vaddr, gpa)),
+            ACCESS::EXEC    => Err(Exception::InstGuestPageFault(vaddr, gpa)),
+            _               => unimplemented!(),
+        }
+    }
+
+    fn access_fault_exception(&self, vaddr: usize) -> Result<(), Exception> {
         match self.access {
-            ACCESS::LOAD    => Err(Exception::LoadPageFault),
-            ACCESS::STORE   => Err(Exception::StorePageFault),
-            ACCESS::EXEC    => Err(Exception::InstPageFault),
+            ACCESS::LOAD    => Err(Exception::LoadAccessFault(vaddr)),
+            ACCESS::STORE   => Err(Exception::StoreAccessFault(vaddr)),
+            ACCESS::EXEC    => Err(Exception::InstAccessFault(vaddr)),
             _               => unimplemented!(),
         }
     }
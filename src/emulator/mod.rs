@@ -1,6 +1,7 @@
 pub mod cpu;
 pub mod dram;
 pub mod mmu;
+pub mod pmp;
 pub mod csr;
 pub mod exception;
 pub mod bus;
@@ -8,4 +9,10 @@ pub mod clint;
 pub mod plic;
 pub mod uart;
 pub mod interrupt;
-pub mod virtio;
\ No newline at end of file
+pub mod virtio;
+pub mod disk;
+pub mod vector;
+pub mod float;
+pub mod crypto;
+pub mod compressed;
+pub mod fdt;
\ No newline at end of file
@@ -6,7 +6,7 @@
 
 use crate::emulator::bus::*;
 use crate::emulator::interrupt::IrqNumber;
-use crate::emulator::csr::MIP_SEIP;
+use crate::emulator::csr::{ MIP_MEIP, MIP_SEIP };
 
 pub const PLIC_SIZE: usize = PLIC_TOP - PLIC_BASE;
 
@@ -22,10 +22,23 @@ pub const ENABLE_TOP:   usize = 0x001F_2002;
 pub const CONTEXT_BASE: usize = 0x0020_0000;
 pub const CONTEXT_TOP:  usize = 0x03FF_F007;
 
+// Hart 0 has an M-mode and an S-mode context, as on qemu's virt machine
+pub const CONTEXT_MACHINE:      usize = 0;
+pub const CONTEXT_SUPERVISOR:   usize = 1;
+
+pub const ENABLE_STRIDE:    usize = 0x80;       // Enable bits of each context
+pub const CONTEXT_STRIDE:   usize = 0x1000;     // Priority threshold and claim/complete of each context
+pub const CLAIM_OFFSET:     usize = 4;
+
+// Interrupt sources in order of their IDs, which breaks ties between equal priorities
+const SOURCES: [IrqNumber; 2] = [IrqNumber::VIRTIO, IrqNumber::UART];
+
 pub struct Plic {
     plic:   Vec<u8>,
     clock:  u64,
     irq:    Option<IrqNumber>,
+    pending:    u32,    // Pending bit of each source
+    claimed:    u32,    // Sources claimed by a context and not completed yet
 }
 
 impl Plic {
@@ -34,6 +47,8 @@ impl Plic {
             plic:   vec![0; PLIC_SIZE],
             clock:  0,
             irq:    None,
+            pending:    0,
+            claimed:    0,
         }
     }
 
@@ -44,43 +59,98 @@ impl Plic {
     pub fn tick(&mut self, virtio_is_interrupting: bool, uart_is_interrupting: bool, mip: &mut u64) {
         self.clock = self.clock.wrapping_add(1);
 
-        let virtio_priority = self.read32(PRIORITY_BASE + (IrqNumber::VIRTIO as usize * 4) as usize);
-        let uart_priority   = self.read32(PRIORITY_BASE + (IrqNumber::UART as usize * 4) as usize);
+        // The gateways are level-triggered: a source is pending while its line is asserted,
+        // except between its claim and its completion
+        let interruptings = [virtio_is_interrupting, uart_is_interrupting];
+        for (source, interrupting) in SOURCES.iter().zip(interruptings.iter()) {
+            let bit = 1 << *source as u32;
+            if (self.claimed & bit) == 0 {
+                self.pending = match *interrupting {
+                    true    => self.pending | bit,
+                    false   => self.pending & !bit,
+                };
+            }
+        }
 
-        let virtio_enabled  = ((self.read32(ENABLE_BASE) >> IrqNumber::VIRTIO as u32) & 0x1) == 1;
-        let uart_enabled    = ((self.read32(ENABLE_BASE) >> IrqNumber::UART as u32) & 0x1) == 1;
+        // Each context notifies its hart while it has an interrupt above its threshold. The hart
+        // ORs the S-context output with the SEIP written by software.
+        let machine = self.highest_pending(CONTEXT_MACHINE, true);
+        self.irq = self.highest_pending(CONTEXT_SUPERVISOR, true);
+        *mip &= !(MIP_MEIP | MIP_SEIP);
+        if machine.is_some() {
+            *mip |= MIP_MEIP;
+        }
+        if self.irq.is_some() {
+            *mip |= MIP_SEIP;
+        }
+    }
 
-        let threshold       = self.read32(CONTEXT_BASE);
-        let interruptings   = [virtio_is_interrupting, uart_is_interrupting];
-        let enables         = [virtio_enabled, uart_enabled];
-        let priorities      = [virtio_priority, uart_priority];
-        let irqs            = [IrqNumber::VIRTIO, IrqNumber::UART];
+    // The pending source with the highest priority among those enabled for `context`. A claim
+    // considers every priority above 0, while notifications also require one above the threshold.
+    fn highest_pending(&self, context: usize, above_threshold: bool) -> Option<IrqNumber> {
+        let enable = self.read32_raw(ENABLE_BASE + context * ENABLE_STRIDE);
+        let threshold = match above_threshold {
+            true    => self.read32_raw(CONTEXT_BASE + context * CONTEXT_STRIDE),
+            false   => 0,
+        };
 
         let mut irq         = None;
-        let mut priority    = 0;
-
-        for i in 0..2 {
-            if  interruptings[i] && enables[i] &&
-                priorities[i] > threshold &&
-                priorities[i] > priority {
-                    irq         = Some(irqs[i]);
-                    priority    = priorities[i];
-                }
+        let mut priority    = threshold;
+
+        for source in SOURCES.iter() {
+            let bit = 1 << *source as u32;
+            let source_priority = self.read32_raw(PRIORITY_BASE + (*source as usize - 1) * 4);
+            if (self.pending & enable & bit) != 0 && source_priority > priority {
+                irq         = Some(*source);
+                priority    = source_priority;
+            }
         }
 
-        if irq != None {
-            self.irq = irq;
-            *mip |= MIP_SEIP;
+        irq
+    }
+
+    // Reading the claim register takes the highest-priority pending interrupt, or 0 if there is none
+    fn claim(&mut self, context: usize) -> u32 {
+        match self.highest_pending(context, false) {
+            Some(source)    => {
+                let bit = 1 << source as u32;
+                self.pending &= !bit;
+                self.claimed |= bit;
+                source as u32
+            },
+            None            => 0,
         }
     }
 
+    // Writing a claimed ID back to the claim register completes it, so the source can be pending again
+    fn complete(&mut self, source: u32) {
+        if SOURCES.iter().any(|irq| *irq as u32 == source) {
+            self.claimed &= !(1 << source);
+        }
+    }
+
+    // The context whose claim/complete register is at `addr`
+    fn claim_context(addr: usize) -> Option<usize> {
+        let offset = addr.checked_sub(CONTEXT_BASE)?;
+        match offset % CONTEXT_STRIDE == CLAIM_OFFSET && addr <= CONTEXT_TOP {
+            true    => Some(offset / CONTEXT_STRIDE),
+            false   => None,
+        }
+    }
+
+    fn read32_raw(&self, addr: usize) -> u32 {
+        self.read8(addr) as u32 | (self.read8(addr + 1) as u32) << 8 |
+        (self.read8(addr + 2) as u32) << 16 | (self.read8(addr + 3) as u32) << 24
+    }
+
     pub fn write8(&mut self, addr: usize, data: u8) {
         match addr {
+            PENDING_ARRAY_BASE ..= PENDING_ARRAY_TOP    => (),
             PRIORITY_BASE ..= PRIORITY_TOP              |
-            PENDING_ARRAY_BASE ..= PENDING_ARRAY_TOP    |
             ENABLE_BASE ..= ENABLE_TOP                  |
             CONTEXT_BASE ..= CONTEXT_TOP                => self.plic[addr] = data,
-            _                                           => unimplemented!(),
+            // Source 0 does not exist, and the gaps are reserved: writes are ignored
+            _                                           => (),
         }
     }
 
@@ -90,6 +160,10 @@ impl Plic {
     }
 
     pub fn write32(&mut self, addr: usize, data: u32) {
+        if Plic::claim_context(addr).is_some() {
+            self.complete(data);
+            return;
+        }
         self.write16(addr, (data & 0xFFFF) as u16);
         self.write16(addr + 2, ((data >> 16) & 0xFFFF) as u16);
     }
@@ -101,11 +175,19 @@ impl Plic {
 
     pub fn read8(&self, addr: usize) -> u8 {
         match addr {
+            // The pending bits are read-only
+            PENDING_ARRAY_BASE ..= PENDING_ARRAY_TOP    => {
+                let shift = (addr - PENDING_ARRAY_BASE) * 8;
+                match shift < 32 {
+                    true    => ((self.pending >> shift) & 0xFF) as u8,
+                    false   => 0,
+                }
+            },
             PRIORITY_BASE ..= PRIORITY_TOP              |
-            PENDING_ARRAY_BASE ..= PENDING_ARRAY_TOP    |
             ENABLE_BASE ..= ENABLE_TOP                  |
             CONTEXT_BASE ..= CONTEXT_TOP                => self.plic[addr],
-            _                                           => unimplemented!(),
+            // Source 0 does not exist, and the gaps are reserved: they read as zero
+            _                                           => 0,
         }
     }
     
@@ -114,6 +196,9 @@ impl Plic {
     }
 
     pub fn read32(&mut self, addr: usize) -> u32 {
+        if let Some(context) = Plic::claim_context(addr) {
+            return self.claim(context);
+        }
         self.read16(addr) as u32 | (self.read16(addr + 2)  as u32) << 16
     }
     
@@ -0,0 +1,109 @@
+/*
+ * PMP: Physical Memory Protection
+ * Reference:   RISC-V Privileged ISA Specification 3.6 Physical Memory Protection
+ *              https://riscv.org/specifications/privileged-isa/
+ */
+
+use crate::emulator::csr::*;
+use crate::emulator::mmu::ACCESS;
+
+/*
+ *  PMP configuration register format (one byte per entry, eight entries per pmpcfg on RV64)
+ *
+ *    7   6   5  4   3   2   1   0
+ *  +---+-------+-------+---+---+---+
+ *  | L |   0   |   A   | X | W | R |
+ *  +---+-------+-------+---+---+---+
+ *
+ */
+
+pub const PMP_R:        u8 = 1 << 0;    // Read permission
+pub const PMP_W:        u8 = 1 << 1;    // Write permission
+pub const PMP_X:        u8 = 1 << 2;    // Execute permission
+pub const PMP_A:        u8 = 0b11 << 3; // Address-matching mode
+pub const PMP_L:        u8 = 1 << 7;    // Locked: also enforced in M-mode and ignores writes
+
+// Address-matching modes (pmpcfg.A)
+pub const PMP_A_OFF:    u8 = 0b00 << 3; // Null region (disabled)
+pub const PMP_A_TOR:    u8 = 0b01 << 3; // Top of range
+pub const PMP_A_NA4:    u8 = 0b10 << 3; // Naturally aligned four-byte region
+pub const PMP_A_NAPOT:  u8 = 0b11 << 3; // Naturally aligned power-of-two region, >= 8 bytes
+
+// pmpaddr holds bits 55:2 of a 56-bit physical address
+pub const PMPADDR_MASK: u64 = 0x003F_FFFF_FFFF_FFFF;
+
+// Supported number of PMP entries
+pub const PMP_ENTRIES:  [usize; 3] = [0, 16, 64];
+
+// Configuration byte of the PMP entry `index`
+pub fn pmpcfg(csr: &Csr, index: usize) -> u8 {
+    let reg = PMPCFG0 + (index / 8 * 2) as u16;
+    (csr.read(reg) >> ((index % 8) * 8)) as u8
+}
+
+// Address range [base, top) covered by the PMP entry `index`
+fn pmp_range(csr: &Csr, index: usize) -> Option<(u64, u64)> {
+    let addr = csr.read(PMPADDR0 + index as u16);
+
+    match pmpcfg(csr, index) & PMP_A {
+        PMP_A_TOR   => {
+            let base = match index {
+                0   => 0,
+                _   => csr.read(PMPADDR0 + index as u16 - 1) << 2,
+            };
+            Some((base, addr << 2))
+        },
+        PMP_A_NA4   => Some((addr << 2, (addr << 2) + 4)),
+        PMP_A_NAPOT => {
+            // The number of trailing ones in pmpaddr encodes the region size
+            let ones = addr.trailing_ones();
+            if ones >= 54 {
+                return Some((0, u64::MAX));
+            }
+            let base = (addr & !((1 << ones) - 1)) << 2;
+            Some((base, base + (1 << (ones + 3))))
+        },
+        _           => None,
+    }
+}
+
+// Check an access of `size` bytes at `paddr` against the PMP entries.
+// Returns true if the access is permitted.
+pub fn pmp_check(csr: &Csr, paddr: usize, size: usize, access: ACCESS, priv_level: PrivLevel) -> bool {
+    let start = paddr as u64;
+    let end = start + size as u64;
+
+    // The lowest-numbered entry that matches any byte of the access determines the result
+    for index in 0..csr.pmp_entries {
+        let (base, top) = match pmp_range(csr, index) {
+            Some(range) => range,
+            None        => continue,
+        };
+
+        if top <= base || end <= base || top <= start {
+            continue;
+        }
+
+        // An access that only partially matches an entry fails
+        if start < base || top < end {
+            return false;
+        }
+
+        let cfg = pmpcfg(csr, index);
+
+        // Unlocked entries do not apply to M-mode
+        if priv_level == PrivLevel::MACHINE && (cfg & PMP_L) == 0 {
+            return true;
+        }
+
+        return match access {
+            ACCESS::LOAD    => (cfg & PMP_R) != 0,
+            ACCESS::STORE   => (cfg & PMP_W) != 0,
+            ACCESS::EXEC    => (cfg & PMP_X) != 0,
+            ACCESS::NONE    => true,
+        };
+    }
+
+    // No entry matched: M-mode succeeds, S/U-mode fails as soon as any entry is implemented
+    priv_level == PrivLevel::MACHINE || csr.pmp_entries == 0
+}
@@ -5,6 +5,11 @@
  *              http://byterunner.com/16550.html
  */
 
+use std::io::{ stdin, BufReader, Read };
+use std::sync::mpsc::{ channel, Receiver, RecvTimeoutError };
+use std::thread;
+use std::time::Duration;
+
 // Write mode
 const THR: usize    = 0b000;    // Transmit Holding Register
 const IER: usize    = 0b001;    // Interrupt Enable Register
@@ -45,6 +50,7 @@ pub struct Uart {
     lsr:    u8,         // Modem Control Register
     msr:    u8,         // Modem Status Register
     spr:    u8,         // Scratchpad Register
+    input:  Option<Receiver<u8>>,   // Bytes read from the host console
 }
 
 impl Uart {
@@ -61,6 +67,7 @@ impl Uart {
             lsr:    LSR_TX_HOLDING_EMPTY,
             msr:    0,
             spr:    0,
+            input:  None,
         }
     }
 
@@ -127,15 +134,11 @@ impl Uart {
     pub fn tick(&mut self) {
         self.clock = self.clock.wrapping_add(1);
 
-        /*
-        if (self.clock % BAUDRATE) == 0 && self.rhr == 0 {
-            let data = input;
-            if data != 0 {
-                self.rhr = data;
-                self.lsr |= LSR_RX_DATA_READY;
+        if self.clock.is_multiple_of(BAUDRATE as u64) && (self.lsr & LSR_RX_DATA_READY) == 0 {
+            if let Some(data) = self.input.as_ref().and_then(|input| input.try_recv().ok()) {
+                self.receive(data);
             }
         }
-        */
         
         if (self.clock % BAUDRATE as u64) == 0 && self.thr != 0 {
             print!("{}", self.thr as char);
@@ -144,6 +147,52 @@ impl Uart {
         }
     }
 
+    // Feed bytes typed on the host console into the receiver.
+    // stdin is read on its own thread, so that an idle guest can block on it.
+    pub fn attach_stdin(&mut self) {
+        let (sender, receiver) = channel();
+
+        thread::spawn(move || {
+            for byte in BufReader::new(stdin()).bytes() {
+                match byte {
+                    Ok(byte)    => if sender.send(byte).is_err() { break; },
+                    Err(_)      => break,
+                }
+            }
+        });
+
+        self.input = Some(receiver);
+    }
+
+    // Block for at most `timeout` until a byte is received
+    pub fn wait_for_input(&mut self, timeout: Duration) {
+        if (self.lsr & LSR_RX_DATA_READY) != 0 {
+            return;
+        }
+
+        let data = match &self.input {
+            Some(input) => input.recv_timeout(timeout),
+            None        => {
+                thread::sleep(timeout);
+                return;
+            },
+        };
+
+        match data {
+            Ok(data)                                => self.receive(data),
+            Err(RecvTimeoutError::Timeout)          => (),
+            Err(RecvTimeoutError::Disconnected)     => {
+                self.input = None;      // EOF on stdin
+                thread::sleep(timeout);
+            },
+        }
+    }
+
+    fn receive(&mut self, data: u8) {
+        self.rhr = data;
+        self.lsr |= LSR_RX_DATA_READY;
+    }
+
     pub fn is_interrupting(&mut self) -> bool {
         if (self.ier & IER_RHR_IRQ) != 0 {
             if self.rhr != 0 {
@@ -0,0 +1,1556 @@
+/*
+ * Vector Extension (RVV 1.0)
+ *
+ * The vector register file holds 32 registers of VLEN bits. Elements are packed little-endian,
+ * and a register group (LMUL > 1) is addressed as one long register starting at its first
+ * register, so element i of a group of SEW-bit elements lives at byte i * SEW / 8 of the group.
+ *
+ * Tail and inactive elements are always left undisturbed, which is a valid implementation of
+ * both the agnostic and the undisturbed policies.
+ *
+ * Floating-point elements are binary32 (SEW=32) or binary64 (SEW=64). The .vf forms take their
+ * scalar from the f registers, which only the vector unit uses, and the arithmetic is done by the
+ * software floating-point unit in float.rs, rounding as frm says and accruing fflags.
+ * vfrec7.v and vfrsqrt7.v are not implemented and raise an illegal instruction exception.
+ */
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::csr::*;
+use crate::emulator::exception::Exception;
+use crate::emulator::float::*;
+
+pub const VLEN: usize           = 128;      // Default vector register length in bits
+pub const ELEN: usize           = 64;       // Default maximum element width in bits
+pub const VLEN_MAX: usize       = 65536;    // Upper limit of VLEN set by the specification
+
+pub const VTYPE_VILL: u64       = 1 << 63;  // Illegal vtype: vector instructions other than vset{i}vl{i} trap
+
+const NVREGISTERS: usize        = 32;
+
+// funct3 of OP-V: the operand types
+const OPIVV: u8     = 0b000;    // integer, vector-vector
+const OPFVV: u8     = 0b001;    // floating-point, vector-vector
+const OPMVV: u8     = 0b010;    // mask/multiply, vector-vector
+const OPIVI: u8     = 0b011;    // integer, vector-immediate
+const OPIVX: u8     = 0b100;    // integer, vector-scalar
+const OPFVF: u8     = 0b101;    // floating-point, vector-scalar
+const OPMVX: u8     = 0b110;    // mask/multiply, vector-scalar
+const OPCFG: u8     = 0b111;    // vset{i}vl{i}
+
+// Fixed-point rounding modes (vxrm)
+const VXRM_RNU: u64 = 0b00;     // round-to-nearest-up
+const VXRM_RNE: u64 = 0b01;     // round-to-nearest-even
+const VXRM_RDN: u64 = 0b10;     // round-down (truncate)
+
+// Whether VLEN/ELEN is a configuration the vector unit supports
+pub fn is_valid_config(vlen: usize, elen: usize) -> bool {
+    (elen == 32 || elen == 64) && vlen.is_power_of_two() && vlen >= elen && vlen <= VLEN_MAX
+}
+
+pub struct VRegisters {
+    vreg: Vec<u8>,              // Vector registers v0..v31
+    pub vlen: usize,            // Bits per vector register
+    pub elen: usize,            // Widest supported element
+}
+
+impl VRegisters {
+    pub fn new(vlen: usize, elen: usize) -> Self {
+        VRegisters {
+            vreg: vec![0; NVREGISTERS * vlen / 8],
+            vlen,
+            elen,
+        }
+    }
+
+    pub fn vlenb(&self) -> usize {
+        self.vlen / 8
+    }
+
+    // Read element `index` of `eew` bits from the register group starting at `vreg`
+    pub fn read(&self, vreg: usize, index: usize, eew: usize) -> u64 {
+        let bytes = eew / 8;
+        let offset = vreg * self.vlenb() + index * bytes;
+        let mut data = [0; 8];
+        data[..bytes].copy_from_slice(&self.vreg[offset..offset + bytes]);
+        u64::from_le_bytes(data)
+    }
+
+    pub fn write(&mut self, vreg: usize, index: usize, eew: usize, data: u64) {
+        let bytes = eew / 8;
+        let offset = vreg * self.vlenb() + index * bytes;
+        self.vreg[offset..offset + bytes].copy_from_slice(&data.to_le_bytes()[..bytes]);
+    }
+
+    // Mask registers hold one bit per element
+    pub fn mask(&self, vreg: usize, index: usize) -> bool {
+        (self.vreg[vreg * self.vlenb() + index / 8] >> (index % 8)) & 1 != 0
+    }
+
+    pub fn write_mask(&mut self, vreg: usize, index: usize, bit: bool) {
+        let offset = vreg * self.vlenb() + index / 8;
+        match bit {
+            true    => self.vreg[offset] |= 1 << (index % 8),
+            false   => self.vreg[offset] &= !(1 << (index % 8)),
+        }
+    }
+}
+
+/*
+ *  vtype
+ *
+ *  63   62       8 7   6   5    3 2    0
+ *  +----+---------+---+---+------+------+
+ *  |vill| reserved|vma|vta| vsew | vlmul|
+ *  +----+---------+---+---+------+------+
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct VType {
+    pub sew: usize,             // Selected element width in bits
+    pub lmul_log2: i32,         // log2 of the register group multiplier (-3 ..= 3)
+    pub vill: bool,
+}
+
+impl VType {
+    pub fn new(vtype: u64, elen: usize) -> Self {
+        let illegal = VType { sew: 8, lmul_log2: 0, vill: true };
+
+        if (vtype & VTYPE_VILL) != 0 || (vtype >> 8) != 0 {
+            return illegal;
+        }
+
+        let lmul_log2 = match vtype & 0b111 {
+            0b100       => return illegal,  // reserved
+            vlmul @ 0b000 ..= 0b011 => vlmul as i32,
+            vlmul       => vlmul as i32 - 8,
+        };
+
+        let sew = match (vtype >> 3) & 0b111 {
+            vsew @ 0b000 ..= 0b011  => 8 << vsew,
+            _                       => return illegal,
+        };
+
+        // SEW must fit in LMUL * ELEN
+        if sew > elen || (lmul_log2 < 0 && sew > (elen >> -lmul_log2)) {
+            return illegal;
+        }
+
+        VType { sew, lmul_log2, vill: false }
+    }
+
+    // Maximum number of elements in a register group
+    pub fn vlmax(&self, vlen: usize) -> usize {
+        emul_elements(vlen, self.sew, self.lmul_log2)
+    }
+}
+
+// Number of `eew`-bit elements in a group of 2^emul_log2 registers
+fn emul_elements(vlen: usize, eew: usize, emul_log2: i32) -> usize {
+    match emul_log2 >= 0 {
+        true    => (vlen / eew) << emul_log2,
+        false   => (vlen / eew) >> -emul_log2,
+    }
+}
+
+// Number of registers in a group of 2^emul_log2 registers
+fn emul_registers(emul_log2: i32) -> usize {
+    1 << emul_log2.max(0)
+}
+
+// log2 of the EMUL that keeps EEW/EMUL equal to SEW/LMUL
+fn emul_log2(vtype: &VType, eew: usize) -> Result<i32, Exception> {
+    let emul_log2 = vtype.lmul_log2 + eew.trailing_zeros() as i32 - vtype.sew.trailing_zeros() as i32;
+    match emul_log2 {
+        -3 ..= 3    => Ok(emul_log2),
+        _           => Err(Exception::IllegalInst),
+    }
+}
+
+// A register group must start at a multiple of its size and fit in the register file
+fn check_group(vreg: usize, emul_log2: i32) -> Result<(), Exception> {
+    let regs = emul_registers(emul_log2);
+    if !vreg.is_multiple_of(regs) || vreg + regs > NVREGISTERS {
+        return Err(Exception::IllegalInst);
+    }
+    Ok(())
+}
+
+fn element_mask(eew: usize) -> u64 {
+    u64::MAX >> (64 - eew)
+}
+
+fn sext(data: u64, eew: usize) -> i64 {
+    ((data << (64 - eew)) as i64) >> (64 - eew)
+}
+
+fn signed_min(eew: usize) -> i128 {
+    -(1i128 << (eew - 1))
+}
+
+fn signed_max(eew: usize) -> i128 {
+    (1i128 << (eew - 1)) - 1
+}
+
+// Rounding increment when shifting `data` right by `shift` bits under the rounding mode `vxrm`
+fn rounding_increment(data: u128, shift: u32, vxrm: u64) -> u128 {
+    if shift == 0 {
+        return 0;
+    }
+
+    let bit = |n: u32| (data >> n) & 1;
+    let below = |n: u32| (data & ((1u128 << n) - 1)) != 0;
+
+    match vxrm {
+        VXRM_RNU    => bit(shift - 1),
+        VXRM_RNE    => bit(shift - 1) & ((below(shift - 1) as u128) | bit(shift)),
+        VXRM_RDN    => 0,
+        _           => ((bit(shift) == 0) && below(shift)) as u128,     // round-to-odd
+    }
+}
+
+fn roundoff_unsigned(data: u128, shift: u32, vxrm: u64) -> u128 {
+    (data >> shift) + rounding_increment(data, shift, vxrm)
+}
+
+fn roundoff_signed(data: i128, shift: u32, vxrm: u64) -> i128 {
+    (data >> shift) + rounding_increment(data as u128, shift, vxrm) as i128
+}
+
+// The second source operand of an arithmetic instruction
+#[derive(Copy, Clone)]
+enum Operand {
+    Vector(usize),      // vs1
+    Scalar(u64),        // x[rs1] or an immediate
+}
+
+impl Cpu {
+    // Replace the vector register file with one of `vlen` bits per register and `elen`-bit elements
+    pub fn configure_vector(&mut self, vlen: usize, elen: usize) {
+        self.vreg = VRegisters::new(vlen, elen);
+        self.csr.write_hw(VLENB, (vlen / 8) as u64);
+        self.csr.write_hw(VTYPE, VTYPE_VILL);
+        self.csr.write_hw(VL, 0);
+    }
+
+    fn vtype(&self) -> VType {
+        VType::new(self.csr.read(VTYPE), self.vreg.elen)
+    }
+
+    fn vl(&self) -> usize {
+        self.csr.read(VL) as usize
+    }
+
+    fn vstart(&self) -> usize {
+        self.csr.read(VSTART) as usize
+    }
+
+    fn is_active(&self, vm: bool, index: usize) -> bool {
+        vm || self.vreg.mask(0, index)
+    }
+
+    fn operand(&self, operand: Operand, index: usize, eew: usize) -> u64 {
+        match operand {
+            Operand::Vector(vs1)    => self.vreg.read(vs1, index, eew),
+            Operand::Scalar(data)   => data & element_mask(eew),
+        }
+    }
+
+    // Copy `count` elements of a register group, so that it can be overwritten while reading it
+    fn snapshot(&self, vreg: usize, eew: usize, count: usize) -> Vec<u64> {
+        (0..count).map(|i| self.vreg.read(vreg, i, eew)).collect()
+    }
+
+    // The common element loop: vd[i] = f(vs2[i], op[i], vd[i]) for the active elements in
+    // vstart..vl, where vd, vs2 and the operand each have their own element width.
+    // Results are written once all sources are read, so vd may overlap the sources.
+    fn vloop(&mut self, vm: bool, vd: (usize, usize), vs2: (usize, usize), op: (Operand, usize), mut f: impl FnMut(u64, u64, u64) -> u64) {
+        let (vd, vd_eew) = vd;
+        let (vs2, vs2_eew) = vs2;
+        let (op, op_eew) = op;
+
+        let results: Vec<(usize, u64)> = (self.vstart()..self.vl())
+            .filter(|&i| self.is_active(vm, i))
+            .map(|i| {
+                let a = self.vreg.read(vs2, i, vs2_eew);
+                let b = self.operand(op, i, op_eew);
+                let d = self.vreg.read(vd, i, vd_eew);
+                (i, f(a, b, d) & element_mask(vd_eew))
+            })
+            .collect();
+
+        for (i, data) in results {
+            self.vreg.write(vd, i, vd_eew, data);
+        }
+    }
+
+    // Compare loop: mask bit vd[i] = f(vs2[i], op[i]) for the active elements in vstart..vl
+    fn vcmp(&mut self, vm: bool, vd: usize, vs2: usize, op: Operand, sew: usize, mut f: impl FnMut(u64, u64) -> bool) {
+        let results: Vec<(usize, bool)> = (self.vstart()..self.vl())
+            .filter(|&i| self.is_active(vm, i))
+            .map(|i| (i, f(self.vreg.read(vs2, i, sew), self.operand(op, i, sew))))
+            .collect();
+
+        for (i, bit) in results {
+            self.vreg.write_mask(vd, i, bit);
+        }
+    }
+
+    fn set_vxsat(&mut self, saturated: bool) {
+        if saturated {
+            self.csr.write_hw(VXSAT, 1);
+        }
+    }
+
+    pub fn decode_opv(&mut self) -> Result<(), Exception> {
+        if !self.csr.vector_enabled() {
+            return Err(Exception::IllegalInst);
+        }
+
+        let funct3: u8 = ((self.instruction >> 12) & 0x7) as u8;
+
+        if funct3 == OPCFG {
+            self.vsetvl()?;
+        }
+        else {
+            if self.vtype().vill {
+                return Err(Exception::IllegalInst);
+            }
+
+            match funct3 {
+                OPIVV | OPIVX | OPIVI   => self.decode_opi(funct3)?,
+                OPMVV | OPMVX           => self.decode_opm(funct3)?,
+                OPFVV | OPFVF           => self.decode_opf(funct3)?,
+                _                       => unreachable!(),
+            }
+        }
+
+        self.csr.write_hw(VSTART, 0);
+        self.csr.set_vs_dirty();
+
+        Ok(())
+    }
+
+    /*
+     *  vsetvli     0 | zimm[10:0] | rs1  | 111 | rd | 1010111
+     *  vsetivli    11 | zimm[9:0] | uimm | 111 | rd | 1010111
+     *  vsetvl      1000000 | rs2  | rs1  | 111 | rd | 1010111
+     */
+    fn vsetvl(&mut self) -> Result<(), Exception> {
+        let rs1:    usize   = ((self.instruction >> 15) & 0x1F) as usize;
+        let rd:     usize   = ((self.instruction >> 7)  & 0x1F) as usize;
+
+        let (vtype, avl) = match self.instruction >> 30 {
+            // vsetvli
+            0b00 | 0b01 => (((self.instruction >> 20) & 0x7FF) as u64, None),
+            // vsetivli
+            0b11        => (((self.instruction >> 20) & 0x3FF) as u64, Some(rs1 as u64)),
+            // vsetvl
+            _           => {
+                if ((self.instruction >> 25) & 0x3F) != 0 {
+                    return Err(Exception::IllegalInst);
+                }
+                let rs2 = ((self.instruction >> 20) & 0x1F) as usize;
+                (self.register.read(rs2), None)
+            },
+        };
+
+        let decoded = VType::new(vtype, self.vreg.elen);
+        if decoded.vill {
+            self.csr.write_hw(VTYPE, VTYPE_VILL);
+            self.csr.write_hw(VL, 0);
+            self.register.write(rd, 0);
+            return Ok(());
+        }
+
+        let vlmax = decoded.vlmax(self.vreg.vlen) as u64;
+        let avl = match avl {
+            Some(uimm)                  => uimm,
+            None if rs1 != 0            => self.register.read(rs1),
+            None if rd != 0             => u64::MAX,            // vl = VLMAX
+            None                        => self.csr.read(VL),   // keep vl
+        };
+        let vl = avl.min(vlmax);
+
+        self.csr.write_hw(VTYPE, vtype);
+        self.csr.write_hw(VL, vl);
+        self.register.write(rd, vl);
+
+        Ok(())
+    }
+
+    /*
+     *  Vector loads and stores
+     *
+     *  31 29  28  27 26  25  24    20 19   15 14   12 11   7 6       0
+     *  +----+---+-----+----+--------+-------+-------+------+---------+
+     *  | nf |mew| mop | vm | lumop/ |  rs1  | width | vd/  | 0000111/|
+     *  |    |   |     |    |rs2/vs2 |       |       | vs3  | 0100111 |
+     *  +----+---+-----+----+--------+-------+-------+------+---------+
+     *
+     *  mop: 00 unit-stride, 01 indexed-unordered, 10 strided, 11 indexed-ordered
+     */
+    pub fn decode_vector_memory(&mut self, store: bool) -> Result<(), Exception> {
+        let nf:     usize   = ((self.instruction >> 29) & 0x7) as usize + 1;
+        let mew:    u32     = (self.instruction >> 28) & 0x1;
+        let mop:    u32     = (self.instruction >> 26) & 0x3;
+        let vm:     bool    = ((self.instruction >> 25) & 0x1) != 0;
+        let umop:   u32     = (self.instruction >> 20) & 0x1F;
+        let rs2:    usize   = ((self.instruction >> 20) & 0x1F) as usize;
+        let rs1:    usize   = ((self.instruction >> 15) & 0x1F) as usize;
+        let width:  u32     = (self.instruction >> 12) & 0x7;
+        let vd:     usize   = ((self.instruction >> 7)  & 0x1F) as usize;
+
+        // Other widths are scalar floating-point loads and stores, which are not implemented
+        let eew = match width {
+            0b000   => 8,
+            0b101   => 16,
+            0b110   => 32,
+            0b111   => 64,
+            _       => return Err(Exception::IllegalInst),
+        };
+
+        if !self.csr.vector_enabled() || mew != 0 || eew > self.vreg.elen {
+            return Err(Exception::IllegalInst);
+        }
+
+        let base = self.register.read(rs1) as usize;
+
+        match (mop, umop) {
+            // Whole register
+            (0b00, 0b01000) => {
+                if !vm || !nf.is_power_of_two() {
+                    return Err(Exception::IllegalInst);
+                }
+                check_group(vd, nf.trailing_zeros() as i32)?;
+                let evl = nf * self.vreg.vlen / eew;
+                self.vector_access(store, vd, eew, base, evl)?;
+            },
+            // Mask
+            (0b00, 0b01011) => {
+                if !vm || nf != 1 || eew != 8 || self.vtype().vill {
+                    return Err(Exception::IllegalInst);
+                }
+                let evl = self.vl().div_ceil(8);
+                self.vector_access(store, vd, 8, base, evl)?;
+            },
+            // Unit-stride, strided and fault-only-first
+            (0b00, 0b00000) | (0b00, 0b10000) | (0b10, _)  => {
+                let vtype = self.vtype();
+                if vtype.vill || (store && umop == 0b10000) {
+                    return Err(Exception::IllegalInst);
+                }
+                let emul_log2 = emul_log2(&vtype, eew)?;
+                self.check_segment(store, vd, emul_log2, nf, vm)?;
+
+                let stride = match mop {
+                    0b10    => self.register.read(rs2) as usize,
+                    _       => nf * eew / 8,
+                };
+                let fault_only_first = mop == 0b00 && umop == 0b10000;
+
+                self.segment_access(store, vd, eew, emul_log2, nf, vm, fault_only_first, |_, i, field| {
+                    base.wrapping_add(i.wrapping_mul(stride)).wrapping_add(field * eew / 8)
+                })?;
+            },
+            // Indexed: the data has EEW = SEW, the offsets in vs2 have the EEW given by width
+            (0b01, _) | (0b11, _)  => {
+                let vtype = self.vtype();
+                if vtype.vill {
+                    return Err(Exception::IllegalInst);
+                }
+                let index_emul_log2 = emul_log2(&vtype, eew)?;
+                check_group(rs2, index_emul_log2)?;
+                self.check_segment(store, vd, vtype.lmul_log2, nf, vm)?;
+
+                let sew = vtype.sew;
+                self.segment_access(store, vd, sew, vtype.lmul_log2, nf, vm, false, |cpu, i, field| {
+                    let offset = cpu.vreg.read(rs2, i, eew) as usize;
+                    base.wrapping_add(offset).wrapping_add(field * sew / 8)
+                })?;
+            },
+            _   => return Err(Exception::IllegalInst),
+        }
+
+        self.csr.write_hw(VSTART, 0);
+        if !store {
+            self.csr.set_vs_dirty();
+        }
+
+        Ok(())
+    }
+
+    // nf fields of 2^emul_log2 registers each, starting at vd
+    fn check_segment(&self, store: bool, vd: usize, emul_log2: i32, nf: usize, vm: bool) -> Result<(), Exception> {
+        check_group(vd, emul_log2)?;
+        if vd + nf * emul_registers(emul_log2) > NVREGISTERS {
+            return Err(Exception::IllegalInst);
+        }
+        // A masked load may not overwrite the mask
+        if !store && !vm && vd == 0 {
+            return Err(Exception::IllegalInst);
+        }
+        Ok(())
+    }
+
+    // Access elements vstart..evl of the registers from `vreg` on, consecutive in memory from `base`,
+    // regardless of vl and the mask as whole register and mask loads and stores do
+    fn vector_access(&mut self, store: bool, vreg: usize, eew: usize, base: usize, evl: usize) -> Result<(), Exception> {
+        for i in self.vstart()..evl {
+            let addr = base.wrapping_add(i * eew / 8);
+            if let Err(exception) = self.access_element(store, vreg, i, eew, addr) {
+                self.csr.write_hw(VSTART, i as u64);
+                return Err(exception);
+            }
+        }
+        Ok(())
+    }
+
+    // Access the nf fields of each active element in vstart..vl; field f of element i is
+    // element i of the register group vd + f * EMUL
+    #[allow(clippy::too_many_arguments)]
+    fn segment_access(&mut self, store: bool, vd: usize, eew: usize, emul_log2: i32, nf: usize, vm: bool, fault_only_first: bool, address: impl Fn(&Cpu, usize, usize) -> usize) -> Result<(), Exception> {
+        let regs = emul_registers(emul_log2);
+
+        for i in self.vstart()..self.vl() {
+            if !self.is_active(vm, i) {
+                continue;
+            }
+            for field in 0..nf {
+                let addr = address(self, i, field);
+                if let Err(exception) = self.access_element(store, vd + field * regs, i, eew, addr) {
+                    // Fault-only-first: only element 0 traps, a later fault shortens vl instead
+                    if fault_only_first && i > 0 {
+                        self.csr.write_hw(VL, i as u64);
+                        return Ok(());
+                    }
+                    self.csr.write_hw(VSTART, i as u64);
+                    return Err(exception);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn access_element(&mut self, store: bool, vreg: usize, index: usize, eew: usize, addr: usize) -> Result<(), Exception> {
+        if store {
+            let data = self.vreg.read(vreg, index, eew);
+            match eew {
+                8   => self.mmu.write8(&self.csr, addr, data as u8),
+                16  => self.mmu.write16(&self.csr, addr, data as u16),
+                32  => self.mmu.write32(&self.csr, addr, data as u32),
+                _   => self.mmu.write64(&self.csr, addr, data),
+            }
+        }
+        else {
+            let data = match eew {
+                8   => self.mmu.read8(&self.csr, addr)? as u64,
+                16  => self.mmu.read16(&self.csr, addr)? as u64,
+                32  => self.mmu.read32(&self.csr, addr)? as u64,
+                _   => self.mmu.read64(&self.csr, addr)?,
+            };
+            self.vreg.write(vreg, index, eew, data);
+            Ok(())
+        }
+    }
+
+    /*
+     *  Vector arithmetic instructions
+     *
+     *  31    26  25  24   20 19   15 14  12 11  7 6       0
+     *  +-------+----+-------+-------+------+-----+---------+
+     *  | funct6| vm |  vs2  |vs1/rs1|funct3| vd  | 1010111 |
+     *  |       |    |       | /imm  |      | /rd |         |
+     *  +-------+----+-------+-------+------+-----+---------+
+     */
+    fn decode_opi(&mut self, funct3: u8) -> Result<(), Exception> {
+        let funct6: u8      = ((self.instruction >> 26) & 0x3F) as u8;
+        let vm:     bool    = ((self.instruction >> 25) & 0x1) != 0;
+        let vs2:    usize   = ((self.instruction >> 20) & 0x1F) as usize;
+        let rs1:    usize   = ((self.instruction >> 15) & 0x1F) as usize;
+        let vd:     usize   = ((self.instruction >> 7)  & 0x1F) as usize;
+
+        let vtype = self.vtype();
+        let sew = vtype.sew;
+        let vlmax = vtype.vlmax(self.vreg.vlen);
+        let vxrm = self.csr.read(VXRM);
+
+        // Immediates are sign-extended, except for shift amounts and slide offsets
+        let uimm = rs1 as u64;
+        let op = match funct3 {
+            OPIVV   => Operand::Vector(rs1),
+            OPIVX   => Operand::Scalar(self.register.read(rs1)),
+            _       => Operand::Scalar(sext(uimm, 5) as u64),
+        };
+        let shift_op = match funct3 {
+            OPIVI   => Operand::Scalar(uimm),
+            _       => op,
+        };
+
+        // Whole register moves check their own registers, masks and reductions write a single register
+        let single_dest = matches!(funct6, 0b010_001 | 0b010_011 | 0b011_000 ..= 0b011_111 | 0b110_000 | 0b110_001);
+        let narrowing = (0b101_100 ..= 0b101_111).contains(&funct6);
+        if funct6 != 0b100_111 || funct3 != OPIVI {
+            check_group(vs2, if narrowing { vtype.lmul_log2 + 1 } else { vtype.lmul_log2 })?;
+            if !single_dest {
+                check_group(vd, vtype.lmul_log2)?;
+                if !vm && vd == 0 {
+                    return Err(Exception::IllegalInst);
+                }
+            }
+            // vrgatherei16 has its own index EMUL
+            if funct3 == OPIVV && funct6 != 0b001_110 {
+                check_group(rs1, vtype.lmul_log2)?;
+            }
+        }
+
+        let wide = (vd, sew);
+        let same = (vs2, sew);
+        let signed = |x: u64| sext(x, sew);
+        let shamt = |x: u64| (x & (sew as u64 - 1)) as u32;
+
+        match (funct6, funct3) {
+            // VADD
+            (0b000_000, _)          => self.vloop(vm, wide, same, (op, sew), |a, b, _| a.wrapping_add(b)),
+            // VSUB
+            (0b000_010, OPIVV)      |
+            (0b000_010, OPIVX)      => self.vloop(vm, wide, same, (op, sew), |a, b, _| a.wrapping_sub(b)),
+            // VRSUB
+            (0b000_011, OPIVX)      |
+            (0b000_011, OPIVI)      => self.vloop(vm, wide, same, (op, sew), |a, b, _| b.wrapping_sub(a)),
+            // VMINU
+            (0b000_100, OPIVV)      |
+            (0b000_100, OPIVX)      => self.vloop(vm, wide, same, (op, sew), |a, b, _| a.min(b)),
+            // VMIN
+            (0b000_101, OPIVV)      |
+            (0b000_101, OPIVX)      => self.vloop(vm, wide, same, (op, sew), |a, b, _| signed(a).min(signed(b)) as u64),
+            // VMAXU
+            (0b000_110, OPIVV)      |
+            (0b000_110, OPIVX)      => self.vloop(vm, wide, same, (op, sew), |a, b, _| a.max(b)),
+            // VMAX
+            (0b000_111, OPIVV)      |
+            (0b000_111, OPIVX)      => self.vloop(vm, wide, same, (op, sew), |a, b, _| signed(a).max(signed(b)) as u64),
+            // VAND
+            (0b001_001, _)          => self.vloop(vm, wide, same, (op, sew), |a, b, _| a & b),
+            // VOR
+            (0b001_010, _)          => self.vloop(vm, wide, same, (op, sew), |a, b, _| a | b),
+            // VXOR
+            (0b001_011, _)          => self.vloop(vm, wide, same, (op, sew), |a, b, _| a ^ b),
+            // VRGATHER
+            (0b001_100, _)          => {
+                if vd == vs2 || (funct3 == OPIVV && vd == rs1) {
+                    return Err(Exception::IllegalInst);
+                }
+                // A scalar index is XLEN bits wide, whatever SEW is
+                let source = self.snapshot(vs2, sew, vlmax);
+                let index_eew = if funct3 == OPIVV { sew } else { 64 };
+                self.vloop(vm, wide, same, (shift_op, index_eew), |_, b, _| source.get(b as usize).copied().unwrap_or(0));
+            },
+            // VRGATHEREI16
+            (0b001_110, OPIVV)      => {
+                if vd == vs2 || vd == rs1 {
+                    return Err(Exception::IllegalInst);
+                }
+                check_group(rs1, emul_log2(&vtype, 16)?)?;
+                let source = self.snapshot(vs2, sew, vlmax);
+                self.vloop(vm, wide, same, (op, 16), |_, b, _| source.get(b as usize).copied().unwrap_or(0));
+            },
+            // VSLIDEUP
+            (0b001_110, _)          => {
+                if vd == vs2 {
+                    return Err(Exception::IllegalInst);
+                }
+                let offset = match shift_op { Operand::Scalar(offset) => offset as usize, _ => unreachable!() };
+                for i in self.vstart().max(offset)..self.vl() {
+                    if self.is_active(vm, i) {
+                        let data = self.vreg.read(vs2, i - offset, sew);
+                        self.vreg.write(vd, i, sew, data);
+                    }
+                }
+            },
+            // VSLIDEDOWN
+            (0b001_111, OPIVX)      |
+            (0b001_111, OPIVI)      => {
+                let offset = match shift_op { Operand::Scalar(offset) => offset as usize, _ => unreachable!() };
+                let source = self.snapshot(vs2, sew, vlmax);
+                for i in self.vstart()..self.vl() {
+                    if self.is_active(vm, i) {
+                        let data = i.checked_add(offset).and_then(|src| source.get(src)).copied().unwrap_or(0);
+                        self.vreg.write(vd, i, sew, data);
+                    }
+                }
+            },
+            // VADC
+            (0b010_000, _) if !vm   => {
+                for i in self.vstart()..self.vl() {
+                    let carry = self.vreg.mask(0, i) as u64;
+                    let data = self.vreg.read(vs2, i, sew).wrapping_add(self.operand(op, i, sew)).wrapping_add(carry);
+                    self.vreg.write(vd, i, sew, data & element_mask(sew));
+                }
+            },
+            // VMADC
+            (0b010_001, _)          => {
+                let results: Vec<bool> = (0..self.vl()).map(|i| {
+                    let carry = !vm && self.vreg.mask(0, i);
+                    let sum = self.vreg.read(vs2, i, sew) as u128 + self.operand(op, i, sew) as u128 + carry as u128;
+                    (sum >> sew) != 0
+                }).collect();
+                for (i, bit) in results.into_iter().enumerate().skip(self.vstart()) {
+                    self.vreg.write_mask(vd, i, bit);
+                }
+            },
+            // VSBC
+            (0b010_010, OPIVV)      |
+            (0b010_010, OPIVX) if !vm   => {
+                for i in self.vstart()..self.vl() {
+                    let borrow = self.vreg.mask(0, i) as u64;
+                    let data = self.vreg.read(vs2, i, sew).wrapping_sub(self.operand(op, i, sew)).wrapping_sub(borrow);
+                    self.vreg.write(vd, i, sew, data & element_mask(sew));
+                }
+            },
+            // VMSBC
+            (0b010_011, OPIVV)      |
+            (0b010_011, OPIVX)      => {
+                let results: Vec<bool> = (0..self.vl()).map(|i| {
+                    let borrow = !vm && self.vreg.mask(0, i);
+                    (self.vreg.read(vs2, i, sew) as u128) < self.operand(op, i, sew) as u128 + borrow as u128
+                }).collect();
+                for (i, bit) in results.into_iter().enumerate().skip(self.vstart()) {
+                    self.vreg.write_mask(vd, i, bit);
+                }
+            },
+            // VMERGE
+            (0b010_111, _) if !vm   => {
+                for i in self.vstart()..self.vl() {
+                    let data = match self.vreg.mask(0, i) {
+                        true    => self.operand(op, i, sew),
+                        false   => self.vreg.read(vs2, i, sew),
+                    };
+                    self.vreg.write(vd, i, sew, data);
+                }
+            },
+            // VMV.V
+            (0b010_111, _) if vs2 == 0  => self.vloop(true, wide, same, (op, sew), |_, b, _| b),
+            // VMSEQ
+            (0b011_000, _)          => self.vcmp(vm, vd, vs2, op, sew, |a, b| a == b),
+            // VMSNE
+            (0b011_001, _)          => self.vcmp(vm, vd, vs2, op, sew, |a, b| a != b),
+            // VMSLTU
+            (0b011_010, OPIVV)      |
+            (0b011_010, OPIVX)      => self.vcmp(vm, vd, vs2, op, sew, |a, b| a < b),
+            // VMSLT
+            (0b011_011, OPIVV)      |
+            (0b011_011, OPIVX)      => self.vcmp(vm, vd, vs2, op, sew, |a, b| signed(a) < signed(b)),
+            // VMSLEU
+            (0b011_100, _)          => self.vcmp(vm, vd, vs2, op, sew, |a, b| a <= b),
+            // VMSLE
+            (0b011_101, _)          => self.vcmp(vm, vd, vs2, op, sew, |a, b| signed(a) <= signed(b)),
+            // VMSGTU
+            (0b011_110, OPIVX)      |
+            (0b011_110, OPIVI)      => self.vcmp(vm, vd, vs2, op, sew, |a, b| a > b),
+            // VMSGT
+            (0b011_111, OPIVX)      |
+            (0b011_111, OPIVI)      => self.vcmp(vm, vd, vs2, op, sew, |a, b| signed(a) > signed(b)),
+            // VSADDU
+            (0b100_000, _)          => {
+                let mut sat = false;
+                self.vloop(vm, wide, same, (op, sew), |a, b, _| {
+                    let sum = a as u128 + b as u128;
+                    sat |= sum > element_mask(sew) as u128;
+                    sum.min(element_mask(sew) as u128) as u64
+                });
+                self.set_vxsat(sat);
+            },
+            // VSADD
+            (0b100_001, _)          => {
+                let mut sat = false;
+                self.vloop(vm, wide, same, (op, sew), |a, b, _| {
+                    let sum = signed(a) as i128 + signed(b) as i128;
+                    let clamped = sum.clamp(signed_min(sew), signed_max(sew));
+                    sat |= sum != clamped;
+                    clamped as u64
+                });
+                self.set_vxsat(sat);
+            },
+            // VSSUBU
+            (0b100_010, OPIVV)      |
+            (0b100_010, OPIVX)      => {
+                let mut sat = false;
+                self.vloop(vm, wide, same, (op, sew), |a, b, _| {
+                    sat |= a < b;
+                    a.saturating_sub(b)
+                });
+                self.set_vxsat(sat);
+            },
+            // VSSUB
+            (0b100_011, OPIVV)      |
+            (0b100_011, OPIVX)      => {
+                let mut sat = false;
+                self.vloop(vm, wide, same, (op, sew), |a, b, _| {
+                    let diff = signed(a) as i128 - signed(b) as i128;
+                    let clamped = diff.clamp(signed_min(sew), signed_max(sew));
+                    sat |= diff != clamped;
+                    clamped as u64
+                });
+                self.set_vxsat(sat);
+            },
+            // VSLL
+            (0b100_101, _)          => self.vloop(vm, wide, same, (shift_op, sew), |a, b, _| a << shamt(b)),
+            // VSMUL
+            (0b100_111, OPIVV)      |
+            (0b100_111, OPIVX)      => {
+                let mut sat = false;
+                self.vloop(vm, wide, same, (op, sew), |a, b, _| {
+                    let product = signed(a) as i128 * signed(b) as i128;
+                    let result = roundoff_signed(product, sew as u32 - 1, vxrm);
+                    let clamped = result.clamp(signed_min(sew), signed_max(sew));
+                    sat |= result != clamped;
+                    clamped as u64
+                });
+                self.set_vxsat(sat);
+            },
+            // VMV<nr>R.V
+            (0b100_111, OPIVI)      => {
+                let nr = uimm as usize + 1;
+                if !vm || !nr.is_power_of_two() || nr > 8 {
+                    return Err(Exception::IllegalInst);
+                }
+                check_group(vd, nr.trailing_zeros() as i32)?;
+                check_group(vs2, nr.trailing_zeros() as i32)?;
+                let evl = nr * self.vreg.vlen / sew;
+                for i in self.vstart()..evl {
+                    let data = self.vreg.read(vs2, i, sew);
+                    self.vreg.write(vd, i, sew, data);
+                }
+            },
+            // VSRL
+            (0b101_000, _)          => self.vloop(vm, wide, same, (shift_op, sew), |a, b, _| a >> shamt(b)),
+            // VSRA
+            (0b101_001, _)          => self.vloop(vm, wide, same, (shift_op, sew), |a, b, _| (signed(a) >> shamt(b)) as u64),
+            // VSSRL
+            (0b101_010, _)          => self.vloop(vm, wide, same, (shift_op, sew), |a, b, _| roundoff_unsigned(a as u128, shamt(b), vxrm) as u64),
+            // VSSRA
+            (0b101_011, _)          => self.vloop(vm, wide, same, (shift_op, sew), |a, b, _| roundoff_signed(signed(a) as i128, shamt(b), vxrm) as u64),
+            // VNSRL, VNSRA, VNCLIPU and VNCLIP narrow a 2*SEW source
+            (0b101_100 ..= 0b101_111, _)    => {
+                if sew * 2 > self.vreg.elen || vtype.lmul_log2 == 3 {
+                    return Err(Exception::IllegalInst);
+                }
+                let narrow_shamt = |x: u64| (x & (2 * sew as u64 - 1)) as u32;
+                let signed_wide = |x: u64| sext(x, 2 * sew);
+                let mut sat = false;
+                self.vloop(vm, wide, (vs2, 2 * sew), (shift_op, sew), |a, b, _| {
+                    match funct6 {
+                        0b101_100   => a >> narrow_shamt(b),
+                        0b101_101   => (signed_wide(a) >> narrow_shamt(b)) as u64,
+                        0b101_110   => {
+                            let result = roundoff_unsigned(a as u128, narrow_shamt(b), vxrm);
+                            sat |= result > element_mask(sew) as u128;
+                            result.min(element_mask(sew) as u128) as u64
+                        },
+                        _           => {
+                            let result = roundoff_signed(signed_wide(a) as i128, narrow_shamt(b), vxrm);
+                            let clamped = result.clamp(signed_min(sew), signed_max(sew));
+                            sat |= result != clamped;
+                            clamped as u64
+                        },
+                    }
+                });
+                self.set_vxsat(sat);
+            },
+            // VWREDSUMU
+            (0b110_000, OPIVV)      => self.widening_reduction(vm, vd, vs2, rs1, false)?,
+            // VWREDSUM
+            (0b110_001, OPIVV)      => self.widening_reduction(vm, vd, vs2, rs1, true)?,
+            _   => return Err(Exception::IllegalInst),
+        }
+
+        Ok(())
+    }
+
+    // vd[0] (2*SEW) = vs1[0] (2*SEW) + the sum of the active vs2 elements, extended to 2*SEW
+    fn widening_reduction(&mut self, vm: bool, vd: usize, vs2: usize, vs1: usize, signed: bool) -> Result<(), Exception> {
+        let sew = self.vtype().sew;
+        if self.vstart() != 0 || sew * 2 > self.vreg.elen {
+            return Err(Exception::IllegalInst);
+        }
+        if self.vl() == 0 {
+            return Ok(());
+        }
+
+        let mut sum = self.vreg.read(vs1, 0, 2 * sew);
+        for i in (0..self.vl()).filter(|&i| self.is_active(vm, i)) {
+            let data = self.vreg.read(vs2, i, sew);
+            let data = match signed {
+                true    => sext(data, sew) as u64,
+                false   => data,
+            };
+            sum = sum.wrapping_add(data);
+        }
+        self.vreg.write(vd, 0, 2 * sew, sum & element_mask(2 * sew));
+
+        Ok(())
+    }
+
+    fn decode_opm(&mut self, funct3: u8) -> Result<(), Exception> {
+        let funct6: u8      = ((self.instruction >> 26) & 0x3F) as u8;
+        let vm:     bool    = ((self.instruction >> 25) & 0x1) != 0;
+        let vs2:    usize   = ((self.instruction >> 20) & 0x1F) as usize;
+        let rs1:    usize   = ((self.instruction >> 15) & 0x1F) as usize;
+        let vd:     usize   = ((self.instruction >> 7)  & 0x1F) as usize;
+
+        let vtype = self.vtype();
+        let sew = vtype.sew;
+        let vxrm = self.csr.read(VXRM);
+
+        let op = match funct3 {
+            OPMVV   => Operand::Vector(rs1),
+            _       => Operand::Scalar(self.register.read(rs1)),
+        };
+
+        let same = (vs2, sew);
+        let signed = |x: u64| sext(x, sew);
+
+        // Widening instructions need 2*SEW elements and a 2*LMUL destination group
+        if funct6 >= 0b110_000 {
+            if sew * 2 > self.vreg.elen || vtype.lmul_log2 == 3 {
+                return Err(Exception::IllegalInst);
+            }
+            check_group(vd, vtype.lmul_log2 + 1)?;
+            if !vm && vd == 0 {
+                return Err(Exception::IllegalInst);
+            }
+        }
+
+        match (funct6, funct3) {
+            // VREDSUM, VREDAND, VREDOR, VREDXOR, VREDMINU, VREDMIN, VREDMAXU and VREDMAX
+            (0b000_000 ..= 0b000_111, OPMVV)    => {
+                if self.vstart() != 0 {
+                    return Err(Exception::IllegalInst);
+                }
+                check_group(vs2, vtype.lmul_log2)?;
+                if self.vl() == 0 {
+                    return Ok(());
+                }
+                let mut acc = self.vreg.read(rs1, 0, sew);
+                for i in (0..self.vl()).filter(|&i| self.is_active(vm, i)) {
+                    let data = self.vreg.read(vs2, i, sew);
+                    acc = match funct6 {
+                        0b000_000   => acc.wrapping_add(data),
+                        0b000_001   => acc & data,
+                        0b000_010   => acc | data,
+                        0b000_011   => acc ^ data,
+                        0b000_100   => acc.min(data),
+                        0b000_101   => signed(acc).min(signed(data)) as u64,
+                        0b000_110   => acc.max(data),
+                        _           => signed(acc).max(signed(data)) as u64,
+                    };
+                }
+                self.vreg.write(vd, 0, sew, acc & element_mask(sew));
+            },
+            // VAADDU, VAADD, VASUBU and VASUB
+            (0b001_000 ..= 0b001_011, _)    => {
+                self.check_arithmetic(&vtype, vm, vd, vs2, funct3 == OPMVV, rs1)?;
+                self.vloop(vm, (vd, sew), same, (op, sew), |a, b, _| {
+                    match funct6 {
+                        0b001_000   => roundoff_unsigned(a as u128 + b as u128, 1, vxrm) as u64,
+                        0b001_001   => roundoff_signed(signed(a) as i128 + signed(b) as i128, 1, vxrm) as u64,
+                        0b001_010   => roundoff_signed(a as i128 - b as i128, 1, vxrm) as u64,
+                        _           => roundoff_signed(signed(a) as i128 - signed(b) as i128, 1, vxrm) as u64,
+                    }
+                });
+            },
+            // VSLIDE1UP
+            (0b001_110, OPMVX)  => self.vslide1(true, vm, vd, vs2, self.register.read(rs1))?,
+            // VSLIDE1DOWN
+            (0b001_111, OPMVX)  => self.vslide1(false, vm, vd, vs2, self.register.read(rs1))?,
+            // VWXUNARY0
+            (0b010_000, OPMVV)  => {
+                match rs1 {
+                    // VMV.X.S
+                    0b0_0000 if vm  => self.register.write(vd, sext(self.vreg.read(vs2, 0, sew), sew) as u64),
+                    // VCPOP.M
+                    0b1_0000        => {
+                        if self.vstart() != 0 {
+                            return Err(Exception::IllegalInst);
+                        }
+                        let count = (0..self.vl()).filter(|&i| self.is_active(vm, i) && self.vreg.mask(vs2, i)).count();
+                        self.register.write(vd, count as u64);
+                    },
+                    // VFIRST.M
+                    0b1_0001        => {
+                        if self.vstart() != 0 {
+                            return Err(Exception::IllegalInst);
+                        }
+                        let first = (0..self.vl()).find(|&i| self.is_active(vm, i) && self.vreg.mask(vs2, i));
+                        self.register.write(vd, first.map_or(u64::MAX, |i| i as u64));
+                    },
+                    _               => return Err(Exception::IllegalInst),
+                }
+            },
+            // VMV.S.X
+            (0b010_000, OPMVX) if vs2 == 0 && vm    => {
+                if self.vstart() < self.vl() {
+                    let data = self.register.read(rs1) & element_mask(sew);
+                    self.vreg.write(vd, 0, sew, data);
+                }
+            },
+            // VXUNARY0: VZEXT.VF8, VSEXT.VF8, VZEXT.VF4, VSEXT.VF4, VZEXT.VF2 and VSEXT.VF2
+            (0b010_010, OPMVV)  => {
+                let factor = match rs1 {
+                    0b0_0010 | 0b0_0011 => 8,
+                    0b0_0100 | 0b0_0101 => 4,
+                    0b0_0110 | 0b0_0111 => 2,
+                    _                   => return Err(Exception::IllegalInst),
+                };
+                let source_eew = sew / factor;
+                if source_eew < 8 {
+                    return Err(Exception::IllegalInst);
+                }
+                check_group(vd, vtype.lmul_log2)?;
+                check_group(vs2, emul_log2(&vtype, source_eew)?)?;
+                if !vm && vd == 0 {
+                    return Err(Exception::IllegalInst);
+                }
+                let sign = (rs1 & 1) != 0;
+                self.vloop(vm, (vd, sew), (vs2, source_eew), (Operand::Scalar(0), sew), |a, _, _| {
+                    match sign {
+                        true    => sext(a, source_eew) as u64,
+                        false   => a,
+                    }
+                });
+            },
+            // VMUNARY0
+            (0b010_100, OPMVV)  => self.decode_vmunary0(vm, vd, vs2, rs1, &vtype)?,
+            // VCOMPRESS
+            (0b010_111, OPMVV)  => {
+                if !vm || self.vstart() != 0 || vd == vs2 || vd == rs1 {
+                    return Err(Exception::IllegalInst);
+                }
+                check_group(vd, vtype.lmul_log2)?;
+                check_group(vs2, vtype.lmul_log2)?;
+                let selected: Vec<u64> = (0..self.vl())
+                    .filter(|&i| self.vreg.mask(rs1, i))
+                    .map(|i| self.vreg.read(vs2, i, sew))
+                    .collect();
+                for (i, data) in selected.into_iter().enumerate() {
+                    self.vreg.write(vd, i, sew, data);
+                }
+            },
+            // VMANDN, VMAND, VMOR, VMXOR, VMORN, VMNAND, VMNOR and VMXNOR
+            (0b011_000 ..= 0b011_111, OPMVV)    => {
+                if !vm {
+                    return Err(Exception::IllegalInst);
+                }
+                for i in self.vstart()..self.vl() {
+                    let a = self.vreg.mask(vs2, i);
+                    let b = self.vreg.mask(rs1, i);
+                    let bit = match funct6 {
+                        0b011_000   => a & !b,
+                        0b011_001   => a & b,
+                        0b011_010   => a | b,
+                        0b011_011   => a ^ b,
+                        0b011_100   => a | !b,
+                        0b011_101   => !(a & b),
+                        0b011_110   => !(a | b),
+                        _           => !(a ^ b),
+                    };
+                    self.vreg.write_mask(vd, i, bit);
+                }
+            },
+            // VDIVU, VDIV, VREMU, VREM, VMULHU, VMUL, VMULHSU and VMULH
+            (0b100_000 ..= 0b100_111, _)    => {
+                self.check_arithmetic(&vtype, vm, vd, vs2, funct3 == OPMVV, rs1)?;
+                let mask = element_mask(sew);
+                self.vloop(vm, (vd, sew), same, (op, sew), |a, b, _| {
+                    match funct6 {
+                        0b100_000   => a.checked_div(b).unwrap_or(mask),
+                        0b100_001   => match b {
+                            0   => mask,
+                            _   => (signed(a) as i128 / signed(b) as i128) as u64,   // overflow wraps to the dividend
+                        },
+                        0b100_010   => a.checked_rem(b).unwrap_or(a),
+                        0b100_011   => match b {
+                            0   => a,
+                            _   => (signed(a) as i128 % signed(b) as i128) as u64,
+                        },
+                        0b100_100   => ((a as u128 * b as u128) >> sew) as u64,
+                        0b100_101   => a.wrapping_mul(b),
+                        0b100_110   => ((signed(a) as i128 * b as i128) >> sew) as u64,
+                        _           => ((signed(a) as i128 * signed(b) as i128) >> sew) as u64,
+                    }
+                });
+            },
+            // VMADD, VNMSUB, VMACC and VNMSAC
+            (0b101_001, _) | (0b101_011, _) | (0b101_101, _) | (0b101_111, _)   => {
+                self.check_arithmetic(&vtype, vm, vd, vs2, funct3 == OPMVV, rs1)?;
+                self.vloop(vm, (vd, sew), same, (op, sew), |a, b, d| {
+                    match funct6 {
+                        0b101_001   => d.wrapping_mul(b).wrapping_add(a),
+                        0b101_011   => a.wrapping_sub(d.wrapping_mul(b)),
+                        0b101_101   => b.wrapping_mul(a).wrapping_add(d),
+                        _           => d.wrapping_sub(b.wrapping_mul(a)),
+                    }
+                });
+            },
+            // VWADDU, VWADD, VWSUBU, VWSUB and their .W forms
+            (0b110_000 ..= 0b110_111, _)    => {
+                let wide_source = funct6 >= 0b110_100;
+                let vs2_eew = if wide_source { 2 * sew } else { sew };
+                check_group(vs2, if wide_source { vtype.lmul_log2 + 1 } else { vtype.lmul_log2 })?;
+                let sign = (funct6 & 1) != 0;
+                let extend = move |x: u64, eew: usize| match sign {
+                    true    => sext(x, eew) as u64,
+                    false   => x,
+                };
+                self.vloop(vm, (vd, 2 * sew), (vs2, vs2_eew), (op, sew), |a, b, _| {
+                    let a = extend(a, vs2_eew);
+                    let b = extend(b, sew);
+                    match (funct6 >> 1) & 1 {
+                        0   => a.wrapping_add(b),
+                        _   => a.wrapping_sub(b),
+                    }
+                });
+            },
+            // VWMULU, VWMULSU, VWMUL, VWMACCU, VWMACC, VWMACCUS and VWMACCSU
+            (0b111_000, _) | (0b111_010, _) | (0b111_011, _) |
+            (0b111_100, _) | (0b111_101, _) | (0b111_110, OPMVX) | (0b111_111, _)  => {
+                check_group(vs2, vtype.lmul_log2)?;
+                let unsigned = |x: u64| x as i128;
+                let signed = |x: u64| sext(x, sew) as i128;
+                self.vloop(vm, (vd, 2 * sew), same, (op, sew), |a, b, d| {
+                    let product = match funct6 {
+                        0b111_000 | 0b111_100   => unsigned(a) * unsigned(b),
+                        0b111_011 | 0b111_101   => signed(a) * signed(b),
+                        0b111_010 | 0b111_110   => signed(a) * unsigned(b),
+                        _                       => unsigned(a) * signed(b),
+                    } as u64;
+                    match funct6 >= 0b111_100 {
+                        true    => product.wrapping_add(d),
+                        false   => product,
+                    }
+                });
+            },
+            _   => return Err(Exception::IllegalInst),
+        }
+
+        Ok(())
+    }
+
+    // vslide1up (up = true) or vslide1down by one element, inserting `scalar` at the vacated end
+    fn vslide1(&mut self, up: bool, vm: bool, vd: usize, vs2: usize, scalar: u64) -> Result<(), Exception> {
+        let vtype = self.vtype();
+        let sew = vtype.sew;
+        if up && vd == vs2 {
+            return Err(Exception::IllegalInst);
+        }
+        self.check_arithmetic(&vtype, vm, vd, vs2, false, 0)?;
+
+        let scalar = scalar & element_mask(sew);
+        let source = self.snapshot(vs2, sew, vtype.vlmax(self.vreg.vlen));
+        let vl = self.vl();
+        for i in self.vstart()..vl {
+            if self.is_active(vm, i) {
+                let data = match (up, i) {
+                    (true, 0)                   => scalar,
+                    (true, _)                   => source[i - 1],
+                    (false, _) if i + 1 == vl   => scalar,
+                    (false, _)                  => source[i + 1],
+                };
+                self.vreg.write(vd, i, sew, data);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Register groups of a single-width instruction
+    fn check_arithmetic(&self, vtype: &VType, vm: bool, vd: usize, vs2: usize, vector_source: bool, vs1: usize) -> Result<(), Exception> {
+        check_group(vd, vtype.lmul_log2)?;
+        check_group(vs2, vtype.lmul_log2)?;
+        if vector_source {
+            check_group(vs1, vtype.lmul_log2)?;
+        }
+        if !vm && vd == 0 {
+            return Err(Exception::IllegalInst);
+        }
+        Ok(())
+    }
+
+    fn decode_vmunary0(&mut self, vm: bool, vd: usize, vs2: usize, vs1: usize, vtype: &VType) -> Result<(), Exception> {
+        let sew = vtype.sew;
+
+        match vs1 {
+            // VMSBF, VMSOF and VMSIF
+            0b0_0001 ..= 0b0_0011   => {
+                if self.vstart() != 0 || vd == vs2 || (!vm && vd == 0) {
+                    return Err(Exception::IllegalInst);
+                }
+                let mut found = false;
+                for i in 0..self.vl() {
+                    if !self.is_active(vm, i) {
+                        continue;
+                    }
+                    let first = !found && self.vreg.mask(vs2, i);
+                    let bit = match vs1 {
+                        0b0_0001    => !found && !first,    // before first
+                        0b0_0010    => first,               // only first
+                        _           => !found,              // including first
+                    };
+                    found |= first;
+                    self.vreg.write_mask(vd, i, bit);
+                }
+            },
+            // VIOTA
+            0b1_0000    => {
+                if self.vstart() != 0 || (!vm && vd == 0) {
+                    return Err(Exception::IllegalInst);
+                }
+                check_group(vd, vtype.lmul_log2)?;
+                let mut count = 0;
+                for i in 0..self.vl() {
+                    if !self.is_active(vm, i) {
+                        continue;
+                    }
+                    let bit = self.vreg.mask(vs2, i);
+                    self.vreg.write(vd, i, sew, count & element_mask(sew));
+                    count += bit as u64;
+                }
+            },
+            // VID
+            0b1_0001 if vs2 == 0    => {
+                check_group(vd, vtype.lmul_log2)?;
+                if !vm && vd == 0 {
+                    return Err(Exception::IllegalInst);
+                }
+                for i in self.vstart()..self.vl() {
+                    if self.is_active(vm, i) {
+                        self.vreg.write(vd, i, sew, i as u64 & element_mask(sew));
+                    }
+                }
+            },
+            _   => return Err(Exception::IllegalInst),
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Floating-point instructions (OPFVV and OPFVF)
+     *
+     * The .vf forms take f[rs1], NaN-boxed to SEW bits. Results are rounded as frm says, and the
+     * exception flags of all elements accrue in fflags.
+     */
+    fn decode_opf(&mut self, funct3: u8) -> Result<(), Exception> {
+        let funct6: u8      = ((self.instruction >> 26) & 0x3F) as u8;
+        let vm:     bool    = ((self.instruction >> 25) & 0x1) != 0;
+        let vs2:    usize   = ((self.instruction >> 20) & 0x1F) as usize;
+        let rs1:    usize   = ((self.instruction >> 15) & 0x1F) as usize;
+        let vd:     usize   = ((self.instruction >> 7)  & 0x1F) as usize;
+
+        let vtype = self.vtype();
+        let sew = vtype.sew;
+
+        // The floating-point state must not be Off, and frm must hold a valid rounding mode
+        let mut env = FloatEnv::new(self.csr.read(FRM));
+        if !self.csr.float_enabled() || env.rm > RM_RMM {
+            return Err(Exception::IllegalInst);
+        }
+
+        // Conversions pick their own formats, as one side has integer elements
+        if (funct6, funct3) == (0b010_010, OPFVV) {
+            self.decode_vfunary0(vm, vd, vs2, rs1, &vtype, &mut env)?;
+            self.accrue_fflags(env.flags);
+            return Ok(());
+        }
+
+        let f = Format::of_width(sew).ok_or(Exception::IllegalInst)?;
+        let scalar = self.freg.read(rs1, f);
+        let op = match funct3 {
+            OPFVV   => Operand::Vector(rs1),
+            _       => Operand::Scalar(scalar),
+        };
+        let same = (vs2, sew);
+
+        // Widening instructions need 2*SEW elements, and all but the reductions a 2*LMUL destination group
+        let w = match funct6 >= 0b110_000 {
+            true    => Format::of_width(2 * sew).filter(|_| 2 * sew <= self.vreg.elen).ok_or(Exception::IllegalInst)?,
+            false   => f,
+        };
+        if funct6 >= 0b110_000 && funct6 != 0b110_001 && funct6 != 0b110_011 {
+            if vtype.lmul_log2 == 3 {
+                return Err(Exception::IllegalInst);
+            }
+            check_group(vd, vtype.lmul_log2 + 1)?;
+            if !vm && vd == 0 {
+                return Err(Exception::IllegalInst);
+            }
+            if funct3 == OPFVV {
+                check_group(rs1, vtype.lmul_log2)?;
+            }
+        }
+
+        match (funct6, funct3) {
+            // VFADD, VFSUB, VFRSUB, VFMUL, VFDIV and VFRDIV
+            (0b000_000, _) | (0b000_010, _) | (0b100_111, OPFVF) | (0b100_100, _) | (0b100_000, _) | (0b100_001, OPFVF)   => {
+                self.check_arithmetic(&vtype, vm, vd, vs2, funct3 == OPFVV, rs1)?;
+                self.vloop(vm, (vd, sew), same, (op, sew), |a, b, _| {
+                    match funct6 {
+                        0b000_000   => env.add(f, a, b),
+                        0b000_010   => env.sub(f, a, b),
+                        0b100_111   => env.sub(f, b, a),
+                        0b100_100   => env.mul(f, a, b),
+                        0b100_000   => env.div(f, a, b),
+                        _           => env.div(f, b, a),
+                    }
+                });
+            },
+            // VFMIN and VFMAX
+            (0b000_100, _) | (0b000_110, _) => {
+                self.check_arithmetic(&vtype, vm, vd, vs2, funct3 == OPFVV, rs1)?;
+                self.vloop(vm, (vd, sew), same, (op, sew), |a, b, _| {
+                    match funct6 {
+                        0b000_100   => env.min(f, a, b),
+                        _           => env.max(f, a, b),
+                    }
+                });
+            },
+            // VFSGNJ, VFSGNJN and VFSGNJX
+            (0b001_000 ..= 0b001_010, _)    => {
+                self.check_arithmetic(&vtype, vm, vd, vs2, funct3 == OPFVV, rs1)?;
+                let sign_bit = f.sign_bit();
+                self.vloop(vm, (vd, sew), same, (op, sew), |a, b, _| {
+                    let sign = match funct6 {
+                        0b001_000   => b & sign_bit,
+                        0b001_001   => !b & sign_bit,
+                        _           => (a ^ b) & sign_bit,
+                    };
+                    (a & !sign_bit) | sign
+                });
+            },
+            // VFSLIDE1UP
+            (0b001_110, OPFVF)  => self.vslide1(true, vm, vd, vs2, scalar)?,
+            // VFSLIDE1DOWN
+            (0b001_111, OPFVF)  => self.vslide1(false, vm, vd, vs2, scalar)?,
+            // VFMERGE
+            (0b010_111, OPFVF) if !vm   => {
+                self.check_arithmetic(&vtype, vm, vd, vs2, false, rs1)?;
+                for i in self.vstart()..self.vl() {
+                    let data = match self.vreg.mask(0, i) {
+                        true    => scalar,
+                        false   => self.vreg.read(vs2, i, sew),
+                    };
+                    self.vreg.write(vd, i, sew, data);
+                }
+            },
+            // VFMV.V.F
+            (0b010_111, OPFVF) if vs2 == 0  => {
+                check_group(vd, vtype.lmul_log2)?;
+                self.vloop(true, (vd, sew), same, (op, sew), |_, b, _| b);
+            },
+            // VMFEQ, VMFLE, VMFLT, VMFNE, VMFGT and VMFGE
+            (0b011_000, _) | (0b011_001, _) | (0b011_011, _) | (0b011_100, _) | (0b011_101, OPFVF) | (0b011_111, OPFVF) => {
+                check_group(vs2, vtype.lmul_log2)?;
+                if funct3 == OPFVV {
+                    check_group(rs1, vtype.lmul_log2)?;
+                }
+                self.vcmp(vm, vd, vs2, op, sew, |a, b| {
+                    match funct6 {
+                        0b011_000   => env.eq(f, a, b),
+                        0b011_001   => env.le(f, a, b),
+                        0b011_011   => env.lt(f, a, b),
+                        0b011_100   => !env.eq(f, a, b),
+                        0b011_101   => env.lt(f, b, a),
+                        _           => env.le(f, b, a),
+                    }
+                });
+            },
+            // VFREDUSUM, VFREDOSUM, VFREDMIN and VFREDMAX
+            (0b000_001, OPFVV) | (0b000_011, OPFVV) | (0b000_101, OPFVV) | (0b000_111, OPFVV) => {
+                if self.vstart() != 0 {
+                    return Err(Exception::IllegalInst);
+                }
+                check_group(vs2, vtype.lmul_log2)?;
+                if self.vl() == 0 {
+                    return Ok(());
+                }
+                // The unordered sum is computed in element order as well
+                let mut acc = self.vreg.read(rs1, 0, sew);
+                for i in (0..self.vl()).filter(|&i| self.is_active(vm, i)) {
+                    let data = self.vreg.read(vs2, i, sew);
+                    acc = match funct6 {
+                        0b000_101   => env.min(f, acc, data),
+                        0b000_111   => env.max(f, acc, data),
+                        _           => env.add(f, acc, data),
+                    };
+                }
+                self.vreg.write(vd, 0, sew, acc);
+            },
+            // VFWREDUSUM and VFWREDOSUM: vd[0] (2*SEW) = vs1[0] (2*SEW) + the active vs2 elements
+            (0b110_001, OPFVV) | (0b110_011, OPFVV) => {
+                if self.vstart() != 0 {
+                    return Err(Exception::IllegalInst);
+                }
+                check_group(vs2, vtype.lmul_log2)?;
+                if self.vl() == 0 {
+                    return Ok(());
+                }
+                let mut acc = self.vreg.read(rs1, 0, 2 * sew);
+                for i in (0..self.vl()).filter(|&i| self.is_active(vm, i)) {
+                    let data = env.convert(f, w, self.vreg.read(vs2, i, sew));
+                    acc = env.add(w, acc, data);
+                }
+                self.vreg.write(vd, 0, 2 * sew, acc);
+            },
+            // VFMV.F.S
+            (0b010_000, OPFVV) if rs1 == 0 && vm    => {
+                self.freg.write(vd, f, self.vreg.read(vs2, 0, sew));
+                self.csr.set_fs_dirty();
+            },
+            // VFMV.S.F
+            (0b010_000, OPFVF) if vs2 == 0 && vm    => {
+                if self.vstart() < self.vl() {
+                    self.vreg.write(vd, 0, sew, scalar);
+                }
+            },
+            // VFUNARY1
+            (0b010_011, OPFVV)  => {
+                self.check_arithmetic(&vtype, vm, vd, vs2, false, rs1)?;
+                match rs1 {
+                    // VFSQRT.V
+                    0b0_0000    => self.vloop(vm, (vd, sew), same, (Operand::Scalar(0), sew), |a, _, _| env.sqrt(f, a)),
+                    // VFCLASS.V
+                    0b1_0000    => self.vloop(vm, (vd, sew), same, (Operand::Scalar(0), sew), |a, _, _| f.classify(a)),
+                    _           => return Err(Exception::IllegalInst),
+                }
+            },
+            // VFMADD, VFNMADD, VFMSUB, VFNMSUB, VFMACC, VFNMACC, VFMSAC and VFNMSAC
+            (0b101_000 ..= 0b101_111, _)    => {
+                self.check_arithmetic(&vtype, vm, vd, vs2, funct3 == OPFVV, rs1)?;
+                let (negate_product, negate_addend) = fma_negations(funct6);
+                let negate = |x: u64, flip: bool| if flip { x ^ f.sign_bit() } else { x };
+                self.vloop(vm, (vd, sew), same, (op, sew), |a, b, d| {
+                    // The vfmadd family multiplies vs1 by vd and adds vs2, the vfmacc family multiplies vs1 by vs2 and adds vd
+                    let (multiplicand, addend) = match funct6 < 0b101_100 {
+                        true    => (d, a),
+                        false   => (a, d),
+                    };
+                    env.fma(f, negate(b, negate_product), multiplicand, negate(addend, negate_addend))
+                });
+            },
+            // VFWADD, VFWSUB and their .W forms, whose vs2 is already 2*SEW wide
+            (0b110_000, _) | (0b110_010, _) | (0b110_100, _) | (0b110_110, _)   => {
+                let wide_source = funct6 >= 0b110_100;
+                let vs2_eew = if wide_source { 2 * sew } else { sew };
+                check_group(vs2, if wide_source { vtype.lmul_log2 + 1 } else { vtype.lmul_log2 })?;
+                self.vloop(vm, (vd, 2 * sew), (vs2, vs2_eew), (op, sew), |a, b, _| {
+                    let a = if wide_source { a } else { env.convert(f, w, a) };
+                    let b = env.convert(f, w, b);
+                    match funct6 & 0b010 {
+                        0   => env.add(w, a, b),
+                        _   => env.sub(w, a, b),
+                    }
+                });
+            },
+            // VFWMUL
+            (0b111_000, _)  => {
+                check_group(vs2, vtype.lmul_log2)?;
+                self.vloop(vm, (vd, 2 * sew), same, (op, sew), |a, b, _| {
+                    let (a, b) = (env.convert(f, w, a), env.convert(f, w, b));
+                    env.mul(w, a, b)
+                });
+            },
+            // VFWMACC, VFWNMACC, VFWMSAC and VFWNMSAC
+            (0b111_100 ..= 0b111_111, _)    => {
+                check_group(vs2, vtype.lmul_log2)?;
+                let (negate_product, negate_addend) = fma_negations(funct6);
+                let negate = |x: u64, flip: bool| if flip { x ^ w.sign_bit() } else { x };
+                self.vloop(vm, (vd, 2 * sew), same, (op, sew), |a, b, d| {
+                    let (a, b) = (env.convert(f, w, a), env.convert(f, w, b));
+                    env.fma(w, negate(b, negate_product), a, negate(d, negate_addend))
+                });
+            },
+            _   => return Err(Exception::IllegalInst),
+        }
+
+        self.accrue_fflags(env.flags);
+
+        Ok(())
+    }
+
+    /*
+     * VFUNARY0: conversions between integers and floating-point values, and between binary32 and binary64
+     *
+     *  vs1[4:3]: 00 single-width (vfcvt), 01 widening (vfwcvt), 10 narrowing (vfncvt)
+     *  vs1[2:0]: 000 xu.f, 001 x.f, 010 f.xu, 011 f.x, 100 f.f, 101 rod.f.f, 110 rtz.xu.f, 111 rtz.x.f
+     */
+    fn decode_vfunary0(&mut self, vm: bool, vd: usize, vs2: usize, vs1: usize, vtype: &VType, env: &mut FloatEnv) -> Result<(), Exception> {
+        let sew = vtype.sew;
+        let (source_eew, dest_eew) = match vs1 >> 3 {
+            0b00    => (sew, sew),
+            0b01    => (sew, 2 * sew),
+            0b10    => (2 * sew, sew),
+            _       => return Err(Exception::IllegalInst),
+        };
+        if source_eew.max(dest_eew) > self.vreg.elen {
+            return Err(Exception::IllegalInst);
+        }
+        check_group(vd, emul_log2(vtype, dest_eew)?)?;
+        check_group(vs2, emul_log2(vtype, source_eew)?)?;
+        if !vm && vd == 0 {
+            return Err(Exception::IllegalInst);
+        }
+
+        let float = |eew: usize| Format::of_width(eew).ok_or(Exception::IllegalInst);
+        let op = vs1 & 0b111;
+        let signed = (op & 1) != 0;
+
+        // The RTZ and ROD forms round towards zero and to odd whatever frm says
+        let mut conv = FloatEnv::new(match op {
+            0b101           => RM_ROD,
+            0b110 | 0b111   => RM_RTZ,
+            _               => env.rm,
+        });
+
+        let source = (vs2, source_eew);
+        let dest = (vd, dest_eew);
+        match op {
+            // XU.F, X.F, RTZ.XU.F and RTZ.X.F
+            0b000 | 0b001 | 0b110 | 0b111   => {
+                let from = float(source_eew)?;
+                self.vloop(vm, dest, source, (Operand::Scalar(0), sew), |a, _, _| conv.to_int(from, a, dest_eew, signed));
+            },
+            // F.XU and F.X
+            0b010 | 0b011   => {
+                let to = float(dest_eew)?;
+                self.vloop(vm, dest, source, (Operand::Scalar(0), sew), |a, _, _| conv.from_int(to, a, source_eew, signed));
+            },
+            // F.F widens or narrows, ROD.F.F only narrows
+            0b100 | 0b101 if source_eew > dest_eew || (op == 0b100 && source_eew < dest_eew)  => {
+                let (from, to) = (float(source_eew)?, float(dest_eew)?);
+                self.vloop(vm, dest, source, (Operand::Scalar(0), sew), |a, _, _| conv.convert(from, to, a));
+            },
+            _   => return Err(Exception::IllegalInst),
+        }
+
+        env.flags |= conv.flags;
+
+        Ok(())
+    }
+
+    // Accrue the exception flags raised by an instruction in fflags
+    fn accrue_fflags(&mut self, flags: u64) {
+        if flags != 0 {
+            let fflags = self.csr.read(FFLAGS) | flags;
+            self.csr.write(FFLAGS, fflags);
+        }
+    }
+}
+
+// Negations of the product and of the addend of a fused multiply-add (funct6[1:0]: macc, nmacc, msac, nmsac)
+fn fma_negations(funct6: u8) -> (bool, bool) {
+    match funct6 & 0b11 {
+        0b00    => (false, false),
+        0b01    => (true, true),
+        0b10    => (false, true),
+        _       => (true, false),
+    }
+}
@@ -3,11 +3,13 @@
 
 use crate::emulator::bus::DRAM_BASE;
 use crate::emulator::dram::*;
+use crate::emulator::disk::*;
 
-const MAX_DISK:                 usize   = 1024 * 1024 * 128;  // 128MiB
-const SECTOR_SIZE:              usize   = 512;
+use std::convert::TryInto;
 
-// MMIO Device Legacy Register Layout
+// MMIO Device Register Layout
+// GuestPageSize, QueueAlign and QueuePFN only exist in the legacy interface (version 1), and QueueReady,
+// QueueDesc, QueueDriver, QueueDevice and ConfigGeneration only in the modern one (version 2)
 const MAGIC_VALUE_BASE:         usize   = 0x000;
 const MAGIC_VALUE_TOP:          usize   = 0x003;
 const VERSION:                  usize   = 0x004;
@@ -34,6 +36,8 @@ const QUEUE_ALIGN_BASE:         usize   = 0x03C;
 const QUEUE_ALIGN_TOP:          usize   = 0x03F;
 const QUEUE_PFN_BASE:           usize   = 0x040;
 const QUEUE_PFN_TOP:            usize   = 0x043;
+const QUEUE_READY_BASE:         usize   = 0x044;
+const QUEUE_READY_TOP:          usize   = 0x047;
 const QUEUE_NOTIFY_BASE:        usize   = 0x050;
 const QUEUE_NOTIFY_TOP:         usize   = 0x053;
 const INTERRUPT_STATUS_BASE:    usize   = 0x060;
@@ -41,13 +45,23 @@ const INTERRUPT_STATUS_TOP:     usize   = 0x063;
 const INTERRUPT_ACK:            usize   = 0x064;
 const STATUS_BASE:              usize   = 0x070;
 const STATUS_TOP:               usize   = 0x073;
+const QUEUE_DESC_BASE:          usize   = 0x080;    // QueueDescLow and QueueDescHigh
+const QUEUE_DESC_TOP:           usize   = 0x087;
+const QUEUE_DRIVER_BASE:        usize   = 0x090;    // QueueDriverLow and QueueDriverHigh
+const QUEUE_DRIVER_TOP:         usize   = 0x097;
+const QUEUE_DEVICE_BASE:        usize   = 0x0A0;    // QueueDeviceLow and QueueDeviceHigh
+const QUEUE_DEVICE_TOP:         usize   = 0x0A7;
+const CONFIG_GENERATION_BASE:   usize   = 0x0FC;
+const CONFIG_GENERATION_TOP:    usize   = 0x0FF;
+const CONFIG_BASE:              usize   = 0x100;    // Device-specific configuration space
+const CONFIG_TOP:               usize   = 0x1FF;
 
 /* This marks a buffer as continuing via the next field. */ 
 const VIRTQ_DESC_F_NEXT:            u8  = 1;
 /* This marks a buffer as write-only (otherwise read-only). */
 const VIRTQ_DESC_F_WRITE:           u8  = 2;
 /* This means the buffer contains a list of buffer descriptors. */
-const _VIRTQ_DESC_F_INDIRECT:        u8  = 4;
+const VIRTQ_DESC_F_INDIRECT:         u8  = 4;
 
 /* The device uses this in used->flags to advise the driver: don’t kick me 
  * when you add a buffer.  It’s unreliable, so it’s simply an 
@@ -56,31 +70,34 @@ const _VIRTQ_USED_F_NO_NOTIFY:       u8  = 1;
 /* The driver uses this in avail->flags to advise the device: don’t 
  * interrupt me when you consume a buffer.  It’s unreliable, so it’s 
  * simply an optimization.  */ 
-const _VIRTQ_AVAIL_F_NO_INTERRUPT:   u8  = 1;
+const VIRTQ_AVAIL_F_NO_INTERRUPT:    u8  = 1;
 
 /* Support for indirect descriptors */
-const _VIRTIO_F_INDIRECT_DESC:       u8  = 28;
+const VIRTIO_F_INDIRECT_DESC:        u8  = 28;
 
 /* Support for avail_event and used_event fields */
-const _VIRTIO_F_EVENT_IDX:           u8  = 29;
+const VIRTIO_F_EVENT_IDX:            u8  = 29;
 
 /* Arbitrary descriptor layouts. */
 const _VIRTIO_F_ANY_LAYOUT:          u8  = 27;
 
+// Ring features supported by both interfaces
+const RING_FEATURES:                u64 = (1 << VIRTIO_F_INDIRECT_DESC) | (1 << VIRTIO_F_EVENT_IDX);
+
 // Device Status Field
 const _STATUS_ACK:               u8  = 1;
 const _STATUS_DRIVER:            u8  = 2;
 const _STATUS_FAILED:            u8  = 128;
-const _STATUS_FEATURED:          u8  = 8;
+const STATUS_FEATURED:           u8  = 8;
 const _STATUS_DRIVER_OK:         u8  = 4;
-const _STATUS_DEVICE_NEED_RESET: u8  = 64;
+const STATUS_DEVICE_NEED_RESET:  u8  = 64;
 
 // Feature bits
 const _VIRTIO_BLK_F_BARRIER:         u8  = 0;    // only legacy interface
 const _VIRTIO_BLK_F_SIZE_MAX:        u8  = 1;
 const _VIRTIO_BLK_F_SEG_MAX:         u8  = 2;
 const _VIRTIO_BLK_F_GEOMETRY:        u8  = 4;
-const _VIRTIO_BLK_F_RO:              u8  = 5;
+const VIRTIO_BLK_F_RO:               u8  = 5;
 const _VIRTIO_BLK_F_BLK_SIZE:        u8  = 6;
 const _VIRTIO_BLK_F_SCSI:            u8  = 7;    // only legacy interface
 const _VIRTIO_BLK_F_FLUSH:           u8  = 9;    // also called  VIRTIO_BLK_F_WCE
@@ -92,7 +109,7 @@ const _VIRTIO_BLK_F_WRITE_ZEROES:    u8  = 14;
 // Reserved Feature Bits
 const _VIRTIO_F_RING_INDIRECT_DESC : u8  = 28;
 const _VIRTIO_F_RING_EVENT_IDX:      u8  = 29;
-const _VIRTIO_F_VERSION_1:           u8  = 32;
+const VIRTIO_F_VERSION_1:            u8  = 32;
 const _VIRTIO_F_ACCESS_PLATFORM:     u8  = 33;
 const _VIRTIO_F_RING_PACKED:         u8  = 34;
 const _VIRTIO_F_IN_ORDER:            u8  = 35;
@@ -102,16 +119,24 @@ const _VIRTIO_F_NOTIFICATION_DATA:   u8  = 38;
 
 // The type of the request (virtio_blk_req.type)
 // ToDo: rewrite to enum
-const _VIRTIO_BLK_T_IN:              u8  = 0;
-const _VIRTIO_BLK_T_OUT:             u8  = 1;
-const _VIRTIO_BLK_T_FLUSH:           u8  = 4;
+const VIRTIO_BLK_T_IN:               u32 = 0;
+const VIRTIO_BLK_T_OUT:              u32 = 1;
+const VIRTIO_BLK_T_FLUSH:            u32 = 4;
+const VIRTIO_BLK_T_GET_ID:           u32 = 8;
 const _VIRTIO_BLK_T_DISCARD:         u8  = 11;
 const _VIRTIO_BLK_T_WRITE_ZEROES:    u8  = 13;
 
 // The final status byte (virtio_blk_req.status)
-const _VIRTIO_BLK_S_OK:              u8  = 0;
-const _VIRTIO_BLK_S_IOERR:           u8  = 1;
-const _VIRTIO_BLK_S_UNSUPP:          u8  = 2;
+const VIRTIO_BLK_S_OK:               u8  = 0;
+const VIRTIO_BLK_S_IOERR:            u8  = 1;
+const VIRTIO_BLK_S_UNSUPP:           u8  = 2;
+
+// Device identifier returned by VIRTIO_BLK_T_GET_ID, zero-padded to 20 bytes
+const VIRTIO_BLK_ID_BYTES:           usize = 20;
+const VIRTIO_BLK_ID:                 &[u8] = b"riscv-virtio-blk";
+
+// Interrupt status bits
+const INTERRUPT_USED_BUFFER:         u64 = 1;   // The device has used a buffer in a virtqueue
 
 // For legacy interface
 const _VIRTIO_BLK_T_FLUSH_OUT:       u8  = 5;
@@ -119,15 +144,6 @@ const _VIRTIO_BLK_T_BARRIER:         u32 = 0x80000000;
 const _VIRTIO_BLK_T_SCSI_CMD:        u8  = 2;
 const _VIRTIO_BLK_T_SCSI_CMD_OUT:    u8  = 3;
 
-#[derive(Debug)]
-#[repr(C)]
-#[repr(align(4096))]
-struct virtq {
-    desc:   Vec<virtq_desc>,
-    avail:  Vec<virtq_avail>,
-    used:   Vec<virtq_used>,
-}
-
 /* Virtqueue descriptors: 16 bytes. 
  * These can chain together via "next". */ 
 #[derive(Debug)]
@@ -151,21 +167,6 @@ struct virtq_desc {
     next:   u16,
 }
 
-#[derive(Debug)]
-#[repr(C)]
-struct virtq_avail {
-    /*
-     * Flags
-     *  0x1:    The driver uses this in avail->flags to advise the device: don’t
-     *          interrupt me when you consume a buffer.  It’s unreliable, so it’s
-     *          simply an optimization.
-     */
-    flags:      u16,
-    idx:        u16,
-    ring:       Vec<u16>,
-    used_event: u16,
-}
-
 #[derive(Debug)]
 #[repr(C)]
 struct virtq_used_elem {
@@ -175,20 +176,6 @@ struct virtq_used_elem {
     len:    u32,
 }
 
-#[derive(Debug)]
-#[repr(C)]
-struct virtq_used {
-    /*
-     * Flags
-     *  0x1:    The device uses this in used->flags to advise the driver: don’t kick me
-     *          when you add a buffer.  It’s unreliable, so it’s simply an optimization. 
-     */
-    flags:          u16,
-    idx:            u16,
-    ring:           Vec<virtq_used_elem>,
-    avail_event:    u16,
-}
-
 #[derive(Debug)]
 #[repr(C)]
 struct virtio_blk_config {
@@ -294,18 +281,19 @@ pub enum DeviceID {
 #[derive(Debug)]
 pub struct Virtio {
     clock:              u64,
-    disk:               Vec<u8>,
-    last_avail_idx:     usize,      // The number of the last entry on the Available Ring
+    disk:               Disk,
+    last_avail_idx:     u16,        // The number of available entries processed so far (free-running)
     notify_changed:     bool,       // dirty bit of the queue notifier register
+    legacy:             bool,       // Legacy interface (version 1) with the queue in guest pages
 
-    // MMIO Device Legacy Register
+    // MMIO Device Register
     magic_value:        u32,        // Magic value
     vendor_id:          u32,        // Virtio Subsystem Vendor ID
     version:            u8,         // Device version number
     device_id:          DeviceID,   // Virtio Subsystem Device ID 
-    host_features:      u32,        // Flags representing features the device supports
+    host_features:      u64,        // Flags representing features the device supports
     host_features_sel:  u32,        // Device (host) features word selection 
-    guest_features:     u32,        // Flags representing device features understood and activated by the driver 
+    guest_features:     u64,        // Flags representing device features understood and activated by the driver 
     guest_features_sel: u32,        // Activated (guest) features word selection 
     guest_page_size:    u16,        // Guest page size 
     queue_sel:          u32,        // Virtual queue index 
@@ -313,6 +301,11 @@ pub struct Virtio {
     queue_num:          u32,        // Virtual queue size 
     queue_align:        u32,        // Used Ring alignment in the virtual queue 
     queue_pfn:          u32,        // Guest physical page number of the virtual queue
+    queue_ready:        u32,        // Virtual queue ready bit
+    queue_desc:         u64,        // Guest physical address of the Descriptor Area
+    queue_driver:       u64,        // Guest physical address of the Driver Area (available ring)
+    queue_device:       u64,        // Guest physical address of the Device Area (used ring)
+    config_generation:  u32,        // Configuration atomicity value
     queue_notify:       u32,        // Queue notifier
     interrupt_status:   u64,        // Interrupt status
     status:             u32,        // Device status 
@@ -322,15 +315,16 @@ impl Virtio {
     pub fn new(device_id: DeviceID) -> Self {
         Virtio {
             clock:              0,
-            disk:               Vec::new(),
+            disk:               Disk::Memory(Vec::new()),
             last_avail_idx:     0,
             notify_changed:     false,
+            legacy:             false,
             
             magic_value:        0x74726976,     // 0x74726976 (A Little Endian equivalent of the “virt” string)
             vendor_id:          0x554d4551,     // QEMU's Vendor ID
-            version:            0x1,            // Legacy devices used 0x1.
+            version:            0x2,            // Legacy devices used 0x1.
             device_id:          device_id,      // Virtio Subsystem Device ID
-            host_features:      0,
+            host_features:      RING_FEATURES | (1 << VIRTIO_F_VERSION_1),
             host_features_sel:  0,
             guest_features:     0,
             guest_features_sel: 0,
@@ -340,45 +334,110 @@ impl Virtio {
             queue_num:          0,
             queue_align:        0,
             queue_pfn:          0,
+            queue_ready:        0,
+            queue_desc:         0,
+            queue_driver:       0,
+            queue_device:       0,
+            config_generation:  0,
             queue_notify:       0,
             interrupt_status:   0,
             status:             0,
         }
     }
 
-    pub fn load(&mut self, binary: Vec<u8>) {
-        if binary.len() > MAX_DISK {
-            panic!("[ERROR] too large binary: {}[Byte] (limit: {}[Byte])", binary.len(), MAX_DISK);
-        }
+    // Switch between the legacy interface and the modern one, which requires VIRTIO_F_VERSION_1
+    pub fn set_legacy(&mut self, legacy: bool) {
+        self.legacy = legacy;
+        self.version = if legacy { 0x1 } else { 0x2 };
+        self.update_host_features();
+    }
 
-        for byte in binary {
-            self.disk.push(byte);
+    fn update_host_features(&mut self) {
+        self.host_features = RING_FEATURES;
+        if !self.legacy {
+            self.host_features |= 1 << VIRTIO_F_VERSION_1;
         }
+        if self.disk.is_readonly() {
+            self.host_features |= 1 << VIRTIO_BLK_F_RO;
+        }
+    }
+
+    // Writing 0 to the status register resets the device
+    fn reset(&mut self) {
+        self.last_avail_idx = 0;
+        self.notify_changed = false;
+        self.host_features_sel = 0;
+        self.guest_features = 0;
+        self.guest_features_sel = 0;
+        self.queue_sel = 0;
+        self.queue_num = 0;
+        self.queue_pfn = 0;
+        self.queue_ready = 0;
+        self.queue_desc = 0;
+        self.queue_driver = 0;
+        self.queue_device = 0;
+        self.interrupt_status = 0;
+    }
+
+    // The modern interface refuses FEATURES_OK unless the driver accepted VIRTIO_F_VERSION_1
+    // and nothing the device does not offer
+    fn features_accepted(&self) -> bool {
+        self.legacy || (
+            (self.guest_features & (1 << VIRTIO_F_VERSION_1)) != 0 &&
+            (self.guest_features & !self.host_features) == 0
+        )
+    }
+
+    // The queue can be used once the driver has told the device where it is
+    fn is_queue_ready(&self) -> bool {
+        let ready = match self.legacy {
+            true    => self.queue_pfn != 0,
+            false   => self.queue_ready == 1,
+        };
+        ready && self.queue_num != 0
+    }
+
+    pub fn load(&mut self, binary: Vec<u8>) {
+        self.set_disk(Disk::Memory(binary));
+    }
+
+    pub fn set_disk(&mut self, disk: Disk) {
+        self.disk = disk;
+        self.update_host_features();
+
+        // The capacity in the configuration space has changed
+        self.config_generation = self.config_generation.wrapping_add(1);
     }
 
     pub fn tick(&mut self, dram: &mut Dram) {
         if self.notify_changed {
-            self.disk_access(dram);
+            if self.is_queue_ready() && self.process_queue(dram) {
+                self.interrupt_status |= INTERRUPT_USED_BUFFER;
+            }
             self.notify_changed = false;
         }
         self.clock = self.clock.wrapping_add(1);
     }
 
+    pub fn is_interrupting(&self) -> bool {
+        self.interrupt_status != 0
+    }
+
     pub fn write8(&mut self, addr: usize, data: u8) {
         match addr {
             HOST_FEATURES_SEL_BASE ..= HOST_FEATURES_SEL_TOP   => {
                 let shift = (addr - HOST_FEATURES_SEL_BASE) * 8;
                 self.host_features_sel = (self.host_features_sel & !(0xFF << shift)) | ((data as u32) << shift);
             },
-            GUEST_FEATURES_BASE ..= GUEST_FEATURES_TOP  => {
-                let shift = (addr - GUEST_FEATURES_BASE) * 8;
-                self.guest_features = (self.guest_features & !(0xFF << shift)) | ((data as u32) << shift);
+            GUEST_FEATURES_BASE ..= GUEST_FEATURES_TOP if self.guest_features_sel < 2 => {
+                let shift = (addr - GUEST_FEATURES_BASE) * 8 + self.guest_features_sel as usize * 32;
+                self.guest_features = (self.guest_features & !(0xFF << shift)) | ((data as u64) << shift);
             },
             GUEST_FEATURES_SEL_BASE ..= GUEST_FEATURES_SEL_TOP  => {
                 let shift = (addr - GUEST_FEATURES_SEL_BASE) * 8;
                 self.guest_features_sel = (self.guest_features_sel & !(0xFF << shift)) | ((data as u32) << shift);
             },
-            GUEST_PAGE_SIZE_BASE ..= GUEST_PAGE_SIZE_TOP  => {
+            GUEST_PAGE_SIZE_BASE ..= GUEST_PAGE_SIZE_TOP if self.legacy => {
                 let shift = (addr - GUEST_PAGE_SIZE_BASE) * 8;
                 self.guest_page_size = (self.guest_page_size & !(0xFF << shift)) | ((data as u16) << shift);
             },
@@ -390,30 +449,46 @@ impl Virtio {
                 let shift = (addr - QUEUE_NUM_BASE) * 8;
                 self.queue_num = (self.queue_num & !(0xFF << shift)) | ((data as u32) << shift);
             },
-            QUEUE_ALIGN_BASE ..= QUEUE_ALIGN_TOP   => {
+            QUEUE_ALIGN_BASE ..= QUEUE_ALIGN_TOP if self.legacy => {
                 let shift = (addr - QUEUE_ALIGN_BASE) * 8;
                 self.queue_align = (self.queue_align & !(0xFF << shift)) | ((data as u32) << shift);
             },
-            QUEUE_PFN_BASE ..= QUEUE_PFN_TOP => {
+            QUEUE_PFN_BASE ..= QUEUE_PFN_TOP if self.legacy => {
                 let shift = (addr - QUEUE_PFN_BASE) * 8;
                 self.queue_pfn = (self.queue_pfn & !(0xFF << shift)) | ((data as u32) << shift);
             },
+            QUEUE_READY_BASE ..= QUEUE_READY_TOP if !self.legacy => {
+                let shift = (addr - QUEUE_READY_BASE) * 8;
+                self.queue_ready = (self.queue_ready & !(0xFF << shift)) | ((data as u32) << shift);
+            },
+            QUEUE_DESC_BASE ..= QUEUE_DESC_TOP if !self.legacy => {
+                let shift = (addr - QUEUE_DESC_BASE) * 8;
+                self.queue_desc = (self.queue_desc & !(0xFF << shift)) | ((data as u64) << shift);
+            },
+            QUEUE_DRIVER_BASE ..= QUEUE_DRIVER_TOP if !self.legacy => {
+                let shift = (addr - QUEUE_DRIVER_BASE) * 8;
+                self.queue_driver = (self.queue_driver & !(0xFF << shift)) | ((data as u64) << shift);
+            },
+            QUEUE_DEVICE_BASE ..= QUEUE_DEVICE_TOP if !self.legacy => {
+                let shift = (addr - QUEUE_DEVICE_BASE) * 8;
+                self.queue_device = (self.queue_device & !(0xFF << shift)) | ((data as u64) << shift);
+            },
             QUEUE_NOTIFY_BASE ..= QUEUE_NOTIFY_TOP  => {
                 let shift = (addr - QUEUE_NOTIFY_BASE) * 8;
                 self.queue_notify = (self.queue_notify & !(0xFF << shift)) | ((data as u32) << shift);
                 self.notify_changed = true;
             },
-            INTERRUPT_ACK       => {
-                if data & 0x1 == 1 {
-                    self.interrupt_status &= !0x1;
-                }
-                else {
-                    panic!();
-                }
-            },
+            // The interrupt line stays asserted until every status bit has been acknowledged
+            INTERRUPT_ACK       => self.interrupt_status &= !(data as u64),
             STATUS_BASE ..= STATUS_TOP  => {
                 let shift = (addr - STATUS_BASE) * 8;
                 self.status = self.status & !(0xFF << shift) | (data as u32) << shift;
+                if self.status == 0 {
+                    self.reset();
+                }
+                else if (self.status & STATUS_FEATURED as u32) != 0 && !self.features_accepted() {
+                    self.status &= !(STATUS_FEATURED as u32);
+                }
             },
             _                   => (),
         }
@@ -447,11 +522,12 @@ impl Virtio {
                 let shift = (addr - VENDOR_ID_BASE) * 8;
                 ((self.vendor_id >> shift) & 0xFF) as u8
             },
-            HOST_FEATURES_BASE ..= HOST_FEATURES_TOP    => {
-                let shift = (addr - HOST_FEATURES_BASE) * 8;
+            HOST_FEATURES_BASE ..= HOST_FEATURES_TOP if self.host_features_sel < 2 => {
+                let shift = (addr - HOST_FEATURES_BASE) * 8 + self.host_features_sel as usize * 32;
                 ((self.host_features >> shift) & 0xFF) as u8
             },
-            QUEUE_NUM_MAX_BASE ..= QUEUE_NUM_MAX_TOP    => {
+            // The block device has a single queue
+            QUEUE_NUM_MAX_BASE ..= QUEUE_NUM_MAX_TOP if self.queue_sel == 0 => {
                 let shift = (addr - QUEUE_NUM_MAX_BASE) * 8;
                 ((self.queue_num_max >> shift) & 0xFF) as u8
             },
@@ -459,10 +535,23 @@ impl Virtio {
                 let shift = (addr - INTERRUPT_STATUS_BASE) * 8;
                 ((self.interrupt_status >> shift) & 0xFF) as u8
             },
+            QUEUE_PFN_BASE ..= QUEUE_PFN_TOP if self.legacy => {
+                let shift = (addr - QUEUE_PFN_BASE) * 8;
+                ((self.queue_pfn >> shift) & 0xFF) as u8
+            },
+            QUEUE_READY_BASE ..= QUEUE_READY_TOP if !self.legacy => {
+                let shift = (addr - QUEUE_READY_BASE) * 8;
+                ((self.queue_ready >> shift) & 0xFF) as u8
+            },
             STATUS_BASE ..= STATUS_TOP  => {
                 let shift = (addr - STATUS_BASE) * 8;
                 ((self.status >> shift) & 0xFF) as u8
             },
+            CONFIG_GENERATION_BASE ..= CONFIG_GENERATION_TOP if !self.legacy => {
+                let shift = (addr - CONFIG_GENERATION_BASE) * 8;
+                ((self.config_generation >> shift) & 0xFF) as u8
+            },
+            CONFIG_BASE ..= CONFIG_TOP  => self.read8_config(addr - CONFIG_BASE),
             _                   => 0,
         }
     }
@@ -481,25 +570,67 @@ impl Virtio {
       ((self.read32(addr + 4) as u64) << 32)
     }
 
-    fn write8_disk(&mut self, addr: usize, data: u8) {
-        self.disk[addr] = data;
+    // struct virtio_blk_config, of which only the capacity in 512-byte sectors is non-zero
+    fn read8_config(&self, offset: usize) -> u8 {
+        match offset {
+            0 ..= 7 => {
+                let capacity = self.disk.size() / SECTOR_SIZE as u64;
+                ((capacity >> (offset * 8)) & 0xFF) as u8
+            },
+            _       => 0,
+        }
     }
 
-    fn read8_disk(&self, addr: usize) -> u8 {
-        self.disk[addr]
+    // The legacy interface lays the descriptor table, the available ring and the used ring out
+    // consecutively from QueuePFN, with the used ring aligned to QueueAlign
+    fn get_desc_addr(&self) -> u64 {
+        match self.legacy {
+            true    => self.queue_pfn as u64 * self.guest_page_size as u64,
+            false   => self.queue_desc,
+        }
     }
 
-    fn get_page_addr(&self) -> usize {
-        self.queue_pfn as usize * self.guest_page_size as usize - DRAM_BASE
+    fn get_avail_addr(&self) -> u64 {
+        match self.legacy {
+            true    => self.get_desc_addr() + (self.queue_num as u64) * 16,
+            false   => self.queue_driver,
+        }
     }
     
-    fn get_base_used_addr(&self) -> usize {
-        self.get_page_addr() + 4 + (self.queue_num as usize) * 2 + (self.queue_align as usize) - 1
+    fn get_used_addr(&self) -> u64 {
+        match self.legacy {
+            true    => {
+                let align = (self.queue_align as u64).max(1);
+                (self.get_avail_addr() + 6 + (self.queue_num as u64) * 2).next_multiple_of(align)
+            },
+            false   => self.queue_device,
+        }
+    }
+
+    // Offset in DRAM of the guest physical range [addr, addr + len), if it lies entirely in DRAM
+    fn dram_offset(mem: &Dram, addr: u64, len: usize) -> Option<usize> {
+        let offset = (addr as usize).checked_sub(DRAM_BASE)?;
+        match offset.checked_add(len)? <= mem.size() {
+            true    => Some(offset),
+            false   => None,
+        }
+    }
+
+    // DRAM offsets of the descriptor table, the available ring and the used ring
+    fn get_queue_layout(&self, mem: &Dram) -> Option<(usize, usize, usize)> {
+        let queue_num = self.queue_num as usize;
+        if queue_num as u64 > self.queue_num_max {
+            return None;
+        }
+        Some((
+            Self::dram_offset(mem, self.get_desc_addr(), queue_num * 16)?,
+            Self::dram_offset(mem, self.get_avail_addr(), 6 + queue_num * 2)?,
+            Self::dram_offset(mem, self.get_used_addr(), 6 + queue_num * 8)?,
+        ))
     }
 
-    fn get_virtq_desc(&mut self, mem: &mut Dram, desc_idx: usize) -> virtq_desc {
-        let base_addr = self.get_page_addr() + desc_idx * 16;
-        // println!("[DEBUG] {}-{}\tbase_addr:\t0x{:x}", file!(), line!(), base_addr);
+    fn get_virtq_desc(mem: &Dram, table: usize, desc_idx: usize) -> virtq_desc {
+        let base_addr = table + desc_idx * 16;
 
         virtq_desc {
             addr:   mem.read64(base_addr) as usize,
@@ -509,113 +640,211 @@ impl Virtio {
         }
     } 
 
-    fn get_virtq_avail(&mut self, mem: &mut Dram) -> virtq_avail {
-        let base_addr = self.get_page_addr() + (self.queue_num as usize) * 16;
-        // println!("[DEBUG] {}-{}\tbase_addr:\t0x{:x}", file!(), line!(), base_addr);
+    // Follow the descriptor chain starting at `head`, through an indirect table if it has one.
+    // Returns None if the chain loops, indexes outside its table or points outside DRAM.
+    fn get_desc_chain(&self, mem: &Dram, desc_table: usize, head: usize) -> Option<Vec<virtq_desc>> {
+        let mut chain = Vec::new();
+        let mut table = desc_table;
+        let mut table_size = self.queue_num as usize;
+        let mut desc_idx = head;
+        let mut indirect = false;
+
+        loop {
+            if desc_idx >= table_size || chain.len() >= table_size {
+                return None;
+            }
+            let vq_desc = Self::get_virtq_desc(mem, table, desc_idx);
+
+            // An indirect table stands for the whole chain, and can not be nested
+            if (vq_desc.flags & VIRTQ_DESC_F_INDIRECT as u16) != 0 {
+                if indirect || !chain.is_empty() || (vq_desc.flags & VIRTQ_DESC_F_NEXT as u16) != 0 ||
+                   vq_desc.len == 0 || !vq_desc.len.is_multiple_of(16) {
+                    return None;
+                }
+                table = Self::dram_offset(mem, vq_desc.addr as u64, vq_desc.len as usize)?;
+                table_size = vq_desc.len as usize / 16;
+                desc_idx = 0;
+                indirect = true;
+                continue;
+            }
+
+            Self::dram_offset(mem, vq_desc.addr as u64, vq_desc.len as usize)?;
+            desc_idx = vq_desc.next as usize;
+            let has_next = (vq_desc.flags & VIRTQ_DESC_F_NEXT as u16) != 0;
+            chain.push(vq_desc);
 
-        virtq_avail {
-            flags:      mem.read16(base_addr),
-            idx:        mem.read16(base_addr + 2) % self.queue_num as u16,
-            ring:       Vec::new(), // ToDo
-            used_event: 0,          // ToDo
+            if !has_next {
+                return Some(chain);
+            }
         }
     }
-    
-    fn get_virtq_used(&mut self, mem: &mut Dram) -> virtq_used {
-        let base_addr = self.get_base_used_addr();
-        // println!("[DEBUG] {}-{}\tbase_addr:\t0x{:x}", file!(), line!(), base_addr);
-
-        virtq_used {
-            flags:          mem.read16(base_addr),
-            idx:            mem.read16(base_addr + 2),
-            ring:           Vec::new(),     // ToDo
-            avail_event:    0,              // ToDo
+
+    // Copy `data` into the buffers of `chain`, starting `offset` bytes into them
+    fn write_chain(mem: &mut Dram, chain: &[virtq_desc], mut offset: usize, data: &[u8]) {
+        let mut data = data;
+        for vq_desc in chain {
+            if data.is_empty() {
+                break;
+            }
+            let len = vq_desc.len as usize;
+            if offset >= len {
+                offset -= len;
+                continue;
+            }
+            let count = (len - offset).min(data.len());
+            for (i, byte) in data[..count].iter().enumerate() {
+                mem.write8(vq_desc.addr - DRAM_BASE + offset + i, *byte);
+            }
+            data = &data[count..];
+            offset = 0;
+        }
+    }
+
+    // Byte offset in the disk of `len` bytes from `sector`, if they lie entirely on the disk
+    fn get_disk_offset(&self, sector: u64, len: usize) -> Option<u64> {
+        let offset = sector.checked_mul(SECTOR_SIZE as u64)?;
+        match offset.checked_add(len as u64)? <= self.disk.size() {
+            true    => Some(offset),
+            false   => None,
         }
     }
 
-    fn set_virtq_used_ring_idx(&self, mem: &mut Dram, idx: u32) {
-        mem.write32(self.get_base_used_addr() + 4 + self.last_avail_idx as usize * 8, idx);
+    // Carry out the request in `vq_blk_req` and return the data for the device-writable buffers,
+    // of which there are `writable_len` bytes before the status byte
+    fn blk_request(&mut self, vq_blk_req: &mut virtio_blk_req, writable_len: usize) -> Vec<u8> {
+        let (status, data) = match vq_blk_req.r#type {
+            VIRTIO_BLK_T_IN     => {
+                match self.get_disk_offset(vq_blk_req.sector, writable_len) {
+                    Some(offset) if writable_len.is_multiple_of(SECTOR_SIZE) => {
+                        let mut data = vec![0; writable_len];
+                        match self.disk.read_at(&mut data, offset) {
+                            Ok(())  => (VIRTIO_BLK_S_OK, data),
+                            Err(_)  => (VIRTIO_BLK_S_IOERR, Vec::new()),
+                        }
+                    },
+                    _   => (VIRTIO_BLK_S_IOERR, Vec::new()),
+                }
+            },
+            VIRTIO_BLK_T_OUT    => {
+                let len = vq_blk_req.data.len();
+                match self.get_disk_offset(vq_blk_req.sector, len) {
+                    Some(offset) if len.is_multiple_of(SECTOR_SIZE) => {
+                        match self.disk.write_at(&vq_blk_req.data, offset) {
+                            Ok(())  => (VIRTIO_BLK_S_OK, Vec::new()),
+                            Err(_)  => (VIRTIO_BLK_S_IOERR, Vec::new()),
+                        }
+                    },
+                    _   => (VIRTIO_BLK_S_IOERR, Vec::new()),
+                }
+            },
+            VIRTIO_BLK_T_FLUSH  => {
+                match self.disk.flush() {
+                    Ok(())  => (VIRTIO_BLK_S_OK, Vec::new()),
+                    Err(_)  => (VIRTIO_BLK_S_IOERR, Vec::new()),
+                }
+            },
+            VIRTIO_BLK_T_GET_ID => {
+                let mut id = VIRTIO_BLK_ID.to_vec();
+                id.resize(VIRTIO_BLK_ID_BYTES.min(writable_len), 0);
+                (VIRTIO_BLK_S_OK, id)
+            },
+            _                   => (VIRTIO_BLK_S_UNSUPP, Vec::new()),
+        };
+        vq_blk_req.status = status;
+        data
     }
 
-    fn get_virtio_blk_req(&mut self, mem: &mut Dram, addr: usize) -> virtio_blk_req {
-        // println!("[DEBUG] {}-{}\taddr:\t0x{:x}", file!(), line!(), addr);
+    // Process the block request in `chain` and return the number of bytes written to its
+    // device-writable buffers. Those follow the device-readable ones, and end with the status byte.
+    fn process_request(&mut self, mem: &mut Dram, chain: &[virtq_desc]) -> u32 {
+        let is_writable = |vq_desc: &virtq_desc| (vq_desc.flags & VIRTQ_DESC_F_WRITE as u16) != 0;
+        let split = chain.iter().position(is_writable).unwrap_or(chain.len());
+        let (readable, writable) = chain.split_at(split);
+        let writable_len: usize = writable.iter().map(|vq_desc| vq_desc.len as usize).sum();
+
+        // Without a well-formed place for the status byte, the request can not even be failed
+        if writable.iter().any(|vq_desc| !is_writable(vq_desc)) || writable_len == 0 {
+            return 0;
+        }
 
-        virtio_blk_req {
-            r#type:     mem.read32(addr),
-            reserved:   mem.read32(addr + 4),
-            sector:     mem.read64(addr + 8),
-            data:       Vec::new(),     // ToDo
-            status:     0,              // ToDo
+        let mut input = Vec::new();
+        for vq_desc in readable {
+            let base_addr = vq_desc.addr - DRAM_BASE;
+            input.extend((0 .. vq_desc.len as usize).map(|i| mem.read8(base_addr + i)));
         }
-    }
 
-    fn disk_access(&mut self, mem: &mut Dram) {
-        let vq_avail    = self.get_virtq_avail(mem);
-        // println!("[DEBUG] {}-{}\tvq_avail:\t{:?}", file!(), line!(), vq_avail);
-
-        let desc_idx_addr =   self.get_page_addr()
-                            + self.queue_num as usize * 16
-                            + self.last_avail_idx as usize * 2
-                            + 4;
-        let desc_head_idx = mem.read16(desc_idx_addr) as usize % self.queue_num as usize;
-        let mut desc_idx = desc_head_idx;
-        let mut desc_num = 0;
+        // struct virtio_blk_req: type, reserved and sector, followed by the data
         let mut vq_blk_req = virtio_blk_req {
             r#type:     0,
             reserved:   0,
             sector:     0,
             data:       Vec::new(),
-            status:     0,
+            status:     VIRTIO_BLK_S_IOERR,
+        };
+        let data = match input.len() >= 16 {
+            true    => {
+                vq_blk_req.r#type = u32::from_le_bytes(input[0..4].try_into().unwrap());
+                vq_blk_req.reserved = u32::from_le_bytes(input[4..8].try_into().unwrap());
+                vq_blk_req.sector = u64::from_le_bytes(input[8..16].try_into().unwrap());
+                vq_blk_req.data = input.split_off(16);
+                self.blk_request(&mut vq_blk_req, writable_len - 1)
+            },
+            false   => Vec::new(),
         };
-        
-        loop {
-            let vq_desc = self.get_virtq_desc(mem, desc_idx);
-            // println!("[DEBUG] {}-{}\tvq_desc:\t{:?}", file!(), line!(), vq_desc);
-            desc_idx = vq_desc.next as usize;
-            
-            match desc_num {
-                0   => {
-                    vq_blk_req = self.get_virtio_blk_req(mem, vq_desc.addr - DRAM_BASE);
-                    // println!("[DEBUG] {}-{}\tvq_blk_req:\t{:?}", file!(), line!(), vq_blk_req);
-                },
-                1   => {
-                    // write to disk
-                    if (vq_desc.flags & VIRTQ_DESC_F_WRITE as u16) == 0 {
-                        for i in 0 .. vq_desc.len {
-                            let data = mem.read8(vq_desc.addr - DRAM_BASE + i as usize);
-                            self.write8_disk(vq_blk_req.sector as usize * SECTOR_SIZE + i as usize, data);
-                        }
-                    }
-                    // read from disk
-                    else {
-                        for i in 0 .. vq_desc.len {
-                            let data = self.read8_disk(vq_blk_req.sector as usize * SECTOR_SIZE + i as usize);
-                            mem.write8(vq_desc.addr - DRAM_BASE + i as usize, data);
-                        }
-                    }
-                },
-                2   => {
-                    if (vq_desc.flags & VIRTQ_DESC_F_WRITE as u16) == 0 {
-                        panic!();
-                    }
-                    if vq_desc.len != 1 {
-                        panic!();
-                    }
-                    mem.write8(vq_desc.addr - DRAM_BASE, 0);
-                },
-                _   => panic!(),
-            }
 
-            desc_num += 1;
+        Self::write_chain(mem, writable, 0, &data);
+        Self::write_chain(mem, writable, writable_len - 1, &[vq_blk_req.status]);
+        (data.len() + 1) as u32
+    }
 
-            if (vq_desc.flags & VIRTQ_DESC_F_NEXT as u16) == 0 {
-                break;
-            }
+    // Process every buffer the driver has made available since the last notification, and
+    // return whether the driver asked to be interrupted for them
+    fn process_queue(&mut self, mem: &mut Dram) -> bool {
+        let (desc_table, avail, used) = match self.get_queue_layout(mem) {
+            Some(layout)    => layout,
+            None            => {
+                self.status |= STATUS_DEVICE_NEED_RESET as u32;
+                return false;
+            },
+        };
+        let queue_num = self.queue_num as usize;
+        let event_idx = (self.guest_features & (1 << VIRTIO_F_EVENT_IDX)) != 0;
+        let old_idx = self.last_avail_idx;
+        let avail_idx = mem.read16(avail + 2);
+
+        while self.last_avail_idx != avail_idx {
+            let ring_idx = self.last_avail_idx as usize % queue_num;
+            let head = mem.read16(avail + 4 + ring_idx * 2) as usize;
+
+            // A malformed chain is returned unprocessed rather than bringing the emulator down
+            let len = match self.get_desc_chain(mem, desc_table, head) {
+                Some(chain) => self.process_request(mem, &chain),
+                None        => 0,
+            };
+
+            let elem = virtq_used_elem { id: head as u32, len };
+            mem.write32(used + 4 + ring_idx * 8, elem.id);
+            mem.write32(used + 8 + ring_idx * 8, elem.len);
+            self.last_avail_idx = self.last_avail_idx.wrapping_add(1);
+            mem.write16(used + 2, self.last_avail_idx);
         }
 
-        let vq_used     = self.get_virtq_used(mem);
-        self.set_virtq_used_ring_idx(mem, desc_head_idx as u32);
-        self.last_avail_idx = (self.last_avail_idx + 1) % self.queue_num as usize;
-        mem.write16(self.get_base_used_addr() + vq_used.idx as usize, self.last_avail_idx as u16);
+        // With EVENT_IDX, avail_event asks for a notification as soon as anything new is available,
+        // and used_event tells after which used index the driver wants to be interrupted
+        if event_idx {
+            mem.write16(used + 4 + queue_num * 8, self.last_avail_idx);
+        }
+        if self.last_avail_idx == old_idx {
+            return false;
+        }
+        match event_idx {
+            true    => vring_need_event(mem.read16(avail + 4 + queue_num * 2), self.last_avail_idx, old_idx),
+            false   => (mem.read16(avail) & VIRTQ_AVAIL_F_NO_INTERRUPT as u16) == 0,
+        }
     }
-}
\ No newline at end of file
+}
+
+// Whether the used index passed `event_idx` when it moved from `old_idx` to `new_idx`
+fn vring_need_event(event_idx: u16, new_idx: u16, old_idx: u16) -> bool {
+    new_idx.wrapping_sub(event_idx).wrapping_sub(1) < new_idx.wrapping_sub(old_idx)
+}
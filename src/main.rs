@@ -2,7 +2,12 @@ mod test;
 pub mod emulator;
 
 use structopt::StructOpt;
+use emulator::bus::Machine;
+use emulator::disk::DiskMode;
 use emulator::cpu::{ Cpu, Registers, WatchExec };
+use emulator::mmu::{ self, AdUpdate, Misaligned };
+use emulator::pmp::PMP_ENTRIES;
+use emulator::vector;
 
 #[derive(Debug, StructOpt)]
 struct Opt {
@@ -21,6 +26,66 @@ struct Opt {
     /// disk image
     #[structopt(short, long)]
     pub disk: String,
+
+    /// Save disk writes to the image file, instead of discarding them at exit
+    #[structopt(long, conflicts_with_all = &["readonly", "overlay"])]
+    pub writeback: bool,
+
+    /// Attach the disk image read-only
+    #[structopt(long, conflicts_with = "overlay")]
+    pub readonly: bool,
+
+    /// Keep disk writes in a copy-on-write overlay file, leaving the image unmodified
+    #[structopt(long)]
+    pub overlay: Option<String>,
+
+    /// Raise page faults instead of setting PTE A/D bits in hardware (Svade)
+    #[structopt(long)]
+    pub svade: bool,
+
+    /// Connect host stdin to the UART receiver
+    #[structopt(long)]
+    pub stdin: bool,
+
+    /// Use the legacy virtio-mmio interface (version 1) instead of version 2
+    #[structopt(long)]
+    pub virtio_legacy: bool,
+
+    /// Raise address-misaligned exceptions instead of performing misaligned loads and stores
+    #[structopt(long)]
+    pub trap_misaligned: bool,
+
+    /// Register width of the hart in bits (32 or 64)
+    #[structopt(long, default_value = "64")]
+    pub xlen: usize,
+
+    /// Use the E base ISA (RV32E/RV64E) with 16 integer registers
+    #[structopt(long)]
+    pub embedded: bool,
+
+    /// Exit when the program jumps to address 0 (virt only, where nothing is mapped there)
+    #[structopt(long)]
+    pub exit_at_zero: bool,
+
+    /// Memory map of the machine (virt or mcu)
+    #[structopt(long, default_value = "virt")]
+    pub machine: Machine,
+
+    /// Number of implemented PMP entries (0, 16 or 64)
+    #[structopt(long, default_value = "16")]
+    pub pmp_entries: usize,
+
+    /// Vector register length in bits (a power of two, ELEN..=65536)
+    #[structopt(long, default_value = "128")]
+    pub vlen: usize,
+
+    /// Maximum vector element width in bits (32 or 64)
+    #[structopt(long, default_value = "64")]
+    pub elen: usize,
+
+    /// Cache-block size in bytes for the CBO instructions (a power of two, 8..=4096)
+    #[structopt(long, default_value = "64")]
+    pub cache_block_size: usize,
 }
 
 fn main() {
@@ -30,8 +95,52 @@ fn main() {
     let mut cpu = Cpu::new();
     cpu.debug = opt.debug;
     cpu.step = opt.step;
+    if opt.svade {
+        cpu.mmu.ad_update = AdUpdate::Software;
+    }
+    if opt.stdin {
+        cpu.mmu.attach_stdin();
+    }
+    cpu.mmu.set_virtio_legacy(opt.virtio_legacy);
+    if opt.trap_misaligned {
+        cpu.mmu.misaligned = Misaligned::Trap;
+    }
+    if opt.xlen != 32 && opt.xlen != 64 {
+        panic!("[ERROR] unsupported XLEN: {}", opt.xlen);
+    }
+    cpu.configure_xlen(opt.xlen);
+    if opt.embedded {
+        cpu.configure_embedded();
+    }
+    cpu.configure_machine(opt.machine);
+    if opt.exit_at_zero && opt.machine != Machine::Virt {
+        panic!("[ERROR] --exit-at-zero is only supported on the virt machine");
+    }
+    cpu.exit_at_zero = opt.exit_at_zero;
+    if !PMP_ENTRIES.contains(&opt.pmp_entries) {
+        panic!("[ERROR] unsupported number of PMP entries: {}", opt.pmp_entries);
+    }
+    cpu.csr.pmp_entries = opt.pmp_entries;
+    if !vector::is_valid_config(opt.vlen, opt.elen) {
+        panic!("[ERROR] unsupported vector configuration: VLEN={} ELEN={}", opt.vlen, opt.elen);
+    }
+    cpu.configure_vector(opt.vlen, opt.elen);
+    if !mmu::is_valid_cache_block_size(opt.cache_block_size) {
+        panic!("[ERROR] unsupported cache-block size: {}", opt.cache_block_size);
+    }
+    cpu.mmu.cache_block_size = opt.cache_block_size;
+    if cpu.debug { println!("[INFO] isa: {}", cpu.csr.isa_string()); }
     cpu.load_dram(&opt.kernel);
-    cpu.load_disk(&opt.disk);
+    if opt.machine == Machine::Virt {
+        cpu.load_dtb();
+    }
+    let disk_mode = match (opt.writeback, opt.readonly, opt.overlay) {
+        (true, _, _)            => DiskMode::WriteBack,
+        (_, true, _)            => DiskMode::ReadOnly,
+        (_, _, Some(overlay))   => DiskMode::Overlay(overlay),
+        _                       => DiskMode::Volatile,
+    };
+    cpu.load_disk(&opt.disk, disk_mode);
     //cpu.watch(Registers::PC, 0x800029cc, WatchExec::STOP);
 
     cpu.run();
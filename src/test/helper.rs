@@ -0,0 +1,44 @@
+#![cfg(test)]
+
+// Instruction encoders and runners shared by the instruction tests. The encoded instructions
+// read their sources from x1 and x2 and write their result to x3.
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::exception::Exception;
+
+pub const OP:           u32 = 0b011_0011;
+pub const OP_32:        u32 = 0b011_1011;
+pub const OP_IMM:       u32 = 0b001_0011;
+pub const OP_IMM_32:    u32 = 0b001_1011;
+
+// x3 = op(x1, x2)
+pub fn rtype(opcode: u32, funct3: u32, funct7: u32) -> u32 {
+    (funct7 << 25) | (2 << 20) | (1 << 15) | (funct3 << 12) | (3 << 7) | opcode
+}
+
+// x3 = op(x1, imm)
+pub fn itype(opcode: u32, funct3: u32, imm: u32) -> u32 {
+    (imm << 20) | (1 << 15) | (funct3 << 12) | (3 << 7) | opcode
+}
+
+// Execute `inst` with the registers as they are
+pub fn exec(cpu: &mut Cpu, inst: u32) -> Result<(), Exception> {
+    cpu.instruction = inst;
+    cpu.execute()
+}
+
+// Execute `inst` with x1 and x2 set to the operands, and return x3
+pub fn exec_rr(cpu: &mut Cpu, inst: u32, x1: u64, x2: u64) -> Result<u64, Exception> {
+    cpu.register.write(1, x1);
+    cpu.register.write(2, x2);
+    exec(cpu, inst)?;
+    Ok(cpu.register.read(3))
+}
+
+// Fetch and execute one instruction the way Cpu::run does
+pub fn step(cpu: &mut Cpu) {
+    match cpu.fetch().and_then(|_| cpu.execute()) {
+        Ok(_)           => cpu.pc = cpu.pc.wrapping_add(cpu.inst_len),
+        Err(exception)  => exception.take_trap(cpu),
+    }
+}
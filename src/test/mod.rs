@@ -1,3 +1,23 @@
+pub mod helper;
+pub mod test_bitmanip;
+pub mod test_cmo;
+pub mod test_compressed;
+pub mod test_counter;
+pub mod test_crypto;
 pub mod test_csr;
+pub mod test_disk;
+pub mod test_embedded;
+pub mod test_fdt;
+pub mod test_float;
+pub mod test_hypervisor;
+pub mod test_interrupt;
+pub mod test_mmu;
+pub mod test_plic;
+pub mod test_pma;
+pub mod test_pmp;
+pub mod test_rv32;
 pub mod test_rvtests;
-pub mod test_virtio;
\ No newline at end of file
+pub mod test_sstc;
+pub mod test_vector;
+pub mod test_virtio;
+pub mod test_wfi;
@@ -0,0 +1,594 @@
+#![cfg(test)]
+
+// Register-register and register-immediate tests for Zba, Zbb, Zbc and Zbs in the style of
+// riscv-tests: each case sets the source registers, executes one instruction and checks rd.
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::csr::*;
+use crate::emulator::exception::Exception;
+use super::helper::*;
+
+macro_rules! test_rr_op {
+    ($name: ident, $inst: expr, [$(($result: expr, $rs1: expr, $rs2: expr)),* $(,)?]) => {
+        #[test]
+        pub fn $name() {
+            let mut cpu = Cpu::new();
+            cpu.instruction = $inst;
+            $(
+                let (rs1, rs2, result): (u64, u64, u64) = ($rs1, $rs2, $result);
+                cpu.register.write(1, rs1);
+                cpu.register.write(2, rs2);
+                cpu.execute().unwrap();
+                assert_eq!(cpu.register.read(3), result, "{}(0x{:x}, 0x{:x})", stringify!($name), rs1, rs2);
+            )*
+        }
+    };
+}
+
+macro_rules! test_r_op {
+    ($name: ident, $inst: expr, [$(($result: expr, $rs1: expr)),* $(,)?]) => {
+        #[test]
+        pub fn $name() {
+            let mut cpu = Cpu::new();
+            cpu.instruction = $inst;
+            $(
+                let (rs1, result): (u64, u64) = ($rs1, $result);
+                cpu.register.write(1, rs1);
+                cpu.execute().unwrap();
+                assert_eq!(cpu.register.read(3), result, "{}(0x{:x})", stringify!($name), rs1);
+            )*
+        }
+    };
+}
+
+macro_rules! test_imm_op {
+    ($name: ident, $opcode: expr, $funct3: expr, $imm: expr, [$(($result: expr, $rs1: expr, $shamt: expr)),* $(,)?]) => {
+        #[test]
+        pub fn $name() {
+            let mut cpu = Cpu::new();
+            $(
+                let (rs1, result): (u64, u64) = ($rs1, $result);
+                cpu.instruction = itype($opcode, $funct3, $imm | $shamt);
+                cpu.register.write(1, rs1);
+                cpu.execute().unwrap();
+                assert_eq!(cpu.register.read(3), result, "{}(0x{:x}, {})", stringify!($name), rs1, $shamt);
+            )*
+        }
+    };
+}
+
+// Zba: address generation
+
+test_rr_op!(test_sh1add, rtype(OP, 0b010, 0b001_0000), [
+    (0x0123_4567_89AB_CDEE, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x7FFF_FFFF_FFFF_FFFF, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0002_0000_0001, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0xFFFF_FFFF_0000_0021, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_003F, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_sh2add, rtype(OP, 0b100, 0b001_0000), [
+    (0x0369_D036_9D03_69CC, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFD, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x7FFF_FFFF_FFFF_FFFF, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0003_FFFF_FFFF, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0xFFFF_FFFE_0000_0021, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_003F, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_sh3add, rtype(OP, 0b110, 0b001_0000), [
+    (0x07F6_E5D4_C3B2_A188, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFF9, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x7FFF_FFFF_FFFF_FFFF, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0007_FFFF_FFFB, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0xFFFF_FFFC_0000_0021, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_003F, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_add_uw, rtype(OP_32, 0b000, 0b000_0100), [
+    (0xFEDC_BA98_FFFF_FFFF, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0x0000_0001_0000_0000, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x7FFF_FFFF_FFFF_FFFF, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0001_0000_0002, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0x0000_0000_8000_0021, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_003F, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_sh1add_uw, rtype(OP_32, 0b010, 0b001_0000), [
+    (0xFEDC_BA99_89AB_CDEE, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0x0000_0001_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x7FFF_FFFF_FFFF_FFFF, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0002_0000_0001, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0x0000_0001_0000_0021, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_003F, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_sh2add_uw, rtype(OP_32, 0b100, 0b001_0000), [
+    (0xFEDC_BA9A_9D03_69CC, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0x0000_0003_FFFF_FFFD, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x7FFF_FFFF_FFFF_FFFF, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0003_FFFF_FFFF, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0x0000_0002_0000_0021, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_003F, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_sh3add_uw, rtype(OP_32, 0b110, 0b001_0000), [
+    (0xFEDC_BA9C_C3B2_A188, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0x0000_0007_FFFF_FFF9, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x7FFF_FFFF_FFFF_FFFF, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0007_FFFF_FFFB, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0x0000_0004_0000_0021, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_003F, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_imm_op!(test_slli_uw, OP_IMM_32, 0b001, 0x080, [
+    (0x0000_0000_89AB_CDEF, 0x0123_4567_89AB_CDEF, 0),
+    (0x0000_0001_1357_9BDE, 0x0123_4567_89AB_CDEF, 1),
+    (0x0000_0044_D5E6_F780, 0x0123_4567_89AB_CDEF, 7),
+    (0x44D5_E6F7_8000_0000, 0x0123_4567_89AB_CDEF, 31),
+    (0x89AB_CDEF_0000_0000, 0x0123_4567_89AB_CDEF, 32),
+    (0x8000_0000_0000_0000, 0x0123_4567_89AB_CDEF, 63),
+    (0x0000_0000_8000_0001, 0xFFFF_FFFF_8000_0001, 0),
+    (0x0000_0001_0000_0002, 0xFFFF_FFFF_8000_0001, 1),
+    (0x0000_0040_0000_0080, 0xFFFF_FFFF_8000_0001, 7),
+    (0x4000_0000_8000_0000, 0xFFFF_FFFF_8000_0001, 31),
+    (0x8000_0001_0000_0000, 0xFFFF_FFFF_8000_0001, 32),
+    (0x8000_0000_0000_0000, 0xFFFF_FFFF_8000_0001, 63),
+]);
+
+// Zbb: basic bit-manipulation
+
+test_rr_op!(test_andn, rtype(OP, 0b111, 0b010_0000), [
+    (0x0123_4567_89AB_CDEF, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFE, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x8000_0000_0000_0000, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_FFFF_FFFC, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0xFFFF_FFFF_8000_0000, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_orn, rtype(OP, 0b110, 0b010_0000), [
+    (0x0123_4567_89AB_CDEF, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x8000_0000_0000_0000, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0xFFFF_FFFF_FFFF_FFDE, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0xFFFF_FFFF_FFFF_FFC0, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_xnor, rtype(OP, 0b100, 0b010_0000), [
+    (0x0000_0000_0000_0000, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0x0000_0000_0000_0001, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x0000_0000_0000_0000, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0xFFFF_FFFF_0000_0003, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0x0000_0000_7FFF_FFDE, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0xFFFF_FFFF_FFFF_FFC0, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_r_op!(test_clz, itype(OP_IMM, 0b001, 0x600), [
+    (0x0000_0000_0000_0040, 0x0000_0000_0000_0000),
+    (0x0000_0000_0000_003F, 0x0000_0000_0000_0001),
+    (0x0000_0000_0000_0000, 0xFFFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0000, 0x8000_0000_0000_0000),
+    (0x0000_0000_0000_0001, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0020, 0x0000_0000_8000_0000),
+    (0x0000_0000_0000_0020, 0x0000_0000_FFFF_FFFF),
+    (0x0000_0000_0000_0007, 0x0123_4567_89AB_CDEF),
+    (0x0000_0000_0000_0000, 0xFEDC_BA98_7654_3210),
+    (0x0000_0000_0000_0008, 0x00FF_00FF_00FF_00FF),
+    (0x0000_0000_0000_0000, 0xFFFF_FFFF_8000_0000),
+]);
+
+test_r_op!(test_clzw, itype(OP_IMM_32, 0b001, 0x600), [
+    (0x0000_0000_0000_0020, 0x0000_0000_0000_0000),
+    (0x0000_0000_0000_001F, 0x0000_0000_0000_0001),
+    (0x0000_0000_0000_0000, 0xFFFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0020, 0x8000_0000_0000_0000),
+    (0x0000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0000, 0x0000_0000_8000_0000),
+    (0x0000_0000_0000_0000, 0x0000_0000_FFFF_FFFF),
+    (0x0000_0000_0000_0000, 0x0123_4567_89AB_CDEF),
+    (0x0000_0000_0000_0001, 0xFEDC_BA98_7654_3210),
+    (0x0000_0000_0000_0008, 0x00FF_00FF_00FF_00FF),
+    (0x0000_0000_0000_0000, 0xFFFF_FFFF_8000_0000),
+]);
+
+test_r_op!(test_ctz, itype(OP_IMM, 0b001, 0x601), [
+    (0x0000_0000_0000_0040, 0x0000_0000_0000_0000),
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0001),
+    (0x0000_0000_0000_0000, 0xFFFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_003F, 0x8000_0000_0000_0000),
+    (0x0000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_001F, 0x0000_0000_8000_0000),
+    (0x0000_0000_0000_0000, 0x0000_0000_FFFF_FFFF),
+    (0x0000_0000_0000_0000, 0x0123_4567_89AB_CDEF),
+    (0x0000_0000_0000_0004, 0xFEDC_BA98_7654_3210),
+    (0x0000_0000_0000_0000, 0x00FF_00FF_00FF_00FF),
+    (0x0000_0000_0000_001F, 0xFFFF_FFFF_8000_0000),
+]);
+
+test_r_op!(test_ctzw, itype(OP_IMM_32, 0b001, 0x601), [
+    (0x0000_0000_0000_0020, 0x0000_0000_0000_0000),
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0001),
+    (0x0000_0000_0000_0000, 0xFFFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0020, 0x8000_0000_0000_0000),
+    (0x0000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_001F, 0x0000_0000_8000_0000),
+    (0x0000_0000_0000_0000, 0x0000_0000_FFFF_FFFF),
+    (0x0000_0000_0000_0000, 0x0123_4567_89AB_CDEF),
+    (0x0000_0000_0000_0004, 0xFEDC_BA98_7654_3210),
+    (0x0000_0000_0000_0000, 0x00FF_00FF_00FF_00FF),
+    (0x0000_0000_0000_001F, 0xFFFF_FFFF_8000_0000),
+]);
+
+test_r_op!(test_cpop, itype(OP_IMM, 0b001, 0x602), [
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000),
+    (0x0000_0000_0000_0001, 0x0000_0000_0000_0001),
+    (0x0000_0000_0000_0040, 0xFFFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0001, 0x8000_0000_0000_0000),
+    (0x0000_0000_0000_003F, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0001, 0x0000_0000_8000_0000),
+    (0x0000_0000_0000_0020, 0x0000_0000_FFFF_FFFF),
+    (0x0000_0000_0000_0020, 0x0123_4567_89AB_CDEF),
+    (0x0000_0000_0000_0020, 0xFEDC_BA98_7654_3210),
+    (0x0000_0000_0000_0020, 0x00FF_00FF_00FF_00FF),
+    (0x0000_0000_0000_0021, 0xFFFF_FFFF_8000_0000),
+]);
+
+test_r_op!(test_cpopw, itype(OP_IMM_32, 0b001, 0x602), [
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000),
+    (0x0000_0000_0000_0001, 0x0000_0000_0000_0001),
+    (0x0000_0000_0000_0020, 0xFFFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0000, 0x8000_0000_0000_0000),
+    (0x0000_0000_0000_0020, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0001, 0x0000_0000_8000_0000),
+    (0x0000_0000_0000_0020, 0x0000_0000_FFFF_FFFF),
+    (0x0000_0000_0000_0014, 0x0123_4567_89AB_CDEF),
+    (0x0000_0000_0000_000C, 0xFEDC_BA98_7654_3210),
+    (0x0000_0000_0000_0010, 0x00FF_00FF_00FF_00FF),
+    (0x0000_0000_0000_0001, 0xFFFF_FFFF_8000_0000),
+]);
+
+test_rr_op!(test_max, rtype(OP, 0b110, 0b000_0101), [
+    (0x0123_4567_89AB_CDEF, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0x0000_0000_0000_0001, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x7FFF_FFFF_FFFF_FFFF, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_FFFF_FFFF, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0x0000_0000_0000_0021, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_003F, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_maxu, rtype(OP, 0b111, 0b000_0101), [
+    (0xFEDC_BA98_7654_3210, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x8000_0000_0000_0000, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_FFFF_FFFF, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0xFFFF_FFFF_8000_0000, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_003F, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_min, rtype(OP, 0b100, 0b000_0101), [
+    (0xFEDC_BA98_7654_3210, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x8000_0000_0000_0000, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0003, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0xFFFF_FFFF_8000_0000, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_minu, rtype(OP, 0b101, 0b000_0101), [
+    (0x0123_4567_89AB_CDEF, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0x0000_0000_0000_0001, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x7FFF_FFFF_FFFF_FFFF, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0003, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0x0000_0000_0000_0021, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_r_op!(test_sext_b, itype(OP_IMM, 0b001, 0x604), [
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000),
+    (0x0000_0000_0000_0001, 0x0000_0000_0000_0001),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0000, 0x8000_0000_0000_0000),
+    (0xFFFF_FFFF_FFFF_FFFF, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0000, 0x0000_0000_8000_0000),
+    (0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_FFFF_FFFF),
+    (0xFFFF_FFFF_FFFF_FFEF, 0x0123_4567_89AB_CDEF),
+    (0x0000_0000_0000_0010, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFF, 0x00FF_00FF_00FF_00FF),
+    (0x0000_0000_0000_0000, 0xFFFF_FFFF_8000_0000),
+]);
+
+test_r_op!(test_sext_h, itype(OP_IMM, 0b001, 0x605), [
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000),
+    (0x0000_0000_0000_0001, 0x0000_0000_0000_0001),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0000, 0x8000_0000_0000_0000),
+    (0xFFFF_FFFF_FFFF_FFFF, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0000, 0x0000_0000_8000_0000),
+    (0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_FFFF_FFFF),
+    (0xFFFF_FFFF_FFFF_CDEF, 0x0123_4567_89AB_CDEF),
+    (0x0000_0000_0000_3210, 0xFEDC_BA98_7654_3210),
+    (0x0000_0000_0000_00FF, 0x00FF_00FF_00FF_00FF),
+    (0x0000_0000_0000_0000, 0xFFFF_FFFF_8000_0000),
+]);
+
+test_r_op!(test_zext_h, itype(OP_32, 0b100, 0x080), [
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000),
+    (0x0000_0000_0000_0001, 0x0000_0000_0000_0001),
+    (0x0000_0000_0000_FFFF, 0xFFFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0000, 0x8000_0000_0000_0000),
+    (0x0000_0000_0000_FFFF, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0000, 0x0000_0000_8000_0000),
+    (0x0000_0000_0000_FFFF, 0x0000_0000_FFFF_FFFF),
+    (0x0000_0000_0000_CDEF, 0x0123_4567_89AB_CDEF),
+    (0x0000_0000_0000_3210, 0xFEDC_BA98_7654_3210),
+    (0x0000_0000_0000_00FF, 0x00FF_00FF_00FF_00FF),
+    (0x0000_0000_0000_0000, 0xFFFF_FFFF_8000_0000),
+]);
+
+test_rr_op!(test_rol, rtype(OP, 0b001, 0b011_0000), [
+    (0x4567_89AB_CDEF_0123, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x4000_0000_0000_0000, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0007_FFFF_FFF8, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0x0000_0001_FFFF_FFFF, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_rolw, rtype(OP_32, 0b001, 0b011_0000), [
+    (0xFFFF_FFFF_CDEF_89AB, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x0000_0000_0000_0000, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0x0000_0000_0000_0001, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_ror, rtype(OP, 0b101, 0b011_0000), [
+    (0xCDEF_0123_4567_89AB, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x0000_0000_0000_0001, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0xE000_0000_1FFF_FFFF, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0xC000_0000_7FFF_FFFF, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_imm_op!(test_rori, OP_IMM, 0b101, 0x600, [
+    (0x0123_4567_89AB_CDEF, 0x0123_4567_89AB_CDEF, 0),
+    (0x8091_A2B3_C4D5_E6F7, 0x0123_4567_89AB_CDEF, 1),
+    (0xDE02_468A_CF13_579B, 0x0123_4567_89AB_CDEF, 7),
+    (0x1357_9BDE_0246_8ACF, 0x0123_4567_89AB_CDEF, 31),
+    (0x89AB_CDEF_0123_4567, 0x0123_4567_89AB_CDEF, 32),
+    (0x0246_8ACF_1357_9BDE, 0x0123_4567_89AB_CDEF, 63),
+    (0xFFFF_FFFF_8000_0001, 0xFFFF_FFFF_8000_0001, 0),
+    (0xFFFF_FFFF_C000_0000, 0xFFFF_FFFF_8000_0001, 1),
+    (0x03FF_FFFF_FF00_0000, 0xFFFF_FFFF_8000_0001, 7),
+    (0x0000_0003_FFFF_FFFF, 0xFFFF_FFFF_8000_0001, 31),
+    (0x8000_0001_FFFF_FFFF, 0xFFFF_FFFF_8000_0001, 32),
+    (0xFFFF_FFFF_0000_0003, 0xFFFF_FFFF_8000_0001, 63),
+]);
+
+test_imm_op!(test_roriw, OP_IMM_32, 0b101, 0x600, [
+    (0xFFFF_FFFF_89AB_CDEF, 0x0123_4567_89AB_CDEF, 0),
+    (0xFFFF_FFFF_C4D5_E6F7, 0x0123_4567_89AB_CDEF, 1),
+    (0xFFFF_FFFF_DF13_579B, 0x0123_4567_89AB_CDEF, 7),
+    (0x0000_0000_1357_9BDF, 0x0123_4567_89AB_CDEF, 31),
+    (0xFFFF_FFFF_8000_0001, 0xFFFF_FFFF_8000_0001, 0),
+    (0xFFFF_FFFF_C000_0000, 0xFFFF_FFFF_8000_0001, 1),
+    (0x0000_0000_0300_0000, 0xFFFF_FFFF_8000_0001, 7),
+    (0x0000_0000_0000_0003, 0xFFFF_FFFF_8000_0001, 31),
+]);
+
+test_rr_op!(test_rorw, rtype(OP_32, 0b101, 0b011_0000), [
+    (0xFFFF_FFFF_CDEF_89AB, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x0000_0000_0000_0000, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0x0000_0000_4000_0000, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_r_op!(test_orc_b, itype(OP_IMM, 0b101, 0x287), [
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000),
+    (0x0000_0000_0000_00FF, 0x0000_0000_0000_0001),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF),
+    (0xFF00_0000_0000_0000, 0x8000_0000_0000_0000),
+    (0xFFFF_FFFF_FFFF_FFFF, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_FF00_0000, 0x0000_0000_8000_0000),
+    (0x0000_0000_FFFF_FFFF, 0x0000_0000_FFFF_FFFF),
+    (0xFFFF_FFFF_FFFF_FFFF, 0x0123_4567_89AB_CDEF),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFEDC_BA98_7654_3210),
+    (0x00FF_00FF_00FF_00FF, 0x00FF_00FF_00FF_00FF),
+    (0xFFFF_FFFF_FF00_0000, 0xFFFF_FFFF_8000_0000),
+]);
+
+test_r_op!(test_rev8, itype(OP_IMM, 0b101, 0x6B8), [
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000),
+    (0x0100_0000_0000_0000, 0x0000_0000_0000_0001),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0080, 0x8000_0000_0000_0000),
+    (0xFFFF_FFFF_FFFF_FF7F, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0080_0000_0000, 0x0000_0000_8000_0000),
+    (0xFFFF_FFFF_0000_0000, 0x0000_0000_FFFF_FFFF),
+    (0xEFCD_AB89_6745_2301, 0x0123_4567_89AB_CDEF),
+    (0x1032_5476_98BA_DCFE, 0xFEDC_BA98_7654_3210),
+    (0xFF00_FF00_FF00_FF00, 0x00FF_00FF_00FF_00FF),
+    (0x0000_0080_FFFF_FFFF, 0xFFFF_FFFF_8000_0000),
+]);
+
+// Zbc: carry-less multiplication
+
+test_rr_op!(test_clmul, rtype(OP, 0b001, 0b000_0101), [
+    (0x40A0_7898_28C8_10F0, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x8000_0000_0000_0000, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0001_0000_0001, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0x0000_000F_8000_0000, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_clmulh, rtype(OP, 0b011, 0b000_0101), [
+    (0x00E0_38D8_6888_50B0, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0x0000_0000_0000_0000, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x3FFF_FFFF_FFFF_FFFF, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0000, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0x0000_0000_0000_001F, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_rr_op!(test_clmulr, rtype(OP, 0b010, 0b000_0101), [
+    (0x01C0_71B0_D110_A160, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0x0000_0000_0000_0001, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x7FFF_FFFF_FFFF_FFFF, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0000, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0x0000_0000_0000_003E, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+// Zbs: single-bit instructions
+
+test_rr_op!(test_bclr, rtype(OP, 0b001, 0b010_0100), [
+    (0x0123_4567_89AA_CDEF, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFD, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x0000_0000_0000_0000, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_FFFF_FFF7, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0xFFFF_FFFD_8000_0000, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_imm_op!(test_bclri, OP_IMM, 0b001, 0x480, [
+    (0x0123_4567_89AB_CDEE, 0x0123_4567_89AB_CDEF, 0),
+    (0x0123_4567_89AB_CDED, 0x0123_4567_89AB_CDEF, 1),
+    (0x0123_4567_09AB_CDEF, 0x0123_4567_89AB_CDEF, 31),
+    (0x0123_4566_89AB_CDEF, 0x0123_4567_89AB_CDEF, 32),
+    (0x0123_4567_89AB_CDEF, 0x0123_4567_89AB_CDEF, 63),
+    (0xFFFF_FFFF_8000_0000, 0xFFFF_FFFF_8000_0001, 0),
+    (0xFFFF_FFFF_8000_0001, 0xFFFF_FFFF_8000_0001, 1),
+    (0xFFFF_FFFF_0000_0001, 0xFFFF_FFFF_8000_0001, 31),
+    (0xFFFF_FFFE_8000_0001, 0xFFFF_FFFF_8000_0001, 32),
+    (0x7FFF_FFFF_8000_0001, 0xFFFF_FFFF_8000_0001, 63),
+]);
+
+test_rr_op!(test_bext, rtype(OP, 0b101, 0b010_0100), [
+    (0x0000_0000_0000_0001, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0x0000_0000_0000_0001, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x0000_0000_0000_0001, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_0000_0001, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0x0000_0000_0000_0001, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x0000_0000_0000_0000, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_imm_op!(test_bexti, OP_IMM, 0b101, 0x480, [
+    (0x0000_0000_0000_0001, 0x0123_4567_89AB_CDEF, 0),
+    (0x0000_0000_0000_0001, 0x0123_4567_89AB_CDEF, 1),
+    (0x0000_0000_0000_0001, 0x0123_4567_89AB_CDEF, 31),
+    (0x0000_0000_0000_0001, 0x0123_4567_89AB_CDEF, 32),
+    (0x0000_0000_0000_0000, 0x0123_4567_89AB_CDEF, 63),
+    (0x0000_0000_0000_0001, 0xFFFF_FFFF_8000_0001, 0),
+    (0x0000_0000_0000_0000, 0xFFFF_FFFF_8000_0001, 1),
+    (0x0000_0000_0000_0001, 0xFFFF_FFFF_8000_0001, 31),
+    (0x0000_0000_0000_0001, 0xFFFF_FFFF_8000_0001, 32),
+    (0x0000_0000_0000_0001, 0xFFFF_FFFF_8000_0001, 63),
+]);
+
+test_rr_op!(test_binv, rtype(OP, 0b001, 0b011_0100), [
+    (0x0123_4567_89AA_CDEF, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFD, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x0000_0000_0000_0000, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_FFFF_FFF7, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0xFFFF_FFFD_8000_0000, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x8000_0000_0000_0000, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_imm_op!(test_binvi, OP_IMM, 0b001, 0x680, [
+    (0x0123_4567_89AB_CDEE, 0x0123_4567_89AB_CDEF, 0),
+    (0x0123_4567_89AB_CDED, 0x0123_4567_89AB_CDEF, 1),
+    (0x0123_4567_09AB_CDEF, 0x0123_4567_89AB_CDEF, 31),
+    (0x0123_4566_89AB_CDEF, 0x0123_4567_89AB_CDEF, 32),
+    (0x8123_4567_89AB_CDEF, 0x0123_4567_89AB_CDEF, 63),
+    (0xFFFF_FFFF_8000_0000, 0xFFFF_FFFF_8000_0001, 0),
+    (0xFFFF_FFFF_8000_0003, 0xFFFF_FFFF_8000_0001, 1),
+    (0xFFFF_FFFF_0000_0001, 0xFFFF_FFFF_8000_0001, 31),
+    (0xFFFF_FFFE_8000_0001, 0xFFFF_FFFF_8000_0001, 32),
+    (0x7FFF_FFFF_8000_0001, 0xFFFF_FFFF_8000_0001, 63),
+]);
+
+test_rr_op!(test_bset, rtype(OP, 0b001, 0b001_0100), [
+    (0x0123_4567_89AB_CDEF, 0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210),
+    (0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFF, 0x0000_0000_0000_0001),
+    (0x8000_0000_0000_0000, 0x8000_0000_0000_0000, 0x7FFF_FFFF_FFFF_FFFF),
+    (0x0000_0000_FFFF_FFFF, 0x0000_0000_FFFF_FFFF, 0x0000_0000_0000_0003),
+    (0xFFFF_FFFF_8000_0000, 0xFFFF_FFFF_8000_0000, 0x0000_0000_0000_0021),
+    (0x8000_0000_0000_0000, 0x0000_0000_0000_0000, 0x0000_0000_0000_003F),
+]);
+
+test_imm_op!(test_bseti, OP_IMM, 0b001, 0x280, [
+    (0x0123_4567_89AB_CDEF, 0x0123_4567_89AB_CDEF, 0),
+    (0x0123_4567_89AB_CDEF, 0x0123_4567_89AB_CDEF, 1),
+    (0x0123_4567_89AB_CDEF, 0x0123_4567_89AB_CDEF, 31),
+    (0x0123_4567_89AB_CDEF, 0x0123_4567_89AB_CDEF, 32),
+    (0x8123_4567_89AB_CDEF, 0x0123_4567_89AB_CDEF, 63),
+    (0xFFFF_FFFF_8000_0001, 0xFFFF_FFFF_8000_0001, 0),
+    (0xFFFF_FFFF_8000_0003, 0xFFFF_FFFF_8000_0001, 1),
+    (0xFFFF_FFFF_8000_0001, 0xFFFF_FFFF_8000_0001, 31),
+    (0xFFFF_FFFF_8000_0001, 0xFFFF_FFFF_8000_0001, 32),
+    (0xFFFF_FFFF_8000_0001, 0xFFFF_FFFF_8000_0001, 63),
+]);
+
+#[test]
+pub fn test_bitmanip_disabled() {
+    let mut cpu = Cpu::new();
+
+    // Zba, Zbb and Zbs follow misa.B, Zbc has no misa bit
+    let misa = cpu.csr.read(MISA);
+    cpu.csr.write(MISA, misa & !misa_bit('B'));
+
+    for inst in [rtype(OP, 0b010, 0b001_0000), rtype(OP, 0b111, 0b010_0000), itype(OP_IMM, 0b001, 0x600), rtype(OP_32, 0b000, 0b000_0100)].iter() {
+        cpu.instruction = *inst;
+        match cpu.execute() {
+            Err(Exception::IllegalInst) => (),
+            _                           => panic!("0x{:08x} must be illegal while misa.B is clear", inst),
+        }
+    }
+
+    cpu.instruction = rtype(OP, 0b001, 0b000_0101);
+    cpu.execute().unwrap();
+}
+
+#[test]
+pub fn test_bitmanip_reserved() {
+    let mut cpu = Cpu::new();
+
+    // Reserved unary operations, shift-immediate groups and ORC.B/REV8 immediates
+    for (funct3, imm) in [(0b001, 0x603), (0b001, 0x606), (0b001, 0xFC0), (0b101, 0x280), (0b101, 0x681)].iter() {
+        match exec(&mut cpu, itype(OP_IMM, *funct3, *imm)) {
+            Err(Exception::IllegalInst) => (),
+            _                           => panic!("funct3 {:03b} imm 0x{:03x} must be illegal", funct3, imm),
+        }
+    }
+}
+
+#[test]
+pub fn test_isa_string() {
+    let mut cpu = Cpu::new();
+
+    let isa = cpu.csr.isa_string();
+    let extensions: Vec<&str> = isa.split('_').collect();
+    assert!(extensions[0].starts_with("rv64i") && extensions[0].contains('b'));
+    for ext in ["zba", "zbb", "zbc", "zbs"] {
+        assert!(extensions.contains(&ext), "{} is missing from {}", ext, isa);
+    }
+
+    // Zba, Zbb and Zbs make up B, while Zbc does not depend on misa.B
+    let misa = cpu.csr.read(MISA);
+    cpu.csr.write(MISA, misa & !misa_bit('B'));
+    let isa = cpu.csr.isa_string();
+    let extensions: Vec<&str> = isa.split('_').collect();
+    assert!(!extensions[0].contains('b'));
+    for ext in ["zba", "zbb", "zbs"] {
+        assert!(!extensions.contains(&ext), "{} must not be advertised in {}", ext, isa);
+    }
+    assert!(extensions.contains(&"zbc"));
+}
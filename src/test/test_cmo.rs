@@ -0,0 +1,171 @@
+#![cfg(test)]
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::csr::*;
+use crate::emulator::bus::{ DRAM_BASE, UART0_BASE };
+use crate::emulator::exception::Exception;
+use crate::emulator::pmp::*;
+use super::helper::*;
+
+const CBO_INVAL:    u32 = 0x0000_A00F;  // CBO.INVAL (x1)
+const CBO_CLEAN:    u32 = 0x0010_A00F;  // CBO.CLEAN (x1)
+const CBO_FLUSH:    u32 = 0x0020_A00F;  // CBO.FLUSH (x1)
+const CBO_ZERO:     u32 = 0x0040_A00F;  // CBO.ZERO (x1)
+const PAUSE:        u32 = 0x0100_000F;
+const PREFETCH_W:   u32 = 0x0030_E013;  // PREFETCH.W 0(x1)
+
+// x3 = op(x1, x2)
+fn czero(funct3: u32) -> u32 {
+    rtype(OP, funct3, 0b000_0111)
+}
+
+// Execute a CBO, PREFETCH or hint with the address in x1
+fn exec_addr(cpu: &mut Cpu, inst: u32, addr: usize) -> Result<(), Exception> {
+    cpu.register.write(1, addr as u64);
+    exec(cpu, inst)
+}
+
+fn fill(cpu: &mut Cpu, base: usize, size: usize) {
+    for offset in (0..size).step_by(8) {
+        cpu.mmu.write64(&cpu.csr, base + offset, u64::MAX).unwrap();
+    }
+}
+
+#[test]
+pub fn test_cbo_zero() -> Result<(), Exception> {
+    let mut cpu = Cpu::new();
+    let block = DRAM_BASE + 0x1_0040;
+    fill(&mut cpu, block - 0x40, 0xC0);
+
+    // Any address in the block zeros the whole block and nothing else
+    exec_addr(&mut cpu, CBO_ZERO, block + 0x2B)?;
+    for offset in (0..0x40).step_by(8) {
+        assert_eq!(cpu.mmu.read64(&cpu.csr, block + offset)?, 0);
+    }
+    assert_eq!(cpu.mmu.read64(&cpu.csr, block - 8)?, u64::MAX);
+    assert_eq!(cpu.mmu.read64(&cpu.csr, block + 0x40)?, u64::MAX);
+
+    // The block size is configurable
+    cpu.mmu.cache_block_size = 128;
+    exec_addr(&mut cpu, CBO_ZERO, block - 0x30)?;
+    assert_eq!(cpu.mmu.read64(&cpu.csr, block - 0x40)?, 0);
+    assert_eq!(cpu.mmu.read64(&cpu.csr, block + 0x40)?, u64::MAX);
+
+    // Only cacheable memory is divided into blocks
+    match exec_addr(&mut cpu, CBO_ZERO, UART0_BASE) {
+        Err(Exception::StoreAccessFault(UART0_BASE))    => (),
+        _                                               => panic!("CBO.ZERO to I/O must raise a store access fault"),
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_cbo_envcfg() -> Result<(), Exception> {
+    let mut cpu = Cpu::new();
+    cpu.csr.write(PMPADDR0, u64::MAX);
+    cpu.csr.write(PMPCFG0, (PMP_A_NAPOT | PMP_R | PMP_W | PMP_X) as u64);
+
+    // M-mode ignores the enables
+    exec_addr(&mut cpu, CBO_INVAL, DRAM_BASE)?;
+
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    for inst in [CBO_INVAL, CBO_CLEAN, CBO_FLUSH, CBO_ZERO] {
+        match exec_addr(&mut cpu, inst, DRAM_BASE) {
+            Err(Exception::IllegalInst) => (),
+            _                           => panic!("CBO below M-mode must be illegal while menvcfg disables it"),
+        }
+    }
+
+    cpu.csr.write(MENVCFG, ENVCFG_CBIE | ENVCFG_CBCFE | ENVCFG_CBZE);
+    for inst in [CBO_INVAL, CBO_CLEAN, CBO_FLUSH, CBO_ZERO] {
+        exec_addr(&mut cpu, inst, DRAM_BASE)?;
+    }
+
+    // U-mode also needs senvcfg, and VS/VU-mode henvcfg
+    cpu.csr.priv_level = PrivLevel::USER;
+    match exec_addr(&mut cpu, CBO_ZERO, DRAM_BASE) {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("CBO.ZERO in U-mode must be illegal while senvcfg.CBZE is clear"),
+    }
+    cpu.csr.write(SENVCFG, ENVCFG_CBZE);
+    exec_addr(&mut cpu, CBO_ZERO, DRAM_BASE)?;
+
+    cpu.csr.virt = true;
+    match exec_addr(&mut cpu, CBO_ZERO, DRAM_BASE) {
+        Err(Exception::VirtualInst) => (),
+        _                           => panic!("CBO.ZERO with V=1 must be virtual while henvcfg.CBZE is clear"),
+    }
+    cpu.csr.virt = false;
+
+    // The reserved CBIE encoding is never stored
+    cpu.csr.write(SENVCFG, ENVCFG_CBIE);
+    cpu.csr.write(SENVCFG, 0b10 << 4);
+    assert_eq!(cpu.csr.read(SENVCFG), ENVCFG_CBIE);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_cbo_permissions() -> Result<(), Exception> {
+    let mut cpu = Cpu::new();
+    cpu.csr.write(MENVCFG, ENVCFG_CBIE | ENVCFG_CBCFE | ENVCFG_CBZE);
+
+    // Read-only and inaccessible 4KiB regions
+    cpu.csr.write(PMPADDR0, ((DRAM_BASE | 0x7FF) >> 2) as u64);
+    cpu.csr.write(PMPADDR1, (((DRAM_BASE + 0x1000) | 0x7FF) >> 2) as u64);
+    cpu.csr.write(PMPCFG0, ((PMP_A_NAPOT | PMP_R) as u64) | ((PMP_A_NAPOT as u64) << 8));
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+
+    // Managing a block needs load or store permission, zeroing it needs store permission
+    exec_addr(&mut cpu, CBO_FLUSH, DRAM_BASE + 0x80)?;
+    match exec_addr(&mut cpu, CBO_ZERO, DRAM_BASE + 0x80) {
+        Err(Exception::StoreAccessFault(addr))  => assert_eq!(addr, DRAM_BASE + 0x80),
+        _                                       => panic!("CBO.ZERO to a read-only block must fail"),
+    }
+    match exec_addr(&mut cpu, CBO_CLEAN, DRAM_BASE + 0x1080) {
+        Err(Exception::StoreAccessFault(addr))  => assert_eq!(addr, DRAM_BASE + 0x1080),
+        _                                       => panic!("CBO.CLEAN to an inaccessible block must raise a store fault"),
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_zicond_hints() -> Result<(), Exception> {
+    let mut cpu = Cpu::new();
+
+    for (inst, rs2, expected) in [(czero(0b101), 0, 0), (czero(0b101), 7, 42), (czero(0b111), 0, 42), (czero(0b111), 7, 0)] {
+        cpu.instruction = inst;
+        cpu.register.write(1, 42);
+        cpu.register.write(2, rs2);
+        cpu.execute()?;
+        assert_eq!(cpu.register.read(3), expected);
+    }
+
+    // PAUSE and the prefetches are hints without architectural effect
+    exec_addr(&mut cpu, PAUSE, 0)?;
+    exec_addr(&mut cpu, PREFETCH_W, UART0_BASE)?;
+    assert_eq!(cpu.register.read(1), UART0_BASE as u64);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_reserved_encodings() {
+    let mut cpu = Cpu::new();
+
+    let reserved = [
+        czero(0b001),               // CZERO space with funct3 other than 101 and 111
+        0x0030_A00F,                // CBO with funct12 = 3
+        CBO_ZERO | (3 << 7),        // CBO.ZERO with rd != 0
+        0x0000_B00F,                // MISC-MEM with funct3 = 011
+        0x0000_F00F,                // MISC-MEM with funct3 = 111
+    ];
+    for inst in reserved {
+        match exec_addr(&mut cpu, inst, DRAM_BASE) {
+            Err(Exception::IllegalInst) => (),
+            _                           => panic!("0x{:08x} is reserved and must be illegal", inst),
+        }
+    }
+}
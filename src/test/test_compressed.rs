@@ -0,0 +1,101 @@
+#![cfg(test)]
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::csr::*;
+use crate::emulator::bus::DRAM_BASE;
+use crate::emulator::compressed::expand;
+use super::helper::step;
+
+#[test]
+pub fn test_expand() {
+    let cases: [(u16, u32); 11] = [
+        (0x0515, 0x0055_0513),  // c.addi a0, 5         -> addi a0, a0, 5
+        (0x55F5, 0xFFD0_0593),  // c.li a1, -3          -> addi a1, x0, -3
+        (0x862E, 0x00B0_0633),  // c.mv a2, a1          -> add a2, x0, a1
+        (0x962A, 0x00A6_0633),  // c.add a2, a0         -> add a2, a2, a0
+        (0xA801, 0x0100_006F),  // c.j 16               -> jal x0, 16
+        (0xDD65, 0xFE05_0CE3),  // c.beqz a0, -8        -> beq a0, x0, -8
+        (0xC42A, 0x00A1_2423),  // c.swsp a0, 8(sp)     -> sw a0, 8(sp)
+        (0x46A2, 0x0081_2683),  // c.lwsp a3, 8(sp)     -> lw a3, 8(sp)
+        (0x8082, 0x0000_8067),  // c.jr ra              -> jalr x0, 0(ra)
+        (0x9002, 0x0010_0073),  // c.ebreak             -> ebreak
+        (0x8505, 0x4015_5513),  // c.srai a0, 1         -> srai a0, a0, 1
+    ];
+
+    for &(compressed, expanded) in cases.iter() {
+        assert_eq!(expand(compressed, 32), Some(expanded));
+        assert_eq!(expand(compressed, 64), Some(expanded));
+    }
+}
+
+#[test]
+pub fn test_expand_xlen() {
+    // The same encoding is C.JAL on RV32 and C.ADDIW on RV64, where rd=x0 is reserved
+    assert_eq!(expand(0x2021, 32), Some(0x0080_00EF));     // c.jal 8 -> jal ra, 8
+    assert_eq!(expand(0x2021, 64), None);
+    assert_eq!(expand(0x2505, 64), Some(0x0015_051B));     // c.addiw a0, 1 -> addiw a0, a0, 1
+
+    // C.SDSP is C.FSWSP on RV32, and shift amounts above 31 only exist on RV64
+    assert_eq!(expand(0xE82A, 64), Some(0x00A1_3823));     // c.sdsp a0, 16(sp) -> sd a0, 16(sp)
+    assert_eq!(expand(0xE82A, 32), None);
+    assert_eq!(expand(0x1522, 64), Some(0x0285_1513));     // c.slli a0, 40 -> slli a0, a0, 40
+    assert_eq!(expand(0x1522, 32), None);
+}
+
+#[test]
+pub fn test_expand_reserved() {
+    assert_eq!(expand(0x0000, 64), None);      // The all-zero parcel is defined to be illegal
+    assert_eq!(expand(0x0004, 64), None);      // C.ADDI4SPN with a zero immediate
+    assert_eq!(expand(0x6501, 64), None);      // C.LUI with a zero immediate
+    assert_eq!(expand(0x6101, 64), None);      // C.ADDI16SP with a zero immediate
+    assert_eq!(expand(0x8002, 64), None);      // C.JR with rs1=x0
+    assert_eq!(expand(0x4002, 64), None);      // C.LWSP with rd=x0
+    assert_eq!(expand(0x9C41, 32), None);      // C.SUBW on RV32
+}
+
+#[test]
+pub fn test_compressed_execution() {
+    let mut cpu = Cpu::new();
+    cpu.pc = DRAM_BASE;
+    cpu.mmu.write16(&cpu.csr, DRAM_BASE, 0x0515).unwrap();          // c.addi a0, 5
+    cpu.mmu.write32(&cpu.csr, DRAM_BASE + 2, 0x0015_0593).unwrap(); // addi a1, a0, 1
+    cpu.mmu.write16(&cpu.csr, DRAM_BASE + 6, 0xA801).unwrap();      // c.j 16
+
+    // A 32-bit instruction only needs to be 2-byte aligned
+    step(&mut cpu);
+    assert_eq!((cpu.pc, cpu.register.read(10)), (DRAM_BASE + 2, 5));
+    step(&mut cpu);
+    assert_eq!((cpu.pc, cpu.register.read(11)), (DRAM_BASE + 6, 6));
+    step(&mut cpu);
+    assert_eq!(cpu.pc, DRAM_BASE + 6 + 16);
+}
+
+#[test]
+pub fn test_compressed_link() {
+    let mut cpu = Cpu::new();
+    cpu.configure_xlen(32);
+    cpu.pc = DRAM_BASE;
+    cpu.mmu.write16(&cpu.csr, DRAM_BASE, 0x2021).unwrap();          // c.jal 8
+    cpu.mmu.write16(&cpu.csr, DRAM_BASE + 8, 0x8082).unwrap();      // c.jr ra
+
+    // The link register points past the 2-byte instruction
+    step(&mut cpu);
+    assert_eq!((cpu.pc, cpu.register.read_unsigned(1)), (DRAM_BASE + 8, (DRAM_BASE + 2) as u64));
+    step(&mut cpu);
+    assert_eq!(cpu.pc, DRAM_BASE + 2);
+}
+
+#[test]
+pub fn test_compressed_illegal() {
+    let mut cpu = Cpu::new();
+    cpu.csr.write(MTVEC, DRAM_BASE as u64 + 0x100);
+    cpu.pc = DRAM_BASE;
+    cpu.mmu.write16(&cpu.csr, DRAM_BASE, 0x0004).unwrap();          // reserved C.ADDI4SPN
+
+    // mtval holds the 16-bit encoding that trapped
+    step(&mut cpu);
+    assert_eq!(cpu.pc, DRAM_BASE + 0x100);
+    assert_eq!(cpu.csr.read(MCAUSE), 2);
+    assert_eq!(cpu.csr.read(MEPC), DRAM_BASE as u64);
+    assert_eq!(cpu.csr.read(MTVAL), 0x0004);
+}
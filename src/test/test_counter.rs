@@ -0,0 +1,147 @@
+#![cfg(test)]
+
+use crate::emulator::cpu::{ Cpu, Registers, WatchExec };
+use crate::emulator::csr::*;
+use crate::emulator::bus::DRAM_BASE;
+use crate::emulator::exception::Exception;
+use super::helper::exec;
+
+#[test]
+pub fn test_instret_excludes_traps() {
+    let mut cpu = Cpu::new();
+
+    // ECALL traps to a handler that runs a single NOP
+    cpu.mmu.write32(&cpu.csr, DRAM_BASE, 0x0000_0073).unwrap();
+    cpu.mmu.write32(&cpu.csr, DRAM_BASE + 0x10, 0x0000_0013).unwrap();
+    cpu.csr.write(MTVEC, (DRAM_BASE + 0x10) as u64);
+    cpu.csr.write(MHPMEVENT3, HPM_EVENT_TRAP);
+
+    cpu.watch(Registers::PC, (DRAM_BASE + 0x14) as u64, WatchExec::EXIT);
+    cpu.run();
+
+    assert_eq!(cpu.csr.read(MCYCLE), 2);
+    assert_eq!(cpu.csr.read(MINSTRET), 1);
+    assert_eq!(cpu.csr.read(INSTRET), 1);
+    assert_eq!(cpu.csr.read(MHPMCOUNTER3), 1);
+}
+
+#[test]
+pub fn test_minstret_write() {
+    let mut cpu = Cpu::new();
+
+    // csrw minstret, x1 leaves the written value, which the next instruction increments
+    cpu.register.write(1, 41);
+    exec(&mut cpu, ((MINSTRET as u32) << 20) | (1 << 15) | (0b001 << 12) | 0b111_0011).unwrap();
+    cpu.retire();
+    assert_eq!(cpu.csr.read(MINSTRET), 41);
+
+    exec(&mut cpu, 0x0000_0013).unwrap();
+    cpu.retire();
+    assert_eq!(cpu.csr.read(MINSTRET), 42);
+}
+
+#[test]
+pub fn test_mcountinhibit() {
+    let mut cpu = Cpu::new();
+
+    cpu.csr.write(MINSTRET, 41);
+    cpu.csr.increment_counter(MINSTRET);
+    assert_eq!(cpu.csr.read(MINSTRET), 42);
+
+    // IR inhibits minstret, the TM bit does not exist
+    cpu.csr.write(MCOUNTINHIBIT, 0b111);
+    assert_eq!(cpu.csr.read(MCOUNTINHIBIT), 0b101);
+    cpu.csr.increment_counter(MINSTRET);
+    assert_eq!(cpu.csr.read(MINSTRET), 42);
+
+    // HPM3 inhibits mhpmcounter3 whatever event it selects
+    cpu.csr.write(MHPMEVENT3, HPM_EVENT_LOAD);
+    cpu.csr.count_event(HPM_EVENT_LOAD, 1);
+    assert_eq!(cpu.csr.read(MHPMCOUNTER3), 1);
+    cpu.csr.write(MCOUNTINHIBIT, 1 << 3);
+    cpu.csr.count_event(HPM_EVENT_LOAD, 1);
+    assert_eq!(cpu.csr.read(MHPMCOUNTER3), 1);
+}
+
+#[test]
+pub fn test_mhpmevent() {
+    let mut cpu = Cpu::new();
+
+    cpu.csr.write(MHPMEVENT4, HPM_EVENT_STORE);
+    cpu.csr.write(MHPMEVENT5, 0xFF);
+    assert_eq!(cpu.csr.read(MHPMEVENT5), HPM_EVENT_NONE);
+
+    cpu.csr.count_event(HPM_EVENT_STORE, 3);
+    cpu.csr.count_event(HPM_EVENT_LOAD, 1);
+    assert_eq!(cpu.csr.read(MHPMCOUNTER4), 3);
+    assert_eq!(cpu.csr.read(MHPMCOUNTER5), 0);
+    assert_eq!(cpu.csr.read(HPMCOUNTER4), 3);
+
+    // MINH stops counting in M-mode only
+    cpu.csr.write(MHPMEVENT4, HPM_EVENT_STORE | MHPMEVENT_MINH);
+    cpu.csr.count_event(HPM_EVENT_STORE, 1);
+    assert_eq!(cpu.csr.read(MHPMCOUNTER4), 3);
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    cpu.csr.count_event(HPM_EVENT_STORE, 1);
+    assert_eq!(cpu.csr.read(MHPMCOUNTER4), 4);
+}
+
+#[test]
+pub fn test_counter_overflow() {
+    let mut cpu = Cpu::new();
+
+    cpu.csr.write(MHPMEVENT3, HPM_EVENT_BRANCH);
+    cpu.csr.write(MHPMCOUNTER3, u64::MAX);
+    cpu.csr.count_event(HPM_EVENT_BRANCH, 1);
+
+    assert_eq!(cpu.csr.read(MHPMCOUNTER3), 0);
+    assert_eq!(cpu.csr.read(MHPMEVENT3), MHPMEVENT_OF | HPM_EVENT_BRANCH);
+    assert_eq!(cpu.csr.read(MIP) & MIP_LCOFIP, MIP_LCOFIP);
+    assert_eq!(cpu.csr.read(SCOUNTOVF), 1 << 3);
+
+    // scountovf only shows the counters S-mode may read
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    assert_eq!(cpu.csr.read(SCOUNTOVF), 0);
+    cpu.csr.write(MCOUNTEREN, 1 << 3);
+    assert_eq!(cpu.csr.read(SCOUNTOVF), 1 << 3);
+
+    // A counter that already overflowed does not interrupt again
+    cpu.csr.write_hw(MIP, 0);
+    cpu.csr.write(MHPMCOUNTER3, u64::MAX);
+    cpu.csr.count_event(HPM_EVENT_BRANCH, 1);
+    assert_eq!(cpu.csr.read(MIP) & MIP_LCOFIP, 0);
+}
+
+#[test]
+pub fn test_counteren() -> Result<(), Exception> {
+    let mut cpu = Cpu::new();
+
+    // CSRRS x1, cycle, x0
+    cpu.instruction = 0xC000_20F3;
+    cpu.execute()?;
+
+    for priv_level in [PrivLevel::SUPERVISOR, PrivLevel::USER].iter() {
+        cpu.csr.priv_level = *priv_level;
+        match cpu.execute() {
+            Err(Exception::IllegalInst) => (),
+            _                           => panic!("cycle must not be readable while mcounteren.CY is clear"),
+        }
+    }
+
+    cpu.csr.priv_level = PrivLevel::MACHINE;
+    cpu.csr.write(MCOUNTEREN, 1);
+
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    cpu.execute()?;
+
+    // U-mode also needs scounteren.CY
+    cpu.csr.priv_level = PrivLevel::USER;
+    match cpu.execute() {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("cycle must not be readable from U-mode while scounteren.CY is clear"),
+    }
+    cpu.csr.write(SCOUNTEREN, 1);
+    cpu.execute()?;
+
+    Ok(())
+}
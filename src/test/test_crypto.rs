@@ -0,0 +1,278 @@
+#![cfg(test)]
+
+// Scalar cryptography: the AES, SHA-2, SM3 and SM4 algorithms are built from the instructions
+// and checked against the test vectors published with their standards.
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::csr::*;
+use crate::emulator::exception::Exception;
+use super::helper::*;
+use std::convert::TryInto;
+
+fn aes64ks1i(rnum: u32) -> u32 {
+    itype(OP_IMM, 0b001, 0x310 | rnum)
+}
+
+fn sm4ed(bs: u32) -> u32 {
+    rtype(OP, 0b000, (bs << 5) | 0b1_1000)
+}
+
+fn sm4ks(bs: u32) -> u32 {
+    rtype(OP, 0b000, (bs << 5) | 0b1_1010)
+}
+
+// A 128-bit AES block as two little-endian doublewords
+fn block(hex: &str) -> [u64; 2] {
+    let bytes: Vec<u8> = (0..16).map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap()).collect();
+    [u64::from_le_bytes(bytes[..8].try_into().unwrap()), u64::from_le_bytes(bytes[8..].try_into().unwrap())]
+}
+
+// SHA-256 round constants
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+// SHA-512 round constants
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22, 0x7137449123ef65cd, 0xb5c0fbcfec4d3b2f, 0xe9b5dba58189dbbc,
+    0x3956c25bf348b538, 0x59f111f1b605d019, 0x923f82a4af194f9b, 0xab1c5ed5da6d8118,
+    0xd807aa98a3030242, 0x12835b0145706fbe, 0x243185be4ee4b28c, 0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f, 0x80deb1fe3b1696b1, 0x9bdc06a725c71235, 0xc19bf174cf692694,
+    0xe49b69c19ef14ad2, 0xefbe4786384f25e3, 0x0fc19dc68b8cd5b5, 0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275, 0x4a7484aa6ea6e483, 0x5cb0a9dcbd41fbd4, 0x76f988da831153b5,
+    0x983e5152ee66dfab, 0xa831c66d2db43210, 0xb00327c898fb213f, 0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2, 0xd5a79147930aa725, 0x06ca6351e003826f, 0x142929670a0e6e70,
+    0x27b70a8546d22ffc, 0x2e1b21385c26c926, 0x4d2c6dfc5ac42aed, 0x53380d139d95b3df,
+    0x650a73548baf63de, 0x766a0abb3c77b2a8, 0x81c2c92e47edaee6, 0x92722c851482353b,
+    0xa2bfe8a14cf10364, 0xa81a664bbc423001, 0xc24b8b70d0f89791, 0xc76c51a30654be30,
+    0xd192e819d6ef5218, 0xd69906245565a910, 0xf40e35855771202a, 0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8, 0x1e376c085141ab53, 0x2748774cdf8eeb99, 0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63, 0x4ed8aa4ae3418acb, 0x5b9cca4f7763e373, 0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc, 0x78a5636f43172f60, 0x84c87814a1f0ab72, 0x8cc702081a6439ec,
+    0x90befffa23631e28, 0xa4506cebde82bde9, 0xbef9a3f7b2c67915, 0xc67178f2e372532b,
+    0xca273eceea26619c, 0xd186b8c721c0c207, 0xeada7dd6cde0eb1e, 0xf57d4f7fee6ed178,
+    0x06f067aa72176fba, 0x0a637dc5a2c898a6, 0x113f9804bef90dae, 0x1b710b35131c471b,
+    0x28db77f523047d84, 0x32caab7b40c72493, 0x3c9ebe0a15c9bebc, 0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6, 0x597f299cfc657e2a, 0x5fcb6fab3ad6faec, 0x6c44198c4a475817,
+];
+
+#[test]
+pub fn test_aes128() {
+    let mut cpu = Cpu::new();
+    let aes64es     = rtype(OP, 0b000, 0b001_1001);
+    let aes64esm    = rtype(OP, 0b000, 0b001_1011);
+    let aes64ds     = rtype(OP, 0b000, 0b001_1101);
+    let aes64dsm    = rtype(OP, 0b000, 0b001_1111);
+    let aes64ks2    = rtype(OP, 0b000, 0b011_1111);
+    let aes64im     = itype(OP_IMM, 0b001, 0x300);
+
+    // FIPS 197, Appendix C.1
+    let plaintext   = block("00112233445566778899aabbccddeeff");
+    let ciphertext  = block("69c4e0d86a7b0430d8cdb78070b4c55a");
+
+    let mut round_keys = vec![block("000102030405060708090a0b0c0d0e0f")];
+    for rnum in 0..10 {
+        let [lo, hi] = round_keys[rnum as usize];
+        let temp = exec_rr(&mut cpu, aes64ks1i(rnum), hi, 0).unwrap();
+        let lo = exec_rr(&mut cpu, aes64ks2, temp, lo).unwrap();
+        let hi = exec_rr(&mut cpu, aes64ks2, lo, hi).unwrap();
+        round_keys.push([lo, hi]);
+    }
+
+    let mut state = [plaintext[0] ^ round_keys[0][0], plaintext[1] ^ round_keys[0][1]];
+    for (round, key) in round_keys.iter().enumerate().skip(1) {
+        let inst = if round == 10 { aes64es } else { aes64esm };
+        state = [exec_rr(&mut cpu, inst, state[0], state[1]).unwrap() ^ key[0], exec_rr(&mut cpu, inst, state[1], state[0]).unwrap() ^ key[1]];
+    }
+    assert_eq!(state, ciphertext);
+
+    // The equivalent inverse cipher uses InvMixColumns'd round keys
+    let mut state = [ciphertext[0] ^ round_keys[10][0], ciphertext[1] ^ round_keys[10][1]];
+    for round in (0..10).rev() {
+        let key = match round {
+            0   => round_keys[0],
+            _   => [exec_rr(&mut cpu, aes64im, round_keys[round][0], 0).unwrap(), exec_rr(&mut cpu, aes64im, round_keys[round][1], 0).unwrap()],
+        };
+        let inst = if round == 0 { aes64ds } else { aes64dsm };
+        state = [exec_rr(&mut cpu, inst, state[0], state[1]).unwrap() ^ key[0], exec_rr(&mut cpu, inst, state[1], state[0]).unwrap() ^ key[1]];
+    }
+    assert_eq!(state, plaintext);
+
+    // Round numbers above 0xA are reserved
+    cpu.instruction = aes64ks1i(0xB);
+    match cpu.execute() {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("AES64KS1I with rnum 0xB must be illegal"),
+    }
+}
+
+#[test]
+pub fn test_crypto_reserved() {
+    let mut cpu = Cpu::new();
+
+    // Reserved Zknh/Zksh and Zkne/Zknd unary immediates
+    for imm in [0x10A, 0x13F, 0x301, 0x320].iter() {
+        match exec(&mut cpu, itype(OP_IMM, 0b001, *imm)) {
+            Err(Exception::IllegalInst) => (),
+            _                           => panic!("imm 0x{:03x} must be illegal", imm),
+        }
+    }
+}
+
+#[test]
+pub fn test_sha256() {
+    let mut cpu = Cpu::new();
+    let mut op = |imm: u32, x: u32| exec_rr(&mut cpu, itype(OP_IMM, 0b001, imm), x as u64, 0).unwrap() as u32;
+
+    // FIPS 180-4 example: SHA-256("abc"), a single padded block
+    let mut w = [0u32; 64];
+    w[0] = 0x6162_6380;
+    w[15] = 24;
+    for i in 16..64 {
+        w[i] = op(0x103, w[i - 2]).wrapping_add(w[i - 7]).wrapping_add(op(0x102, w[i - 15])).wrapping_add(w[i - 16]);
+    }
+
+    let iv: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = iv;
+    for i in 0..64 {
+        let t1 = h.wrapping_add(op(0x101, e)).wrapping_add((e & f) ^ (!e & g)).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+        let t2 = op(0x100, a).wrapping_add((a & b) ^ (a & c) ^ (b & c));
+        h = g; g = f; f = e; e = d.wrapping_add(t1);
+        d = c; c = b; b = a; a = t1.wrapping_add(t2);
+    }
+
+    let digest: Vec<u32> = [a, b, c, d, e, f, g, h].iter().zip(iv.iter()).map(|(x, iv)| x.wrapping_add(*iv)).collect();
+    assert_eq!(digest, [0xba7816bf, 0x8f01cfea, 0x414140de, 0x5dae2223, 0xb00361a3, 0x96177a9c, 0xb410ff61, 0xf20015ad]);
+
+    // The 32-bit results are sign-extended and the upper half of rs1 is ignored
+    assert_eq!(exec_rr(&mut cpu, itype(OP_IMM, 0b001, 0x100), 0x2, 0).unwrap(), 0xFFFF_FFFF_8010_0800);
+    assert_eq!(exec_rr(&mut cpu, itype(OP_IMM, 0b001, 0x102), 0xFFFF_FFFF_0000_0008, 0).unwrap(), 0x1002_0001);
+}
+
+#[test]
+pub fn test_sha512() {
+    let mut cpu = Cpu::new();
+    let mut op = |imm: u32, x: u64| exec_rr(&mut cpu, itype(OP_IMM, 0b001, imm), x, 0).unwrap();
+
+    // FIPS 180-4 example: SHA-512("abc"), a single padded block
+    let mut w = [0u64; 80];
+    w[0] = 0x6162_6380_0000_0000;
+    w[15] = 24;
+    for i in 16..80 {
+        w[i] = op(0x107, w[i - 2]).wrapping_add(w[i - 7]).wrapping_add(op(0x106, w[i - 15])).wrapping_add(w[i - 16]);
+    }
+
+    let iv: [u64; 8] = [
+        0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+        0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+    ];
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = iv;
+    for i in 0..80 {
+        let t1 = h.wrapping_add(op(0x105, e)).wrapping_add((e & f) ^ (!e & g)).wrapping_add(SHA512_K[i]).wrapping_add(w[i]);
+        let t2 = op(0x104, a).wrapping_add((a & b) ^ (a & c) ^ (b & c));
+        h = g; g = f; f = e; e = d.wrapping_add(t1);
+        d = c; c = b; b = a; a = t1.wrapping_add(t2);
+    }
+
+    let digest: Vec<u64> = [a, b, c, d, e, f, g, h].iter().zip(iv.iter()).map(|(x, iv)| x.wrapping_add(*iv)).collect();
+    assert_eq!(digest, [
+        0xddaf35a193617aba, 0xcc417349ae204131, 0x12e6fa4e89a97ea2, 0x0a9eeee64b55d39a,
+        0x2192992a274fc1a8, 0x36ba3c23a3feebbd, 0x454d4423643ce80e, 0x2a9ac94fa54ca49f,
+    ]);
+}
+
+#[test]
+pub fn test_sm3() {
+    let mut cpu = Cpu::new();
+    let mut op = |imm: u32, x: u32| exec_rr(&mut cpu, itype(OP_IMM, 0b001, imm), x as u64, 0).unwrap() as u32;
+
+    // GB/T 32905-2016 example 1: SM3("abc"), a single padded block
+    let mut w = [0u32; 68];
+    w[0] = 0x6162_6380;
+    w[15] = 24;
+    for j in 16..68 {
+        w[j] = op(0x109, w[j - 16] ^ w[j - 9] ^ w[j - 3].rotate_left(15)) ^ w[j - 13].rotate_left(7) ^ w[j - 6];
+    }
+
+    let iv: [u32; 8] = [0x7380166f, 0x4914b2b9, 0x172442d7, 0xda8a0600, 0xa96f30bc, 0x163138aa, 0xe38dee4d, 0xb0fb0e4e];
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = iv;
+    for j in 0..64 {
+        let t: u32 = if j < 16 { 0x79cc4519 } else { 0x7a879d8a };
+        let ss1 = a.rotate_left(12).wrapping_add(e).wrapping_add(t.rotate_left(j as u32 % 32)).rotate_left(7);
+        let ss2 = ss1 ^ a.rotate_left(12);
+        let (ff, gg) = match j < 16 {
+            true    => (a ^ b ^ c, e ^ f ^ g),
+            false   => ((a & b) | (a & c) | (b & c), (e & f) | (!e & g)),
+        };
+        let tt1 = ff.wrapping_add(d).wrapping_add(ss2).wrapping_add(w[j] ^ w[j + 4]);
+        let tt2 = gg.wrapping_add(h).wrapping_add(ss1).wrapping_add(w[j]);
+        d = c; c = b.rotate_left(9); b = a; a = tt1;
+        h = g; g = f.rotate_left(19); f = e; e = op(0x108, tt2);
+    }
+
+    let digest: Vec<u32> = [a, b, c, d, e, f, g, h].iter().zip(iv.iter()).map(|(x, iv)| x ^ iv).collect();
+    assert_eq!(digest, [0x66c7f0f4, 0x62eeedd9, 0xd1f2d46b, 0xdc10e4e2, 0x4167c487, 0x5cf2f7a2, 0x297da02b, 0x8f4ba8e0]);
+}
+
+#[test]
+pub fn test_sm4() {
+    let mut cpu = Cpu::new();
+
+    // T (or T' with `ks`) of the round input accumulated into x, one byte at a time
+    let mut round = |x: u32, input: u32, ks: bool| {
+        (0..4).fold(x, |x, bs| exec_rr(&mut cpu, if ks { sm4ks(bs) } else { sm4ed(bs) }, x as u64, input as u64).unwrap() as u32)
+    };
+
+    // GB/T 32907-2016 example 1: the key is also the plaintext
+    let key: [u32; 4] = [0x01234567, 0x89abcdef, 0xfedcba98, 0x76543210];
+    let fk: [u32; 4] = [0xa3b1bac6, 0x56aa3350, 0x677d9197, 0xb27022dc];
+
+    let mut k: Vec<u32> = key.iter().zip(fk.iter()).map(|(k, fk)| k ^ fk).collect();
+    for i in 0..32 {
+        let ck = (0..4).fold(0, |ck, j| (ck << 8) | (((4 * i + j) * 7) & 0xFF) as u32);
+        let next = round(k[i], k[i + 1] ^ k[i + 2] ^ k[i + 3] ^ ck, true);
+        k.push(next);
+    }
+
+    let mut x = key.to_vec();
+    for i in 0..32 {
+        let next = round(x[i], x[i + 1] ^ x[i + 2] ^ x[i + 3] ^ k[i + 4], false);
+        x.push(next);
+    }
+
+    assert_eq!([x[35], x[34], x[33], x[32]], [0x681edf34, 0xd206965e, 0x86b3e94f, 0x536e4246]);
+}
+
+#[test]
+pub fn test_zbkb_zbkx() {
+    let mut cpu = Cpu::new();
+
+    // PACK, PACKH and PACKW
+    assert_eq!(exec_rr(&mut cpu, rtype(OP, 0b100, 0b000_0100), 0x1111_2222_3333_4444, 0x5555_6666_7777_8888).unwrap(), 0x7777_8888_3333_4444);
+    assert_eq!(exec_rr(&mut cpu, rtype(OP, 0b111, 0b000_0100), 0xFFFF_FF12, 0xFFFF_FF34).unwrap(), 0x3412);
+    assert_eq!(exec_rr(&mut cpu, rtype(OP_32, 0b100, 0b000_0100), 0xFFFF_1234, 0x8765_ABCD).unwrap(), 0xFFFF_FFFF_ABCD_1234);
+
+    // BREV8
+    assert_eq!(exec_rr(&mut cpu, itype(OP_IMM, 0b101, 0x687), 0x0102_0408_1020_4080, 0).unwrap(), 0x8040_2010_0804_0201);
+
+    // XPERM8 and XPERM4: out-of-range indices select zero
+    assert_eq!(exec_rr(&mut cpu, rtype(OP, 0b100, 0b001_0100), 0x0807_0605_0403_0201, 0xFF00_0000_0001_0203).unwrap(), 0x0001_0101_0102_0304);
+    assert_eq!(exec_rr(&mut cpu, rtype(OP, 0b010, 0b001_0100), 0xFEDC_BA98_7654_3210, 0x0123_4567_89AB_CDEF).unwrap(), 0x0123_4567_89AB_CDEF);
+
+    // Zbkb follows misa.B, the crossbar permutations do not
+    let misa = cpu.csr.read(MISA);
+    cpu.csr.write(MISA, misa & !misa_bit('B'));
+    cpu.instruction = rtype(OP, 0b100, 0b000_0100);
+    match cpu.execute() {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("PACK must be illegal while misa.B is clear"),
+    }
+    assert_eq!(exec_rr(&mut cpu, rtype(OP, 0b010, 0b001_0100), 0xFEDC_BA98_7654_3210, 0x10).unwrap(), 0x10);
+}
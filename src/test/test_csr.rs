@@ -69,4 +69,257 @@ pub fn test_read_bits() -> Result<(), Exception> {
     assert_eq!(cpu.csr.read_bits(USTATUS, 10..22), 0xF0F);
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+pub fn test_misa() -> Result<(), Exception> {
+    use super::super::emulator;
+    use emulator::cpu::Cpu;
+    use emulator::csr::*;
+
+    let mut cpu = Cpu::new();
+
+    let misa = cpu.csr.read(MISA);
+    assert_eq!(misa >> 62, 2);
+    for ext in "IMASU".chars() {
+        assert_ne!(misa & misa_bit(ext), 0);
+    }
+
+    // M, A and B can be disabled, but the base ISA and S/U-mode can not
+    cpu.csr.write(MISA, 0);
+    assert_eq!(cpu.csr.read(MISA), misa & !(misa_bit('M') | misa_bit('A') | misa_bit('B')));
+
+    // MUL x1, x2, x3 is illegal while M is disabled
+    cpu.instruction = 0x0231_00B3;
+    match cpu.execute() {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("MUL must be illegal while misa.M is clear"),
+    }
+
+    cpu.csr.write(MISA, misa);
+    cpu.execute()?;
+
+    Ok(())
+}
+
+#[test]
+pub fn test_read_only_id() -> Result<(), Exception> {
+    use super::super::emulator;
+    use emulator::csr::*;
+
+    let mut csr = Csr::new(3);
+    assert_eq!(csr.read(MHARTID), 3);
+
+    for id in [MVENDORID, MARCHID, MIMPID, MHARTID].iter() {
+        let data = csr.read(*id);
+        csr.write(*id, 0xdead_beef);
+        assert_eq!(csr.read(*id), data);
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_warl() -> Result<(), Exception> {
+    use super::super::emulator;
+    use emulator::csr::*;
+
+    let mut csr = Csr::new(0);
+
+    // Reserved mtvec modes keep the previous mode
+    csr.write(MTVEC, 0x8000_0001);
+    csr.write(MTVEC, 0x8000_0102);
+    assert_eq!(csr.read(MTVEC), 0x8000_0101);
+
+    // mstatus.MPP never holds the reserved privilege level
+    csr.write(MSTATUS, (PrivLevel::SUPERVISOR as u64) << 11);
+    csr.write(MSTATUS, (PrivLevel::RESERVED as u64) << 11);
+    assert_eq!(csr.read_bits(MSTATUS, 11..12+1), PrivLevel::SUPERVISOR as u64);
+
+    // SXL is read-only, and UXL keeps its value on a write of an unsupported XLEN
+    csr.write(SSTATUS, 0);
+    assert_eq!(csr.read(MSTATUS) >> 32, 0xA);
+
+    // Pending M-mode interrupts can only be raised by the hardware
+    csr.write(MIP, MIP_MTIP | MIP_SSIP);
+    assert_eq!(csr.read(MIP), MIP_SSIP);
+
+    // mepc is aligned to instructions, which are 16-bit aligned with the C extension
+    csr.write(MEPC, 0x8000_0003);
+    assert_eq!(csr.read(MEPC), 0x8000_0002);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_unimplemented_csr() -> Result<(), Exception> {
+    use super::super::emulator;
+    use emulator::cpu::Cpu;
+
+    let mut cpu = Cpu::new();
+
+    // CSRRS x1, tselect, x0
+    cpu.instruction = 0x7A00_20F3;
+    match cpu.execute() {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("accessing an unimplemented CSR must be illegal"),
+    }
+
+    // CSRRS x1, mhartid, x0
+    cpu.instruction = 0xF140_20F3;
+    cpu.execute()?;
+
+    Ok(())
+}
+
+#[test]
+pub fn test_csr_privilege() -> Result<(), Exception> {
+    use super::super::emulator;
+    use emulator::cpu::Cpu;
+    use emulator::csr::*;
+
+    let mut cpu = Cpu::new();
+
+    // CSRRS x1, mstatus, x0
+    cpu.instruction = 0x3000_20F3;
+    cpu.execute()?;
+
+    for priv_level in [PrivLevel::SUPERVISOR, PrivLevel::USER].iter() {
+        cpu.csr.priv_level = *priv_level;
+        match cpu.execute() {
+            Err(Exception::IllegalInst) => (),
+            _                           => panic!("mstatus must only be accessible from M-mode"),
+        }
+    }
+
+    // CSRRS x1, sstatus, x0
+    cpu.instruction = 0x1000_20F3;
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    cpu.execute()?;
+
+    Ok(())
+}
+
+#[test]
+pub fn test_csr_read_only() -> Result<(), Exception> {
+    use super::super::emulator;
+    use emulator::cpu::Cpu;
+
+    let mut cpu = Cpu::new();
+
+    // CSRRS x1, mhartid, x0 only reads
+    cpu.instruction = 0xF140_20F3;
+    cpu.execute()?;
+
+    // CSRRS x1, mhartid, x2 and CSRRW x0, mhartid, x1 write
+    for instruction in [0xF141_20F3, 0xF140_9073].iter() {
+        cpu.instruction = *instruction;
+        match cpu.execute() {
+            Err(Exception::IllegalInst) => (),
+            _                           => panic!("writing a read-only CSR must be illegal"),
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_csrrs_x0_does_not_write() -> Result<(), Exception> {
+    use super::super::emulator;
+    use emulator::cpu::Cpu;
+    use emulator::csr::*;
+
+    let mut cpu = Cpu::new();
+
+    // CSRRS x1, mip, x0 must not write back, or pending bits raised meanwhile could be lost
+    cpu.csr.write_hw(MIP, MIP_MTIP);
+    cpu.instruction = 0x3440_20F3;
+    cpu.execute()?;
+    assert_eq!(cpu.register.read(1), MIP_MTIP);
+    assert_eq!(cpu.csr.read(MIP), MIP_MTIP);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_tvm() -> Result<(), Exception> {
+    use super::super::emulator;
+    use emulator::cpu::Cpu;
+    use emulator::csr::*;
+
+    let mut cpu = Cpu::new();
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+
+    // CSRRS x1, satp, x0 and SFENCE.VMA x0, x0
+    for instruction in [0x1800_20F3, 0x1200_0073].iter() {
+        cpu.instruction = *instruction;
+
+        cpu.csr.write_bit(MSTATUS, MSTATUS_TVM_BIT, false);
+        cpu.execute()?;
+
+        cpu.csr.write_bit(MSTATUS, MSTATUS_TVM_BIT, true);
+        match cpu.execute() {
+            Err(Exception::IllegalInst) => (),
+            _                           => panic!("mstatus.TVM must trap satp and SFENCE.VMA in S-mode"),
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_no_n_extension() -> Result<(), Exception> {
+    use super::super::emulator;
+    use emulator::cpu::Cpu;
+    use emulator::csr::*;
+
+    let mut cpu = Cpu::new();
+
+    // CSRRS x1, {ustatus, utvec, uepc, sedeleg, sideleg}, x0 and URET
+    for instruction in [0x0000_20F3, 0x0050_20F3, 0x0410_20F3, 0x1020_20F3, 0x1030_20F3, 0x0020_0073].iter() {
+        cpu.instruction = *instruction;
+        match cpu.execute() {
+            Err(Exception::IllegalInst) => (),
+            _                           => panic!("0x{:08x} belongs to the N extension, which is not implemented", instruction),
+        }
+    }
+
+    // User-level interrupt bits are hard-wired to zero
+    cpu.csr.write(MSTATUS, 0b1_0001);
+    cpu.csr.write(MIE, 0x111);
+    cpu.csr.write(MIDELEG, 0x111);
+    assert_eq!(cpu.csr.read(MSTATUS) & 0b1_0001, 0);
+    assert_eq!(cpu.csr.read(MIE) & 0x111, 0);
+    assert_eq!(cpu.csr.read(MIDELEG) & 0x111, 0);
+
+    // A delegated trap from U-mode goes no further than S-mode
+    cpu.csr.write(MEDELEG, 1 << 8);
+    cpu.csr.write(STVEC, 0x8000_1000);
+    cpu.csr.priv_level = PrivLevel::USER;
+    Exception::EnvCallUmode.take_trap(&mut cpu);
+    assert!(cpu.csr.priv_level == PrivLevel::SUPERVISOR);
+    assert_eq!(cpu.pc, 0x8000_1000);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_no_delegation_from_machine() -> Result<(), Exception> {
+    use super::super::emulator;
+    use emulator::cpu::Cpu;
+    use emulator::csr::*;
+
+    let mut cpu = Cpu::new();
+
+    // A breakpoint in M-mode stays in M-mode, whatever medeleg holds
+    cpu.csr.write(MEDELEG, 1 << 3);
+    cpu.csr.write(MTVEC, 0x8000_1000);
+    cpu.csr.write(STVEC, 0x8000_2000);
+    cpu.pc = 0x8000_0000;
+    Exception::Breakpoint.take_trap(&mut cpu);
+    assert!(cpu.csr.priv_level == PrivLevel::MACHINE);
+    assert_eq!(cpu.pc, 0x8000_1000);
+    assert_eq!(cpu.csr.read(MCAUSE), 3);
+
+    Ok(())
+}
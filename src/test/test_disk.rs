@@ -0,0 +1,122 @@
+#![cfg(test)]
+
+use crate::emulator::disk::*;
+use std::fs;
+use std::path::PathBuf;
+
+// A scratch file unique to the test, removed first if a previous run left it behind
+fn scratch(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("riscv-test-{}-{}", std::process::id(), name));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+// An image whose sector n is filled with n
+fn create_image(name: &str, sectors: usize) -> String {
+    let path = scratch(name);
+    fs::write(&path, (0 .. sectors * SECTOR_SIZE).map(|i| (i / SECTOR_SIZE) as u8).collect::<Vec<u8>>()).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+fn read_sector(disk: &Disk, sector: u64) -> Vec<u8> {
+    let mut data = vec![0; SECTOR_SIZE];
+    disk.read_at(&mut data, sector * SECTOR_SIZE as u64).unwrap();
+    data
+}
+
+#[test]
+pub fn test_disk_write_back() {
+    let image = create_image("write-back", 4);
+
+    let mut disk = Disk::open(&image, DiskMode::WriteBack).unwrap();
+    assert_eq!(disk.size(), 4 * SECTOR_SIZE as u64);
+    assert!(!disk.is_readonly());
+    disk.write_at(&[0xAB; SECTOR_SIZE], SECTOR_SIZE as u64).unwrap();
+    disk.flush().unwrap();
+    drop(disk);
+
+    // The write reached the image file
+    let data = fs::read(&image).unwrap();
+    assert_eq!(data[SECTOR_SIZE], 0xAB);
+    assert_eq!(data[2 * SECTOR_SIZE], 2);
+    fs::remove_file(&image).unwrap();
+}
+
+#[test]
+pub fn test_disk_large_image() {
+    // Images are not held in memory, so they are not limited by its size
+    let path = scratch("large");
+    let file = fs::File::create(&path).unwrap();
+    file.set_len(1 << 30).unwrap();
+    drop(file);
+
+    let mut disk = Disk::open(path.to_str().unwrap(), DiskMode::WriteBack).unwrap();
+    assert_eq!(disk.size(), 1 << 30);
+    disk.write_at(&[0xCD; SECTOR_SIZE], (1 << 30) - SECTOR_SIZE as u64).unwrap();
+    assert_eq!(read_sector(&disk, ((1 << 30) / SECTOR_SIZE - 1) as u64)[0], 0xCD);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+pub fn test_disk_readonly() {
+    let image = create_image("readonly", 2);
+
+    let mut disk = Disk::open(&image, DiskMode::ReadOnly).unwrap();
+    assert!(disk.is_readonly());
+    assert!(disk.write_at(&[0xAB; SECTOR_SIZE], 0).is_err());
+    assert_eq!(read_sector(&disk, 1), vec![1; SECTOR_SIZE]);
+    fs::remove_file(&image).unwrap();
+}
+
+#[test]
+pub fn test_disk_volatile() {
+    let image = create_image("volatile", 4);
+
+    let mut disk = Disk::open(&image, DiskMode::Volatile).unwrap();
+    assert!(!disk.is_readonly());
+    disk.write_at(&[0xAB; SECTOR_SIZE], SECTOR_SIZE as u64).unwrap();
+    assert_eq!(read_sector(&disk, 1), vec![0xAB; SECTOR_SIZE]);
+    assert!(disk.write_at(&[0; 4], 0).is_err());
+
+    let mut data = vec![0; 2];
+    disk.read_at(&mut data, 2 * SECTOR_SIZE as u64 - 1).unwrap();
+    assert_eq!(data, vec![0xAB, 2]);
+    drop(disk);
+
+    // The write is gone with the disk
+    assert_eq!(fs::read(&image).unwrap()[SECTOR_SIZE], 1);
+    fs::remove_file(&image).unwrap();
+}
+
+#[test]
+pub fn test_disk_overlay() {
+    let image = create_image("overlay-base", 20);
+    let overlay = scratch("overlay").to_str().unwrap().to_string();
+
+    let mut disk = Disk::open(&image, DiskMode::Overlay(overlay.clone())).unwrap();
+    assert!(!disk.is_readonly());
+    disk.write_at(&[0xAB; 2 * SECTOR_SIZE], 9 * SECTOR_SIZE as u64).unwrap();
+    assert_eq!(read_sector(&disk, 8), vec![8; SECTOR_SIZE]);
+    assert_eq!(read_sector(&disk, 10), vec![0xAB; SECTOR_SIZE]);
+    assert!(disk.write_at(&[0; 4], 0).is_err());
+    drop(disk);
+
+    // The image is untouched, and the overlay keeps the writes for the next run
+    assert_eq!(fs::read(&image).unwrap()[9 * SECTOR_SIZE], 9);
+    let disk = Disk::open(&image, DiskMode::Overlay(overlay.clone())).unwrap();
+    assert_eq!(read_sector(&disk, 9), vec![0xAB; SECTOR_SIZE]);
+    assert_eq!(read_sector(&disk, 11), vec![11; SECTOR_SIZE]);
+
+    // Reads that straddle sectors take each part from where it lives
+    let mut data = vec![0; 2];
+    disk.read_at(&mut data, 11 * SECTOR_SIZE as u64 - 1).unwrap();
+    assert_eq!(data, vec![0xAB, 11]);
+
+    // An overlay only fits the image it was created for
+    let other = create_image("overlay-other", 4);
+    assert!(Disk::open(&other, DiskMode::Overlay(overlay.clone())).is_err());
+
+    for path in [&image, &overlay, &other] {
+        fs::remove_file(path).unwrap();
+    }
+}
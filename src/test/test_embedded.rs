@@ -0,0 +1,117 @@
+#![cfg(test)]
+
+use crate::emulator::cpu::{ Cpu, Registers };
+use crate::emulator::csr::*;
+use crate::emulator::bus::*;
+use crate::emulator::exception::Exception;
+use super::helper::*;
+
+const ADD:          u32 = 0x0020_81B3;  // add x3, x1, x2
+const ADD_X16:      u32 = 0x0020_8833;  // add x16, x1, x2
+const ADD_X17_SRC:  u32 = 0x0110_81B3;  // add x3, x1, x17
+const LUI:          u32 = 0xFFFF_F0B7;  // lui x1, 0xFFFFF
+const SW_X16:       u32 = 0x0100_A023;  // sw x16, 0(x1)
+const CSRRWI:       u32 = 0x340F_D0F3;  // csrrwi x1, mscratch, 31
+const CSRRW_X20:    u32 = 0x340A_10F3;  // csrrw x1, mscratch, x20
+const CSRR_MHARTID: u32 = 0xF140_20F3;  // csrrs x1, mhartid, x0
+const CSRW_PMPADDR: u32 = 0x3B00_9073;  // csrrw x0, pmpaddr0, x1
+const SFENCE_X16:   u32 = 0x1300_0073;  // sfence.vma x0, x16
+const C_MV_A6:      u32 = 0x882A;       // c.mv a6, a0
+
+#[test]
+pub fn test_rv32e_isa() {
+    let mut cpu = Cpu::new();
+    cpu.configure_xlen(32);
+    cpu.configure_embedded();
+
+    assert!(cpu.csr.has_extension('E') && !cpu.csr.has_extension('I'));
+    assert!(cpu.csr.isa_string().starts_with("rv32emac_"));
+
+    // RV64E keeps the RV64 extensions that do not need the I base
+    let mut cpu = Cpu::new();
+    cpu.configure_embedded();
+    assert!(cpu.csr.isa_string().starts_with("rv64emacb_"));
+    assert!(!cpu.csr.has_extension('H') && !cpu.csr.has_extension('V'));
+
+    // The E base survives a change of XLEN
+    cpu.configure_xlen(32);
+    assert!(cpu.csr.isa_string().starts_with("rv32emac_"));
+}
+
+#[test]
+pub fn test_rv32e_registers() -> Result<(), Exception> {
+    let mut cpu = Cpu::new();
+    cpu.configure_xlen(32);
+    cpu.configure_embedded();
+
+    // Immediates that happen to overlap a register field are not register numbers
+    exec(&mut cpu, ADD)?;
+    exec(&mut cpu, LUI)?;
+    exec(&mut cpu, CSRRWI)?;
+    assert_eq!(cpu.csr.read(MSCRATCH), 31);
+
+    // The upper bits of a CSR address are not rs2
+    exec(&mut cpu, CSRR_MHARTID)?;
+    assert_eq!(cpu.register.read(1), 0);
+    exec(&mut cpu, CSRW_PMPADDR)?;
+
+    for inst in [ADD_X16, ADD_X17_SRC, SW_X16, CSRRW_X20, SFENCE_X16, C_MV_A6] {
+        match exec(&mut cpu, inst) {
+            Err(Exception::IllegalInst) => (),
+            _                           => panic!("0x{:08x} names a register above x15", inst),
+        }
+    }
+
+    // The full register set is back without E
+    let mut cpu = Cpu::new();
+    exec(&mut cpu, ADD_X16)?;
+
+    Ok(())
+}
+
+#[test]
+pub fn test_mcu_memory_map() {
+    let mut cpu = Cpu::new();
+    cpu.configure_xlen(32);
+    cpu.configure_machine(Machine::Mcu);
+    assert_eq!(cpu.pc, FLASH_BASE);
+    assert_eq!(cpu.register.read(Registers::SP as usize), SRAM_TOP as u64);
+
+    // Firmware runs from flash at the reset vector and keeps its data in SRAM
+    let firmware: [u32; 5] = [
+        0x2000_0537,    // lui a0, 0x20000
+        0x02A0_0593,    // addi a1, x0, 42
+        0x00B5_2023,    // sw a1, 0(a0)
+        0x0005_2603,    // lw a2, 0(a0)
+        0xFF1F_F06F,    // j 0
+    ];
+    cpu.mmu.load_dram(firmware.iter().flat_map(|inst| inst.to_le_bytes()).collect());
+    for _ in 0..4 {
+        step(&mut cpu);
+    }
+    assert_eq!(cpu.pc, FLASH_BASE + 16);
+    assert_eq!(cpu.register.read(12), 42);
+    assert_eq!(cpu.mmu.read32(&cpu.csr, SRAM_BASE).unwrap(), 42);
+
+    // Jumping back to the reset vector does not end the emulation
+    step(&mut cpu);
+    assert_eq!(cpu.pc, FLASH_BASE);
+
+    // Flash is read-only, and the virt board's DRAM and PLIC do not exist
+    match cpu.mmu.write32(&cpu.csr, FLASH_BASE + 0x100, 0) {
+        Err(Exception::StoreAccessFault(0x100))  => (),
+        _                                       => panic!("stores to flash must raise an access fault"),
+    }
+    match cpu.mmu.amo32(&cpu.csr, FLASH_BASE, |data| data) {
+        Err(Exception::StoreAccessFault(FLASH_BASE)) => (),
+        _                                           => panic!("AMOs to flash must raise an access fault"),
+    }
+    match cpu.mmu.read32(&cpu.csr, DRAM_BASE) {
+        Err(Exception::LoadAccessFault(DRAM_BASE))  => (),
+        _                                           => panic!("DRAM is not part of the MCU memory map"),
+    }
+    match cpu.mmu.read32(&cpu.csr, PLIC_BASE) {
+        Err(Exception::LoadAccessFault(PLIC_BASE))  => (),
+        _                                           => panic!("the PLIC is not part of the MCU memory map"),
+    }
+}
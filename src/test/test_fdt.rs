@@ -0,0 +1,109 @@
+#![cfg(test)]
+
+use crate::emulator::cpu::{ Cpu, Registers };
+use crate::emulator::bus::{ DRAM_BASE, DRAM_TOP };
+use crate::emulator::csr::*;
+
+fn be32(blob: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([blob[offset], blob[offset + 1], blob[offset + 2], blob[offset + 3]])
+}
+
+fn c_string(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|&byte| byte == 0).unwrap();
+    std::str::from_utf8(&bytes[..len]).unwrap()
+}
+
+// Read the device tree blob passed to the kernel in a1
+fn read_dtb(cpu: &mut Cpu) -> Vec<u8> {
+    let addr = cpu.register.read(Registers::A1 as usize) as usize;
+    let size = cpu.mmu.read32(&cpu.csr, addr + 4).unwrap().swap_bytes() as usize;
+    (0..size).map(|i| cpu.mmu.read8(&cpu.csr, addr + i).unwrap()).collect()
+}
+
+// Value of the property `name` of the node at `path` (e.g. "/cpus/cpu@0")
+fn property<'a>(blob: &'a [u8], path: &str, name: &str) -> Option<&'a [u8]> {
+    let off_struct = be32(blob, 8) as usize;
+    let off_strings = be32(blob, 12) as usize;
+
+    let mut nodes: Vec<&str> = Vec::new();
+    let mut offset = off_struct;
+    loop {
+        let token = be32(blob, offset);
+        offset += 4;
+        match token {
+            // FDT_BEGIN_NODE
+            0x1 => {
+                let node = c_string(&blob[offset..]);
+                offset = (offset + node.len() + 1).next_multiple_of(4);
+                nodes.push(node);
+            },
+            // FDT_END_NODE
+            0x2 => { nodes.pop(); },
+            // FDT_PROP
+            0x3 => {
+                let len = be32(blob, offset) as usize;
+                let prop = c_string(&blob[off_strings + be32(blob, offset + 4) as usize..]);
+                let value = &blob[offset + 8..offset + 8 + len];
+                offset = (offset + 8 + len).next_multiple_of(4);
+                if nodes.join("/") == path && prop == name {
+                    return Some(value);
+                }
+            },
+            // FDT_END
+            0x9 => return None,
+            _   => panic!("invalid token 0x{:x}", token),
+        }
+    }
+}
+
+#[test]
+pub fn test_fdt_header() {
+    let mut cpu = Cpu::new();
+    let addr = cpu.load_dtb();
+
+    // The blob is 8-byte aligned at the top of DRAM, and the stack starts below it
+    assert_eq!(addr % 8, 0);
+    assert_eq!(cpu.register.read(Registers::A0 as usize), 0);
+    assert_eq!(cpu.register.read(Registers::A1 as usize), addr as u64);
+    assert_eq!(cpu.register.read(Registers::SP as usize), addr as u64);
+
+    let dtb = read_dtb(&mut cpu);
+    assert_eq!(be32(&dtb, 0), 0xD00D_FEED);
+    assert!(addr + dtb.len() <= DRAM_TOP + 1);
+    assert_eq!(be32(&dtb, 20), 17);
+}
+
+#[test]
+pub fn test_fdt_isa_string() {
+    let mut cpu = Cpu::new();
+    cpu.load_dtb();
+
+    let dtb = read_dtb(&mut cpu);
+    let isa = property(&dtb, "/cpus/cpu@0", "riscv,isa").unwrap();
+    assert_eq!(c_string(isa), cpu.csr.isa_string());
+    assert!(c_string(isa).split('_').any(|ext| ext == "zbb"));
+    assert_eq!(c_string(property(&dtb, "/cpus/cpu@0", "mmu-type").unwrap()), "riscv,sv57");
+
+    // The device tree follows misa
+    let misa = cpu.csr.read(MISA);
+    cpu.csr.write(MISA, misa & !misa_bit('B'));
+    cpu.load_dtb();
+
+    let dtb = read_dtb(&mut cpu);
+    let isa = property(&dtb, "/cpus/cpu@0", "riscv,isa").unwrap();
+    assert!(!c_string(isa).split('_').any(|ext| ext == "zbb"));
+}
+
+#[test]
+pub fn test_fdt_memory() {
+    let mut cpu = Cpu::new();
+    cpu.load_dtb();
+
+    let dtb = read_dtb(&mut cpu);
+    let reg = property(&dtb, "/memory@80000000", "reg").unwrap();
+    assert_eq!(be32(reg, 4) as usize, DRAM_BASE);
+    assert_eq!(be32(reg, 12) as usize, DRAM_TOP - DRAM_BASE + 1);
+
+    assert!(property(&dtb, "/soc/serial@10000000", "interrupts").is_some());
+    assert!(property(&dtb, "/soc/virtio_mmio@10001000", "reg").is_some());
+}
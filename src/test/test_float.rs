@@ -0,0 +1,252 @@
+#![cfg(test)]
+
+// Software floating-point: results rounded to nearest, ties to even, are checked against the host,
+// and the directed rounding modes against those results.
+
+use crate::emulator::float::*;
+
+// xorshift64: operands with random bit patterns cover every exponent, subnormals, infinities and NaNs
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // Mostly values of similar magnitude, so that sums cancel and round, with some random bit patterns
+    fn f64(&mut self) -> u64 {
+        let bits = self.next();
+        match bits % 4 {
+            0   => bits,
+            _   => (bits & 0x800F_FFFF_FFFF_FFFF) | ((0x3F0 + (bits >> 52) % 32) << 52),
+        }
+    }
+
+    fn f32(&mut self) -> u64 {
+        let bits = self.next() as u32;
+        match bits % 4 {
+            0   => bits as u64,
+            _   => ((bits & 0x807F_FFFF) | ((0x70 + (bits >> 23) % 32) << 23)) as u64,
+        }
+    }
+}
+
+fn host64(f: impl Fn(f64, f64, f64) -> f64, a: u64, b: u64, c: u64) -> u64 {
+    let result = f(f64::from_bits(a), f64::from_bits(b), f64::from_bits(c));
+    if result.is_nan() { F64.canonical_nan() } else { result.to_bits() }
+}
+
+fn host32(f: impl Fn(f32, f32, f32) -> f32, a: u64, b: u64, c: u64) -> u64 {
+    let result = f(f32::from_bits(a as u32), f32::from_bits(b as u32), f32::from_bits(c as u32));
+    if result.is_nan() { F32.canonical_nan() } else { result.to_bits() as u64 }
+}
+
+// The operation `op` of the software unit with rounding mode `rm`, and its flags
+fn soft(format: Format, rm: u64, op: usize, a: u64, b: u64, c: u64) -> (u64, u64) {
+    let mut env = FloatEnv::new(rm);
+    let result = match op {
+        0   => env.add(format, a, b),
+        1   => env.sub(format, a, b),
+        2   => env.mul(format, a, b),
+        3   => env.div(format, a, b),
+        4   => env.sqrt(format, a),
+        _   => env.fma(format, a, b, c),
+    };
+    (result, env.flags)
+}
+
+// The next value towards +infinity of a finite value
+fn next_up(format: Format, x: u64) -> u64 {
+    match (format.is_negative(x), format.is_zero(x)) {
+        (_, true)       => 1,
+        (true, false)   => x - 1,
+        (false, false)  => x + 1,
+    }
+}
+
+#[test]
+pub fn test_float_nearest_even() {
+    let mut random = Random(0x2545_F491_4F6C_DD1D);
+
+    for _ in 0..200_000 {
+        let (a, b, c) = (random.f64(), random.f64(), random.f64());
+        let expected = [
+            host64(|a, b, _| a + b, a, b, c),
+            host64(|a, b, _| a - b, a, b, c),
+            host64(|a, b, _| a * b, a, b, c),
+            host64(|a, b, _| a / b, a, b, c),
+            host64(|a, _, _| a.sqrt(), a, b, c),
+            host64(|a, b, c| a.mul_add(b, c), a, b, c),
+        ];
+        for (op, &expected) in expected.iter().enumerate() {
+            assert_eq!(soft(F64, RM_RNE, op, a, b, c).0, expected, "binary64 op {} of {:#x}, {:#x}, {:#x}", op, a, b, c);
+        }
+
+        let (a, b, c) = (random.f32(), random.f32(), random.f32());
+        let expected = [
+            host32(|a, b, _| a + b, a, b, c),
+            host32(|a, b, _| a - b, a, b, c),
+            host32(|a, b, _| a * b, a, b, c),
+            host32(|a, b, _| a / b, a, b, c),
+            host32(|a, _, _| a.sqrt(), a, b, c),
+            host32(|a, b, c| a.mul_add(b, c), a, b, c),
+        ];
+        for (op, &expected) in expected.iter().enumerate() {
+            assert_eq!(soft(F32, RM_RNE, op, a, b, c).0, expected, "binary32 op {} of {:#x}, {:#x}, {:#x}", op, a, b, c);
+        }
+    }
+}
+
+#[test]
+pub fn test_float_directed_rounding() {
+    let mut random = Random(0x9E37_79B9_7F4A_7C15);
+
+    for _ in 0..100_000 {
+        let (a, b, c) = (random.f64(), random.f64(), random.f64());
+        for op in 0..6 {
+            let (nearest, flags) = soft(F64, RM_RNE, op, a, b, c);
+            let (down, _) = soft(F64, RM_RDN, op, a, b, c);
+            let (up, _) = soft(F64, RM_RUP, op, a, b, c);
+            let (zero, _) = soft(F64, RM_RTZ, op, a, b, c);
+            let (away, _) = soft(F64, RM_RMM, op, a, b, c);
+            let message = format!("op {} of {:#x}, {:#x}, {:#x}", op, a, b, c);
+
+            // Overflows and exact results do not bracket the nearest value
+            if (flags & (FFLAGS_NX | FFLAGS_OF)) != FFLAGS_NX {
+                if (flags & FFLAGS_NX) == 0 {
+                    assert!([down, up, zero, away].iter().all(|&x| x == nearest), "{}", message);
+                }
+                continue;
+            }
+
+            // An inexact result lies between two neighbours: round down and up pick one each
+            assert_eq!(next_up(F64, down), up, "{}", message);
+            assert!(nearest == down || nearest == up, "{}", message);
+            assert!(away == down || away == up, "{}", message);
+            assert_eq!(zero, if F64.is_negative(down) { up } else { down }, "{}", message);
+        }
+    }
+}
+
+#[test]
+pub fn test_float_flags() {
+    let one = 0x3F80_0000;
+    let max = 0x7F7F_FFFF;
+    let min_normal = 0x0080_0000;
+    let half = 0x3F00_0000;
+
+    // Overflow rounds to infinity or to the largest finite value
+    assert_eq!(soft(F32, RM_RNE, 2, max, 0x4000_0000, 0), (0x7F80_0000, FFLAGS_OF | FFLAGS_NX));
+    assert_eq!(soft(F32, RM_RTZ, 2, max, 0x4000_0000, 0), (max, FFLAGS_OF | FFLAGS_NX));
+    assert_eq!(soft(F32, RM_RDN, 2, max | 1 << 31, 0x4000_0000, 0), (0xFF80_0000, FFLAGS_OF | FFLAGS_NX));
+    assert_eq!(soft(F32, RM_RUP, 2, max | 1 << 31, 0x4000_0000, 0), (max | 1 << 31, FFLAGS_OF | FFLAGS_NX));
+
+    // Exact subnormal results do not underflow, inexact ones do
+    assert_eq!(soft(F32, RM_RNE, 2, min_normal, half, 0), (0x0040_0000, 0));
+    assert_eq!(soft(F32, RM_RNE, 2, 1, half, 0), (0, FFLAGS_UF | FFLAGS_NX));
+    assert_eq!(soft(F32, RM_RUP, 2, 1, half, 0), (1, FFLAGS_UF | FFLAGS_NX));
+
+    // Tininess is detected after rounding: 2^-126 - 2^-160 rounds up to the smallest normal value
+    let (tiny, minus_tiny) = (0x1780_0000, 0x9780_0000);
+    assert_eq!(soft(F32, RM_RUP, 5, tiny, minus_tiny, min_normal), (min_normal, FFLAGS_NX));
+    assert_eq!(soft(F32, RM_RTZ, 5, tiny, minus_tiny, min_normal), (min_normal - 1, FFLAGS_UF | FFLAGS_NX));
+
+    // Invalid operations and division by zero
+    assert_eq!(soft(F32, RM_RNE, 3, 0, 0, 0), (F32.canonical_nan(), FFLAGS_NV));
+    assert_eq!(soft(F32, RM_RNE, 3, one | 1 << 31, 0, 0), (0xFF80_0000, FFLAGS_DZ));
+    assert_eq!(soft(F32, RM_RNE, 4, one | 1 << 31, 0, 0), (F32.canonical_nan(), FFLAGS_NV));
+    assert_eq!(soft(F32, RM_RNE, 0, 0x7F80_0000, 0xFF80_0000, 0), (F32.canonical_nan(), FFLAGS_NV));
+    assert_eq!(soft(F32, RM_RNE, 0, 0x7FA0_0000, one, 0), (F32.canonical_nan(), FFLAGS_NV));
+    assert_eq!(soft(F32, RM_RNE, 0, 0x7FC0_0001, one, 0), (F32.canonical_nan(), 0));
+    // Infinity times zero is invalid even with a quiet NaN addend
+    assert_eq!(soft(F32, RM_RNE, 5, 0x7F80_0000, 0, 0x7FC0_0000), (F32.canonical_nan(), FFLAGS_NV));
+
+    // An exact zero sum is +0, or -0 when rounding down
+    assert_eq!(soft(F32, RM_RNE, 1, one, one, 0), (0, 0));
+    assert_eq!(soft(F32, RM_RDN, 1, one, one, 0), (1 << 31, 0));
+    assert_eq!(soft(F32, RM_RNE, 0, 1 << 31, 1 << 31, 0), (1 << 31, 0));
+}
+
+#[test]
+pub fn test_float_compare() {
+    let mut env = FloatEnv::new(RM_RNE);
+    let (one, two, qnan, snan) = (0x3F80_0000, 0x4000_0000, 0x7FC0_0000, 0x7F80_0001);
+
+    assert_eq!(env.min(F32, one, two), one);
+    assert_eq!(env.max(F32, one, qnan), one);
+    assert_eq!(env.min(F32, 0, 1 << 31), 1 << 31);
+    assert_eq!(env.max(F32, 0, 1 << 31), 0);
+    assert_eq!(env.flags, 0);
+    assert_eq!(env.min(F32, snan, qnan), F32.canonical_nan());
+    assert_eq!(env.flags, FFLAGS_NV);
+
+    // Equality is quiet, ordering signals on quiet NaNs as well
+    let mut env = FloatEnv::new(RM_RNE);
+    assert!(env.eq(F32, 0, 1 << 31));
+    assert!(!env.eq(F32, qnan, qnan));
+    assert!(env.lt(F32, one | 1 << 31, one));
+    assert!(env.le(F32, one, one));
+    assert_eq!(env.flags, 0);
+    assert!(!env.lt(F32, qnan, one));
+    assert_eq!(env.flags, FFLAGS_NV);
+
+    assert_eq!(F32.classify(0xFF80_0000), 1 << 0);
+    assert_eq!(F32.classify(1 << 31 | 1), 1 << 2);
+    assert_eq!(F32.classify(1 << 31), 1 << 3);
+    assert_eq!(F32.classify(one), 1 << 6);
+    assert_eq!(F32.classify(snan), 1 << 8);
+    assert_eq!(F32.classify(qnan), 1 << 9);
+}
+
+#[test]
+pub fn test_float_convert() {
+    let mut env = FloatEnv::new(RM_RNE);
+
+    // 2.5 rounds to even, and out of range values saturate
+    assert_eq!(env.to_int(F64, 2.5f64.to_bits(), 32, true), 2);
+    assert_eq!(env.flags, FFLAGS_NX);
+    assert_eq!(env.to_int(F64, (-2.5f64).to_bits(), 32, true), (-2i32) as u32 as u64);
+    assert_eq!(env.to_int(F64, 3e9f64.to_bits(), 32, true), i32::MAX as u64);
+    assert_eq!(env.flags, FFLAGS_NX | FFLAGS_NV);
+    assert_eq!(env.to_int(F64, (-3e9f64).to_bits(), 32, true), i32::MIN as u32 as u64);
+    assert_eq!(env.to_int(F64, F64.canonical_nan(), 64, false), u64::MAX);
+    assert_eq!(env.to_int(F64, (-1.0f64).to_bits(), 64, false), 0);
+    assert_eq!(env.to_int(F64, 1e300f64.to_bits(), 64, false), u64::MAX);
+
+    // Small negative values round to an unsigned zero without being invalid
+    let mut env = FloatEnv::new(RM_RTZ);
+    assert_eq!(env.to_int(F32, (-0.5f32).to_bits() as u64, 32, false), 0);
+    assert_eq!(env.flags, FFLAGS_NX);
+
+    let mut env = FloatEnv::new(RM_RDN);
+    assert_eq!(env.to_int(F32, (-0.5f32).to_bits() as u64, 16, true), 0xFFFF);
+    assert_eq!(env.from_int(F32, u64::MAX, 64, true), (-1.0f32).to_bits() as u64);
+    assert_eq!(env.from_int(F32, 0x0100_0001, 32, false), 0x4B80_0000);
+    assert_eq!(env.flags, FFLAGS_NX);
+
+    // binary64 to binary32, rounding to odd keeps the information that the result is inexact
+    let mut env = FloatEnv::new(RM_ROD);
+    let above_one = 0x3FF0_0000_0000_0001;
+    assert_eq!(env.convert(F64, F32, above_one), 0x3F80_0001);
+    assert_eq!(env.convert(F64, F32, 1e300f64.to_bits()), 0x7F7F_FFFF);
+    let mut env = FloatEnv::new(RM_RNE);
+    assert_eq!(env.convert(F64, F32, above_one), 0x3F80_0000);
+    assert_eq!(env.convert(F32, F64, 0x7F80_0001), F64.canonical_nan());
+    assert_eq!(env.flags, FFLAGS_NX | FFLAGS_NV);
+    assert_eq!(env.convert(F32, F64, 1), 2f64.powi(-149).to_bits());
+}
+
+#[test]
+pub fn test_float_registers() {
+    let mut freg = FRegisters::new();
+
+    // binary32 values are NaN-boxed, and a binary32 read of a binary64 value is the canonical NaN
+    freg.write(1, F32, 0x3F80_0000);
+    assert_eq!(freg.read(1, F64), 0xFFFF_FFFF_3F80_0000);
+    assert_eq!(freg.read(1, F32), 0x3F80_0000);
+    freg.write(2, F64, 1f64.to_bits());
+    assert_eq!(freg.read(2, F32), F32.canonical_nan());
+}
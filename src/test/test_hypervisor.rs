@@ -0,0 +1,398 @@
+#![cfg(test)]
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::csr::*;
+use crate::emulator::bus::DRAM_BASE;
+use crate::emulator::exception::Exception;
+use crate::emulator::interrupt::Interrupt;
+use crate::emulator::pmp::*;
+
+const G_ROOT_TABLE:     usize = DRAM_BASE + 0x20_0000;  // Sv39x4 root: 2048 entries, 16KiB aligned
+const VS_ROOT_TABLE:    usize = 0x10_0000;              // Guest physical address of the VS-stage root
+const GUEST_DATA:       usize = 0x30_0000;              // Guest physical address of the test data
+const PTE_V:            u64   = 1 << 0;
+const PTE_X:            u64   = 1 << 3;
+const PTE_U:            u64   = 1 << 4;
+const PTE_RW:           u64   = 0b0110;
+const PTE_AD:           u64   = 0b1100_0000;
+
+const ECALL:        u32 = 0x0000_0073;
+const SRET:         u32 = 0x1020_0073;
+const MRET:         u32 = 0x3020_0073;
+const WFI:          u32 = 0x1050_0073;
+const SFENCE_VMA:   u32 = 0x1200_0073;
+const HFENCE_VVMA:  u32 = 0x2200_0073;
+const HFENCE_GVMA:  u32 = 0x6200_0073;
+const READ_HSTATUS: u32 = 0x6000_20F3;  // CSRRS x1, hstatus, x0
+const READ_SATP:    u32 = 0x1800_20F3;  // CSRRS x1, satp, x0
+const READ_CYCLE:   u32 = 0xC000_20F3;  // CSRRS x1, cycle, x0
+
+// HLV/HLVX/HSV with the address in x2
+fn hypervisor_memory(funct7: u32, rs2: u32, rd: u32) -> u32 {
+    (funct7 << 25) | (rs2 << 20) | (2 << 15) | (0b100 << 12) | (rd << 7) | 0b111_0011
+}
+
+// Guest physical memory is DRAM: G-stage maps GPA 0~1GiB with a 1GiB superpage, and the VS-stage maps
+// VA 0x4000_0000~ to GPA 0~ and VA 0x8000_0000~ to GPA 0x4000_0000~, whose G-stage page lacks U
+fn setup_guest(cpu: &mut Cpu) {
+    cpu.csr.write(PMPADDR0, u64::MAX);
+    cpu.csr.write(PMPCFG0, (PMP_A_NAPOT | PMP_R | PMP_W | PMP_X) as u64);
+
+    let g_leaf = ((DRAM_BASE as u64 >> 12) << 10) | PTE_AD | PTE_X | PTE_RW | PTE_U | PTE_V;
+    cpu.mmu.write64(&cpu.csr, G_ROOT_TABLE, g_leaf).unwrap();
+    cpu.mmu.write64(&cpu.csr, G_ROOT_TABLE + 8, (((DRAM_BASE as u64 + 0x4000_0000) >> 12) << 10) | PTE_AD | PTE_RW | PTE_V).unwrap();
+
+    let vs_root = DRAM_BASE + VS_ROOT_TABLE;
+    cpu.mmu.write64(&cpu.csr, vs_root + 8, PTE_AD | PTE_RW | PTE_V).unwrap();
+    cpu.mmu.write64(&cpu.csr, vs_root + 16, ((0x4000_0000u64 >> 12) << 10) | PTE_AD | PTE_RW | PTE_V).unwrap();
+
+    cpu.csr.write(HGATP, (HGATP_MODE_SV39X4 << 60) | (G_ROOT_TABLE as u64 >> 12));
+    cpu.csr.write(VSATP, (SATP_MODE_SV39 << 60) | (VS_ROOT_TABLE as u64 >> 12));
+}
+
+#[test]
+pub fn test_hypervisor_csrs() {
+    let mut cpu = Cpu::new();
+
+    assert!(cpu.csr.has_extension('H'));
+    assert_eq!(cpu.csr.read(HSTATUS) >> 32 & 0x3, 2);   // VSXL: 64-bit
+
+    // ECALL from VS-mode and HS-mode-only exceptions can not be delegated to VS-mode
+    cpu.csr.write(HEDELEG, u64::MAX);
+    assert_eq!(cpu.csr.read(HEDELEG), 0xB1FF);
+    cpu.csr.write(HIDELEG, u64::MAX);
+    assert_eq!(cpu.csr.read(HIDELEG), MIP_VSSIP | MIP_VSTIP | MIP_VSEIP);
+
+    // VS-level interrupts are always delegated to HS-mode
+    cpu.csr.write(MIDELEG, 0);
+    assert_eq!(cpu.csr.read(MIDELEG), MIP_VSSIP | MIP_VSTIP | MIP_VSEIP | MIP_SGEIP);
+
+    // hgatp: unsupported modes are ignored, and the root is 16KiB aligned
+    cpu.csr.write(HGATP, (HGATP_MODE_SV48X4 << 60) | 0x1237);
+    assert_eq!(cpu.csr.read(HGATP), (HGATP_MODE_SV48X4 << 60) | 0x1234);
+    cpu.csr.write(HGATP, (5 << 60) | 0x5678);
+    assert_eq!(cpu.csr.read(HGATP), (HGATP_MODE_SV48X4 << 60) | 0x1234);
+
+    // hvip injects VS-level interrupts, which vsip shows at the S-level positions once delegated
+    cpu.csr.write(HVIP, MIP_VSSIP | MIP_VSTIP);
+    assert_eq!(cpu.csr.read(MIP) & (MIP_VSSIP | MIP_VSTIP), MIP_VSSIP | MIP_VSTIP);
+    assert_eq!(cpu.csr.read(HIP), MIP_VSSIP | MIP_VSTIP);
+    assert_eq!(cpu.csr.read(VSIP), MIP_SSIP | MIP_STIP);
+    cpu.csr.write(HIDELEG, MIP_VSTIP);
+    assert_eq!(cpu.csr.read(VSIP), MIP_STIP);
+
+    cpu.csr.write(VSIE, MIP_STIP | MIP_SSIP);
+    assert_eq!(cpu.csr.read(HIE), MIP_VSTIP);
+}
+
+#[test]
+pub fn test_vs_csr_substitution() {
+    let mut cpu = Cpu::new();
+
+    cpu.csr.write(SEPC, 0x1000);
+    cpu.csr.write(VSEPC, 0x2000);
+    cpu.csr.write(HTIMEDELTA, 100);
+    cpu.csr.update_time(50);
+
+    // With V=1, S-level CSRs are their VS counterparts and time is offset by htimedelta
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    cpu.csr.virt = true;
+    assert_eq!(cpu.csr.read(SEPC), 0x2000);
+    assert_eq!(cpu.csr.read(TIME), 150);
+    cpu.csr.write(SSCRATCH, 0x1234);
+    cpu.csr.write(SSTATUS, SSTATUS_SIE);
+    assert_eq!(cpu.csr.read(SSTATUS) & SSTATUS_SIE, SSTATUS_SIE);
+
+    cpu.csr.virt = false;
+    assert_eq!(cpu.csr.read(SEPC), 0x1000);
+    assert_eq!(cpu.csr.read(TIME), 50);
+    assert_eq!(cpu.csr.read(SSCRATCH), 0);
+    assert_eq!(cpu.csr.read(VSSCRATCH), 0x1234);
+    assert_eq!(cpu.csr.read(SSTATUS) & SSTATUS_SIE, 0);
+}
+
+#[test]
+pub fn test_two_stage_translation() {
+    let mut cpu = Cpu::new();
+    setup_guest(&mut cpu);
+    cpu.mmu.write64(&cpu.csr, DRAM_BASE + GUEST_DATA, 0xdead_beef).unwrap();
+
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    cpu.csr.virt = true;
+    assert_eq!(cpu.mmu.translate_addr(&cpu.csr, 0x4000_0000 + GUEST_DATA).unwrap(), DRAM_BASE + GUEST_DATA);
+    assert_eq!(cpu.mmu.read64(&cpu.csr, 0x4000_0000 + GUEST_DATA).unwrap(), 0xdead_beef);
+
+    // Every G-stage access is a U-mode access
+    match cpu.mmu.write64(&cpu.csr, 0x8000_0010, 0) {
+        Err(Exception::StoreGuestPageFault(0x8000_0010, 0x4000_0010))   => (),
+        _   => panic!("G-stage pages without U must raise a guest-page fault"),
+    }
+
+    // Without V, satp (Bare) applies
+    cpu.csr.virt = false;
+    assert_eq!(cpu.mmu.translate_addr(&cpu.csr, DRAM_BASE + GUEST_DATA).unwrap(), DRAM_BASE + GUEST_DATA);
+}
+
+#[test]
+pub fn test_guest_page_fault_trap() {
+    let mut cpu = Cpu::new();
+    setup_guest(&mut cpu);
+    cpu.csr.write(STVEC, 0x3000);
+    cpu.csr.write(MTVEC, 0x4000);
+
+    // A guest-page fault delegated by medeleg only is taken in HS-mode
+    cpu.csr.write(MEDELEG, 1 << 21);
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    cpu.csr.virt = true;
+    cpu.pc = 0x100;
+    let exception = cpu.mmu.read64(&cpu.csr, 0x8000_0010).unwrap_err();
+    exception.take_trap(&mut cpu);
+
+    assert_eq!(cpu.csr.priv_level, PrivLevel::SUPERVISOR);
+    assert!(!cpu.csr.virt);
+    assert_eq!(cpu.pc, 0x3000);
+    assert_eq!(cpu.csr.read(SCAUSE), 21);
+    assert_eq!(cpu.csr.read(STVAL), 0x8000_0010);
+    assert_eq!(cpu.csr.read(HTVAL), 0x4000_0010 >> 2);
+    let hstatus = cpu.csr.read(HSTATUS);
+    assert_eq!(hstatus & (HSTATUS_SPV | HSTATUS_SPVP | HSTATUS_GVA), HSTATUS_SPV | HSTATUS_SPVP | HSTATUS_GVA);
+
+    // Without delegation, M-mode gets it with mtval2 and mstatus.MPV
+    cpu.csr.write(MEDELEG, 0);
+    cpu.csr.virt = true;
+    let exception = cpu.mmu.read64(&cpu.csr, 0x8000_0020).unwrap_err();
+    exception.take_trap(&mut cpu);
+
+    assert_eq!(cpu.csr.priv_level, PrivLevel::MACHINE);
+    assert!(!cpu.csr.virt);
+    assert_eq!(cpu.csr.read(MCAUSE), 21);
+    assert_eq!(cpu.csr.read(MTVAL2), 0x4000_0020 >> 2);
+    let mstatus = cpu.csr.read(MSTATUS);
+    assert_eq!(mstatus & (MSTATUS_MPV | MSTATUS_GVA), MSTATUS_MPV | MSTATUS_GVA);
+}
+
+#[test]
+pub fn test_hlv_hsv() -> Result<(), Exception> {
+    let mut cpu = Cpu::new();
+    setup_guest(&mut cpu);
+    cpu.mmu.write64(&cpu.csr, DRAM_BASE + GUEST_DATA, 0x8000_0000_ffff_fff0).unwrap();
+
+    // HLV/HSV from HS-mode access the guest as VS-mode (hstatus.SPVP)
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    cpu.csr.write(HSTATUS, HSTATUS_SPVP);
+    cpu.register.write(2, 0x4000_0000 + GUEST_DATA as u64);
+
+    cpu.instruction = hypervisor_memory(0b011_0110, 0b0_0000, 1);   // HLV.D x1, (x2)
+    cpu.execute()?;
+    assert_eq!(cpu.register.read(1), 0x8000_0000_ffff_fff0);
+
+    cpu.instruction = hypervisor_memory(0b011_0100, 0b0_0000, 1);   // HLV.W x1, (x2)
+    cpu.execute()?;
+    assert_eq!(cpu.register.read(1), 0xffff_ffff_ffff_fff0);
+
+    cpu.instruction = hypervisor_memory(0b011_0100, 0b0_0001, 1);   // HLV.WU x1, (x2)
+    cpu.execute()?;
+    assert_eq!(cpu.register.read(1), 0xffff_fff0);
+
+    cpu.register.write(3, 0x1234_5678);
+    cpu.instruction = hypervisor_memory(0b011_0101, 3, 0);          // HSV.W x3, (x2)
+    cpu.execute()?;
+    assert_eq!(cpu.mmu.read64(&cpu.csr, DRAM_BASE + GUEST_DATA)?, 0x8000_0000_1234_5678);
+
+    // HLVX needs execute permission, which the VS-stage page lacks
+    cpu.instruction = hypervisor_memory(0b011_0100, 0b0_0011, 1);   // HLVX.WU x1, (x2)
+    match cpu.execute() {
+        Err(Exception::LoadPageFault(addr)) => assert_eq!(addr, 0x4000_0000 + GUEST_DATA),
+        _                                   => panic!("HLVX must require execute permission"),
+    }
+
+    // U-mode may use them only while hstatus.HU is set, and the guest never
+    cpu.instruction = hypervisor_memory(0b011_0110, 0b0_0000, 1);
+    cpu.csr.priv_level = PrivLevel::USER;
+    match cpu.execute() {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("HLV must be illegal in U-mode while hstatus.HU is clear"),
+    }
+    cpu.csr.write(HSTATUS, HSTATUS_SPVP | HSTATUS_HU);
+    cpu.execute()?;
+
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    cpu.csr.virt = true;
+    match cpu.execute() {
+        Err(Exception::VirtualInst) => (),
+        _                           => panic!("HLV must raise a virtual-instruction exception in VS-mode"),
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_virtual_instruction() {
+    let mut cpu = Cpu::new();
+    cpu.csr.write(MCOUNTEREN, 1);
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    cpu.csr.virt = true;
+
+    let expect_virtual = |cpu: &mut Cpu, instruction: u32, what: &str| {
+        cpu.instruction = instruction;
+        match cpu.execute() {
+            Err(Exception::VirtualInst) => (),
+            _                           => panic!("{} must raise a virtual-instruction exception", what),
+        }
+    };
+
+    expect_virtual(&mut cpu, READ_HSTATUS, "hstatus in VS-mode");
+    expect_virtual(&mut cpu, HFENCE_VVMA, "HFENCE.VVMA in VS-mode");
+    expect_virtual(&mut cpu, HFENCE_GVMA, "HFENCE.GVMA in VS-mode");
+    expect_virtual(&mut cpu, READ_CYCLE, "cycle with hcounteren.CY clear");
+
+    cpu.csr.write(HSTATUS, HSTATUS_VTVM | HSTATUS_VTW | HSTATUS_VTSR);
+    expect_virtual(&mut cpu, READ_SATP, "vsatp with hstatus.VTVM set");
+    expect_virtual(&mut cpu, SFENCE_VMA, "SFENCE.VMA with hstatus.VTVM set");
+    expect_virtual(&mut cpu, WFI, "WFI with hstatus.VTW set");
+    expect_virtual(&mut cpu, SRET, "SRET with hstatus.VTSR set");
+
+    cpu.csr.write(HSTATUS, 0);
+    cpu.csr.write(HCOUNTEREN, 1);
+    cpu.instruction = READ_CYCLE;
+    cpu.execute().unwrap();
+    cpu.instruction = READ_SATP;
+    cpu.execute().unwrap();
+
+    // mstatus.TW is the stronger control
+    cpu.csr.write_bit(MSTATUS, MSTATUS_TW_BIT, true);
+    cpu.instruction = WFI;
+    match cpu.execute() {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("WFI must be illegal while mstatus.TW is set"),
+    }
+
+    // Outside a virtual machine, hypervisor CSRs belong to HS-mode
+    cpu.csr.virt = false;
+    cpu.instruction = READ_HSTATUS;
+    cpu.execute().unwrap();
+    cpu.csr.priv_level = PrivLevel::USER;
+    match cpu.execute() {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("hstatus must be illegal in U-mode"),
+    }
+}
+
+#[test]
+pub fn test_hedeleg_outside_guest() {
+    let mut cpu = Cpu::new();
+    cpu.csr.write(MTVEC, 0x1000);
+    cpu.csr.write(STVEC, 0x3000);
+    cpu.csr.write(VSTVEC, 0x2000);
+    cpu.csr.write(MEDELEG, 1 << 3);
+    cpu.csr.write(HEDELEG, 1 << 3);
+
+    // hedeleg only applies while V=1: a breakpoint in HS-mode is taken in HS-mode
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    Exception::Breakpoint.take_trap(&mut cpu);
+    assert_eq!(cpu.csr.priv_level, PrivLevel::SUPERVISOR);
+    assert!(!cpu.csr.virt);
+    assert_eq!(cpu.pc, 0x3000);
+
+    // and one in M-mode in M-mode
+    cpu.csr.priv_level = PrivLevel::MACHINE;
+    Exception::Breakpoint.take_trap(&mut cpu);
+    assert_eq!(cpu.csr.priv_level, PrivLevel::MACHINE);
+    assert!(!cpu.csr.virt);
+    assert_eq!(cpu.pc, 0x1000);
+}
+
+#[test]
+pub fn test_guest_trap_and_return() {
+    let mut cpu = Cpu::new();
+    cpu.csr.write(VSTVEC, 0x2000);
+    cpu.csr.write(STVEC, 0x3000);
+    cpu.csr.write(MEDELEG, (1 << 8) | (1 << 10));
+    cpu.csr.write(HEDELEG, 1 << 8);
+
+    // ECALL from VU-mode delegated by hedeleg stays in the virtual machine
+    cpu.csr.priv_level = PrivLevel::USER;
+    cpu.csr.virt = true;
+    cpu.pc = 0x100;
+    cpu.instruction = ECALL;
+    cpu.execute().unwrap_err().take_trap(&mut cpu);
+    assert_eq!(cpu.csr.priv_level, PrivLevel::SUPERVISOR);
+    assert!(cpu.csr.virt);
+    assert_eq!(cpu.pc, 0x2000);
+    assert_eq!(cpu.csr.read(VSCAUSE), 8);
+    assert_eq!(cpu.csr.read(VSEPC), 0x100);
+    assert_eq!(cpu.csr.read(HSTATUS) & HSTATUS_SPV, 0);
+
+    // SRET in VS-mode returns to VU-mode
+    cpu.instruction = SRET;
+    cpu.execute().unwrap();
+    assert_eq!(cpu.csr.priv_level, PrivLevel::USER);
+    assert!(cpu.csr.virt);
+    assert_eq!(cpu.pc, 0x100 - 4);
+
+    // ECALL from VS-mode is taken by the hypervisor
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    cpu.pc = 0x200;
+    cpu.instruction = ECALL;
+    cpu.execute().unwrap_err().take_trap(&mut cpu);
+    assert_eq!(cpu.csr.priv_level, PrivLevel::SUPERVISOR);
+    assert!(!cpu.csr.virt);
+    assert_eq!(cpu.pc, 0x3000);
+    assert_eq!(cpu.csr.read(SCAUSE), 10);
+    assert_eq!(cpu.csr.read(HSTATUS) & (HSTATUS_SPV | HSTATUS_SPVP), HSTATUS_SPV | HSTATUS_SPVP);
+
+    // SRET in HS-mode re-enters the virtual machine
+    cpu.instruction = SRET;
+    cpu.execute().unwrap();
+    assert_eq!(cpu.csr.priv_level, PrivLevel::SUPERVISOR);
+    assert!(cpu.csr.virt);
+    assert_eq!(cpu.pc, 0x200 - 4);
+    assert_eq!(cpu.csr.read(HSTATUS) & HSTATUS_SPV, 0);
+
+    // MRET enters it with mstatus.MPV
+    cpu.csr.priv_level = PrivLevel::MACHINE;
+    cpu.csr.virt = false;
+    cpu.csr.write_bits(MSTATUS, 11..12+1, PrivLevel::USER as u64);
+    cpu.csr.write(MSTATUS, cpu.csr.read(MSTATUS) | MSTATUS_MPV);
+    cpu.csr.write(MEPC, 0x300);
+    cpu.instruction = MRET;
+    cpu.execute().unwrap();
+    assert_eq!(cpu.csr.priv_level, PrivLevel::USER);
+    assert!(cpu.csr.virt);
+    assert_eq!(cpu.csr.read(MSTATUS) & MSTATUS_MPV, 0);
+}
+
+#[test]
+pub fn test_vs_interrupt() {
+    let mut cpu = Cpu::new();
+    cpu.csr.write(HIDELEG, MIP_VSSIP);
+    cpu.csr.write(HIE, MIP_VSSIP);
+    cpu.csr.write(HVIP, MIP_VSSIP);
+    cpu.csr.write(VSTVEC, 0x2000);
+
+    // VS-level interrupts are never taken in HS-mode
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    cpu.csr.write(SSTATUS, SSTATUS_SIE);
+    cpu.pc = 0x100;
+    if let Some(mut interrupt) = cpu.check_interrupt() {
+        interrupt.take_trap(&mut cpu);
+    }
+    assert_eq!(cpu.pc, 0x100);
+
+    // In VS-mode, vsstatus.SIE enables them
+    cpu.csr.virt = true;
+    if let Some(mut interrupt) = cpu.check_interrupt() {
+        interrupt.take_trap(&mut cpu);
+    }
+    assert_eq!(cpu.pc, 0x100);
+    cpu.csr.write(SSTATUS, SSTATUS_SIE);
+    let mut interrupt = cpu.check_interrupt().unwrap();
+    assert!(matches!(interrupt, Interrupt::VirtualSupervisorSoftwareIrq));
+    interrupt.take_trap(&mut cpu);
+
+    assert_eq!(cpu.csr.priv_level, PrivLevel::SUPERVISOR);
+    assert!(cpu.csr.virt);
+    assert_eq!(cpu.pc, 0x2000);
+    assert_eq!(cpu.csr.read(SCAUSE), (1 << 63) | 1);
+    assert_eq!(cpu.csr.read(SEPC), 0x100);
+}
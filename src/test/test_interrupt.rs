@@ -0,0 +1,125 @@
+#![cfg(test)]
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::csr::*;
+use crate::emulator::bus::CLINT_BASE;
+use crate::emulator::clint::MTIMECMP_BASE;
+use crate::emulator::interrupt::Interrupt;
+use super::helper::exec;
+
+const ALL_INTERRUPTS: u64 = MIP_MEIP | MIP_MSIP | MIP_MTIP | MIP_SEIP | MIP_SSIP | MIP_STIP;
+
+fn cpu_at(priv_level: PrivLevel) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.csr.write(MIE, ALL_INTERRUPTS);
+    cpu.csr.write(MTVEC, 0x1000);
+    cpu.csr.write(STVEC, 0x2000);
+    cpu.csr.priv_level = priv_level;
+    cpu
+}
+
+#[test]
+pub fn test_interrupt_priority() {
+    let mut cpu = cpu_at(PrivLevel::MACHINE);
+    cpu.csr.write(MSTATUS, MSTATUS_MIE);
+    cpu.csr.write_hw(MIP, ALL_INTERRUPTS);
+
+    // MEI > MSI > MTI > SEI > SSI > STI
+    for bit in [MIP_MEIP, MIP_MSIP, MIP_MTIP, MIP_SEIP, MIP_SSIP, MIP_STIP] {
+        let interrupt = cpu.check_interrupt().unwrap();
+        let expected = match bit {
+            MIP_MEIP    => matches!(interrupt, Interrupt::MachineExtIrq(_)),
+            MIP_MSIP    => matches!(interrupt, Interrupt::MachineSoftwareIrq),
+            MIP_MTIP    => matches!(interrupt, Interrupt::MachineTimerIrq),
+            MIP_SEIP    => matches!(interrupt, Interrupt::SupervisorExtIrq(_)),
+            MIP_SSIP    => matches!(interrupt, Interrupt::SupervisorSoftwareIrq),
+            _           => matches!(interrupt, Interrupt::SupervisorTimerIrq),
+        };
+        assert!(expected, "{:?} taken before 0x{:x}", interrupt, bit);
+        cpu.csr.write_hw(MIP, cpu.csr.read(MIP) & !bit);
+    }
+    assert!(cpu.check_interrupt().is_none());
+}
+
+#[test]
+pub fn test_interrupt_machine_enable() {
+    // M-mode interrupts are masked by mstatus.MIE in M-mode only
+    let mut cpu = cpu_at(PrivLevel::MACHINE);
+    cpu.csr.write_hw(MIP, MIP_MTIP);
+    assert!(cpu.check_interrupt().is_none());
+
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    let mut interrupt = cpu.check_interrupt().unwrap();
+    assert!(matches!(interrupt, Interrupt::MachineTimerIrq));
+    interrupt.take_trap(&mut cpu);
+    assert!(cpu.csr.priv_level == PrivLevel::MACHINE);
+    assert_eq!(cpu.pc, 0x1000);
+    assert_eq!(cpu.csr.read(MCAUSE), (1 << 63) | 7);
+    assert_eq!(cpu.csr.read_bits(MSTATUS, 11..12+1), PrivLevel::SUPERVISOR as u64);
+}
+
+#[test]
+pub fn test_interrupt_delegation() {
+    let mut cpu = cpu_at(PrivLevel::MACHINE);
+    cpu.csr.write(MSTATUS, MSTATUS_MIE | SSTATUS_SIE);
+    cpu.csr.write(MIDELEG, MIP_STIP);
+    cpu.csr.write_hw(MIP, MIP_STIP);
+
+    // An interrupt delegated to S-mode is never taken in M-mode
+    assert!(cpu.check_interrupt().is_none());
+
+    // In U-mode it is taken whatever sstatus.SIE holds
+    cpu.csr.priv_level = PrivLevel::USER;
+    cpu.csr.write(SSTATUS, 0);
+    cpu.pc = 0x100;
+    let mut interrupt = cpu.check_interrupt().unwrap();
+    assert!(matches!(interrupt, Interrupt::SupervisorTimerIrq));
+    interrupt.take_trap(&mut cpu);
+    assert!(cpu.csr.priv_level == PrivLevel::SUPERVISOR);
+    assert_eq!(cpu.pc, 0x2000);
+    assert_eq!(cpu.csr.read(SCAUSE), (1 << 63) | 5);
+    assert_eq!(cpu.csr.read(SEPC), 0x100);
+
+    // In S-mode it waits for sstatus.SIE
+    cpu.csr.write_hw(MIP, MIP_STIP);
+    assert!(cpu.check_interrupt().is_none());
+    cpu.csr.write(SSTATUS, SSTATUS_SIE);
+    assert!(matches!(cpu.check_interrupt(), Some(Interrupt::SupervisorTimerIrq)));
+}
+
+#[test]
+pub fn test_interrupt_target_priority() {
+    // An interrupt for M-mode comes before a higher-priority one delegated to S-mode
+    let mut cpu = cpu_at(PrivLevel::USER);
+    cpu.csr.write(MIDELEG, MIP_SEIP);
+    cpu.csr.write_hw(MIP, MIP_SEIP | MIP_SSIP);
+    assert!(matches!(cpu.check_interrupt(), Some(Interrupt::SupervisorSoftwareIrq)));
+
+    cpu.csr.write(MIDELEG, MIP_SEIP | MIP_SSIP);
+    assert!(matches!(cpu.check_interrupt(), Some(Interrupt::SupervisorExtIrq(_))));
+}
+
+#[test]
+pub fn test_interrupt_software_seip() {
+    let mut cpu = cpu_at(PrivLevel::MACHINE);
+
+    // The PLIC has nothing pending, but a SEIP written by M-mode survives its updates
+    cpu.csr.write(MIP, MIP_SEIP);
+    cpu.csr.write(MIE, MIP_MTIP);
+    cpu.mmu.write64(&cpu.csr, CLINT_BASE + MTIMECMP_BASE, 10).unwrap();
+    cpu.wait_for_interrupt();
+    assert_eq!(cpu.csr.read(MIP) & (MIP_MTIP | MIP_SEIP), MIP_MTIP | MIP_SEIP);
+
+    // S-mode can not write it through sip
+    cpu.csr.write(MIP, 0);
+    cpu.csr.write(SIP, MIP_SEIP);
+    assert_eq!(cpu.csr.read(MIP) & MIP_SEIP, 0);
+
+    // While the PLIC raises it, csrrs x3, mip, x1 reads SEIP but does not write it back
+    cpu.csr.write_hw(MIP, MIP_SEIP);
+    cpu.register.write(1, MIP_SSIP);
+    exec(&mut cpu, (MIP as u32) << 20 | (1 << 15) | (0b010 << 12) | (3 << 7) | 0b111_0011).unwrap();
+    assert_eq!(cpu.register.read(3), MIP_SEIP);
+    assert_eq!(cpu.csr.software_seip(), 0);
+    assert_eq!(cpu.csr.read(MIP), MIP_SSIP);
+}
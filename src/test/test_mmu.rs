@@ -0,0 +1,264 @@
+#![cfg(test)]
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::csr::*;
+use crate::emulator::bus::DRAM_BASE;
+use crate::emulator::exception::Exception;
+use crate::emulator::pmp::*;
+use crate::emulator::mmu::Misaligned;
+use super::helper::{ exec, step };
+
+const ROOT_TABLE:   usize = DRAM_BASE + 0x10_0000;
+const PTE_V:        u64   = 1 << 0;
+const PTE_R:        u64   = 1 << 1;
+const PTE_X:        u64   = 1 << 3;
+const PTE_U:        u64   = 1 << 4;
+const PTE_RWX:      u64   = 0b1110;
+const PTE_AD:       u64   = 0b1100_0000;
+
+// Map `vaddr` to `paddr` with a 4KiB page, allocating one table per level below the root.
+fn map_page(cpu: &mut Cpu, levels: usize, vaddr: usize, paddr: usize) {
+    map_page_with(cpu, levels, vaddr, paddr, PTE_AD | PTE_RWX);
+}
+
+fn map_page_with(cpu: &mut Cpu, levels: usize, vaddr: usize, paddr: usize, flags: u64) {
+    let mut table = ROOT_TABLE;
+
+    for level in (0..levels).rev() {
+        let vpn = (vaddr >> (12 + 9 * level)) & 0x1FF;
+        let pte_addr = table + vpn * 8;
+
+        if level == 0 {
+            let pte = ((paddr as u64 >> 12) << 10) | flags | PTE_V;
+            cpu.mmu.write64(&cpu.csr, pte_addr, pte).unwrap();
+        }
+        else {
+            let next = ROOT_TABLE + (levels - level) * 0x1000;
+            let pte = ((next as u64 >> 12) << 10) | PTE_V;
+            cpu.mmu.write64(&cpu.csr, pte_addr, pte).unwrap();
+            table = next;
+        }
+    }
+}
+
+// Grant S/U-mode full access to physical memory, as firmware does before dropping out of M-mode
+fn open_pmp(cpu: &mut Cpu) {
+    cpu.csr.write(PMPADDR0, u64::MAX);
+    cpu.csr.write(PMPCFG0, (PMP_A_NAPOT | PMP_R | PMP_W | PMP_X) as u64);
+}
+
+fn enable_paging(cpu: &mut Cpu, mode: u64) {
+    open_pmp(cpu);
+    cpu.csr.write(SATP, (mode << 60) | (ROOT_TABLE as u64 >> 12));
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+}
+
+#[test]
+pub fn test_translate_sv39() {
+    let mut cpu = Cpu::new();
+
+    map_page(&mut cpu, 3, 0x40_2000, 0x8020_0000);
+    enable_paging(&mut cpu, SATP_MODE_SV39);
+
+    assert_eq!(cpu.mmu.translate_addr(&cpu.csr, 0x40_2123).unwrap(), 0x8020_0123);
+}
+
+#[test]
+pub fn test_translate_sv48() {
+    let mut cpu = Cpu::new();
+
+    map_page(&mut cpu, 4, 0x7F_8040_2000, 0x8030_0000);
+    enable_paging(&mut cpu, SATP_MODE_SV48);
+
+    assert_eq!(cpu.mmu.translate_addr(&cpu.csr, 0x7F_8040_2ABC).unwrap(), 0x8030_0ABC);
+}
+
+#[test]
+pub fn test_translate_sv57() {
+    let mut cpu = Cpu::new();
+
+    map_page(&mut cpu, 5, 0xFF_7F80_4020_1000, 0x8040_0000);
+    enable_paging(&mut cpu, SATP_MODE_SV57);
+
+    assert_eq!(cpu.mmu.translate_addr(&cpu.csr, 0xFF_7F80_4020_1008).unwrap(), 0x8040_0008);
+}
+
+#[test]
+pub fn test_translate_non_canonical() {
+    let mut cpu = Cpu::new();
+
+    map_page(&mut cpu, 3, 0x2000, 0x8020_0000);
+    enable_paging(&mut cpu, SATP_MODE_SV39);
+
+    // Bit 39 differs from bit 38, so the address is not a valid Sv39 address
+    match cpu.mmu.read64(&cpu.csr, 0x80_0000_2000) {
+        Err(Exception::LoadPageFault(0x80_0000_2000))  => (),
+        _                                           => panic!("non-canonical address must raise a page fault"),
+    }
+}
+
+#[test]
+pub fn test_satp_unsupported_mode() {
+    let mut cpu = Cpu::new();
+
+    cpu.csr.write(SATP, (SATP_MODE_SV48 << 60) | 0x1234);
+    cpu.csr.write(SATP, (11 << 60) | 0x5678);     // Sv64 is not supported
+
+    assert_eq!(cpu.csr.read(SATP), (SATP_MODE_SV48 << 60) | 0x1234);
+}
+
+#[test]
+pub fn test_user_page_from_supervisor() {
+    let mut cpu = Cpu::new();
+
+    map_page_with(&mut cpu, 3, 0x4000, 0x8020_0000, PTE_AD | PTE_RWX | PTE_U);
+    enable_paging(&mut cpu, SATP_MODE_SV39);
+
+    // Without SUM, S-mode can not touch U pages
+    match cpu.mmu.read64(&cpu.csr, 0x4000) {
+        Err(Exception::LoadPageFault(0x4000))   => (),
+        _                                       => panic!("U page must not be readable from S-mode without SUM"),
+    }
+
+    // With SUM, loads and stores are permitted, but instruction fetches are not
+    cpu.csr.write_bit(SSTATUS, MSTATUS_SUM_BIT, true);
+    cpu.mmu.write64(&cpu.csr, 0x4000, 0xdead_beef).unwrap();
+    assert_eq!(cpu.mmu.read64(&cpu.csr, 0x4000).unwrap(), 0xdead_beef);
+
+    match cpu.mmu.fetch(&cpu.csr, 0x4000) {
+        Err(Exception::InstPageFault(0x4000))   => (),
+        _                                       => panic!("S-mode must never execute from U pages"),
+    }
+}
+
+#[test]
+pub fn test_mxr() {
+    let mut cpu = Cpu::new();
+
+    map_page_with(&mut cpu, 3, 0x5000, 0x8020_0000, PTE_AD | PTE_X);
+    enable_paging(&mut cpu, SATP_MODE_SV39);
+
+    assert!(cpu.mmu.read32(&cpu.csr, 0x5000).is_err());
+
+    cpu.csr.write_bit(SSTATUS, MSTATUS_MXR_BIT, true);
+    assert!(cpu.mmu.read32(&cpu.csr, 0x5000).is_ok());
+}
+
+#[test]
+pub fn test_mprv() {
+    let mut cpu = Cpu::new();
+
+    map_page_with(&mut cpu, 3, 0x6000, 0x8020_0000, PTE_AD | PTE_R | PTE_U);
+    cpu.mmu.write32(&cpu.csr, 0x8020_0010, 0x1234_5673).unwrap();
+    cpu.csr.write(SATP, (SATP_MODE_SV39 << 60) | (ROOT_TABLE as u64 >> 12));
+    open_pmp(&mut cpu);
+
+    // Loads in M-mode are translated as U-mode while MPRV = 1 and MPP = U
+    cpu.csr.write_bits(MSTATUS, 11..12+1, PrivLevel::USER as u64);
+    cpu.csr.write_bit(MSTATUS, MSTATUS_MPRV_BIT, true);
+    assert_eq!(cpu.mmu.read32(&cpu.csr, 0x6010).unwrap(), 0x1234_5673);
+
+    // Instruction fetches still use the real privilege level
+    assert_eq!(cpu.mmu.fetch(&cpu.csr, 0x8020_0010).unwrap(), 0x1234_5673);
+}
+
+#[test]
+pub fn test_misaligned_superpage() {
+    let mut cpu = Cpu::new();
+
+    // A 2MiB leaf at level 1 whose PPN[0] is not zero
+    let pte = (((0x8020_1000u64) >> 12) << 10) | PTE_AD | PTE_RWX | PTE_V;
+    cpu.mmu.write64(&cpu.csr, ROOT_TABLE, (((ROOT_TABLE as u64 + 0x1000) >> 12) << 10) | PTE_V).unwrap();
+    cpu.mmu.write64(&cpu.csr, ROOT_TABLE + 0x1000, pte).unwrap();
+    enable_paging(&mut cpu, SATP_MODE_SV39);
+
+    match cpu.mmu.read8(&cpu.csr, 0x1234) {
+        Err(Exception::LoadPageFault(0x1234))   => (),
+        _                                       => panic!("misaligned superpage must raise a page fault"),
+    }
+}
+
+#[test]
+pub fn test_reserved_pte_bits() {
+    let mut cpu = Cpu::new();
+
+    map_page_with(&mut cpu, 3, 0x7000, 0x8020_0000, PTE_AD | PTE_RWX | (1 << 60));
+    enable_paging(&mut cpu, SATP_MODE_SV39);
+
+    match cpu.mmu.write8(&cpu.csr, 0x7000, 0) {
+        Err(Exception::StorePageFault(0x7000))  => (),
+        _                                       => panic!("reserved PTE bits must raise a page fault"),
+    }
+}
+
+#[test]
+pub fn test_misaligned_trap() {
+    let mut cpu = Cpu::new();
+    cpu.mmu.misaligned = Misaligned::Trap;
+
+    match cpu.mmu.read32(&cpu.csr, DRAM_BASE + 2) {
+        Err(Exception::LoadAddrMislign(addr))   => assert_eq!(addr, DRAM_BASE + 2),
+        _                                       => panic!("misaligned load must trap"),
+    }
+
+    match cpu.mmu.write64(&cpu.csr, DRAM_BASE + 4, 0) {
+        Err(Exception::StoreAddrMisalign(addr)) => assert_eq!(addr, DRAM_BASE + 4),
+        _                                       => panic!("misaligned store must trap"),
+    }
+
+    // Byte accesses are always aligned
+    assert!(cpu.mmu.read8(&cpu.csr, DRAM_BASE + 3).is_ok());
+}
+
+#[test]
+pub fn test_misaligned_page_crossing() {
+    let mut cpu = Cpu::new();
+
+    // Two adjacent virtual pages backed by physical pages in reverse order
+    map_page(&mut cpu, 3, 0x8000, 0x8021_0000);
+    map_page(&mut cpu, 3, 0x9000, 0x8020_0000);
+    enable_paging(&mut cpu, SATP_MODE_SV39);
+
+    cpu.mmu.write64(&cpu.csr, 0x8FFC, 0x0123_4567_89AB_CDEF).unwrap();
+    assert_eq!(cpu.mmu.read64(&cpu.csr, 0x8FFC).unwrap(), 0x0123_4567_89AB_CDEF);
+    assert_eq!(cpu.mmu.read32(&cpu.csr, 0x9000).unwrap(), 0x0123_4567);
+
+    // The second page is not mapped: the fault reports it and the first page is left untouched
+    match cpu.mmu.write32(&cpu.csr, 0x9FFE, 0xFFFF_FFFF) {
+        Err(Exception::StorePageFault(0xA000))  => (),
+        _                                       => panic!("store crossing into an unmapped page must fault"),
+    }
+    assert_eq!(cpu.mmu.read16(&cpu.csr, 0x9FFE).unwrap(), 0);
+}
+
+#[test]
+pub fn test_fence_i() {
+    let mut cpu = Cpu::new();
+    let code = DRAM_BASE + 0x20_0000;
+    map_page(&mut cpu, 3, 0x4000, code);
+    map_page(&mut cpu, 3, 0x5000, code);
+    cpu.csr.write(SATP, (SATP_MODE_SV39 << 60) | (ROOT_TABLE as u64 >> 12));
+    open_pmp(&mut cpu);
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+
+    cpu.mmu.write32(&cpu.csr, 0x5000, 0x0010_0093).unwrap();    // addi x1, x0, 1
+    cpu.pc = 0x4000;
+    step(&mut cpu);
+    assert_eq!(cpu.register.read(1), 1);
+
+    // Code modified through another mapping of the same physical page runs after FENCE.I
+    cpu.mmu.write32(&cpu.csr, 0x5000, 0x0020_0093).unwrap();    // addi x1, x0, 2
+    exec(&mut cpu, 0x0000_100F).unwrap();                       // fence.i
+    cpu.pc = 0x4000;
+    step(&mut cpu);
+    assert_eq!(cpu.register.read(1), 2);
+
+    // Instructions are fetched through the page tables
+    cpu.csr.priv_level = PrivLevel::MACHINE;
+    map_page_with(&mut cpu, 3, 0x4000, code, PTE_AD | PTE_R);
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    match cpu.mmu.fetch(&cpu.csr, 0x4000) {
+        Err(Exception::InstPageFault(0x4000))   => (),
+        _                                       => panic!("a fetch must not bypass the page tables"),
+    }
+}
@@ -0,0 +1,79 @@
+#![cfg(test)]
+
+use crate::emulator::plic::*;
+use crate::emulator::csr::{ MIP_MEIP, MIP_SEIP };
+
+const VIRTIO_IRQ:   u32 = 1;
+const UART_IRQ:     u32 = 10;
+
+fn enable(plic: &mut Plic, context: usize, sources: u32) {
+    plic.write32(ENABLE_BASE + context * ENABLE_STRIDE, sources);
+}
+
+fn claim_addr(context: usize) -> usize {
+    CONTEXT_BASE + context * CONTEXT_STRIDE + CLAIM_OFFSET
+}
+
+#[test]
+pub fn test_plic_threshold() {
+    let mut plic = Plic::new();
+    let mut mip = 0;
+
+    // A source needs to be enabled for the context and have a priority above its threshold
+    plic.write32(PRIORITY_BASE + (VIRTIO_IRQ as usize - 1) * 4, 1);
+    plic.tick(true, false, &mut mip);
+    assert_eq!(mip, 0);
+    assert_eq!(plic.read32(PENDING_ARRAY_BASE), 1 << VIRTIO_IRQ);
+
+    enable(&mut plic, CONTEXT_SUPERVISOR, 1 << VIRTIO_IRQ);
+    plic.write32(CONTEXT_BASE + CONTEXT_SUPERVISOR * CONTEXT_STRIDE, 1);
+    plic.tick(true, false, &mut mip);
+    assert_eq!(mip, 0);
+
+    plic.write32(CONTEXT_BASE + CONTEXT_SUPERVISOR * CONTEXT_STRIDE, 0);
+    plic.tick(true, false, &mut mip);
+    assert_eq!(mip, MIP_SEIP);
+
+    // The M-mode context raises MEIP instead
+    enable(&mut plic, CONTEXT_MACHINE, 1 << VIRTIO_IRQ);
+    plic.tick(true, false, &mut mip);
+    assert_eq!(mip, MIP_MEIP | MIP_SEIP);
+
+    // The line is level-triggered
+    plic.tick(false, false, &mut mip);
+    assert_eq!(mip, 0);
+}
+
+#[test]
+pub fn test_plic_claim_complete() {
+    let mut plic = Plic::new();
+    let mut mip = 0;
+    plic.write32(PRIORITY_BASE + (VIRTIO_IRQ as usize - 1) * 4, 2);
+    plic.write32(PRIORITY_BASE + (UART_IRQ as usize - 1) * 4, 3);
+    enable(&mut plic, CONTEXT_SUPERVISOR, (1 << VIRTIO_IRQ) | (1 << UART_IRQ));
+    plic.tick(true, true, &mut mip);
+
+    // Claims return the highest priority first, then nothing is left
+    assert_eq!(plic.read32(claim_addr(CONTEXT_SUPERVISOR)), UART_IRQ);
+    assert_eq!(plic.read32(claim_addr(CONTEXT_SUPERVISOR)), VIRTIO_IRQ);
+    assert_eq!(plic.read32(claim_addr(CONTEXT_SUPERVISOR)), 0);
+
+    // A claimed source is not pending again until it is completed
+    plic.tick(true, true, &mut mip);
+    assert_eq!(mip, 0);
+    plic.write32(claim_addr(CONTEXT_SUPERVISOR), VIRTIO_IRQ);
+    plic.tick(true, true, &mut mip);
+    assert_eq!(mip, MIP_SEIP);
+    assert_eq!(plic.read32(claim_addr(CONTEXT_SUPERVISOR)), VIRTIO_IRQ);
+}
+
+#[test]
+pub fn test_plic_reserved() {
+    let mut plic = Plic::new();
+
+    // The priority of source 0 and the gap after the pending bits read as zero and ignore writes
+    for addr in [0, PENDING_ARRAY_TOP + 1, ENABLE_BASE - 4] {
+        plic.write32(addr, 0xFFFF_FFFF);
+        assert_eq!(plic.read32(addr), 0);
+    }
+}
@@ -0,0 +1,89 @@
+#![cfg(test)]
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::bus::*;
+use crate::emulator::exception::Exception;
+
+#[test]
+pub fn test_pma_amo_to_uart() {
+    let mut cpu = Cpu::new();
+
+    match cpu.mmu.amo32(&cpu.csr, UART0_BASE, |data| data) {
+        Err(Exception::StoreAccessFault(UART0_BASE))    => (),
+        _                                               => panic!("UART does not support AMOs"),
+    }
+
+    match cpu.mmu.load_reserved32(&cpu.csr, UART0_BASE) {
+        Err(Exception::LoadAccessFault(UART0_BASE))     => (),
+        _                                               => panic!("UART does not support LR/SC"),
+    }
+}
+
+#[test]
+pub fn test_pma_fetch_from_plic() {
+    let mut cpu = Cpu::new();
+
+    match cpu.mmu.fetch(&cpu.csr, PLIC_BASE) {
+        Err(Exception::InstAccessFault(PLIC_BASE))  => (),
+        _                                           => panic!("PLIC is not executable"),
+    }
+}
+
+#[test]
+pub fn test_pma_unmapped() {
+    let mut cpu = Cpu::new();
+
+    match cpu.mmu.read64(&cpu.csr, 0x4000_0000) {
+        Err(Exception::LoadAccessFault(0x4000_0000))    => (),
+        _                                               => panic!("unmapped address must raise an access fault"),
+    }
+
+    // An access running off the end of DRAM is not contained in a single region
+    assert!(cpu.mmu.write64(&cpu.csr, DRAM_TOP - 3, 0).is_err());
+}
+
+#[test]
+pub fn test_pma_access_width() {
+    let mut cpu = Cpu::new();
+
+    // UART registers are a byte wide, PLIC registers are a word wide and must be aligned
+    assert!(cpu.mmu.read8(&cpu.csr, UART0_BASE + 5).is_ok());
+    assert!(cpu.mmu.read32(&cpu.csr, UART0_BASE).is_err());
+    assert!(cpu.mmu.read8(&cpu.csr, PLIC_BASE).is_err());
+    assert!(cpu.mmu.read32(&cpu.csr, PLIC_BASE + 2).is_err());
+
+    // DRAM is idempotent, so misaligned accesses are fine
+    cpu.mmu.write64(&cpu.csr, DRAM_BASE + 3, 0x0123_4567_89AB_CDEF).unwrap();
+    assert_eq!(cpu.mmu.read64(&cpu.csr, DRAM_BASE + 3).unwrap(), 0x0123_4567_89AB_CDEF);
+}
+
+#[test]
+pub fn test_amo32_width() {
+    let mut cpu = Cpu::new();
+
+    cpu.mmu.write64(&cpu.csr, DRAM_BASE, 0x1111_1111_2222_2222).unwrap();
+
+    // A word AMO must leave the neighbouring word untouched
+    assert_eq!(cpu.mmu.amo32(&cpu.csr, DRAM_BASE, |data| data + 1).unwrap(), 0x2222_2222);
+    assert_eq!(cpu.mmu.read64(&cpu.csr, DRAM_BASE).unwrap(), 0x1111_1111_2222_2223);
+
+    match cpu.mmu.amo32(&cpu.csr, DRAM_BASE + 2, |data| data) {
+        Err(Exception::StoreAddrMisalign(_))    => (),
+        _                                       => panic!("misaligned AMO must raise an address-misaligned exception"),
+    }
+}
+
+#[test]
+pub fn test_lrsc() {
+    let mut cpu = Cpu::new();
+
+    // SC without a reservation fails
+    assert!(!cpu.mmu.store_conditional64(&cpu.csr, DRAM_BASE, 1).unwrap());
+
+    cpu.mmu.load_reserved64(&cpu.csr, DRAM_BASE).unwrap();
+    assert!(cpu.mmu.store_conditional64(&cpu.csr, DRAM_BASE, 1).unwrap());
+    assert_eq!(cpu.mmu.read64(&cpu.csr, DRAM_BASE).unwrap(), 1);
+
+    // The reservation is consumed by the SC
+    assert!(!cpu.mmu.store_conditional64(&cpu.csr, DRAM_BASE, 2).unwrap());
+}
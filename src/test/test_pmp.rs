@@ -0,0 +1,115 @@
+#![cfg(test)]
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::csr::*;
+use crate::emulator::bus::DRAM_BASE;
+use crate::emulator::exception::Exception;
+use crate::emulator::pmp::*;
+
+const ROOT_TABLE:   usize = DRAM_BASE + 0x10_0000;
+
+fn napot(base: usize, size: usize) -> u64 {
+    ((base | (size / 2 - 1)) >> 2) as u64
+}
+
+#[test]
+pub fn test_pmp_tor() {
+    let mut cpu = Cpu::new();
+
+    // Entry 0 (OFF) only provides the base for entry 1 (TOR, read-only)
+    cpu.csr.write(PMPADDR0, (DRAM_BASE >> 2) as u64);
+    cpu.csr.write(PMPADDR1, ((DRAM_BASE + 0x1000) >> 2) as u64);
+    cpu.csr.write(PMPCFG0, ((PMP_A_TOR | PMP_R) as u64) << 8);
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+
+    assert!(cpu.mmu.read64(&cpu.csr, DRAM_BASE + 0xFF8).is_ok());
+
+    match cpu.mmu.write64(&cpu.csr, DRAM_BASE, 0) {
+        Err(Exception::StoreAccessFault(DRAM_BASE))     => (),
+        _                                               => panic!("TOR region without W must not be writable"),
+    }
+
+    // An access straddling the top of the region only partially matches and fails
+    match cpu.mmu.read64(&cpu.csr, DRAM_BASE + 0xFFC) {
+        Err(Exception::LoadAccessFault(_))  => (),
+        _                                   => panic!("partially matching access must fail"),
+    }
+}
+
+#[test]
+pub fn test_pmp_na4_napot() {
+    let mut cpu = Cpu::new();
+
+    // Entry 0: NA4 with no permissions in front of entry 1: NAPOT 4KiB RWX
+    cpu.csr.write(PMPADDR0, ((DRAM_BASE + 0x10) >> 2) as u64);
+    cpu.csr.write(PMPADDR1, napot(DRAM_BASE, 0x1000));
+    cpu.csr.write(PMPCFG0, (PMP_A_NA4 as u64) | (((PMP_A_NAPOT | PMP_R | PMP_W | PMP_X) as u64) << 8));
+    cpu.csr.priv_level = PrivLevel::USER;
+
+    assert!(cpu.mmu.write32(&cpu.csr, DRAM_BASE + 0x0C, 1).is_ok());
+    assert!(cpu.mmu.write32(&cpu.csr, DRAM_BASE + 0x14, 1).is_ok());
+    assert!(cpu.mmu.read32(&cpu.csr, DRAM_BASE + 0x10).is_err());
+
+    // Outside of every entry, S/U-mode accesses fail
+    match cpu.mmu.fetch(&cpu.csr, DRAM_BASE + 0x1000) {
+        Err(Exception::InstAccessFault(_))  => (),
+        _                                   => panic!("unmatched S/U access must fail"),
+    }
+}
+
+#[test]
+pub fn test_pmp_locked_machine() {
+    let mut cpu = Cpu::new();
+
+    cpu.csr.write(PMPADDR0, napot(DRAM_BASE, 0x1000));
+    cpu.csr.write(PMPCFG0, PMP_A_NAPOT as u64);
+
+    // Unlocked entries do not restrict M-mode
+    assert!(cpu.mmu.write64(&cpu.csr, DRAM_BASE, 0).is_ok());
+
+    cpu.csr.write(PMPCFG0, (PMP_A_NAPOT | PMP_L | PMP_R) as u64);
+    assert!(cpu.mmu.read64(&cpu.csr, DRAM_BASE).is_ok());
+    assert!(cpu.mmu.write64(&cpu.csr, DRAM_BASE, 0).is_err());
+
+    // A locked entry ignores writes to its pmpcfg and pmpaddr until reset
+    cpu.csr.write(PMPCFG0, (PMP_A_NAPOT | PMP_R | PMP_W) as u64);
+    cpu.csr.write(PMPADDR0, 0);
+    assert_eq!(pmpcfg(&cpu.csr, 0), PMP_A_NAPOT | PMP_L | PMP_R);
+    assert_eq!(cpu.csr.read(PMPADDR0), napot(DRAM_BASE, 0x1000));
+}
+
+#[test]
+pub fn test_pmpcfg_warl() {
+    let mut cpu = Cpu::new();
+
+    // W without R is reserved and the bits between A and L are hardwired to zero
+    cpu.csr.write(PMPCFG0, (PMP_W | 0b0110_0000) as u64);
+    assert_eq!(cpu.csr.read(PMPCFG0), 0);
+
+    // Odd-numbered pmpcfg registers do not exist on RV64
+    cpu.csr.write(PMPCFG1, PMP_R as u64);
+    assert_eq!(cpu.csr.read(PMPCFG1), 0);
+
+    // Entries beyond the implemented number read as zero
+    cpu.csr.write(PMPCFG4, PMP_R as u64);
+    cpu.csr.write(PMPADDR16, 0x1234);
+    assert_eq!(cpu.csr.read(PMPCFG4), 0);
+    assert_eq!(cpu.csr.read(PMPADDR16), 0);
+}
+
+#[test]
+pub fn test_pmp_page_walk() {
+    let mut cpu = Cpu::new();
+
+    // S-mode may touch everything except the page tables
+    cpu.csr.write(PMPADDR0, napot(ROOT_TABLE, 0x10_0000));
+    cpu.csr.write(PMPADDR1, u64::MAX);
+    cpu.csr.write(PMPCFG0, (PMP_A_NAPOT as u64) | (((PMP_A_NAPOT | PMP_R | PMP_W | PMP_X) as u64) << 8));
+    cpu.csr.write(SATP, (SATP_MODE_SV39 << 60) | (ROOT_TABLE as u64 >> 12));
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+
+    match cpu.mmu.read64(&cpu.csr, 0x1000) {
+        Err(Exception::LoadAccessFault(0x1000)) => (),
+        _                                       => panic!("page-table walk must be checked against PMP"),
+    }
+}
@@ -0,0 +1,134 @@
+#![cfg(test)]
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::csr::*;
+use crate::emulator::bus::DRAM_BASE;
+use crate::emulator::exception::Exception;
+use crate::emulator::pmp::*;
+use super::helper::*;
+
+const ADD:          u32 = 0x0020_81B3;  // add x3, x1, x2
+const SRL:          u32 = 0x0020_D1B3;  // srl x3, x1, x2
+const MULH:         u32 = 0x0220_91B3;  // mulh x3, x1, x2
+const MULHU:        u32 = 0x0220_B1B3;  // mulhu x3, x1, x2
+const DIVU:         u32 = 0x0220_D1B3;  // divu x3, x1, x2
+const ADDW:         u32 = 0x0020_81BB;  // addw x3, x1, x2
+const LD:           u32 = 0x0000_B183;  // ld x3, 0(x1)
+const SLLI_32:      u32 = 0x0200_9193;  // slli x3, x1, 32
+const CSRR_MISA:    u32 = 0x3010_21F3;  // csrr x3, misa
+const CSRR_MCYCLEH: u32 = 0xB800_21F3;  // csrr x3, mcycleh
+const CSRW_MCYCLEH: u32 = 0xB800_9073;  // csrw mcycleh, x1
+
+const ROOT_TABLE:   usize = DRAM_BASE + 0x10_0000;
+const PTE_V:        u64   = 1 << 0;
+const PTE_AD_RWX:   u64   = 0b1100_1110;
+
+fn rv32_cpu() -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.configure_xlen(32);
+    cpu
+}
+
+#[test]
+pub fn test_rv32_arithmetic() -> Result<(), Exception> {
+    let mut cpu = rv32_cpu();
+
+    // Results are 32 bits wide and held sign-extended
+    assert_eq!(exec_rr(&mut cpu, ADD, 0x7FFF_FFFF, 1)?, 0xFFFF_FFFF_8000_0000);
+    assert_eq!(exec_rr(&mut cpu, SRL, 0xFFFF_FFF8, 1)?, 0x7FFF_FFFC);
+    assert_eq!(exec_rr(&mut cpu, SRL, 0x10, 33)?, 0x8);        // only the low 5 bits of rs2 count
+    assert_eq!(exec_rr(&mut cpu, MULH, 0xFFFF_FFFF, 0xFFFF_FFFF)?, 0);
+    assert_eq!(exec_rr(&mut cpu, MULHU, 0xFFFF_FFFF, 0xFFFF_FFFF)?, 0xFFFF_FFFF_FFFF_FFFE);
+    assert_eq!(exec_rr(&mut cpu, DIVU, 0xFFFF_FFFF, 2)?, 0x7FFF_FFFF);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_rv32_illegal() {
+    let mut cpu = rv32_cpu();
+
+    // RV64-only instructions and shift amounts do not exist on RV32
+    for inst in [ADDW, LD, SLLI_32] {
+        match exec_rr(&mut cpu, inst, DRAM_BASE as u64, 0) {
+            Err(Exception::IllegalInst) => (),
+            _                           => panic!("0x{:08x} must be illegal on RV32", inst),
+        }
+    }
+}
+
+#[test]
+pub fn test_rv32_csr() -> Result<(), Exception> {
+    let mut cpu = rv32_cpu();
+
+    // misa.MXL is in bits 31:30
+    assert_eq!(exec_rr(&mut cpu, CSRR_MISA, 0, 0)? as u32 >> 30, 1);
+    assert!(cpu.csr.isa_string().starts_with("rv32imac_"));
+
+    // The upper half of mcycle is mcycleh
+    cpu.csr.write(MCYCLE, 0x1_0000_0002);
+    assert_eq!(exec_rr(&mut cpu, CSRR_MCYCLEH, 0, 0)?, 1);
+    exec_rr(&mut cpu, CSRW_MCYCLEH, 0x1234, 0)?;
+    assert_eq!(cpu.csr.read(MCYCLE) >> 32, 0x1234);
+
+    // The interrupt bit of mcause moves to bit 31
+    cpu.csr.write(MCAUSE, (1 << 63) | 7);
+    assert_eq!(cpu.csr.read_rv32(MCAUSE), 0x8000_0007);
+
+    // The odd pmpcfg registers hold entries 4-7
+    cpu.csr.write_rv32(PMPCFG1, (PMP_A_TOR | PMP_R) as u64);
+    assert_eq!(cpu.csr.read(PMPCFG0), ((PMP_A_TOR | PMP_R) as u64) << 32);
+    assert_eq!(cpu.csr.read_rv32(PMPCFG1), (PMP_A_TOR | PMP_R) as u64);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_translate_sv32() {
+    let mut cpu = rv32_cpu();
+    cpu.csr.write(PMPADDR0, u64::MAX);
+    cpu.csr.write(PMPCFG0, (PMP_A_NAPOT | PMP_R | PMP_W | PMP_X) as u64);
+
+    // A 4KiB page through two levels of 4-byte PTEs with 10-bit VPNs, and a 4MiB megapage
+    let table = ROOT_TABLE + 0x1000;
+    cpu.mmu.write32(&cpu.csr, ROOT_TABLE + 0x100 * 4, (((table as u64 >> 12) << 10) | PTE_V) as u32).unwrap();
+    cpu.mmu.write32(&cpu.csr, table + 0x201 * 4, (((0x8020_0000_u64 >> 12) << 10) | PTE_AD_RWX | PTE_V) as u32).unwrap();
+    cpu.mmu.write32(&cpu.csr, ROOT_TABLE + 0x3FF * 4, (((0x8040_0000_u64 >> 12) << 10) | PTE_AD_RWX | PTE_V) as u32).unwrap();
+
+    cpu.csr.write_rv32(SATP, (1 << 31) | (ROOT_TABLE as u64 >> 12));
+    assert_eq!(cpu.csr.read_rv32(SATP), (1 << 31) | (ROOT_TABLE as u64 >> 12));
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+
+    assert_eq!(cpu.mmu.translate_addr(&cpu.csr, 0x4020_1123).unwrap(), 0x8020_0123);
+    assert_eq!(cpu.mmu.translate_addr(&cpu.csr, 0xFFC1_2345).unwrap(), 0x8041_2345);
+
+    // Only the low 32 bits of a sign-extended address are translated
+    assert_eq!(cpu.mmu.translate_addr(&cpu.csr, 0xFFFF_FFFF_FFC1_2345).unwrap(), 0x8041_2345);
+}
+
+#[test]
+pub fn test_uxl() -> Result<(), Exception> {
+    let mut cpu = Cpu::new();
+
+    // UXL is WARL: an unsupported value leaves it unchanged
+    let mstatus = cpu.csr.read(MSTATUS) & !MSTATUS_UXL;
+    cpu.csr.write(MSTATUS, mstatus | (1 << 32));
+    cpu.csr.write(MSTATUS, mstatus | (3 << 32));
+    assert_eq!(cpu.csr.read(MSTATUS) & MSTATUS_UXL, 1 << 32);
+
+    // A 32-bit user process on a 64-bit kernel
+    cpu.csr.priv_level = PrivLevel::USER;
+    assert_eq!(cpu.csr.xlen(), 32);
+    assert_eq!(exec_rr(&mut cpu, ADD, 0x7FFF_FFFF, 1)?, 0xFFFF_FFFF_8000_0000);
+    match exec_rr(&mut cpu, ADDW, 0, 0) {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("ADDW must be illegal with UXL=32"),
+    }
+
+    // S-mode keeps SXL=64
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    assert_eq!(cpu.csr.xlen(), 64);
+    assert_eq!(exec_rr(&mut cpu, ADD, 0x7FFF_FFFF, 1)?, 0x8000_0000);
+
+    Ok(())
+}
@@ -10,7 +10,7 @@ macro_rules! add_test {
             filename.push_str(stringify!($str));
 
             let mut cpu = Cpu::new();
-            cpu.load(&filename);
+            cpu.load_dram(&filename);
 
             // If GP equals 1, test is passed.
             cpu.watch(Registers::GP, 1, WatchExec::EXIT);
@@ -69,6 +69,7 @@ add_test!(rv64ui_p_lbu);
 add_test!(rv64ui_p_lhu);
 add_test!(rv64ui_p_lwu);
 add_test!(rv64ui_p_simple);
+add_test!(rv64ui_p_fence_i);
 
 //RV64 user-level, integer multiplication and division
 add_test!(rv64um_p_mul);
@@ -104,16 +105,45 @@ add_test!(rv64ua_p_amomin_d);
 add_test!(rv64ua_p_amominu_d);
 add_test!(rv64ua_p_amomax_d);
 add_test!(rv64ua_p_amomaxu_d);
+add_test!(rv64ua_p_lrsc);
 
 // RV64 supervisor-level, integer and vector
 
-//add_test!(rv64si_p_csr);
-//add_test!(rv64si_p_dirty);
+add_test!(rv64si_p_csr);
+add_test!(rv64si_p_dirty);
 //add_test!(rv64si_p_icache_alias);
-//add_test!(rv64si_p_ma_fetch);
+add_test!(rv64si_p_ma_fetch);
 //add_test!(rv64si_p_sbreak);
 //add_test!(rv64si_p_scall);
-//add_test!(rv64si_p_wfi);
+add_test!(rv64si_p_wfi);
+
+// A/D bits are maintained by the trap handler instead of the page walker (Svade)
+#[test]
+fn rv64si_p_dirty_svade() -> io::Result<()> {
+    use crate::emulator::mmu::AdUpdate;
+
+    let mut cpu = Cpu::new();
+    cpu.mmu.ad_update = AdUpdate::Software;
+    cpu.load_dram(&"./src/test/rvtests/rv64si_p_dirty".to_string());
+    cpu.watch(Registers::GP, 1, WatchExec::EXIT);
+    cpu.run();
+
+    Ok(())
+}
+
+// Misaligned loads and stores trap instead of being performed by the MMU
+#[test]
+fn rv64si_p_ma_fetch_trap() -> io::Result<()> {
+    use crate::emulator::mmu::Misaligned;
+
+    let mut cpu = Cpu::new();
+    cpu.mmu.misaligned = Misaligned::Trap;
+    cpu.load_dram(&"./src/test/rvtests/rv64si_p_ma_fetch".to_string());
+    cpu.watch(Registers::GP, 1, WatchExec::EXIT);
+    cpu.run();
+
+    Ok(())
+}
 
 // RV64 user-level, integer only, virtual memory is enabled
 /*
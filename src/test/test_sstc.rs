@@ -0,0 +1,86 @@
+#![cfg(test)]
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::csr::*;
+use crate::emulator::bus::CLINT_BASE;
+use crate::emulator::clint::MTIME_BASE;
+use crate::emulator::exception::Exception;
+
+// CSRRS x1, stimecmp, x0
+const READ_STIMECMP: u32 = 0x14D0_20F3;
+
+#[test]
+pub fn test_stimecmp_drives_stip() {
+    let mut cpu = Cpu::new();
+
+    // Without STCE, stimecmp has no effect on STIP
+    cpu.csr.write(STIMECMP, 100);
+    cpu.csr.update_time(200);
+    assert_eq!(cpu.csr.read(MIP) & MIP_STIP, 0);
+    assert_eq!(cpu.csr.read(TIME), 200);
+
+    cpu.csr.write(MENVCFG, MENVCFG_STCE);
+    cpu.csr.update_time(99);
+    assert_eq!(cpu.csr.read(MIP) & MIP_STIP, 0);
+    cpu.csr.update_time(100);
+    assert_eq!(cpu.csr.read(MIP) & MIP_STIP, MIP_STIP);
+
+    // Moving stimecmp past time clears the pending interrupt
+    cpu.csr.write(STIMECMP, 1000);
+    cpu.csr.update_time(101);
+    assert_eq!(cpu.csr.read(MIP) & MIP_STIP, 0);
+
+    // STIP is read-only to software while STCE is set
+    cpu.csr.write(MIP, MIP_STIP);
+    assert_eq!(cpu.csr.read(MIP) & MIP_STIP, 0);
+    cpu.csr.write(SIP, MIP_STIP);
+    assert_eq!(cpu.csr.read(MIP) & MIP_STIP, 0);
+}
+
+#[test]
+pub fn test_stimecmp_access() -> Result<(), Exception> {
+    let mut cpu = Cpu::new();
+    cpu.instruction = READ_STIMECMP;
+
+    // M-mode always has access
+    cpu.execute()?;
+
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    match cpu.execute() {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("stimecmp must trap from S-mode while menvcfg.STCE is clear"),
+    }
+
+    cpu.csr.write(MENVCFG, MENVCFG_STCE);
+    match cpu.execute() {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("stimecmp must trap from S-mode while mcounteren.TM is clear"),
+    }
+
+    cpu.csr.write(MCOUNTEREN, 1 << 1);
+    cpu.execute()?;
+
+    cpu.csr.priv_level = PrivLevel::USER;
+    match cpu.execute() {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("stimecmp must not be accessible from U-mode"),
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_wfi_stimecmp_fast_forward() {
+    let mut cpu = Cpu::new();
+
+    cpu.csr.write(MENVCFG, MENVCFG_STCE);
+    cpu.csr.write(STIMECMP, 1_000_000);
+    cpu.csr.write(MIE, MIP_STIP);
+    cpu.wfi = true;
+
+    // mtimecmp is not armed, so only stimecmp can end the wait
+    cpu.wait_for_interrupt();
+    assert_ne!(cpu.csr.read(MIP) & MIP_STIP, 0);
+    assert!(cpu.mmu.read64(&cpu.csr, CLINT_BASE + MTIME_BASE).unwrap() >= 1_000_000);
+    assert!(cpu.csr.read(TIME) >= 1_000_000);
+}
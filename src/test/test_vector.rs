@@ -0,0 +1,631 @@
+#![cfg(test)]
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::csr::*;
+use crate::emulator::bus::{ DRAM_BASE, DRAM_TOP };
+use crate::emulator::exception::Exception;
+use crate::emulator::vector::{ self, VTYPE_VILL };
+use crate::emulator::float::*;
+use super::helper::exec;
+
+const OPIVV: u32 = 0b000;
+const OPFVV: u32 = 0b001;
+const OPMVV: u32 = 0b010;
+const OPIVI: u32 = 0b011;
+const OPIVX: u32 = 0b100;
+const OPFVF: u32 = 0b101;
+const OPMVX: u32 = 0b110;
+
+// vtype immediates
+const E8M1: u32     = 0b000_000;
+const E16MF2: u32   = 0b001_111;
+const E32M1: u32    = 0b010_000;
+const E32M2: u32    = 0b010_001;
+const E64M1: u32    = 0b011_000;
+const E64MF8: u32   = 0b011_101;
+const E8M2: u32     = 0b000_001;
+
+fn opv(funct6: u32, vm: bool, vs2: u32, rs1: u32, funct3: u32, vd: u32) -> u32 {
+    funct6 << 26 | (vm as u32) << 25 | vs2 << 20 | rs1 << 15 | funct3 << 12 | vd << 7 | 0b101_0111
+}
+
+fn vsetvli(rd: u32, rs1: u32, vtypei: u32) -> u32 {
+    vtypei << 20 | rs1 << 15 | 0b111 << 12 | rd << 7 | 0b101_0111
+}
+
+fn vsetivli(rd: u32, uimm: u32, vtypei: u32) -> u32 {
+    0b11 << 30 | vtypei << 20 | uimm << 15 | 0b111 << 12 | rd << 7 | 0b101_0111
+}
+
+// Vector load (store = false) or store with `nf` fields, addressing mode `mop` and element width `width`
+#[allow(clippy::too_many_arguments)]
+fn vmem(store: bool, nf: u32, mop: u32, vm: bool, rs2: u32, rs1: u32, width: u32, vd: u32) -> u32 {
+    let opcode = if store { 0b010_0111 } else { 0b000_0111 };
+    (nf - 1) << 29 | mop << 26 | (vm as u32) << 25 | rs2 << 20 | rs1 << 15 | width << 12 | vd << 7 | opcode
+}
+
+fn expect_illegal(cpu: &mut Cpu, instruction: u32, message: &str) {
+    match exec(cpu, instruction) {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("{}", message),
+    }
+}
+
+// A CPU with the vector and floating-point units enabled and vl = avl for `vtypei`
+fn vector_cpu(vtypei: u32, avl: u64) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.csr.write(MSTATUS, MSTATUS_VS | MSTATUS_FS);
+    cpu.register.write(31, avl);
+    exec(&mut cpu, vsetvli(0, 31, vtypei)).unwrap();
+    cpu
+}
+
+fn set(cpu: &mut Cpu, vreg: usize, eew: usize, values: &[u64]) {
+    for (i, value) in values.iter().enumerate() {
+        cpu.vreg.write(vreg, i, eew, *value);
+    }
+}
+
+fn get(cpu: &Cpu, vreg: usize, eew: usize, count: usize) -> Vec<u64> {
+    (0..count).map(|i| cpu.vreg.read(vreg, i, eew)).collect()
+}
+
+fn f32s(values: &[f32]) -> Vec<u64> {
+    values.iter().map(|value| value.to_bits() as u64).collect()
+}
+
+fn f64s(values: &[f64]) -> Vec<u64> {
+    values.iter().map(|value| value.to_bits()).collect()
+}
+
+#[test]
+pub fn test_vsetvli() -> Result<(), Exception> {
+    let mut cpu = Cpu::new();
+
+    // The vector unit is off at reset
+    expect_illegal(&mut cpu, vsetvli(2, 1, E32M1), "vsetvli must be illegal while mstatus.VS is off");
+    // CSRRS x1, vl, x0
+    expect_illegal(&mut cpu, 0xC200_20F3, "vl must not be accessible while mstatus.VS is off");
+
+    cpu.csr.write(MSTATUS, MSTATUS_VS);
+    assert_eq!(cpu.csr.read(VLENB), 16);
+    assert_eq!(cpu.csr.read(VTYPE), VTYPE_VILL);
+
+    cpu.register.write(1, 10);
+    exec(&mut cpu, vsetvli(2, 1, E32M1))?;
+    assert_eq!(cpu.register.read(2), 4);
+    assert_eq!(cpu.csr.read(VL), 4);
+    assert_eq!(cpu.csr.read(VTYPE), E32M1 as u64);
+
+    cpu.register.write(1, 100);
+    exec(&mut cpu, vsetvli(2, 1, E8M2))?;
+    assert_eq!(cpu.register.read(2), 32);
+    exec(&mut cpu, vsetvli(2, 1, E16MF2))?;
+    assert_eq!(cpu.register.read(2), 4);
+
+    // rs1 = x0 and rd = x0 keep vl
+    exec(&mut cpu, vsetvli(0, 0, E32M1))?;
+    assert_eq!(cpu.csr.read(VL), 4);
+    assert_eq!(cpu.csr.read(VTYPE), E32M1 as u64);
+
+    // rs1 = x0 and rd != x0 set vl to VLMAX
+    exec(&mut cpu, vsetvli(3, 0, E8M1))?;
+    assert_eq!(cpu.register.read(3), 16);
+
+    exec(&mut cpu, vsetivli(4, 3, E64M1))?;
+    assert_eq!(cpu.register.read(4), 2);
+
+    // SEW = 64 does not fit in LMUL = 1/8
+    exec(&mut cpu, vsetvli(5, 1, E64MF8))?;
+    assert_eq!(cpu.register.read(5), 0);
+    assert_eq!(cpu.csr.read(VTYPE), VTYPE_VILL);
+    expect_illegal(&mut cpu, opv(0b000_000, true, 2, 1, OPIVV, 3), "vadd must be illegal while vtype.vill is set");
+
+    Ok(())
+}
+
+#[test]
+pub fn test_vector_config() -> Result<(), Exception> {
+    assert!(vector::is_valid_config(128, 64));
+    assert!(vector::is_valid_config(32, 32));
+    assert!(!vector::is_valid_config(32, 64));
+    assert!(!vector::is_valid_config(96, 32));
+    assert!(!vector::is_valid_config(128, 16));
+
+    let mut cpu = Cpu::new();
+    cpu.configure_vector(256, 32);
+    cpu.csr.write(MSTATUS, MSTATUS_VS);
+    assert_eq!(cpu.csr.read(VLENB), 32);
+
+    cpu.register.write(1, 100);
+    exec(&mut cpu, vsetvli(2, 1, E32M1))?;
+    assert_eq!(cpu.register.read(2), 8);
+
+    // SEW may not exceed ELEN
+    exec(&mut cpu, vsetvli(2, 1, E64M1))?;
+    assert_eq!(cpu.csr.read(VTYPE), VTYPE_VILL);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_vector_csrs() -> Result<(), Exception> {
+    let mut cpu = Cpu::new();
+    cpu.csr.write(MSTATUS, 1 << 9);     // VS = Initial
+
+    cpu.csr.write(VCSR, 0b101);
+    assert_eq!(cpu.csr.read(VXRM), 0b10);
+    assert_eq!(cpu.csr.read(VXSAT), 1);
+    cpu.csr.write(VXRM, 0b111);
+    assert_eq!(cpu.csr.read(VCSR), 0b111);
+
+    // Writing vector state marks it dirty
+    assert_eq!(cpu.csr.read(MSTATUS) & MSTATUS_VS, MSTATUS_VS);
+    assert_eq!(cpu.csr.read(MSTATUS) >> 63, 1);
+
+    cpu.csr.write(MSTATUS, 1 << 9);
+    cpu.register.write(1, 4);
+    exec(&mut cpu, vsetvli(0, 1, E32M1))?;
+    assert_eq!(cpu.csr.read(MSTATUS) & MSTATUS_VS, MSTATUS_VS);
+
+    // vl, vtype and vlenb are read-only
+    cpu.csr.write(MSTATUS, MSTATUS_VS);
+    expect_illegal(&mut cpu, 0xC200_9073, "vl must be read-only");     // CSRRW x0, vl, x1
+
+    Ok(())
+}
+
+#[test]
+pub fn test_vector_load_store() -> Result<(), Exception> {
+    let mut cpu = vector_cpu(E32M1, 4);
+    let base = DRAM_BASE + 0x1000;
+    for i in 0..64 {
+        cpu.mmu.write8(&cpu.csr, base + i, i as u8)?;
+    }
+    let word = |offset: u64| u32::from_le_bytes([offset as u8, offset as u8 + 1, offset as u8 + 2, offset as u8 + 3]) as u64;
+    cpu.register.write(1, base as u64);
+
+    // vle32.v v1, (x1)
+    exec(&mut cpu, vmem(false, 1, 0b00, true, 0, 1, 0b110, 1))?;
+    assert_eq!(get(&cpu, 1, 32, 4), vec![word(0), word(4), word(8), word(12)]);
+
+    // vlse32.v v2, (x1), x6
+    cpu.register.write(6, 8);
+    exec(&mut cpu, vmem(false, 1, 0b10, true, 6, 1, 0b110, 2))?;
+    assert_eq!(get(&cpu, 2, 32, 4), vec![word(0), word(8), word(16), word(24)]);
+
+    // vluxei8.v v3, (x1), v4
+    set(&mut cpu, 4, 8, &[12, 0, 5, 60]);
+    exec(&mut cpu, vmem(false, 1, 0b01, true, 4, 1, 0b000, 3))?;
+    assert_eq!(get(&cpu, 3, 32, 4), vec![word(12), word(0), word(5), word(60)]);
+
+    // vse32.v v1, (x7), v0.t
+    cpu.register.write(7, (base + 0x100) as u64);
+    set(&mut cpu, 0, 8, &[0b1011]);
+    exec(&mut cpu, vmem(true, 1, 0b00, false, 0, 7, 0b110, 1))?;
+    assert_eq!(cpu.mmu.read32(&cpu.csr, base + 0x100)? as u64, word(0));
+    assert_eq!(cpu.mmu.read32(&cpu.csr, base + 0x108)?, 0);
+    assert_eq!(cpu.mmu.read32(&cpu.csr, base + 0x10C)? as u64, word(12));
+
+    // vlseg2e8.v v8, (x1) with vl = 3
+    cpu.register.write(31, 3);
+    exec(&mut cpu, vsetvli(0, 31, E8M1))?;
+    exec(&mut cpu, vmem(false, 2, 0b00, true, 0, 1, 0b000, 8))?;
+    assert_eq!(get(&cpu, 8, 8, 3), vec![0, 2, 4]);
+    assert_eq!(get(&cpu, 9, 8, 3), vec![1, 3, 5]);
+
+    // vlm.v v10, (x7) loads ceil(vl / 8) bytes
+    cpu.register.write(7, (base + 5) as u64);
+    exec(&mut cpu, vmem(false, 1, 0b00, true, 0b01011, 7, 0b000, 10))?;
+    assert_eq!(get(&cpu, 10, 8, 2), vec![5, 0]);
+
+    // vl2re64.v v12, (x1) ignores vl
+    exec(&mut cpu, vmem(false, 2, 0b00, true, 0b01000, 1, 0b111, 12))?;
+    assert_eq!(cpu.vreg.read(13, 0, 64), u64::from_le_bytes([16, 17, 18, 19, 20, 21, 22, 23]));
+
+    // A segment group may not run past v31
+    expect_illegal(&mut cpu, vmem(false, 2, 0b00, true, 0, 1, 0b000, 31), "vlseg2e8.v v31 must be illegal");
+    // A masked load may not overwrite v0
+    expect_illegal(&mut cpu, vmem(false, 1, 0b00, false, 0, 1, 0b000, 0), "vle8.v v0, v0.t must be illegal");
+
+    Ok(())
+}
+
+#[test]
+pub fn test_vector_load_faults() -> Result<(), Exception> {
+    let mut cpu = vector_cpu(E8M1, 8);
+    set(&mut cpu, 1, 8, &[0xAA; 8]);
+
+    // Elements 0..=2 are the last bytes of DRAM
+    cpu.register.write(1, (DRAM_TOP - 2) as u64);
+
+    // vle8ff.v v1, (x1): a fault after element 0 trims vl
+    exec(&mut cpu, vmem(false, 1, 0b00, true, 0b10000, 1, 0b000, 1))?;
+    assert_eq!(cpu.csr.read(VL), 3);
+    assert_eq!(cpu.csr.read(VSTART), 0);
+    assert_eq!(get(&cpu, 1, 8, 4), vec![0, 0, 0, 0xAA]);
+
+    // vle8.v v1, (x1) traps and records the faulting element in vstart
+    cpu.register.write(31, 8);
+    exec(&mut cpu, vsetvli(0, 31, E8M1))?;
+    match exec(&mut cpu, vmem(false, 1, 0b00, true, 0, 1, 0b000, 1)) {
+        Err(Exception::LoadAccessFault(addr))   => assert_eq!(addr, DRAM_TOP + 1),
+        _                                       => panic!("vle8.v past the end of DRAM must fault"),
+    }
+    assert_eq!(cpu.csr.read(VSTART), 3);
+    assert_eq!(cpu.csr.read(VL), 8);
+
+    // A fault on element 0 traps even for fault-only-first loads
+    cpu.csr.write(VSTART, 0);
+    cpu.register.write(1, (DRAM_TOP + 1) as u64);
+    match exec(&mut cpu, vmem(false, 1, 0b00, true, 0b10000, 1, 0b000, 1)) {
+        Err(Exception::LoadAccessFault(_))  => (),
+        _                                   => panic!("vle8ff.v must trap on element 0"),
+    }
+    assert_eq!(cpu.csr.read(VL), 8);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_vector_integer() -> Result<(), Exception> {
+    let mut cpu = vector_cpu(E32M1, 4);
+    set(&mut cpu, 1, 32, &[1, 2, 3, 0xFFFF_FFFF]);
+    set(&mut cpu, 2, 32, &[10, 20, 30, 40]);
+    cpu.register.write(5, 100);
+
+    // vadd.vv v3, v2, v1
+    exec(&mut cpu, opv(0b000_000, true, 2, 1, OPIVV, 3))?;
+    assert_eq!(get(&cpu, 3, 32, 4), vec![11, 22, 33, 39]);
+
+    // vadd.vi v4, v1, -1
+    exec(&mut cpu, opv(0b000_000, true, 1, 0x1F, OPIVI, 4))?;
+    assert_eq!(get(&cpu, 4, 32, 4), vec![0, 1, 2, 0xFFFF_FFFE]);
+
+    // vrsub.vx v5, v1, x5
+    exec(&mut cpu, opv(0b000_011, true, 1, 5, OPIVX, 5))?;
+    assert_eq!(get(&cpu, 5, 32, 4), vec![99, 98, 97, 101]);
+
+    // vmul.vv v6, v2, v1
+    exec(&mut cpu, opv(0b100_101, true, 2, 1, OPMVV, 6))?;
+    assert_eq!(get(&cpu, 6, 32, 4), vec![10, 40, 90, 0xFFFF_FFD8]);
+
+    // vdivu.vx v7, v2, x0: division by zero gives all ones
+    exec(&mut cpu, opv(0b100_000, true, 2, 0, OPMVX, 7))?;
+    assert_eq!(get(&cpu, 7, 32, 4), vec![0xFFFF_FFFF; 4]);
+
+    // vmin.vv and vminu.vv
+    exec(&mut cpu, opv(0b000_101, true, 2, 1, OPIVV, 13))?;
+    assert_eq!(get(&cpu, 13, 32, 4), vec![1, 2, 3, 0xFFFF_FFFF]);
+    exec(&mut cpu, opv(0b000_100, true, 2, 1, OPIVV, 14))?;
+    assert_eq!(get(&cpu, 14, 32, 4), vec![1, 2, 3, 40]);
+
+    // vmseq.vi v0, v1, 2
+    exec(&mut cpu, opv(0b011_000, true, 1, 2, OPIVI, 0))?;
+    assert_eq!(cpu.vreg.read(0, 0, 8) & 0xF, 0b0010);
+
+    // vadd.vv v8, v2, v1, v0.t leaves inactive elements undisturbed
+    exec(&mut cpu, opv(0b000_000, false, 2, 1, OPIVV, 8))?;
+    assert_eq!(get(&cpu, 8, 32, 4), vec![0, 22, 0, 0]);
+
+    // vmerge.vim v9, v2, 5, v0
+    exec(&mut cpu, opv(0b010_111, false, 2, 5, OPIVI, 9))?;
+    assert_eq!(get(&cpu, 9, 32, 4), vec![10, 5, 30, 40]);
+
+    // vwaddu.vv v10, v2, v1 writes 64-bit elements to v10-v11
+    exec(&mut cpu, opv(0b110_000, true, 2, 1, OPMVV, 10))?;
+    assert_eq!(get(&cpu, 10, 64, 4), vec![11, 22, 33, 0x1_0000_0027]);
+
+    // vnsrl.wi v12, v10, 1
+    exec(&mut cpu, opv(0b101_100, true, 10, 1, OPIVI, 12))?;
+    assert_eq!(get(&cpu, 12, 32, 4), vec![5, 11, 16, 0x8000_0013]);
+
+    // A masked instruction may not write v0, and groups must be aligned
+    expect_illegal(&mut cpu, opv(0b000_000, false, 2, 1, OPIVV, 0), "vadd.vv v0, v2, v1, v0.t must be illegal");
+    expect_illegal(&mut cpu, opv(0b110_000, true, 2, 1, OPMVV, 11), "vwaddu.vv into an odd register must be illegal");
+    exec(&mut cpu, vsetvli(0, 31, E32M2))?;
+    expect_illegal(&mut cpu, opv(0b000_000, true, 2, 4, OPIVV, 1), "vadd.vv into v1 must be illegal with LMUL = 2");
+
+    Ok(())
+}
+
+#[test]
+pub fn test_vector_fixed_point() -> Result<(), Exception> {
+    let mut cpu = vector_cpu(E8M1, 4);
+    set(&mut cpu, 1, 8, &[200, 100, 0x7F, 0x80]);
+    set(&mut cpu, 2, 8, &[100, 100, 1, 0x80]);
+
+    // vsaddu.vv v3, v1, v2
+    exec(&mut cpu, opv(0b100_000, true, 1, 2, OPIVV, 3))?;
+    assert_eq!(get(&cpu, 3, 8, 4), vec![255, 200, 128, 255]);
+    assert_eq!(cpu.csr.read(VXSAT), 1);
+
+    // vsadd.vv v3, v1, v2
+    cpu.csr.write(VXSAT, 0);
+    exec(&mut cpu, opv(0b100_001, true, 1, 2, OPIVV, 3))?;
+    assert_eq!(get(&cpu, 3, 8, 4), vec![44, 127, 127, 0x80]);
+    assert_eq!(cpu.csr.read(VXSAT), 1);
+
+    // vaaddu.vx v4, v1, x5 rounds by vxrm
+    cpu.register.write(5, 1);
+    cpu.csr.write(VXRM, 0b00);
+    exec(&mut cpu, opv(0b001_000, true, 1, 5, OPMVX, 4))?;
+    assert_eq!(get(&cpu, 4, 8, 4), vec![101, 51, 64, 65]);
+    cpu.csr.write(VXRM, 0b10);
+    exec(&mut cpu, opv(0b001_000, true, 1, 5, OPMVX, 4))?;
+    assert_eq!(get(&cpu, 4, 8, 4), vec![100, 50, 64, 64]);
+
+    // vsmul.vv v5, v1, v2
+    cpu.csr.write(VXRM, 0b00);
+    cpu.csr.write(VXSAT, 0);
+    exec(&mut cpu, opv(0b100_111, true, 1, 2, OPIVV, 5))?;
+    assert_eq!(get(&cpu, 5, 8, 4), vec![0xD4, 78, 1, 127]);
+    assert_eq!(cpu.csr.read(VXSAT), 1);
+
+    // vnclipu.wi v9, v8, 4
+    cpu.csr.write(VXSAT, 0);
+    set(&mut cpu, 8, 16, &[0x1234, 0x00FF, 0x0180, 3]);
+    exec(&mut cpu, opv(0b101_110, true, 8, 4, OPIVI, 9))?;
+    assert_eq!(get(&cpu, 9, 8, 4), vec![255, 16, 24, 0]);
+    assert_eq!(cpu.csr.read(VXSAT), 1);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_vector_reduction_permute() -> Result<(), Exception> {
+    let mut cpu = vector_cpu(E32M1, 4);
+    set(&mut cpu, 1, 32, &[1, 2, 3, 4]);
+    set(&mut cpu, 2, 32, &[10]);
+    set(&mut cpu, 0, 8, &[0b1010]);
+    cpu.register.write(5, 7);
+
+    // vredsum.vs v3, v1, v2
+    exec(&mut cpu, opv(0b000_000, true, 1, 2, OPMVV, 3))?;
+    assert_eq!(cpu.vreg.read(3, 0, 32), 20);
+    // vredminu.vs v3, v1, v2, v0.t
+    exec(&mut cpu, opv(0b000_100, false, 1, 2, OPMVV, 3))?;
+    assert_eq!(cpu.vreg.read(3, 0, 32), 2);
+
+    // vslideup.vi v4, v1, 1
+    set(&mut cpu, 4, 32, &[9; 4]);
+    exec(&mut cpu, opv(0b001_110, true, 1, 1, OPIVI, 4))?;
+    assert_eq!(get(&cpu, 4, 32, 4), vec![9, 1, 2, 3]);
+
+    // vslidedown.vi v5, v1, 1
+    exec(&mut cpu, opv(0b001_111, true, 1, 1, OPIVI, 5))?;
+    assert_eq!(get(&cpu, 5, 32, 4), vec![2, 3, 4, 0]);
+
+    // vslide1down.vx v6, v1, x5
+    exec(&mut cpu, opv(0b001_111, true, 1, 5, OPMVX, 6))?;
+    assert_eq!(get(&cpu, 6, 32, 4), vec![2, 3, 4, 7]);
+
+    // vrgather.vv v7, v1, v8
+    set(&mut cpu, 8, 32, &[3, 0, 5, 1]);
+    exec(&mut cpu, opv(0b001_100, true, 1, 8, OPIVV, 7))?;
+    assert_eq!(get(&cpu, 7, 32, 4), vec![4, 1, 0, 2]);
+
+    // vcompress.vm v9, v1, v0
+    exec(&mut cpu, opv(0b010_111, true, 1, 0, OPMVV, 9))?;
+    assert_eq!(get(&cpu, 9, 32, 4), vec![2, 4, 0, 0]);
+
+    // viota.m v10, v0
+    exec(&mut cpu, opv(0b010_100, true, 0, 0b10000, OPMVV, 10))?;
+    assert_eq!(get(&cpu, 10, 32, 4), vec![0, 0, 1, 1]);
+
+    // vid.v v11
+    exec(&mut cpu, opv(0b010_100, true, 0, 0b10001, OPMVV, 11))?;
+    assert_eq!(get(&cpu, 11, 32, 4), vec![0, 1, 2, 3]);
+
+    // vcpop.m x11, v0 and vfirst.m x12, v0
+    exec(&mut cpu, opv(0b010_000, true, 0, 0b10000, OPMVV, 11))?;
+    assert_eq!(cpu.register.read(11), 2);
+    exec(&mut cpu, opv(0b010_000, true, 0, 0b10001, OPMVV, 12))?;
+    assert_eq!(cpu.register.read(12), 1);
+
+    // vmsif.m v13, v0
+    exec(&mut cpu, opv(0b010_100, true, 0, 0b00011, OPMVV, 13))?;
+    assert_eq!(cpu.vreg.read(13, 0, 8) & 0xF, 0b0011);
+
+    // vmnand.mm v14, v0, v13
+    exec(&mut cpu, opv(0b011_101, true, 0, 13, OPMVV, 14))?;
+    assert_eq!(cpu.vreg.read(14, 0, 8) & 0xF, 0b1101);
+
+    // vmv.s.x v12, x5 and vmv.x.s x10, v1 (sign-extended)
+    exec(&mut cpu, opv(0b010_000, true, 0, 5, OPMVX, 12))?;
+    assert_eq!(cpu.vreg.read(12, 0, 32), 7);
+    set(&mut cpu, 1, 32, &[0xFFFF_FFFF]);
+    exec(&mut cpu, opv(0b010_000, true, 1, 0, OPMVV, 10))?;
+    assert_eq!(cpu.register.read(10), u64::MAX);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_vector_float() -> Result<(), Exception> {
+    let mut cpu = vector_cpu(E32M1, 4);
+    set(&mut cpu, 1, 32, &f32s(&[1.5, -2.0, 0.0, 3.0]));
+    set(&mut cpu, 2, 32, &f32s(&[0.5, 4.0, 0.0, -1.0]));
+
+    // vfadd.vv v3, v1, v2
+    exec(&mut cpu, opv(0b000_000, true, 1, 2, OPFVV, 3))?;
+    assert_eq!(get(&cpu, 3, 32, 4), f32s(&[2.0, 2.0, 0.0, 2.0]));
+    assert_eq!(cpu.csr.read(FFLAGS), 0);
+
+    // vfdiv.vv v4, v1, v2: 0 / 0 is an invalid operation with a canonical NaN result
+    exec(&mut cpu, opv(0b100_000, true, 1, 2, OPFVV, 4))?;
+    assert_eq!(get(&cpu, 4, 32, 4), vec![f32::to_bits(3.0) as u64, f32::to_bits(-0.5) as u64, 0x7FC0_0000, f32::to_bits(-3.0) as u64]);
+    assert_eq!(cpu.csr.read(FFLAGS), FFLAGS_NV);
+
+    // vfdiv.vv v4, v8, v9: 1 / 0 divides by zero
+    cpu.csr.write(FFLAGS, 0);
+    set(&mut cpu, 8, 32, &f32s(&[1.0; 4]));
+    set(&mut cpu, 9, 32, &f32s(&[0.0; 4]));
+    exec(&mut cpu, opv(0b100_000, true, 8, 9, OPFVV, 4))?;
+    assert_eq!(get(&cpu, 4, 32, 4), f32s(&[f32::INFINITY; 4]));
+    assert_eq!(cpu.csr.read(FFLAGS), FFLAGS_DZ);
+
+    // vfmin.vv v5, v1, v2
+    exec(&mut cpu, opv(0b000_100, true, 1, 2, OPFVV, 5))?;
+    assert_eq!(get(&cpu, 5, 32, 4), f32s(&[0.5, -2.0, 0.0, -1.0]));
+
+    // vmflt.vv v0, v1, v2
+    exec(&mut cpu, opv(0b011_011, true, 1, 2, OPFVV, 0))?;
+    assert_eq!(cpu.vreg.read(0, 0, 8) & 0xF, 0b0010);
+
+    // vfcvt.x.f.v v6, v1 rounds to nearest even
+    cpu.csr.write(FFLAGS, 0);
+    exec(&mut cpu, opv(0b010_010, true, 1, 0b00001, OPFVV, 6))?;
+    assert_eq!(get(&cpu, 6, 32, 4), vec![2, 0xFFFF_FFFE, 0, 3]);
+    assert_eq!(cpu.csr.read(FFLAGS), FFLAGS_NX);
+
+    // vfmacc.vv v7, v1, v2
+    set(&mut cpu, 7, 32, &f32s(&[1.0; 4]));
+    exec(&mut cpu, opv(0b101_100, true, 2, 1, OPFVV, 7))?;
+    assert_eq!(get(&cpu, 7, 32, 4), f32s(&[1.75, -7.0, 1.0, -2.0]));
+
+    // vfmv.f.s f1, v2 NaN-boxes the element, and vfrsub.vf v3, v1, f1 computes f1 - v1
+    exec(&mut cpu, opv(0b010_000, true, 2, 0, OPFVV, 1))?;
+    assert_eq!(cpu.freg.read(1, F64), 0xFFFF_FFFF_3F00_0000);
+    exec(&mut cpu, opv(0b100_111, true, 1, 1, OPFVF, 3))?;
+    assert_eq!(get(&cpu, 3, 32, 4), f32s(&[-1.0, 2.5, 0.5, -2.5]));
+
+    // vmfgt.vf v0, v1, f1
+    exec(&mut cpu, opv(0b011_101, true, 1, 1, OPFVF, 0))?;
+    assert_eq!(cpu.vreg.read(0, 0, 8) & 0xF, 0b1001);
+
+    // vfmerge.vfm v3, v2, f1, v0 and vfmv.s.f v5, f1
+    exec(&mut cpu, opv(0b010_111, false, 2, 1, OPFVF, 3))?;
+    assert_eq!(get(&cpu, 3, 32, 4), f32s(&[0.5, 4.0, 0.0, 0.5]));
+    exec(&mut cpu, opv(0b010_000, true, 0, 1, OPFVF, 5))?;
+    assert_eq!(get(&cpu, 5, 32, 4), f32s(&[0.5, -2.0, 0.0, -1.0]));
+
+    // A binary64 value in f1 is not NaN-boxed, so vfadd.vf sees the canonical NaN
+    cpu.csr.write(FFLAGS, 0);
+    cpu.freg.write(1, F64, 1f64.to_bits());
+    exec(&mut cpu, opv(0b000_000, true, 1, 1, OPFVF, 3))?;
+    assert_eq!(get(&cpu, 3, 32, 4), vec![0x7FC0_0000; 4]);
+    assert_eq!(cpu.csr.read(FFLAGS), 0);
+
+    // Floating-point results and fcsr writes mark the F state dirty
+    cpu.csr.write(MSTATUS, MSTATUS_VS | (1 << 13));
+    exec(&mut cpu, opv(0b100_000, true, 8, 9, OPFVV, 4))?;
+    assert_eq!(cpu.csr.read(MSTATUS) & MSTATUS_FS, MSTATUS_FS);
+    cpu.csr.write(MSTATUS, MSTATUS_VS | (1 << 13));
+    exec(&mut cpu, opv(0b010_000, true, 2, 0, OPFVV, 1))?;
+    assert_eq!(cpu.csr.read(MSTATUS) & MSTATUS_FS, MSTATUS_FS);
+    cpu.csr.write(MSTATUS, MSTATUS_VS | (1 << 13));
+    cpu.register.write(1, RM_RUP);
+    exec(&mut cpu, 0x0020_9073)?;       // CSRRW x0, frm, x1
+    assert_eq!(cpu.csr.read(FCSR), (RM_RUP << 5) | FFLAGS_DZ);
+    assert_eq!(cpu.csr.read(MSTATUS) & MSTATUS_FS, MSTATUS_FS);
+
+    // Floating-point instructions and CSRs need mstatus.FS, and instructions SEW = 32 or 64
+    cpu.csr.write(MSTATUS, MSTATUS_VS);
+    expect_illegal(&mut cpu, opv(0b000_000, true, 1, 2, OPFVV, 3), "vfadd.vv must be illegal while mstatus.FS is off");
+    expect_illegal(&mut cpu, 0x0010_20F3, "fflags must not be accessible while mstatus.FS is off");     // CSRRS x1, fflags, x0
+    cpu.csr.write(MSTATUS, MSTATUS_VS | MSTATUS_FS);
+    exec(&mut cpu, vsetvli(0, 0, E8M1))?;
+    expect_illegal(&mut cpu, opv(0b000_000, true, 1, 2, OPFVV, 3), "vfadd.vv must be illegal with SEW = 8");
+
+    Ok(())
+}
+
+#[test]
+pub fn test_vector_float_rounding() -> Result<(), Exception> {
+    let mut cpu = vector_cpu(E32M1, 1);
+
+    // 1 + 2^-24 is halfway between 1.0 and the next binary32 value
+    set(&mut cpu, 1, 32, &f32s(&[1.0]));
+    set(&mut cpu, 2, 32, &[0x3380_0000]);
+    for &(rm, expected) in &[(RM_RNE, 0x3F80_0000), (RM_RTZ, 0x3F80_0000), (RM_RDN, 0x3F80_0000), (RM_RUP, 0x3F80_0001), (RM_RMM, 0x3F80_0001)] {
+        cpu.csr.write(FRM, rm);
+        exec(&mut cpu, opv(0b000_000, true, 1, 2, OPFVV, 3))?;     // vfadd.vv v3, v1, v2
+        assert_eq!(get(&cpu, 3, 32, 1), vec![expected], "rounding mode {}", rm);
+    }
+    assert_eq!(cpu.csr.read(FFLAGS), FFLAGS_NX);
+
+    // Reserved rounding modes make floating-point instructions illegal
+    cpu.csr.write(FRM, 0b101);
+    expect_illegal(&mut cpu, opv(0b000_000, true, 1, 2, OPFVV, 3), "vfadd.vv must be illegal with a reserved frm");
+
+    // vfmacc.vv v3, v1, v2 rounds once: the exact 1 + 2^-24 + 2^-70 is above halfway, which rounding
+    // to binary64 first would lose, and then round to even
+    cpu.csr.write(FRM, RM_RNE);
+    set(&mut cpu, 1, 32, &[0x3F80_0001]);
+    set(&mut cpu, 2, 32, &[0x3F7F_FFFF]);
+    set(&mut cpu, 3, 32, &[0x2800_0001]);
+    exec(&mut cpu, opv(0b101_100, true, 2, 1, OPFVV, 3))?;
+    assert_eq!(get(&cpu, 3, 32, 1), vec![0x3F80_0001]);
+
+    // vfcvt.x.f.v follows frm, vfcvt.rtz.x.f.v rounds towards zero
+    cpu.csr.write(FRM, RM_RDN);
+    set(&mut cpu, 4, 32, &f32s(&[-1.5]));
+    exec(&mut cpu, opv(0b010_010, true, 4, 0b0_0001, OPFVV, 5))?;
+    assert_eq!(get(&cpu, 5, 32, 1), vec![0xFFFF_FFFE]);
+    exec(&mut cpu, opv(0b010_010, true, 4, 0b0_0111, OPFVV, 5))?;
+    assert_eq!(get(&cpu, 5, 32, 1), vec![0xFFFF_FFFF]);
+
+    // vfmul.vv v6, v4, v4 overflows and underflows
+    cpu.csr.write(FFLAGS, 0);
+    set(&mut cpu, 4, 32, &f32s(&[f32::MAX]));
+    exec(&mut cpu, opv(0b100_100, true, 4, 4, OPFVV, 6))?;
+    assert_eq!(get(&cpu, 6, 32, 1), f32s(&[f32::MAX]));     // rounding down
+    assert_eq!(cpu.csr.read(FFLAGS), FFLAGS_OF | FFLAGS_NX);
+    cpu.csr.write(FFLAGS, 0);
+    set(&mut cpu, 4, 32, &[1]);
+    exec(&mut cpu, opv(0b100_100, true, 4, 4, OPFVV, 6))?;
+    assert_eq!(get(&cpu, 6, 32, 1), vec![0]);
+    assert_eq!(cpu.csr.read(FFLAGS), FFLAGS_UF | FFLAGS_NX);
+
+    // SEW = 64
+    exec(&mut cpu, vsetvli(0, 31, E64M1))?;
+    cpu.csr.write(FRM, RM_RUP);
+    set(&mut cpu, 1, 64, &f64s(&[1.0]));
+    set(&mut cpu, 2, 64, &f64s(&[f64::EPSILON / 4.0]));
+    exec(&mut cpu, opv(0b000_000, true, 1, 2, OPFVV, 3))?;
+    assert_eq!(get(&cpu, 3, 64, 1), vec![1f64.to_bits() + 1]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_vector_float_widening() -> Result<(), Exception> {
+    let mut cpu = vector_cpu(E32M1, 2);
+    set(&mut cpu, 1, 32, &f32s(&[1.0, 3.0]));
+    set(&mut cpu, 2, 32, &f32s(&[2f32.powi(-30), 0.5]));
+
+    // vfwadd.vv v4, v1, v2 is exact in binary64
+    exec(&mut cpu, opv(0b110_000, true, 1, 2, OPFVV, 4))?;
+    assert_eq!(get(&cpu, 4, 64, 2), f64s(&[1.0 + 2f64.powi(-30), 3.5]));
+
+    // vfwmacc.vv v4, v1, v2
+    exec(&mut cpu, opv(0b111_100, true, 2, 1, OPFVV, 4))?;
+    assert_eq!(get(&cpu, 4, 64, 2), f64s(&[1.0 + 2f64.powi(-29), 5.0]));
+    assert_eq!(cpu.csr.read(FFLAGS), 0);
+
+    // vfncvt.f.f.w v6, v4 rounds to binary32, vfncvt.rod.f.f.w v7, v4 to odd
+    exec(&mut cpu, opv(0b010_010, true, 4, 0b1_0100, OPFVV, 6))?;
+    assert_eq!(get(&cpu, 6, 32, 2), f32s(&[1.0, 5.0]));
+    assert_eq!(cpu.csr.read(FFLAGS), FFLAGS_NX);
+    exec(&mut cpu, opv(0b010_010, true, 4, 0b1_0101, OPFVV, 7))?;
+    assert_eq!(get(&cpu, 7, 32, 2), vec![0x3F80_0001, f32::to_bits(5.0) as u64]);
+
+    // vfwcvt.f.x.v v8, v10 and vfncvt.x.f.w v11, v8
+    set(&mut cpu, 10, 32, &[(-3i32) as u32 as u64, 7]);
+    exec(&mut cpu, opv(0b010_010, true, 10, 0b0_1011, OPFVV, 8))?;
+    assert_eq!(get(&cpu, 8, 64, 2), f64s(&[-3.0, 7.0]));
+    exec(&mut cpu, opv(0b010_010, true, 8, 0b1_0001, OPFVV, 11))?;
+    assert_eq!(get(&cpu, 11, 32, 2), vec![(-3i32) as u32 as u64, 7]);
+
+    // vfwredusum.vs v12, v1, v13
+    set(&mut cpu, 13, 64, &f64s(&[0.25]));
+    exec(&mut cpu, opv(0b110_001, true, 1, 13, OPFVV, 12))?;
+    assert_eq!(get(&cpu, 12, 64, 1), f64s(&[4.25]));
+
+    // There is no binary128, so widening is illegal with SEW = 64
+    exec(&mut cpu, vsetvli(0, 0, E64M1))?;
+    expect_illegal(&mut cpu, opv(0b110_000, true, 1, 2, OPFVV, 4), "vfwadd.vv must be illegal with SEW = 64");
+
+    Ok(())
+}
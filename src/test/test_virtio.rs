@@ -1,3 +1,71 @@
+#![cfg(test)]
+
+use crate::emulator::bus::{ DRAM_BASE, VIRTIO_BASE };
+use crate::emulator::cpu::Cpu;
+
+const DESC:     usize = DRAM_BASE + 0x10_0000;
+const AVAIL:    usize = DRAM_BASE + 0x10_1000;
+const USED:     usize = DRAM_BASE + 0x10_2000;
+const HEADER:   usize = DRAM_BASE + 0x10_3000;
+const BUFFER:   usize = DRAM_BASE + 0x10_4000;
+const STATUS:   usize = DRAM_BASE + 0x10_5000;
+const QUEUE_NUM: usize = 8;
+
+const F_NEXT:       u16 = 1;
+const F_WRITE:      u16 = 2;
+const F_INDIRECT:   u16 = 4;
+
+// A disk whose sector n is filled with n, with a modern queue of QUEUE_NUM entries
+fn setup_queue() -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.mmu.load_disk((0 .. 4 * 512).map(|i| (i / 512) as u8).collect());
+
+    // QueueNum, QueueDescLow/High, QueueDriverLow/High, QueueDeviceLow/High and QueueReady
+    for (offset, data) in [
+        (0x038, QUEUE_NUM), (0x080, DESC), (0x084, 0), (0x090, AVAIL), (0x094, 0), (0x0A0, USED), (0x0A4, 0), (0x044, 1),
+    ] {
+        cpu.mmu.write32(&cpu.csr, VIRTIO_BASE + offset, data as u32).unwrap();
+    }
+    cpu
+}
+
+fn write_desc(cpu: &mut Cpu, table: usize, idx: usize, addr: usize, len: u32, flags: u16, next: u16) {
+    cpu.mmu.write64(&cpu.csr, table + idx * 16, addr as u64).unwrap();
+    cpu.mmu.write32(&cpu.csr, table + idx * 16 + 8, len).unwrap();
+    cpu.mmu.write16(&cpu.csr, table + idx * 16 + 12, flags).unwrap();
+    cpu.mmu.write16(&cpu.csr, table + idx * 16 + 14, next).unwrap();
+}
+
+// A three-descriptor request of `req_type` at `sector`, starting at descriptor `head` of `table`
+fn write_request(cpu: &mut Cpu, table: usize, head: usize, req_type: u32, sector: u64, buffer: usize, write: bool) {
+    let header = HEADER + head * 16;
+    cpu.mmu.write32(&cpu.csr, header, req_type).unwrap();
+    cpu.mmu.write64(&cpu.csr, header + 8, sector).unwrap();
+    let data_flags = if write { F_NEXT } else { F_NEXT | F_WRITE };
+    write_desc(cpu, table, head, header, 16, F_NEXT, head as u16 + 1);
+    write_desc(cpu, table, head + 1, buffer, 512, data_flags, head as u16 + 2);
+    write_desc(cpu, table, head + 2, STATUS + head, 1, F_WRITE, 0);
+    cpu.mmu.write8(&cpu.csr, STATUS + head, 0xFF).unwrap();
+}
+
+// Make the chains at `heads` available and notify the device
+fn submit(cpu: &mut Cpu, heads: &[u16]) {
+    let mut idx = cpu.mmu.read16(&cpu.csr, AVAIL + 2).unwrap();
+    for head in heads {
+        cpu.mmu.write16(&cpu.csr, AVAIL + 4 + (idx as usize % QUEUE_NUM) * 2, *head).unwrap();
+        idx = idx.wrapping_add(1);
+    }
+    cpu.mmu.write16(&cpu.csr, AVAIL + 2, idx).unwrap();
+    cpu.mmu.write32(&cpu.csr, VIRTIO_BASE + 0x050, 0).unwrap();
+    let mut mip = 0;
+    cpu.mmu.tick(&mut mip);
+}
+
+// The id and len of used ring entry `idx`
+fn used_elem(cpu: &mut Cpu, idx: usize) -> (u32, u32) {
+    let addr = USED + 4 + (idx % QUEUE_NUM) * 8;
+    (cpu.mmu.read32(&cpu.csr, addr).unwrap(), cpu.mmu.read32(&cpu.csr, addr + 4).unwrap())
+}
 
 #[test]
 pub fn test_virtio_magic_value() {
@@ -18,6 +86,9 @@ pub fn test_virtio_version() {
 
     let mut cpu = Cpu::new();
 
+    assert_eq!(cpu.mmu.read8(&cpu.csr, VIRTIO_BASE + 0x004).unwrap(), 0x2);
+
+    cpu.mmu.set_virtio_legacy(true);
     assert_eq!(cpu.mmu.read8(&cpu.csr, VIRTIO_BASE + 0x004).unwrap(), 0x1);
 }
 
@@ -42,3 +113,188 @@ pub fn test_virtio_vendor_id() {
 
     assert_eq!(cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x00C).unwrap(), 0x554d4551);
 }
+
+#[test]
+pub fn test_virtio_features() {
+    use super::super::emulator;
+    use crate::emulator::bus::VIRTIO_BASE;
+    use emulator::cpu::Cpu;
+
+    let mut cpu = Cpu::new();
+
+    // VIRTIO_F_VERSION_1 is bit 0 of the second feature word
+    cpu.mmu.write32(&cpu.csr, VIRTIO_BASE + 0x014, 1).unwrap();
+    assert_eq!(cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x010).unwrap(), 1);
+
+    // FEATURES_OK does not stick unless the driver accepts VIRTIO_F_VERSION_1
+    cpu.mmu.write32(&cpu.csr, VIRTIO_BASE + 0x070, 0b1011).unwrap();
+    assert_eq!(cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x070).unwrap(), 0b0011);
+    cpu.mmu.write32(&cpu.csr, VIRTIO_BASE + 0x024, 1).unwrap();
+    cpu.mmu.write32(&cpu.csr, VIRTIO_BASE + 0x020, 1).unwrap();
+    cpu.mmu.write32(&cpu.csr, VIRTIO_BASE + 0x070, 0b1011).unwrap();
+    assert_eq!(cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x070).unwrap(), 0b1011);
+
+    // The legacy interface offers no feature bits
+    cpu.mmu.set_virtio_legacy(true);
+    assert_eq!(cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x010).unwrap(), 0);
+}
+
+#[test]
+pub fn test_virtio_config() {
+    use super::super::emulator;
+    use crate::emulator::bus::VIRTIO_BASE;
+    use emulator::cpu::Cpu;
+
+    let mut cpu = Cpu::new();
+    let generation = cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x0FC).unwrap();
+
+    // The capacity is counted in 512-byte sectors
+    cpu.mmu.load_disk(vec![0; 4 * 512]);
+    assert_eq!(cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x100).unwrap(), 4);
+    assert_eq!(cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x104).unwrap(), 0);
+    assert_ne!(cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x0FC).unwrap(), generation);
+}
+
+#[test]
+pub fn test_virtio_modern_queue() {
+    let mut cpu = setup_queue();
+
+    write_request(&mut cpu, DESC, 0, 0, 1, BUFFER, false);
+    submit(&mut cpu, &[0]);
+
+    assert_eq!(cpu.mmu.read8(&cpu.csr, BUFFER + 511).unwrap(), 1);
+    assert_eq!(cpu.mmu.read8(&cpu.csr, STATUS).unwrap(), 0);
+    assert_eq!(cpu.mmu.read16(&cpu.csr, USED + 2).unwrap(), 1);
+    assert_eq!(used_elem(&mut cpu, 0), (0, 513));
+    assert_eq!(cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x060).unwrap(), 1);
+}
+
+#[test]
+pub fn test_virtio_multiple_requests() {
+    let mut cpu = setup_queue();
+
+    // Write sector 3 with 0xAB, then read it back, with a single notification
+    for i in 0 .. 512 {
+        cpu.mmu.write8(&cpu.csr, BUFFER + i, 0xAB).unwrap();
+    }
+    write_request(&mut cpu, DESC, 0, 1, 3, BUFFER, true);
+    write_request(&mut cpu, DESC, 3, 0, 3, BUFFER + 0x800, false);
+    submit(&mut cpu, &[0, 3]);
+
+    assert_eq!(cpu.mmu.read16(&cpu.csr, USED + 2).unwrap(), 2);
+    assert_eq!(used_elem(&mut cpu, 0), (0, 1));
+    assert_eq!(used_elem(&mut cpu, 1), (3, 513));
+    assert_eq!(cpu.mmu.read8(&cpu.csr, BUFFER + 0x800 + 100).unwrap(), 0xAB);
+    assert_eq!((cpu.mmu.read8(&cpu.csr, STATUS).unwrap(), cpu.mmu.read8(&cpu.csr, STATUS + 3).unwrap()), (0, 0));
+
+    // The available index is free-running across the end of the ring
+    for round in 0 .. 4u16 {
+        write_request(&mut cpu, DESC, 0, 0, 2, BUFFER, false);
+        submit(&mut cpu, &[0, 0, 0]);
+        assert_eq!(cpu.mmu.read16(&cpu.csr, USED + 2).unwrap(), 2 + 3 * (round + 1));
+    }
+    assert_eq!(cpu.mmu.read8(&cpu.csr, BUFFER).unwrap(), 2);
+}
+
+#[test]
+pub fn test_virtio_indirect() {
+    let mut cpu = setup_queue();
+    let table = DRAM_BASE + 0x10_6000;
+
+    write_request(&mut cpu, table, 0, 0, 2, BUFFER, false);
+    write_desc(&mut cpu, DESC, 5, table, 3 * 16, F_INDIRECT, 0);
+    submit(&mut cpu, &[5]);
+
+    assert_eq!(cpu.mmu.read8(&cpu.csr, BUFFER).unwrap(), 2);
+    assert_eq!(cpu.mmu.read8(&cpu.csr, STATUS).unwrap(), 0);
+    assert_eq!(used_elem(&mut cpu, 0), (5, 513));
+}
+
+#[test]
+pub fn test_virtio_bad_requests() {
+    let mut cpu = setup_queue();
+
+    // An unknown request type and a sector beyond the end of the disk
+    write_request(&mut cpu, DESC, 0, 0x99, 0, BUFFER, false);
+    write_request(&mut cpu, DESC, 3, 0, 4, BUFFER, false);
+    submit(&mut cpu, &[0, 3]);
+    assert_eq!(cpu.mmu.read8(&cpu.csr, STATUS).unwrap(), 2);
+    assert_eq!(cpu.mmu.read8(&cpu.csr, STATUS + 3).unwrap(), 1);
+
+    // A buffer outside DRAM and a chain that loops are returned unprocessed
+    write_request(&mut cpu, DESC, 0, 0, 0, 0x1000, false);
+    write_desc(&mut cpu, DESC, 6, HEADER, 16, F_NEXT, 6);
+    submit(&mut cpu, &[0, 6]);
+    assert_eq!(cpu.mmu.read16(&cpu.csr, USED + 2).unwrap(), 4);
+    assert_eq!(used_elem(&mut cpu, 2), (0, 0));
+    assert_eq!(used_elem(&mut cpu, 3), (6, 0));
+}
+
+#[test]
+pub fn test_virtio_interrupt_suppression() {
+    let mut cpu = setup_queue();
+    write_request(&mut cpu, DESC, 0, 0, 0, BUFFER, false);
+
+    // VIRTQ_AVAIL_F_NO_INTERRUPT
+    cpu.mmu.write16(&cpu.csr, AVAIL, 1).unwrap();
+    submit(&mut cpu, &[0]);
+    assert_eq!(cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x060).unwrap(), 0);
+
+    // With VIRTIO_F_EVENT_IDX the driver asks for an interrupt once used idx passes used_event
+    cpu.mmu.write32(&cpu.csr, VIRTIO_BASE + 0x020, 1 << 29).unwrap();
+    cpu.mmu.write16(&cpu.csr, AVAIL + 4 + QUEUE_NUM * 2, 2).unwrap();
+    submit(&mut cpu, &[0]);
+    assert_eq!(cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x060).unwrap(), 0);
+    assert_eq!(cpu.mmu.read16(&cpu.csr, USED + 4 + QUEUE_NUM * 8).unwrap(), 2);
+    submit(&mut cpu, &[0]);
+    assert_eq!(cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x060).unwrap(), 1);
+}
+
+#[test]
+pub fn test_virtio_plic_interrupt() {
+    use crate::emulator::bus::PLIC_BASE;
+    use crate::emulator::csr::MIP_SEIP;
+
+    let mut cpu = setup_queue();
+
+    // Source 1 with priority 1, enabled for the S-mode context of hart 0 with threshold 0
+    cpu.mmu.write32(&cpu.csr, PLIC_BASE + 0x4, 1).unwrap();
+    cpu.mmu.write32(&cpu.csr, PLIC_BASE + 0x2080, 1 << 1).unwrap();
+    cpu.mmu.write32(&cpu.csr, PLIC_BASE + 0x20_1000, 0).unwrap();
+
+    write_request(&mut cpu, DESC, 0, 0, 1, BUFFER, false);
+    submit(&mut cpu, &[0]);
+    let mut mip = 0;
+    cpu.mmu.tick(&mut mip);
+    assert_eq!(mip, MIP_SEIP);
+
+    // The driver claims the interrupt, acknowledges the device and completes it
+    assert_eq!(cpu.mmu.read32(&cpu.csr, PLIC_BASE + 0x20_1004).unwrap(), 1);
+    cpu.mmu.write32(&cpu.csr, VIRTIO_BASE + 0x064, 1).unwrap();
+    assert_eq!(cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x060).unwrap(), 0);
+    cpu.mmu.write32(&cpu.csr, PLIC_BASE + 0x20_1004, 1).unwrap();
+    cpu.mmu.tick(&mut mip);
+    assert_eq!(mip, 0);
+}
+
+#[test]
+pub fn test_virtio_readonly() {
+    use crate::emulator::disk::{ Disk, DiskMode };
+    use std::fs;
+
+    let path = std::env::temp_dir().join(format!("riscv-test-{}-virtio-readonly", std::process::id()));
+    fs::write(&path, vec![7; 2 * 512]).unwrap();
+    let mut cpu = setup_queue();
+    cpu.mmu.set_disk(Disk::open(path.to_str().unwrap(), DiskMode::ReadOnly).unwrap());
+
+    // VIRTIO_BLK_F_RO is offered, and writes fail with VIRTIO_BLK_S_IOERR
+    assert_ne!(cpu.mmu.read32(&cpu.csr, VIRTIO_BASE + 0x010).unwrap() & (1 << 5), 0);
+    write_request(&mut cpu, DESC, 0, 1, 0, BUFFER, true);
+    write_request(&mut cpu, DESC, 3, 0, 1, BUFFER + 0x800, false);
+    submit(&mut cpu, &[0, 3]);
+    assert_eq!(cpu.mmu.read8(&cpu.csr, STATUS).unwrap(), 1);
+    assert_eq!(cpu.mmu.read8(&cpu.csr, STATUS + 3).unwrap(), 0);
+    assert_eq!(cpu.mmu.read8(&cpu.csr, BUFFER + 0x800).unwrap(), 7);
+    assert_eq!(fs::read(&path).unwrap(), vec![7; 2 * 512]);
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,59 @@
+#![cfg(test)]
+
+use crate::emulator::cpu::Cpu;
+use crate::emulator::csr::*;
+use crate::emulator::bus::CLINT_BASE;
+use crate::emulator::clint::{ MTIMECMP_BASE, MTIME_BASE };
+use crate::emulator::exception::Exception;
+
+const WFI: u32 = 0x1050_0073;
+
+#[test]
+pub fn test_wfi_tw() {
+    let mut cpu = Cpu::new();
+    cpu.instruction = WFI;
+
+    cpu.csr.priv_level = PrivLevel::SUPERVISOR;
+    cpu.execute().unwrap();
+    assert!(cpu.wfi);
+    cpu.wfi = false;
+
+    // With TW set, S-mode may not wait
+    cpu.csr.write_bit(MSTATUS, MSTATUS_TW_BIT, true);
+    match cpu.execute() {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("WFI must trap from S-mode while mstatus.TW is set"),
+    }
+
+    // M-mode ignores TW
+    cpu.csr.priv_level = PrivLevel::MACHINE;
+    cpu.execute().unwrap();
+    assert!(cpu.wfi);
+}
+
+#[test]
+pub fn test_wfi_user() {
+    let mut cpu = Cpu::new();
+    cpu.instruction = WFI;
+    cpu.csr.priv_level = PrivLevel::USER;
+
+    match cpu.execute() {
+        Err(Exception::IllegalInst) => (),
+        _                           => panic!("WFI must trap from U-mode"),
+    }
+}
+
+#[test]
+pub fn test_wfi_timer_fast_forward() {
+    let mut cpu = Cpu::new();
+
+    cpu.mmu.write64(&cpu.csr, CLINT_BASE + MTIMECMP_BASE, 1_000_000).unwrap();
+    cpu.csr.write(MIE, MIP_MTIP);
+    cpu.wfi = true;
+
+    // The timer interrupt becomes pending without interpreting a million ticks
+    cpu.wait_for_interrupt();
+    assert!(!cpu.wfi);
+    assert_ne!(cpu.csr.read(MIP) & MIP_MTIP, 0);
+    assert!(cpu.mmu.read64(&cpu.csr, CLINT_BASE + MTIME_BASE).unwrap() >= 1_000_000);
+}